@@ -0,0 +1,202 @@
+//! Throughput of `InMemoryLockManager`, used directly as the state machine
+//! of a node would use it.
+//!
+//! `acquire_release` compares the sharded table against the design it
+//! replaced: one map behind one `RwLock`, taken for writing by every
+//! acquire and release, with the history recorded while it is held.
+//! Threads work on locks of their own, so the sharded table lets them run
+//! side by side where the single lock takes them one at a time. The gap
+//! only shows with as many cores as threads.
+//!
+//! Run with `cargo bench --bench lock_manager`.
+
+use std::{collections::HashMap, sync::{Arc, RwLock, atomic::{AtomicBool, Ordering}}, thread, time::{Duration, Instant}};
+
+use chrono::Duration as ChronoDuration;
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use distlock::{config::server_config::HistoryConfig, lock::{history::{History, HistoryEvent, HistoryEventKind}, manager::InMemoryLockManager, metadata::Metadata, types::{AcquireResult, ApplyContext, ClientId, LeaseId, LockHolder, LockId, LockManager, LockState}}};
+
+const TTL : Duration = Duration::from_secs(30);
+/// Distinct locks each thread cycles through.
+const LOCKS_PER_THREAD : usize = 1_000;
+
+fn lock_ids(thread : usize) -> Vec<LockId> {
+    (0..LOCKS_PER_THREAD).map(|i| LockId(format!("bench/{}/{}" , thread , i))).collect()
+}
+
+fn granted(result : AcquireResult , lock_id : &LockId) -> LeaseId {
+    match result {
+        AcquireResult::Granted { lease_id, .. } => lease_id,
+        other => panic!("'{}' was not free: {:?}" , lock_id.0 , other)
+    }
+}
+
+/// What `acquire_release` runs against.
+trait Table : Send + Sync {
+    fn acquire(&self , lock_id : &LockId , client_id : &ClientId , ctx : &ApplyContext) -> LeaseId;
+    fn release(&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId , ctx : &ApplyContext);
+}
+
+impl Table for InMemoryLockManager{
+    fn acquire(&self , lock_id : &LockId , client_id : &ClientId , ctx : &ApplyContext) -> LeaseId {
+        granted(self.try_acquire_at(lock_id , client_id , TTL , ctx) , lock_id)
+    }
+
+    fn release(&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId , ctx : &ApplyContext) {
+        self.release_at(lock_id , client_id , lease_id , ctx);
+    }
+}
+
+/// The table before sharding: every lock and the history behind one lock.
+struct SingleLockTable{
+    inner : RwLock<(HashMap<LockId , LockState> , History)>
+}
+
+impl SingleLockTable{
+    fn new() -> Self {
+        Self { inner: RwLock::new((HashMap::new() , History::new(HistoryConfig::default()))) }
+    }
+}
+
+fn event(kind : HistoryEventKind , lock_id : &LockId , holder : &LockHolder , ctx : &ApplyContext) -> HistoryEvent {
+    HistoryEvent { lock_id: lock_id.clone(), kind, client_id: holder.client_id.clone(), lease_id: Some(holder.lease_id.clone()), index: ctx.index, at: ctx.now, metadata: Metadata::new(), actor: None }
+}
+
+impl Table for SingleLockTable{
+    fn acquire(&self , lock_id : &LockId , client_id : &ClientId , ctx : &ApplyContext) -> LeaseId {
+        let mut inner = self.inner.write().unwrap();
+        let (locks , history) = &mut *inner;
+        let state = locks.entry(lock_id.clone()).or_insert_with(|| LockState { holder: None, wait_queue: Vec::new(), created_at: ctx.now });
+        if state.holder.as_ref().is_some_and(|holder| holder.expires_at >= ctx.now) {
+            panic!("'{}' was not free" , lock_id.0)
+        }
+        let holder = state.holder.insert(LockHolder {
+            client_id: client_id.clone(),
+            lease_id: ctx.next_lease_id(),
+            acquired_at: ctx.now,
+            expires_at: ctx.now + ChronoDuration::from_std(TTL).unwrap(),
+            renewal_count: 0,
+            fencing_token: ctx.index,
+            metadata: Metadata::new()
+        });
+        history.record(event(HistoryEventKind::Granted , lock_id , holder , ctx) , ctx.now);
+        holder.lease_id.clone()
+    }
+
+    fn release(&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId , ctx : &ApplyContext) {
+        let mut inner = self.inner.write().unwrap();
+        let (locks , history) = &mut *inner;
+        let Some(state) = locks.get_mut(lock_id) else { return };
+        if let Some(holder) = state.holder.take_if(|holder| holder.client_id == *client_id && holder.lease_id == *lease_id) {
+            history.record(event(HistoryEventKind::Released , lock_id , &holder , ctx) , ctx.now);
+        }
+    }
+}
+
+/// Acquires and releases `iterations` locks on each of `threads` threads,
+/// every thread on locks of its own. Returns the time the slowest took.
+fn acquire_release(table : &Arc<dyn Table> , threads : usize , iterations : u64) -> Duration {
+    let ctx = ApplyContext::local();
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for thread in 0..threads {
+            let (table , ctx) = (table.clone() , &ctx);
+            scope.spawn(move || {
+                let client_id = ClientId(format!("client-{}" , thread));
+                let lock_ids = lock_ids(thread);
+                for i in 0..iterations as usize {
+                    let lock_id = &lock_ids[i % lock_ids.len()];
+                    let lease_id = table.acquire(lock_id , &client_id , ctx);
+                    table.release(lock_id , &client_id , &lease_id , ctx);
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn bench_uncontended(c : &mut Criterion) {
+    let manager = InMemoryLockManager::new();
+    let ctx = ApplyContext::local();
+    let client_id = ClientId("client".to_string());
+    let lock_ids = lock_ids(0);
+    let mut next = 0;
+
+    let mut group = c.benchmark_group("uncontended");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("acquire_release" , |b| b.iter(|| {
+        let lock_id = &lock_ids[next % lock_ids.len()];
+        next += 1;
+        let lease_id = granted(manager.try_acquire_at(lock_id , &client_id , TTL , &ctx) , lock_id);
+        manager.release_at(lock_id , &client_id , &lease_id , &ctx)
+    }));
+    group.finish();
+}
+
+fn bench_sharding(c : &mut Criterion) {
+    let mut group = c.benchmark_group("acquire_release");
+    for threads in [1 , 4 , 8] {
+        group.throughput(Throughput::Elements(threads as u64));
+        let tables : [(&str , Arc<dyn Table>) ; 2] = [("single_lock" , Arc::new(SingleLockTable::new())) , ("sharded" , Arc::new(InMemoryLockManager::new()))];
+        for (name , table) in tables {
+            group.bench_with_input(BenchmarkId::new(name , threads) , &threads , |b , &threads| {
+                b.iter_custom(|iterations| acquire_release(&table , threads , iterations));
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Queues `waiters` clients behind a holder, then releases the lock to each
+/// of them in turn until nobody holds it.
+fn bench_contended(c : &mut Criterion) {
+    let lock_id = LockId("hot".to_string());
+    let mut group = c.benchmark_group("contended");
+    for waiters in [10 , 100 , 1_000] {
+        let manager = InMemoryLockManager::new();
+        let ctx = ApplyContext::local();
+        let clients : Vec<ClientId> = (0..=waiters).map(|i| ClientId(format!("client-{}" , i))).collect();
+        group.throughput(Throughput::Elements(waiters as u64));
+        group.bench_with_input(BenchmarkId::new("queue_and_drain" , waiters) , &waiters , |b , _| b.iter(|| {
+            for client_id in &clients {
+                manager.try_acquire_at(&lock_id , client_id , TTL , &ctx);
+            }
+            while let Some(holder) = manager.status(&lock_id).and_then(|state| state.holder) {
+                manager.release_at(&lock_id , &holder.client_id , &holder.lease_id , &ctx);
+            }
+        }));
+    }
+    group.finish();
+}
+
+/// Reads the status of a held lock while another thread keeps acquiring and
+/// releasing locks of its own.
+fn bench_status_under_writes(c : &mut Criterion) {
+    let manager = Arc::new(InMemoryLockManager::new());
+    let ctx = ApplyContext::local();
+    let lock_id = LockId("read".to_string());
+    granted(manager.try_acquire_at(&lock_id , &ClientId("reader".to_string()) , Duration::from_secs(3_600) , &ctx) , &lock_id);
+
+    let stop = AtomicBool::new(false);
+    let mut group = c.benchmark_group("status");
+    group.throughput(Throughput::Elements(1));
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let client_id = ClientId("writer".to_string());
+            let lock_ids = lock_ids(1);
+            for lock_id in lock_ids.iter().cycle() {
+                if stop.load(Ordering::Relaxed) {
+                    return
+                }
+                let lease_id = granted(manager.try_acquire_at(lock_id , &client_id , TTL , &ctx) , lock_id);
+                manager.release_at(lock_id , &client_id , &lease_id , &ctx);
+            }
+        });
+        group.bench_function("under_writes" , |b| b.iter(|| manager.status(&lock_id)));
+        stop.store(true , Ordering::Relaxed);
+    });
+    group.finish();
+}
+
+criterion_group!(benches , bench_uncontended , bench_sharding , bench_contended , bench_status_under_writes);
+criterion_main!(benches);
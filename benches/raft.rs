@@ -0,0 +1,103 @@
+//! Cost of getting a command through raft: encoding it as a log entry, and
+//! the round trip of a proposal on a single node and on three nodes talking
+//! over the in-process transport.
+//!
+//! Run with `cargo bench --bench raft`.
+
+use std::{sync::Arc, time::{Duration, Instant}};
+
+use chrono::Utc;
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use distlock::{lock::metadata::Metadata, raft::{node::RaftNode, raft_client::RaftClient, raft_commands::{CommandResponse, IdempotencyKey, LockCommand, LogEntry}, transport::LocalTransport}};
+use tokio::{runtime::Runtime, sync::mpsc};
+
+fn log_entry() -> LogEntry {
+    let metadata : Metadata = [("owner" , "billing-worker-7") , ("reason" , "nightly reconciliation")].into_iter()
+        .map(|(key , value)| (key.to_string() , value.to_string()))
+        .collect();
+    LogEntry {
+        command: LockCommand::Acquire {
+            lock_id: "billing/invoices/2026-10".to_string(),
+            client_id: "billing-worker-7".to_string(),
+            ttl_seconds: 30,
+            request_id: 42,
+            idempotency_key: Some(IdempotencyKey { client_id: "billing-worker-7".to_string(), seq: 1_234 }),
+            queue: true,
+            metadata
+        },
+        proposer: 1,
+        issued_at: Utc::now(),
+        lease_seed: 0x5eed
+    }
+}
+
+fn bench_serialization(c : &mut Criterion) {
+    let entry = log_entry();
+    let data = serde_json::to_vec(&entry).unwrap();
+
+    let mut group = c.benchmark_group("lock_command");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("serialize" , |b| b.iter(|| serde_json::to_vec(&entry).unwrap()));
+    group.bench_function("deserialize" , |b| b.iter(|| serde_json::from_slice::<LogEntry>(&data).unwrap()));
+    group.finish();
+}
+
+fn start_cluster(ids : &[u64]) -> Vec<RaftClient> {
+    let transport = Arc::new(LocalTransport::new());
+    let mut clients = Vec::new();
+    for id in ids {
+        let (command_tx , command_rx) = mpsc::channel(100);
+        let (admin_tx , admin_rx) = mpsc::channel(10);
+        let node = RaftNode::new(*id , ids.to_vec() , command_rx , admin_rx).with_transport(transport.clone());
+        transport.register(*id , node.message_sender());
+        tokio::spawn(node.run());
+        clients.push(RaftClient::new(command_tx , admin_tx));
+    }
+    clients
+}
+
+/// The client of whichever node won the election.
+async fn leader(clients : Vec<RaftClient>) -> RaftClient {
+    for _ in 0..100 {
+        for (id , client) in clients.iter().enumerate() {
+            let result = client.propose_acquire("probe".to_string() , format!("probe-{}" , id) , 30 , None).await.unwrap();
+            if !matches!(result , CommandResponse::Error { .. }) {
+                return clients.into_iter().nth(id).unwrap()
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("No leader elected")
+}
+
+/// Acquires and releases a lock, two proposals each committed and applied
+/// before the next is made.
+async fn acquire_release(client : &RaftClient) {
+    let CommandResponse::AcquireGranted { lease_id , .. } = client.propose_acquire("bench".to_string() , "client".to_string() , 30 , None).await.unwrap() else {
+        panic!("Expected the lock to be free")
+    };
+    client.propose_release(lease_id , "bench".to_string() , "client".to_string() , None).await.unwrap();
+}
+
+fn bench_proposals(c : &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("proposal");
+    group.throughput(Throughput::Elements(2));
+    for nodes in [1 , 3] {
+        let ids : Vec<u64> = (1..=nodes).collect();
+        let client = runtime.block_on(async { leader(start_cluster(&ids)).await });
+        group.bench_with_input(BenchmarkId::new("acquire_release" , nodes) , &nodes , |b , _| {
+            b.iter_custom(|iterations| runtime.block_on(async {
+                let start = Instant::now();
+                for _ in 0..iterations {
+                    acquire_release(&client).await;
+                }
+                start.elapsed()
+            }));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches , bench_serialization , bench_proposals);
+criterion_main!(benches);
@@ -0,0 +1,9 @@
+// Generates the gRPC service from the vendored proto file. protoc comes from
+// protoc-bin-vendored so the build does not need it installed.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let protoc = protoc_bin_vendored::protoc_bin_path()?;
+    unsafe { std::env::set_var("PROTOC", protoc) };
+    println!("cargo:rerun-if-changed=proto/distlock.proto");
+    tonic_build::compile_protos("proto/distlock.proto")?;
+    Ok(())
+}
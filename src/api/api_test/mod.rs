@@ -0,0 +1 @@
+pub mod test;
\ No newline at end of file
@@ -0,0 +1,95 @@
+
+
+#[cfg(test)]
+mod tests{
+    use crate::api::{etcd::{LeaseGrantRequest, LeaseGrantResponse, LeaseHolder, LockRequest, ResponseHeader, lease_client_id, lock_key, parse_lock_key}, resp::{RespCommand, RespValue, parse_command}};
+
+    fn args(values : &[&str]) -> Vec<Vec<u8>> {
+        values.iter().map(|value| value.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+
+    fn test_parse_array_and_inline_commands(){
+        let frame = b"*3\r\n$3\r\nGET\r\n$4\r\nlock\r\n";
+        assert_eq!(parse_command(&frame[..20]).unwrap() , None);
+
+        let buf = [&b"*2\r\n$3\r\nGET\r\n$4\r\nlock\r\n"[..] , b"PING\r\n"].concat();
+        let (command , used) = parse_command(&buf).unwrap().unwrap();
+        assert_eq!(command , args(&["GET" , "lock"]));
+
+        let (command , rest) = parse_command(&buf[used..]).unwrap().unwrap();
+        assert_eq!(command , args(&["PING"]));
+        assert_eq!(used + rest , buf.len());
+
+        assert!(parse_command(b"*1\r\n+GET\r\n").is_err());
+    }
+
+    #[test]
+
+    fn test_parse_set_nx(){
+        let command = RespCommand::parse(&args(&["set" , "lock" , "token" , "NX" , "PX" , "30000"])).unwrap();
+        assert_eq!(command , RespCommand::SetNx { key : "lock".to_string() , token : "token".to_string() , ttl_ms : Some(30_000) });
+
+        let command = RespCommand::parse(&args(&["SET" , "lock" , "token" , "EX" , "5" , "NX"])).unwrap();
+        assert_eq!(command , RespCommand::SetNx { key : "lock".to_string() , token : "token".to_string() , ttl_ms : Some(5_000) });
+
+        assert!(RespCommand::parse(&args(&["SET" , "lock" , "token" , "PX" , "100"])).is_err());
+        assert!(RespCommand::parse(&args(&["SET" , "lock" , "token" , "NX" , "PX"])).is_err());
+    }
+
+    #[test]
+
+    fn test_recognise_unlock_and_extend_scripts(){
+        let redlock_unlock = r#"if redis.call("get",KEYS[1]) == ARGV[1] then return redis.call("del",KEYS[1]) else return 0 end"#;
+        let command = RespCommand::parse(&args(&["EVAL" , redlock_unlock , "1" , "lock" , "token"])).unwrap();
+        assert_eq!(command , RespCommand::Release { key : "lock".to_string() , token : "token".to_string() });
+
+        let extend = "local token = redis.call('get', KEYS[1]) if not token or token ~= ARGV[1] then return 0 end redis.call('pexpire', KEYS[1], ARGV[2]) return 1";
+        let command = RespCommand::parse(&args(&["eval" , extend , "1" , "lock" , "token" , "2000" , "1"])).unwrap();
+        assert_eq!(command , RespCommand::Extend { key : "lock".to_string() , token : "token".to_string() , ttl_ms : 2_000 });
+
+        assert!(RespCommand::parse(&args(&["EVAL" , "return redis.call('incr', KEYS[1])" , "1" , "lock" , "token"])).is_err());
+        assert_eq!(RespCommand::parse(&args(&["distlock.token" , "lock"])).unwrap() , RespCommand::Token { key : "lock".to_string() });
+        assert!(RespCommand::parse(&args(&["DISTLOCK.TOKEN"])).is_err());
+        assert!(RespCommand::parse(&args(&["EVALSHA" , "abc" , "1" , "lock" , "token"])).unwrap_err().starts_with("NOSCRIPT"));
+    }
+
+    #[test]
+
+    fn test_encode_replies(){
+        let mut out = Vec::new();
+        RespValue::Array(vec![RespValue::ok() , RespValue::Integer(-2) , RespValue::Bulk(b"token".to_vec()) , RespValue::Null , RespValue::Error("ERR no".to_string())]).encode(&mut out);
+        assert_eq!(out , b"*5\r\n+OK\r\n:-2\r\n$5\r\ntoken\r\n$-1\r\n-ERR no\r\n".to_vec());
+    }
+
+    #[test]
+
+    fn test_etcd_lease_holder_round_trip(){
+        let client_id = lease_client_id(0x694d_7a1b , 30 , Some(b"node-a:8080"));
+        assert_eq!(LeaseHolder::parse(&client_id) , Some(LeaseHolder { lease : 0x694d_7a1b , ttl : 30 , value : Some(b"node-a:8080".to_vec()) }));
+        assert_eq!(LeaseHolder::parse(&lease_client_id(7 , 5 , None)) , Some(LeaseHolder { lease : 7 , ttl : 5 , value : None }));
+        assert_eq!(LeaseHolder::parse("client_1") , None);
+
+        let key = lock_key("jobs/leader" , 0x694d_7a1b);
+        assert_eq!(key , "jobs/leader/694d7a1b");
+        assert_eq!(parse_lock_key(&key) , Some(("jobs/leader" , 0x694d_7a1b)));
+    }
+
+    #[test]
+
+    fn test_etcd_json_matches_gateway(){
+        let request : LeaseGrantRequest = serde_json::from_str(r#"{"TTL": "10"}"#).unwrap();
+        assert_eq!((request.ttl , request.id) , (10 , 0));
+        let request : LeaseGrantRequest = serde_json::from_str(r#"{"TTL": 10, "ID": 42}"#).unwrap();
+        assert_eq!((request.ttl , request.id) , (10 , 42));
+
+        let request : LockRequest = serde_json::from_str(r#"{"name": "bXlsb2Nr", "lease": "42"}"#).unwrap();
+        assert_eq!((request.name.as_slice() , request.lease) , (&b"mylock"[..] , 42));
+
+        let response = serde_json::to_value(LeaseGrantResponse { header : ResponseHeader::default() , id : 42 , ttl : 10 }).unwrap();
+        assert_eq!(response["ID"] , "42");
+        assert_eq!(response["TTL"] , "10");
+        assert_eq!(response["header"]["revision"] , "0");
+    }
+}
@@ -0,0 +1,261 @@
+//! JSON types of the etcd v3 gateway endpoints that the compatibility
+//! router serves, and how etcd leases map onto distlock locks.
+//!
+//! An etcd lease is a lock named `__etcd/lease/<id>` held for the lease TTL.
+//! Every lock taken under the lease is held by the same client id,
+//! `etcd-lease:<id>:<ttl>`, so keepalive and revoke find them by holder.
+//! An election candidate appends its value: `etcd-lease:<id>:<ttl>:<value>`.
+//!
+//! As in the gateway, 64-bit integers are strings in JSON and keys and
+//! values are base64.
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+const LEASE_LOCK_PREFIX : &str = "__etcd/lease/";
+const LEASE_CLIENT_PREFIX : &str = "etcd-lease:";
+
+pub fn lease_lock_id(lease : i64) -> String {
+    format!("{}{:x}" , LEASE_LOCK_PREFIX , lease)
+}
+
+pub fn lease_client_id(lease : i64 , ttl : u64 , value : Option<&[u8]>) -> String {
+    match value {
+        Some(value) => format!("{}{:x}:{}:{}" , LEASE_CLIENT_PREFIX , lease , ttl , STANDARD.encode(value)),
+        None => format!("{}{:x}:{}" , LEASE_CLIENT_PREFIX , lease , ttl)
+    }
+}
+
+/// A holder's client id split into lease, TTL and election value.
+#[derive(Debug , Clone , PartialEq)]
+pub struct LeaseHolder{
+    pub lease : i64,
+    pub ttl : u64,
+    pub value : Option<Vec<u8>>
+}
+
+impl LeaseHolder{
+    pub fn parse(client_id : &str) -> Option<Self> {
+        let mut parts = client_id.strip_prefix(LEASE_CLIENT_PREFIX)?.split(':');
+        let lease = i64::from_str_radix(parts.next()? , 16).ok()?;
+        let ttl = parts.next()?.parse().ok()?;
+        let value = match parts.next() {
+            Some(value) => Some(STANDARD.decode(value).ok()?),
+            None => None
+        };
+        Some(Self { lease, ttl, value })
+    }
+}
+
+/// Lock and election keys are `<name>/<lease in hex>`, as in etcd.
+pub fn lock_key(name : &str , lease : i64) -> String {
+    format!("{}/{:x}" , name , lease)
+}
+
+pub fn parse_lock_key(key : &str) -> Option<(&str , i64)> {
+    let (name , lease) = key.rsplit_once('/')?;
+    Some((name , i64::from_str_radix(lease , 16).ok()?))
+}
+
+/// Base64 in JSON, raw bytes in Rust.
+pub mod bytes_b64{
+    use super::*;
+
+    pub fn serialize<S : Serializer>(value : &[u8] , serializer : S) -> Result<S::Ok , S::Error> {
+        serializer.serialize_str(&STANDARD.encode(value))
+    }
+
+    pub fn deserialize<'de , D : Deserializer<'de>>(deserializer : D) -> Result<Vec<u8> , D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+/// The gateway writes 64-bit integers as strings and accepts both forms.
+pub mod int64{
+    use super::*;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrNumber{
+        String(String),
+        Number(i64)
+    }
+
+    pub fn serialize<S : Serializer>(value : &i64 , serializer : S) -> Result<S::Ok , S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de , D : Deserializer<'de>>(deserializer : D) -> Result<i64 , D::Error> {
+        match StringOrNumber::deserialize(deserializer)? {
+            StringOrNumber::String(value) => value.parse().map_err(serde::de::Error::custom),
+            StringOrNumber::Number(value) => Ok(value)
+        }
+    }
+}
+
+#[derive(Serialize , Deserialize , Debug , Clone , Default)]
+pub struct ResponseHeader{
+    #[serde(with = "int64")]
+    pub cluster_id : i64,
+    #[serde(with = "int64")]
+    pub member_id : i64,
+    #[serde(with = "int64")]
+    pub revision : i64,
+    #[serde(with = "int64")]
+    pub raft_term : i64
+}
+
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct LeaseGrantRequest{
+    #[serde(rename = "TTL" , with = "int64")]
+    pub ttl : i64,
+    /// 0 lets the server choose.
+    #[serde(rename = "ID" , with = "int64" , default)]
+    pub id : i64
+}
+
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct LeaseGrantResponse{
+    pub header : ResponseHeader,
+    #[serde(rename = "ID" , with = "int64")]
+    pub id : i64,
+    #[serde(rename = "TTL" , with = "int64")]
+    pub ttl : i64
+}
+
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct LeaseIdRequest{
+    #[serde(rename = "ID" , with = "int64")]
+    pub id : i64
+}
+
+/// Keepalive is a stream in the gateway; each message is wrapped in
+/// `result`.
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct LeaseKeepAliveResponse{
+    pub result : LeaseKeepAliveResult
+}
+
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct LeaseKeepAliveResult{
+    pub header : ResponseHeader,
+    #[serde(rename = "ID" , with = "int64")]
+    pub id : i64,
+    /// 0 when the lease has expired or does not exist.
+    #[serde(rename = "TTL" , with = "int64")]
+    pub ttl : i64
+}
+
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct LeaseTimeToLiveResponse{
+    pub header : ResponseHeader,
+    #[serde(rename = "ID" , with = "int64")]
+    pub id : i64,
+    /// Remaining seconds, -1 when the lease does not exist.
+    #[serde(rename = "TTL" , with = "int64")]
+    pub ttl : i64,
+    #[serde(rename = "grantedTTL" , with = "int64")]
+    pub granted_ttl : i64
+}
+
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct HeaderResponse{
+    pub header : ResponseHeader
+}
+
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct LockRequest{
+    #[serde(with = "bytes_b64")]
+    pub name : Vec<u8>,
+    #[serde(with = "int64")]
+    pub lease : i64
+}
+
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct LockResponse{
+    pub header : ResponseHeader,
+    #[serde(with = "bytes_b64")]
+    pub key : Vec<u8>
+}
+
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct UnlockRequest{
+    #[serde(with = "bytes_b64")]
+    pub key : Vec<u8>
+}
+
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct CampaignRequest{
+    #[serde(with = "bytes_b64")]
+    pub name : Vec<u8>,
+    #[serde(with = "int64")]
+    pub lease : i64,
+    #[serde(with = "bytes_b64" , default)]
+    pub value : Vec<u8>
+}
+
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct LeaderKey{
+    #[serde(with = "bytes_b64")]
+    pub name : Vec<u8>,
+    #[serde(with = "bytes_b64")]
+    pub key : Vec<u8>,
+    #[serde(with = "int64" , default)]
+    pub rev : i64,
+    #[serde(with = "int64")]
+    pub lease : i64
+}
+
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct CampaignResponse{
+    pub header : ResponseHeader,
+    pub leader : LeaderKey
+}
+
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct LeaderRequest{
+    #[serde(with = "bytes_b64")]
+    pub name : Vec<u8>
+}
+
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct ResignRequest{
+    pub leader : LeaderKey
+}
+
+#[derive(Serialize , Deserialize , Debug , Clone , PartialEq)]
+pub struct KeyValue{
+    #[serde(with = "bytes_b64")]
+    pub key : Vec<u8>,
+    #[serde(with = "bytes_b64")]
+    pub value : Vec<u8>,
+    #[serde(with = "int64")]
+    pub lease : i64
+}
+
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct LeaderResponse{
+    pub header : ResponseHeader,
+    pub kv : KeyValue
+}
+
+/// One message of the observe stream.
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct ObserveResponse{
+    pub result : LeaderResponse
+}
+
+/// Error body of the gateway. `code` is the gRPC status code.
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct EtcdError{
+    pub error : String,
+    pub code : i32,
+    pub message : String
+}
+
+impl EtcdError{
+    pub fn new(code : i32 , message : &str) -> Self {
+        Self { error: message.to_string(), code, message: message.to_string() }
+    }
+}
@@ -0,0 +1,5 @@
+//! Types and service stubs generated from `proto/distlock.proto`.
+
+#![allow(clippy::all)]
+
+tonic::include_proto!("distlock.v1");
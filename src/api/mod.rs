@@ -1,4 +1,6 @@
-
-
+pub mod etcd;
+pub mod grpc;
 pub mod models;
-pub mod utils;
\ No newline at end of file
+pub mod resp;
+pub mod utils;
+pub mod api_test;
@@ -1,5 +1,8 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::{auth::acl::{AclRule, AclTable, Principal, hash_token}, lock::{barrier::{Barrier, BarrierKind}, history::HistoryEvent, kv::KvEntry, metadata::Metadata, quota::LockQuotas, types::{LockHolder, LockId, LockState, WaitRequest}}};
+
 
 
 #[derive(Debug,  Deserialize , Serialize)]
@@ -7,14 +10,24 @@ use serde::{Deserialize, Serialize};
 pub struct AcquireRequest{
     pub lock_id : String , 
     pub client_id : String , 
-    pub  time_to_live : u64
+    pub  time_to_live : u64,
+    /// Per-client sequence number. Retries must reuse it to be applied once.
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub seq : Option<u64>,
+    /// Attached to the lease and shown to anyone who can see its status.
+    #[serde(default , skip_serializing_if = "Metadata::is_empty")]
+    pub metadata : Metadata
 }
 
-#[derive(Serialize ,  Debug)]
+#[derive(Serialize , Deserialize , Debug)]
 pub enum AcquireResponse{
     Granted {
          lease_id : String , 
-         expires_at : String
+         expires_at : String ,
+         /// Grows with every grant of the lock; pass it to the resources
+         /// the lock guards so they can refuse a stale holder.
+         #[serde(default)]
+         fencing_token : u64
     } , 
     Queued {
          position : usize , 
@@ -26,13 +39,34 @@ pub enum AcquireResponse{
     }
 }
 
-#[derive(Deserialize , Debug )]
+#[derive(Deserialize , Serialize , Debug )]
 pub struct ReleaseRequest{
     pub lock_id : String , 
     pub lease_id : String , 
     pub client_id : String , 
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub seq : Option<u64>
+}
+/// Leaves the wait queue of a lock without acquiring it.
+#[derive(Deserialize , Serialize , Debug )]
+pub struct CancelWaitRequest{
+    pub lock_id : String , 
+    pub client_id : String
 }
-#[derive(Serialize , Debug )]
+
+#[derive(Serialize , Deserialize , Debug )]
+pub enum CancelWaitResponse{
+    /// Queue entries removed, 0 when the client was not waiting.
+    Cancelled{
+        removed : usize
+    } ,
+     Error{
+        error_type: String,
+        message: String,
+    }
+}
+
+#[derive(Serialize , Deserialize , Debug )]
 pub enum ReleaseResponse{
     Success ,
      Error{
@@ -41,15 +75,20 @@ pub enum ReleaseResponse{
     } 
 }
 
-#[derive(Deserialize , Debug)]
+#[derive(Deserialize , Serialize , Debug)]
 pub struct RenewRequest{
     pub lock_id : String , 
     pub client_id : String , 
     pub lease_id : String , 
-    pub time_to_live : u64
+    pub time_to_live : u64,
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub seq : Option<u64>,
+    /// Replaces the lease's metadata. Left out, it is kept.
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub metadata : Option<Metadata>
 }
 
-#[derive(Serialize , Debug)]
+#[derive(Serialize , Deserialize , Debug)]
 pub enum RenewResponse{
     Success{
         new_expiry : String
@@ -59,7 +98,36 @@ pub enum RenewResponse{
         message: String,
     }
 }
-#[derive(Serialize , Debug)]
+/// Hands a held lock to `target_client_id`. 0 as `time_to_live` gives the
+/// new lease the default TTL.
+#[derive(Serialize , Deserialize , Debug)]
+pub struct TransferRequest{
+    pub lock_id : String , 
+    pub client_id : String , 
+    pub lease_id : String , 
+    pub target_client_id : String , 
+    #[serde(default)]
+    pub time_to_live : u64,
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub seq : Option<u64>
+}
+
+/// The target's lease. Only the holder that asked for the transfer sees it,
+/// so it has to pass it on to the target.
+#[derive(Serialize , Deserialize , Debug)]
+pub enum TransferResponse{
+    Transferred{
+        lease_id : String , 
+        expires_at : String , 
+        fencing_token : u64
+    } , 
+    Error{
+        error_type: String,
+        message: String,
+    }
+}
+
+#[derive(Serialize , Deserialize , Debug)]
 pub enum StatusResponse{
     InUse{
         client_id : String ,
@@ -67,26 +135,494 @@ pub enum StatusResponse{
         lease_id : String, 
         queue_length : usize , 
         created_at : String , 
-
+        #[serde(default , skip_serializing_if = "Metadata::is_empty")]
+        metadata : Metadata ,
+        #[serde(default)]
+        fencing_token : u64
     } ,
     Free, 
     NotFound
 }
 
-#[derive(Serialize , Debug)]
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct LockSummary{
+    pub lock_id : String , 
+    pub holder : Option<String> , 
+    pub lease_id : Option<String> , 
+    pub expires_at : Option<String> , 
+    pub queue_length : usize , 
+    pub created_at : String
+}
+
+#[derive(Serialize , Deserialize , Debug)]
+pub struct ListLocksResponse{
+    pub locks : Vec<LockSummary>
+}
+
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct MemberInfo{
+    pub id : u64 , 
+    /// Raft address, when this node knows it.
+    pub addr : Option<String> , 
+    pub client_addr : Option<String> , 
+    pub voter : bool , 
+    pub leader : bool
+}
+
+#[derive(Serialize , Deserialize , Debug)]
+pub enum MembersResponse{
+    Members{
+        node_id : u64 , 
+        leader_id : u64 , 
+        members : Vec<MemberInfo>
+    } , 
+    Error{
+        error_type: String,
+        message: String,
+    }
+}
+
+#[derive(Serialize , Deserialize , Debug)]
+pub struct AddNodeRequest{
+    pub id : u64 , 
+    /// Raft address of the new node.
+    pub addr : String , 
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub client_addr : Option<String>
+}
+
+#[derive(Serialize , Deserialize , Debug)]
+pub struct RemoveNodeRequest{
+    pub id : u64
+}
+
+#[derive(Serialize , Deserialize , Debug)]
+pub enum MembershipResponse{
+    Changed{
+        voters : Vec<u64>
+    } , 
+    Error{
+        error_type: String,
+        message: String,
+    }
+}
+
+/// Query of `GET /admin/locks`. Pages are ordered by lock id.
+#[derive(Serialize , Deserialize , Debug , Default)]
+pub struct AdminLocksQuery{
+    #[serde(default)]
+    pub prefix : String , 
+    /// `next_after` of the previous page.
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub after : Option<String> , 
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub limit : Option<usize>
+}
+
+/// A lock with its holder and every waiter, for operators.
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct LockDetails{
+    pub lock_id : String , 
+    pub holder : Option<LockHolder> , 
+    /// The holder's lease ran out but nobody took the lock over yet.
+    pub expired : bool , 
+    pub waiters : Vec<WaitRequest> , 
+    pub created_at : String
+}
+
+#[derive(Serialize , Deserialize , Debug)]
+pub struct AdminLocksResponse{
+    pub locks : Vec<LockDetails> , 
+    /// Set when there may be more locks; pass it as `after`.
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub next_after : Option<String>
+}
+
+#[derive(Serialize , Deserialize , Debug)]
+pub struct ForceReleaseRequest{
+    pub lock_id : String , 
+    /// Only take the lock from a holder of this lease, so a lock granted
+    /// to the next waiter meanwhile is left alone.
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub lease_id : Option<String> , 
+    /// Client id of the caller. With `seq`, a retried request is applied
+    /// once, like the writes of a lock holder.
+    #[serde(default)]
+    pub requester : String , 
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub seq : Option<u64>
+}
+
+#[derive(Serialize , Deserialize , Debug)]
+pub struct EvictWaiterRequest{
+    pub lock_id : String , 
+    pub client_id : String , 
+    #[serde(default)]
+    pub requester : String , 
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub seq : Option<u64>
+}
+
+#[derive(Serialize , Deserialize , Debug)]
+pub struct RevokeClientRequest{
+    pub client_id : String , 
+    #[serde(default)]
+    pub requester : String , 
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub seq : Option<u64>
+}
+
+#[derive(Serialize , Deserialize , Debug)]
+pub enum AdminLockResponse{
+    ForceReleased{
+        previous_holder : String , 
+        /// Waiter the lock was granted to, if any.
+        promoted : Option<String>
+    } , 
+    WaiterEvicted{
+        removed : usize
+    } , 
+    ClientRevoked{
+        released : usize , 
+        evicted : usize
+    } , 
+    Error{
+        error_type: String,
+        message: String,
+    }
+}
+
+/// Usage statistics of a lock since this node started or last restored a
+/// snapshot. Durations are in seconds.
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct LockStatsResponse{
+    pub lock_id : String , 
+    pub acquires : u64 , 
+    /// Leases granted, including promotions from the queue.
+    pub acquisitions : u64 , 
+    pub queued : u64 , 
+    /// Share of acquires that had to wait in the queue.
+    pub contention_rate : f64 , 
+    pub mean_hold_seconds : f64 , 
+    /// Over the latest 1000 leases.
+    pub p99_hold_seconds : f64 , 
+    pub mean_wait_seconds : f64 , 
+    pub max_queue_depth : usize , 
+    pub releases : u64 , 
+    pub expirations : u64 , 
+    pub force_releases : u64 , 
+    pub transfers : u64 , 
+    pub top_holders : Vec<HolderCount>
+}
+
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct HolderCount{
+    pub client_id : String , 
+    pub acquisitions : u64
+}
+
+/// Query of `GET /locks/contended`.
+#[derive(Serialize , Deserialize , Debug , Default)]
+pub struct ContendedLocksQuery{
+    #[serde(default)]
+    pub prefix : String , 
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub limit : Option<usize>
+}
+
+/// Locks with the most queued acquires first.
+#[derive(Serialize , Deserialize , Debug)]
+pub struct ContendedLocksResponse{
+    pub locks : Vec<LockStatsResponse>
+}
+
+/// Every lock of the state machine, as saved by `GET /admin/snapshot` and
+/// loaded by `POST /admin/snapshot`.
+#[derive(Serialize , Deserialize , Debug)]
+pub struct LockSnapshot{
+    pub taken_at : String , 
+    pub locks : Vec<(LockId , LockState)> , 
+    /// Ownership history, absent from snapshots of older versions.
+    #[serde(default)]
+    pub history : Vec<HistoryEvent>,
+    /// Key-value entries, absent from snapshots of older versions.
+    #[serde(default)]
+    pub kv : Vec<KvEntry>,
+    /// Barriers and latches, absent from snapshots of older versions.
+    #[serde(default)]
+    pub barriers : Vec<(String , Barrier)>
+}
+
+/// Body of `PUT /kv/*key`.
+#[derive(Serialize , Deserialize , Debug)]
+pub struct KvPutRequest{
+    pub client_id : String , 
+    pub value : String , 
+    /// Write only if the key is at this version, 0 meaning absent.
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub expected_version : Option<u64> , 
+    /// Lock and lease of the client the key belongs to. The key is deleted
+    /// when the lease ends.
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub lock_id : Option<String> , 
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub lease_id : Option<String> , 
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub seq : Option<u64>
+}
+
+/// Query of `DELETE /kv/*key`.
+#[derive(Serialize , Deserialize , Debug)]
+pub struct KvDeleteQuery{
+    pub client_id : String , 
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub expected_version : Option<u64> , 
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub seq : Option<u64>
+}
+
+/// Version written by a put, or of the entry removed by a delete.
+#[derive(Serialize , Deserialize , Debug)]
+pub struct KvWriteResponse{
+    pub version : u64
+}
+
+/// Body of `POST /barriers`.
+#[derive(Serialize , Deserialize , Debug)]
+pub struct BarrierCreateRequest{
+    pub name : String , 
+    pub kind : BarrierKind , 
+    pub count : u32
+}
+
+/// Body of `POST /barriers/:name/join`.
+#[derive(Serialize , Deserialize , Debug)]
+pub struct BarrierJoinRequest{
+    pub client_id : String , 
+    pub time_to_live : u64 , 
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub seq : Option<u64>
+}
+
+/// Body of `POST /barriers/:name/arrive`.
+#[derive(Serialize , Deserialize , Debug)]
+pub struct BarrierArriveRequest{
+    pub client_id : String , 
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub seq : Option<u64>
+}
+
+/// Query of `GET /barriers/:name`.
+#[derive(Serialize , Deserialize , Debug , Default)]
+pub struct BarrierQuery{
+    /// Waits up to this long for the barrier to be released.
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub wait_ms : Option<u64>
+}
+
+/// Arrivals still needed after a create, join or arrive, or when the
+/// barrier was deleted.
+#[derive(Serialize , Deserialize , Debug)]
+pub struct BarrierUpdateResponse{
+    pub remaining : u32
+}
+
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct BarrierParticipant{
+    pub client_id : String , 
+    pub expires_at : String
+}
+
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct BarrierResponse{
+    pub name : String , 
+    pub kind : BarrierKind , 
+    pub count : u32 , 
+    pub remaining : u32 , 
+    pub released : bool , 
+    /// A participant let its lease run out and has not come back. Its
+    /// arrival is open again, so the barrier waits for someone to take it.
+    #[serde(default)]
+    pub degraded : bool , 
+    /// Joined and not arrived yet.
+    pub participants : Vec<BarrierParticipant> , 
+    pub arrived : Vec<String> , 
+    /// Joined, then let their lease run out. They may join again.
+    pub expired : Vec<String> , 
+    pub created_at : String
+}
+
+/// Query of `GET /history`. Times are RFC 3339 and both ends are inclusive.
+/// Leases held at `since` are included with the grant or renewal that
+/// started them.
+#[derive(Serialize , Deserialize , Debug , Default)]
+pub struct HistoryQuery{
+    /// Every lock when unset, which needs the admin permission.
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub lock_id : Option<String> , 
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub since : Option<DateTime<Utc>> , 
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub until : Option<DateTime<Utc>> , 
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub limit : Option<usize>
+}
+
+#[derive(Serialize , Deserialize , Debug)]
+pub struct HistoryResponse{
+    /// Oldest first.
+    pub events : Vec<HistoryEvent> , 
+    /// More events matched than `limit`; ask again from the last one's time.
+    pub truncated : bool
+}
+
+#[derive(Serialize , Deserialize , Debug)]
+pub enum RestoreResponse{
+    Restored{
+        locks : usize
+    } , 
+    Error{
+        error_type: String,
+        message: String,
+    }
+}
+
+#[derive(Serialize , Deserialize , Debug)]
+pub struct TransferLeaderRequest{
+    #[serde(default)]
+    pub target : Option<u64> , 
+    #[serde(default)]
+    pub timeout_ms : Option<u64>
+}
+
+#[derive(Serialize , Deserialize , Debug)]
+pub enum TransferLeaderResponse{
+    Transferred{
+        from : u64 , 
+        to : u64 , 
+        term : u64
+    } , 
+    Error{
+        error_type: String,
+        message: String,
+    }
+}
+
+#[derive(Serialize , Deserialize , Debug)]
+pub struct DrainRequest{
+    pub enabled : bool
+}
+
+#[derive(Serialize , Deserialize , Debug)]
+pub enum DrainResponse{
+    Updated{
+        draining : bool , 
+        in_flight : usize
+    } , 
+    Error{
+        error_type: String,
+        message: String,
+    }
+}
+
+/// A principal as sent to `PUT /admin/acl`: either the token itself, which
+/// the server hashes, or its SHA-256 when the caller already has it.
+#[derive(Serialize , Deserialize , Debug , Clone)]
+pub struct PrincipalSpec{
+    pub name : String , 
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub token : Option<String> , 
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub token_sha256 : Option<String>
+}
+
+/// Replaces the whole ACL table.
+#[derive(Serialize , Deserialize , Debug , Clone , Default)]
+pub struct AclRequest{
+    #[serde(default)]
+    pub principals : Vec<PrincipalSpec> , 
+    #[serde(default)]
+    pub rules : Vec<AclRule>
+}
+
+impl AclRequest{
+    pub fn into_table(self) -> Result<AclTable , String> {
+        let principals = self.principals.into_iter().map(|principal| {
+            let token_sha256 = match (principal.token , principal.token_sha256) {
+                (Some(token) , None) => hash_token(&token),
+                (None , Some(token_sha256)) => token_sha256.to_ascii_lowercase(),
+                _ => return Err(format!("Principal '{}' needs exactly one of token and token_sha256" , principal.name))
+            };
+            Ok(Principal { name: principal.name, token_sha256 })
+        }).collect::<Result<_ , _>>()?;
+        let table = AclTable { principals, rules: self.rules };
+        table.validate()?;
+        Ok(table)
+    }
+}
+
+#[derive(Serialize , Deserialize , Debug)]
+pub enum AclResponse{
+    Updated{
+        principals : usize , 
+        rules : usize
+    } , 
+    Error{
+        error_type: String,
+        message: String,
+    }
+}
+
+#[derive(Serialize , Deserialize , Debug)]
+pub enum QuotasResponse{
+    Updated{
+        quotas : LockQuotas
+    } , 
+    Error{
+        error_type: String,
+        message: String,
+    }
+}
+
+#[derive(Serialize , Deserialize , Debug)]
 pub struct ApiError{
     pub error : String , 
     pub message : String , 
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub details : Option<String>
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub details : Option<String>,
+    /// Client API address of the current leader, when this node knows it.
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub leader_hint : Option<String>
 }
 
 impl ApiError{
     pub fn not_found (message:&str) -> Self{
-        Self { error: "NotFound".to_string(), message: message.to_string(), details: None }
+        Self { error: "NotFound".to_string(), message: message.to_string(), details: None, leader_hint: None }
     }
 
     pub fn conflict(message : &str) -> Self{
-        Self { error: "Conflict".to_string(), message: message.to_string(), details: None }
+        Self { error: "Conflict".to_string(), message: message.to_string(), details: None, leader_hint: None }
+    }
+
+    pub fn bad_request(error : &str , message : &str) -> Self{
+        Self { error: error.to_string(), message: message.to_string(), details: None, leader_hint: None }
+    }
+
+    /// Errors the client should retry later, possibly against another node.
+    pub fn unavailable(error : &str , message : &str) -> Self{
+        Self { error: error.to_string(), message: message.to_string(), details: None, leader_hint: None }
+    }
+
+    pub fn unauthenticated(message : &str) -> Self{
+        Self { error: "Unauthenticated".to_string(), message: message.to_string(), details: None, leader_hint: None }
+    }
+
+    pub fn permission_denied(message : &str) -> Self{
+        Self { error: "PermissionDenied".to_string(), message: message.to_string(), details: None, leader_hint: None }
+    }
+
+    pub fn with_leader_hint(mut self , leader_hint : Option<String>) -> Self{
+        self.leader_hint = leader_hint;
+        self
     }
 }
\ No newline at end of file
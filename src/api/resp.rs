@@ -0,0 +1,261 @@
+//! The subset of the Redis protocol (RESP2) that Redlock-style clients use:
+//! frame parsing, reply encoding and the lock commands they send.
+//!
+//! A key is a lock id and the random value a client stores in it is its
+//! client id, so compare-and-delete becomes a release by that client.
+
+/// Frames larger than this are refused rather than buffered.
+const MAX_BULK_LEN : usize = 1024 * 1024;
+const MAX_ARGS : usize = 1024;
+
+#[derive(Debug , Clone , PartialEq)]
+pub enum RespValue{
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Null,
+    Array(Vec<RespValue>)
+}
+
+impl RespValue{
+    pub fn ok() -> Self {
+        RespValue::Simple("OK".to_string())
+    }
+
+    pub fn encode(&self , out : &mut Vec<u8>) {
+        match self {
+            RespValue::Simple(value) => out.extend_from_slice(format!("+{}\r\n" , value).as_bytes()),
+            RespValue::Error(message) => out.extend_from_slice(format!("-{}\r\n" , message).as_bytes()),
+            RespValue::Integer(value) => out.extend_from_slice(format!(":{}\r\n" , value).as_bytes()),
+            RespValue::Bulk(value) => {
+                out.extend_from_slice(format!("${}\r\n" , value.len()).as_bytes());
+                out.extend_from_slice(value);
+                out.extend_from_slice(b"\r\n");
+            }
+            RespValue::Null => out.extend_from_slice(b"$-1\r\n"),
+            RespValue::Array(values) => {
+                out.extend_from_slice(format!("*{}\r\n" , values.len()).as_bytes());
+                for value in values {
+                    value.encode(out);
+                }
+            }
+        }
+    }
+}
+
+/// Arguments of a command and the number of bytes its frame used.
+pub type Frame = (Vec<Vec<u8>> , usize);
+
+/// Parses the command at the front of `buf`, either an array of bulk
+/// strings or an inline command. `None` means the frame is not complete yet.
+pub fn parse_command(buf : &[u8]) -> Result<Option<Frame> , String> {
+    if buf.is_empty() {
+        return Ok(None)
+    }
+    if buf[0] != b'*' {
+        return Ok(read_line(buf , 0)?.map(|(line , next)| {
+            let args = line.split(|byte| byte.is_ascii_whitespace()).filter(|arg| !arg.is_empty()).map(<[u8]>::to_vec).collect();
+            (args , next)
+        }))
+    }
+
+    let Some((line , mut pos)) = read_line(buf , 1)? else { return Ok(None) };
+    let count = parse_len(line)?;
+    if count > MAX_ARGS {
+        return Err(format!("Protocol error: too many arguments ({})" , count))
+    }
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        if pos >= buf.len() {
+            return Ok(None)
+        }
+        if buf[pos] != b'$' {
+            return Err(format!("Protocol error: expected '$', got '{}'" , buf[pos] as char))
+        }
+        let Some((line , next)) = read_line(buf , pos + 1)? else { return Ok(None) };
+        let len = parse_len(line)?;
+        if len > MAX_BULK_LEN {
+            return Err("Protocol error: invalid bulk length".to_string())
+        }
+        if buf.len() < next + len + 2 {
+            return Ok(None)
+        }
+        if &buf[next + len..next + len + 2] != b"\r\n" {
+            return Err("Protocol error: bulk string not terminated by CRLF".to_string())
+        }
+        args.push(buf[next..next + len].to_vec());
+        pos = next + len + 2;
+    }
+    Ok(Some((args , pos)))
+}
+
+/// The line starting at `start`, without its CRLF, and the offset after it.
+fn read_line(buf : &[u8] , start : usize) -> Result<Option<(&[u8] , usize)> , String> {
+    match buf[start..].windows(2).position(|window| window == b"\r\n") {
+        Some(len) => Ok(Some((&buf[start..start + len] , start + len + 2))),
+        None if buf.len() - start > MAX_BULK_LEN => Err("Protocol error: line too long".to_string()),
+        None => Ok(None)
+    }
+}
+
+fn parse_len(line : &[u8]) -> Result<usize , String> {
+    std::str::from_utf8(line).ok().and_then(|line| line.parse().ok())
+        .ok_or_else(|| "Protocol error: invalid length".to_string())
+}
+
+#[derive(Debug , Clone , PartialEq)]
+pub enum RespCommand{
+    /// `SET key token NX [PX ms | EX s]`: acquire without queueing.
+    SetNx{
+        key : String,
+        token : String,
+        ttl_ms : Option<u64>
+    },
+    Get{
+        key : String
+    },
+    Pttl{
+        key : String
+    },
+    /// `DISTLOCK.TOKEN key`: the fencing token of the current holder.
+    Token{
+        key : String
+    },
+    /// `PEXPIRE key ms`: renews whoever holds the lock.
+    PExpire{
+        key : String,
+        ttl_ms : u64
+    },
+    /// Compare-and-delete, from an `EVAL` script or `DISTLOCK.RELEASE`.
+    Release{
+        key : String,
+        token : String
+    },
+    /// Compare-and-pexpire, from an `EVAL` script or `DISTLOCK.EXTEND`.
+    Extend{
+        key : String,
+        token : String,
+        ttl_ms : u64
+    },
+    Ping(Option<Vec<u8>>),
+    Echo(Vec<u8>),
+    Quit,
+    /// Connection setup commands such as `SELECT 0` or `CLIENT SETNAME`,
+    /// accepted and ignored.
+    Ignored
+}
+
+impl RespCommand{
+    /// Errors are full RESP error lines, starting with the error code.
+    pub fn parse(args : &[Vec<u8>]) -> Result<Self , String> {
+        let Some(name) = args.first() else { return Err("ERR empty command".to_string()) };
+        let name = String::from_utf8_lossy(name).to_ascii_uppercase();
+        let arity = |expected : usize| -> Result<() , String> {
+            if args.len() == expected { Ok(()) } else { Err(format!("ERR wrong number of arguments for '{}' command" , name.to_lowercase())) }
+        };
+
+        match name.as_str() {
+            "SET" => parse_set(args),
+            "GET" => {
+                arity(2)?;
+                Ok(RespCommand::Get { key: text(&args[1])? })
+            }
+            "PTTL" => {
+                arity(2)?;
+                Ok(RespCommand::Pttl { key: text(&args[1])? })
+            }
+            "PEXPIRE" => {
+                arity(3)?;
+                Ok(RespCommand::PExpire { key: text(&args[1])?, ttl_ms: integer(&args[2])? })
+            }
+            "EVAL" => parse_eval(args),
+            // Clients fall back to EVAL on NOSCRIPT.
+            "EVALSHA" => Err("NOSCRIPT No matching script. Please use EVAL.".to_string()),
+            "DISTLOCK.RELEASE" => {
+                arity(3)?;
+                Ok(RespCommand::Release { key: text(&args[1])?, token: text(&args[2])? })
+            }
+            "DISTLOCK.EXTEND" => {
+                arity(4)?;
+                Ok(RespCommand::Extend { key: text(&args[1])?, token: text(&args[2])?, ttl_ms: integer(&args[3])? })
+            }
+            "DISTLOCK.TOKEN" => {
+                arity(2)?;
+                Ok(RespCommand::Token { key: text(&args[1])? })
+            }
+            "PING" => Ok(RespCommand::Ping(args.get(1).cloned())),
+            "ECHO" => {
+                arity(2)?;
+                Ok(RespCommand::Echo(args[1].clone()))
+            }
+            "QUIT" => Ok(RespCommand::Quit),
+            "SELECT" | "CLIENT" | "READONLY" => Ok(RespCommand::Ignored),
+            _ => Err(format!("ERR unknown command '{}'" , String::from_utf8_lossy(&args[0])))
+        }
+    }
+}
+
+/// Only `SET NX` can be served: a plain `SET` would overwrite another
+/// client's lock.
+fn parse_set(args : &[Vec<u8>]) -> Result<RespCommand , String> {
+    if args.len() < 3 {
+        return Err("ERR wrong number of arguments for 'set' command".to_string())
+    }
+    let (key , token) = (text(&args[1])? , text(&args[2])?);
+    let mut nx = false;
+    let mut ttl_ms = None;
+    let mut options = args[3..].iter();
+    while let Some(option) = options.next() {
+        match String::from_utf8_lossy(option).to_ascii_uppercase().as_str() {
+            "NX" => nx = true,
+            "PX" | "EX" if ttl_ms.is_some() => return Err("ERR syntax error".to_string()),
+            "PX" => ttl_ms = Some(integer(options.next().ok_or("ERR syntax error")?)?),
+            "EX" => ttl_ms = Some(integer(options.next().ok_or("ERR syntax error")?)?.saturating_mul(1000)),
+            _ => return Err("ERR syntax error".to_string())
+        }
+    }
+    if !nx {
+        return Err("ERR only SET with NX is supported".to_string())
+    }
+    if ttl_ms == Some(0) {
+        return Err("ERR invalid expire time in 'set' command".to_string())
+    }
+    Ok(RespCommand::SetNx { key, token, ttl_ms })
+}
+
+/// Recognises the usual unlock and extend scripts by what they call:
+/// a script that deletes the key is a compare-and-delete of `KEYS[1]` with
+/// token `ARGV[1]`, one that calls `pexpire` extends it by `ARGV[2]` ms.
+fn parse_eval(args : &[Vec<u8>]) -> Result<RespCommand , String> {
+    if args.len() < 3 {
+        return Err("ERR wrong number of arguments for 'eval' command".to_string())
+    }
+    let script = String::from_utf8_lossy(&args[1]).to_ascii_lowercase();
+    let key_count = integer(&args[2])? as usize;
+    if key_count < 1 || args.len() < 3 + key_count + 1 {
+        return Err("ERR the script needs a key and a token".to_string())
+    }
+    let key = text(&args[3])?;
+    let argv = &args[3 + key_count..];
+    let token = text(&argv[0])?;
+
+    let calls = |command : &str| script.contains(&format!("'{}'" , command)) || script.contains(&format!("\"{}\"" , command));
+    if calls("del") || calls("unlink") {
+        Ok(RespCommand::Release { key, token })
+    } else if calls("pexpire") {
+        let ttl_ms = integer(argv.get(1).ok_or("ERR the extend script needs a TTL in ARGV[2]")?)?;
+        Ok(RespCommand::Extend { key, token, ttl_ms })
+    } else {
+        Err("ERR unsupported script, only compare-and-delete and compare-and-pexpire are served".to_string())
+    }
+}
+
+fn text(arg : &[u8]) -> Result<String , String> {
+    String::from_utf8(arg.to_vec()).map_err(|_| "ERR keys and values must be UTF-8".to_string())
+}
+
+fn integer(arg : &[u8]) -> Result<u64 , String> {
+    std::str::from_utf8(arg).ok().and_then(|arg| arg.parse().ok())
+        .ok_or_else(|| "ERR value is not an integer or out of range".to_string())
+}
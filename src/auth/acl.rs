@@ -0,0 +1,106 @@
+//! Principals, the bearer tokens that identify them and the rules that say
+//! what each may do with which locks.
+//!
+//! The table is replicated through the raft log like the lock table, so
+//! tokens are only ever stored as SHA-256 hashes.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Principal of the bootstrap token from the server config. It is not part
+/// of the replicated table and may do everything.
+pub const ROOT_PRINCIPAL : &str = "root";
+
+#[derive(Serialize , Deserialize , Debug , Clone , Copy , PartialEq , Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission{
+    /// Acquire and renew.
+    Acquire,
+    Release,
+    /// Read the status of locks and list them.
+    Status,
+    /// Cluster operations. Only granted by rules with an empty prefix.
+    Admin
+}
+
+#[derive(Serialize , Deserialize , Debug , Clone , PartialEq)]
+pub struct Principal{
+    pub name : String,
+    /// Hex encoded SHA-256 of the principal's token.
+    pub token_sha256 : String
+}
+
+/// Grants `permissions` on every lock whose id starts with `prefix`.
+#[derive(Serialize , Deserialize , Debug , Clone , PartialEq)]
+pub struct AclRule{
+    pub principal : String,
+    #[serde(default)]
+    pub prefix : String,
+    pub permissions : Vec<Permission>
+}
+
+#[derive(Serialize , Deserialize , Debug , Clone , Default , PartialEq)]
+pub struct AclTable{
+    #[serde(default)]
+    pub principals : Vec<Principal>,
+    #[serde(default)]
+    pub rules : Vec<AclRule>
+}
+
+pub fn hash_token(token : &str) -> String {
+    Sha256::digest(token.as_bytes()).iter().map(|byte| format!("{:02x}" , byte)).collect()
+}
+
+/// Whether `client_id` is `name` or an id under it, such as `name/worker-1`.
+pub fn is_client_id_of(name : &str , client_id : &str) -> bool {
+    client_id.strip_prefix(name).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// A principal may use its own name as client id, or any id under it. Root
+/// may use any.
+pub fn owns_client_id(principal : &str , client_id : &str) -> bool {
+    principal == ROOT_PRINCIPAL || is_client_id_of(principal , client_id)
+}
+
+impl AclTable{
+    /// The principal the token belongs to.
+    pub fn authenticate(&self , token : &str) -> Option<&str> {
+        let token_sha256 = hash_token(token);
+        self.principals.iter().find(|principal| principal.token_sha256 == token_sha256).map(|principal| principal.name.as_str())
+    }
+
+    pub fn allows(&self , principal : &str , permission : Permission , lock_id : &str) -> bool {
+        if principal == ROOT_PRINCIPAL {
+            return true
+        }
+        if permission == Permission::Admin {
+            return self.rules.iter().any(|rule| rule.principal == principal && rule.prefix.is_empty() && rule.permissions.contains(&Permission::Admin))
+        }
+        self.rules.iter().any(|rule| rule.principal == principal && lock_id.starts_with(&rule.prefix) && rule.permissions.contains(&permission))
+    }
+
+    pub fn validate(&self) -> Result<() , String> {
+        let mut names : Vec<&str> = Vec::new();
+        for principal in &self.principals {
+            if principal.name.is_empty() || principal.name.contains('/') {
+                return Err(format!("Invalid principal name '{}'" , principal.name))
+            }
+            if principal.name == ROOT_PRINCIPAL {
+                return Err(format!("'{}' is reserved for the bootstrap token" , ROOT_PRINCIPAL))
+            }
+            if names.contains(&principal.name.as_str()) {
+                return Err(format!("Principal '{}' is listed twice" , principal.name))
+            }
+            if principal.token_sha256.len() != 64 || !principal.token_sha256.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+                return Err(format!("Principal '{}' has an invalid token hash" , principal.name))
+            }
+            names.push(&principal.name);
+        }
+        for rule in &self.rules {
+            if !names.contains(&rule.principal.as_str()) {
+                return Err(format!("Rule for unknown principal '{}'" , rule.principal))
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+#[cfg(test)]
+mod tests{
+    use crate::{api::models::{AclRequest, PrincipalSpec}, auth::acl::{AclRule, AclTable, Permission, ROOT_PRINCIPAL, hash_token, owns_client_id}};
+
+    fn table() -> AclTable {
+        AclRequest {
+            principals: vec![
+                PrincipalSpec { name: "billing".to_string(), token: Some("billing-secret".to_string()), token_sha256: None },
+                PrincipalSpec { name: "ops".to_string(), token: None, token_sha256: Some(hash_token("ops-secret")) }
+            ],
+            rules: vec![
+                AclRule { principal: "billing".to_string(), prefix: "billing/".to_string(), permissions: vec![Permission::Acquire , Permission::Release , Permission::Status] },
+                AclRule { principal: "ops".to_string(), prefix: String::new(), permissions: vec![Permission::Status , Permission::Admin] },
+                AclRule { principal: "ops".to_string(), prefix: "ops/".to_string(), permissions: vec![Permission::Acquire] }
+            ]
+        }.into_table().unwrap()
+    }
+
+    #[test]
+
+    fn test_tokens_map_to_principals(){
+        let acl = table();
+        assert_eq!(acl.authenticate("billing-secret") , Some("billing"));
+        assert_eq!(acl.authenticate("ops-secret") , Some("ops"));
+        assert_eq!(acl.authenticate("guess") , None);
+        assert!(acl.principals.iter().all(|principal| principal.token_sha256.len() == 64));
+    }
+
+    #[test]
+
+    fn test_rules_grant_permissions_by_prefix(){
+        let acl = table();
+        assert!(acl.allows("billing" , Permission::Acquire , "billing/invoices"));
+        assert!(acl.allows("billing" , Permission::Status , "billing/invoices"));
+        assert!(!acl.allows("billing" , Permission::Acquire , "ops/deploy"));
+        assert!(!acl.allows("billing" , Permission::Admin , ""));
+
+        assert!(acl.allows("ops" , Permission::Status , "billing/invoices"));
+        assert!(!acl.allows("ops" , Permission::Release , "billing/invoices"));
+        assert!(!acl.allows("ops" , Permission::Release , "ops/deploy"));
+        assert!(acl.allows("ops" , Permission::Admin , ""));
+
+        assert!(!acl.allows("nobody" , Permission::Status , "billing/invoices"));
+        assert!(acl.allows(ROOT_PRINCIPAL , Permission::Admin , ""));
+    }
+
+    #[test]
+
+    fn test_admin_needs_an_unrestricted_rule(){
+        let mut acl = table();
+        acl.rules.push(AclRule { principal: "billing".to_string(), prefix: "billing/".to_string(), permissions: vec![Permission::Admin] });
+        assert!(!acl.allows("billing" , Permission::Admin , ""));
+        assert!(!acl.allows("billing" , Permission::Admin , "billing/"));
+    }
+
+    #[test]
+
+    fn test_client_ids_are_bound_to_principals(){
+        assert!(owns_client_id("billing" , "billing"));
+        assert!(owns_client_id("billing" , "billing/worker-1"));
+        assert!(!owns_client_id("billing" , "billing-worker"));
+        assert!(!owns_client_id("billing" , "ops/worker-1"));
+        assert!(owns_client_id(ROOT_PRINCIPAL , "ops/worker-1"));
+    }
+
+    #[test]
+
+    fn test_invalid_tables_are_rejected(){
+        let mut acl = table();
+        acl.rules.push(AclRule { principal: "ghost".to_string(), prefix: String::new(), permissions: vec![Permission::Status] });
+        assert!(acl.validate().is_err());
+
+        let request = AclRequest { principals: vec![PrincipalSpec { name: ROOT_PRINCIPAL.to_string(), token: Some("x".to_string()), token_sha256: None }], rules: vec![] };
+        assert!(request.into_table().is_err());
+
+        let request = AclRequest { principals: vec![PrincipalSpec { name: "billing".to_string(), token: None, token_sha256: None }], rules: vec![] };
+        assert!(request.into_table().is_err());
+
+        let request = AclRequest { principals: vec![PrincipalSpec { name: "billing".to_string(), token: None, token_sha256: Some("abc".to_string()) }], rules: vec![] };
+        assert!(request.into_table().is_err());
+    }
+
+}
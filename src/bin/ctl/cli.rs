@@ -0,0 +1,329 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+
+use crate::output::OutputFormat;
+
+/// Global flags can also be set through the environment variable next to
+/// them, so scripts can export them once.
+#[derive(Parser , Debug)]
+#[command(name = "distlockctl" , version , about = "Command line client for distlock clusters")]
+pub struct Cli{
+    /// Node URLs separated by commas; any reachable node is enough
+    #[arg(short , long , env = "DISTLOCK_ENDPOINTS" , value_delimiter = ',' , default_value = "http://127.0.0.1:3000" , global = true)]
+    pub endpoints : Vec<String>,
+
+    /// Client id for lock operations. Release and renew must use the id
+    /// that acquired the lock; a random one is used when unset
+    #[arg(long , env = "DISTLOCK_CLIENT_ID" , global = true)]
+    pub client_id : Option<String>,
+
+    /// Bearer token, for clusters with auth enabled. The client id must
+    /// then be the token's principal or start with `<principal>/`
+    #[arg(long , env = "DISTLOCK_TOKEN" , hide_env_values = true , global = true)]
+    pub token : Option<String>,
+
+    /// PEM file of the CA that signed the nodes' certificates
+    #[arg(long , env = "DISTLOCK_CA_CERT" , global = true)]
+    pub ca_cert : Option<PathBuf>,
+
+    /// PEM client certificate, for nodes that require one
+    #[arg(long , env = "DISTLOCK_CERT" , global = true , requires = "key")]
+    pub cert : Option<PathBuf>,
+
+    /// PEM private key of the client certificate
+    #[arg(long , env = "DISTLOCK_KEY" , global = true , requires = "cert")]
+    pub key : Option<PathBuf>,
+
+    #[arg(short , long , value_enum , env = "DISTLOCK_OUTPUT" , default_value_t = OutputFormat::Table , global = true)]
+    pub output : OutputFormat,
+
+    /// How long a request may be retried against the cluster
+    #[arg(long , env = "DISTLOCK_TIMEOUT_MS" , default_value_t = 10_000 , global = true)]
+    pub timeout_ms : u64,
+
+    #[command(subcommand)]
+    pub command : Command
+}
+
+#[derive(Subcommand , Debug)]
+pub enum Command{
+    /// Acquire a lock once; exits with 3 if the request was queued
+    Acquire{
+        lock_id : String,
+        /// Lease TTL in seconds, 0 for the server default
+        #[arg(long , default_value_t = 30)]
+        ttl : u64,
+        /// Attach key=value to the lease, repeatable
+        #[arg(long = "meta" , value_parser = parse_meta)]
+        meta : Vec<(String , String)>
+    },
+    /// Release a lock held with the given lease
+    Release{
+        lock_id : String,
+        #[arg(long)]
+        lease : String
+    },
+    /// Hand a held lock to another client, which gets a new lease
+    Transfer{
+        lock_id : String,
+        #[arg(long)]
+        lease : String,
+        /// Client id of the new holder
+        #[arg(long)]
+        to : String,
+        /// TTL of the new lease, the server's default when 0
+        #[arg(long , default_value_t = 0)]
+        ttl : u64
+    },
+    /// Extend a held lease
+    Renew{
+        lock_id : String,
+        #[arg(long)]
+        lease : String,
+        #[arg(long , default_value_t = 30)]
+        ttl : u64,
+        /// Replace the lease's metadata with these key=value pairs
+        #[arg(long = "meta" , value_parser = parse_meta)]
+        meta : Vec<(String , String)>
+    },
+    /// Show who holds a lock and how many clients wait for it
+    Status{
+        lock_id : String
+    },
+    /// List every lock known to the node
+    List,
+    /// Print the status of a lock every time it changes, until interrupted
+    Watch{
+        lock_id : String,
+        #[arg(long , default_value_t = 500)]
+        interval_ms : u64
+    },
+    /// Show the cluster members and the leader
+    Members,
+    /// Add a running node to the cluster as a voter
+    AddNode{
+        id : u64,
+        /// Raft address of the node, host:port
+        addr : String,
+        /// Client API address of the node, host:port
+        #[arg(long)]
+        client_addr : Option<String>
+    },
+    /// Remove a node from the cluster
+    RemoveNode{
+        id : u64
+    },
+    /// Hand leadership to another node, the most caught-up one by default
+    TransferLeader{
+        #[arg(long)]
+        to : Option<u64>,
+        #[arg(long)]
+        timeout_ms : Option<u64>
+    },
+    /// Save the lock table to a file or restore it from one
+    #[command(subcommand)]
+    Snapshot(SnapshotCommand),
+    /// Show or replace the principals and ACL rules
+    #[command(subcommand)]
+    Acl(AclCommand),
+    /// Show or change the limits on the lock table
+    #[command(subcommand)]
+    Quotas(QuotasCommand),
+    /// Inspect locks and take them away from clients
+    #[command(subcommand)]
+    Locks(LocksCommand),
+    /// Read and write the key-value store
+    #[command(subcommand)]
+    Kv(KvCommand),
+    /// Create, join and wait on barriers and countdown latches
+    #[command(subcommand)]
+    Barrier(BarrierCommand),
+    /// Run a command while holding a lock. The lease is renewed while the
+    /// command runs and released when it exits; the command is killed if
+    /// the lease is lost
+    Exec{
+        #[arg(long)]
+        lock : String,
+        #[arg(long , default_value_t = 30)]
+        ttl : u64,
+        /// Give up if the lock is not granted in time, waits forever by default
+        #[arg(long)]
+        wait_timeout_ms : Option<u64>,
+        /// Attach key=value to the lease, repeatable
+        #[arg(long = "meta" , value_parser = parse_meta)]
+        meta : Vec<(String , String)>,
+        #[arg(last = true , required = true)]
+        command : Vec<String>
+    }
+}
+
+#[derive(Subcommand , Debug)]
+pub enum KvCommand{
+    /// Print a key with its version; exits with 1 if it does not exist
+    Get{
+        // `key` is taken by the global --key flag.
+        #[arg(id = "kv_key" , value_name = "KEY")]
+        key : String
+    },
+    /// Write a key
+    Put{
+        #[arg(id = "kv_key" , value_name = "KEY")]
+        key : String,
+        value : String,
+        /// Write only if the key is at this version, 0 meaning absent
+        #[arg(long)]
+        expect_version : Option<u64>,
+        /// Delete the key when this lease of --lock ends
+        #[arg(long , requires = "lock")]
+        lease : Option<String>,
+        #[arg(long , requires = "lease")]
+        lock : Option<String>
+    },
+    /// Delete a key
+    Delete{
+        #[arg(id = "kv_key" , value_name = "KEY")]
+        key : String,
+        #[arg(long)]
+        expect_version : Option<u64>
+    }
+}
+
+#[derive(Subcommand , Debug)]
+pub enum BarrierCommand{
+    /// Create a barrier that releases once COUNT participants arrived, or a
+    /// latch that releases after COUNT count-downs
+    Create{
+        name : String,
+        #[arg(long)]
+        count : u32,
+        #[arg(long)]
+        latch : bool
+    },
+    /// Reserve an arrival; it is given back if the lease runs out first
+    Join{
+        name : String,
+        #[arg(long , default_value_t = 30)]
+        ttl : u64
+    },
+    /// Arrive at a barrier or count a latch down
+    Arrive{
+        name : String
+    },
+    /// Print the count, arrivals and participants
+    Status{
+        name : String
+    },
+    /// Block until the barrier is released; exits with 6 on timeout
+    Wait{
+        name : String,
+        /// Waits forever by default
+        #[arg(long)]
+        wait_timeout_ms : Option<u64>
+    },
+    /// Delete a barrier, releasing nobody
+    Delete{
+        name : String
+    }
+}
+
+#[derive(Subcommand , Debug)]
+pub enum SnapshotCommand{
+    /// Write every lock to a file
+    Save{
+        file : PathBuf
+    },
+    /// Replace every lock in the cluster with the ones in a file
+    Restore{
+        file : PathBuf
+    }
+}
+
+#[derive(Subcommand , Debug)]
+pub enum AclCommand{
+    /// Print the ACL table; tokens are shown as SHA-256 hashes
+    Get,
+    /// Replace the ACL table with the one in a JSON file
+    Set{
+        file : PathBuf
+    }
+}
+
+#[derive(Subcommand , Debug)]
+pub enum QuotasCommand{
+    Get,
+    /// Change the given limits and keep the others; 0 removes a limit
+    Set{
+        #[arg(long)]
+        max_locks : Option<usize>,
+        #[arg(long)]
+        max_locks_per_namespace : Option<usize>,
+        #[arg(long)]
+        max_locks_per_client : Option<usize>,
+        #[arg(long)]
+        max_waiters_per_lock : Option<usize>
+    }
+}
+
+#[derive(Subcommand , Debug)]
+pub enum LocksCommand{
+    /// List locks with their holders, a page at a time
+    List{
+        #[arg(long , default_value = "")]
+        prefix : String,
+        /// Start after this lock id, as printed at the end of a page
+        #[arg(long)]
+        after : Option<String>,
+        #[arg(long , default_value_t = 100)]
+        limit : usize
+    },
+    /// Show the holder and every waiter of a lock
+    Show{
+        lock_id : String
+    },
+    /// Show how often a lock is acquired, waited for and held
+    Stats{
+        lock_id : String
+    },
+    /// List the locks clients wait for most
+    Top{
+        #[arg(long , default_value = "")]
+        prefix : String,
+        #[arg(long , default_value_t = 10)]
+        limit : usize
+    },
+    /// Show who held a lock and when, or every lock without an id
+    History{
+        lock_id : Option<String>,
+        /// RFC 3339 time, e.g. 2026-10-19T02:00:00Z
+        #[arg(long)]
+        since : Option<DateTime<Utc>>,
+        #[arg(long)]
+        until : Option<DateTime<Utc>>,
+        #[arg(long , default_value_t = 100)]
+        limit : usize
+    },
+    /// Take a lock from its holder and grant it to the next waiter
+    ForceRelease{
+        lock_id : String,
+        /// Only take the lock from a holder of this lease
+        #[arg(long)]
+        lease_id : Option<String>
+    },
+    /// Remove a client from the wait queue of a lock
+    Evict{
+        lock_id : String,
+        client : String
+    },
+    /// Release every lock of a client and remove it from every queue
+    Revoke{
+        client : String
+    }
+}
+
+fn parse_meta(value : &str) -> Result<(String , String) , String> {
+    value.split_once('=')
+        .map(|(key , value)| (key.to_string() , value.to_string()))
+        .ok_or_else(|| format!("expected key=value, got '{}'" , value))
+}
@@ -0,0 +1,398 @@
+pub mod cli;
+pub mod output;
+
+use std::time::Duration;
+
+use clap::Parser;
+use distlock::{api::models::{AclRequest, AcquireResponse, AdminLocksQuery, BarrierResponse, ContendedLocksQuery, HistoryQuery, LockDetails, LockSnapshot, LockStatsResponse, StatusResponse}, client::{distlock_client::{DistlockClient, LockOptions, TlsOptions}, error::ClientError, retry::RetryPolicy}, lock::{barrier::BarrierKind, quota::LockQuotas}};
+use serde_json::json;
+
+use crate::{cli::{AclCommand, BarrierCommand, Cli, Command, KvCommand, LocksCommand, QuotasCommand, SnapshotCommand}, output::{Output, optional}};
+
+/// Exit codes shared by every command, so scripts can tell failures apart.
+const EXIT_OK : i32 = 0;
+/// The server refused the request.
+const EXIT_ERROR : i32 = 1;
+/// Invalid arguments or input file. Also what clap exits with.
+const EXIT_USAGE : i32 = 2;
+/// The lock is held by someone else: the request was queued or waiting
+/// for it timed out.
+const EXIT_LOCK_BUSY : i32 = 3;
+/// No node could serve the request before the timeout.
+const EXIT_UNAVAILABLE : i32 = 4;
+/// `exec` lost its lease and killed the command.
+const EXIT_LEASE_LOST : i32 = 5;
+/// `barrier wait` timed out before everyone arrived.
+const EXIT_BARRIER_TIMEOUT : i32 = 6;
+
+fn exit_code(error : &ClientError) -> i32 {
+    match error {
+        ClientError::Rejected { .. } => EXIT_ERROR,
+        ClientError::Unavailable { .. } | ClientError::Transport(_) => EXIT_UNAVAILABLE,
+        ClientError::Timeout(_) => EXIT_LOCK_BUSY,
+        ClientError::LeaseLost { .. } => EXIT_LEASE_LOST,
+        ClientError::BarrierTimeout(_) => EXIT_BARRIER_TIMEOUT
+    }
+}
+
+#[tokio::main]
+
+async fn main(){
+    let cli = Cli::parse();
+    let client_id = cli.client_id.clone().unwrap_or_else(|| format!("distlockctl-{}" , uuid::Uuid::new_v4()));
+    let retry = RetryPolicy { deadline: Duration::from_millis(cli.timeout_ms), ..Default::default() };
+    let mut client = DistlockClient::with_endpoints(cli.endpoints.clone() , client_id).with_retry_policy(retry);
+    if let Some(token) = &cli.token {
+        client = client.with_token(token.clone());
+    }
+    if cli.ca_cert.is_some() || cli.cert.is_some() {
+        client = match tls_options(&cli).and_then(|tls| client.with_tls(&tls)) {
+            Ok(client) => client,
+            Err(e) => {
+                eprintln!("error: {}" , e);
+                std::process::exit(EXIT_USAGE);
+            }
+        };
+    }
+
+    let code = match run(&cli , &client).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {}" , e);
+            exit_code(&e)
+        }
+    };
+    std::process::exit(code);
+}
+
+fn tls_options(cli : &Cli) -> Result<TlsOptions , String> {
+    let read = |path : &std::path::PathBuf| std::fs::read(path).map_err(|e| format!("failed to read {} : {}" , path.display() , e));
+    let ca_pem = cli.ca_cert.as_ref().map(read).transpose()?;
+    let identity_pem = match (&cli.cert , &cli.key) {
+        (Some(cert) , Some(key)) => Some([read(cert)? , b"\n".to_vec() , read(key)?].concat()),
+        _ => None
+    };
+    Ok(TlsOptions { ca_pem, identity_pem })
+}
+
+async fn run(cli : &Cli , client : &DistlockClient) -> Result<i32 , ClientError> {
+    let output = match &cli.command {
+        Command::Acquire { lock_id , ttl , meta } => {
+            let response = client.acquire_with_metadata(lock_id , *ttl , meta.iter().cloned().collect()).await?;
+            let (json , row , code) = match &response {
+                AcquireResponse::Granted { lease_id , expires_at , fencing_token } =>
+                    (json!({ "granted" : true , "client_id" : client.client_id() , "lease_id" : lease_id , "expires_at" : expires_at , "fencing_token" : fencing_token }) , vec!["granted".to_string() , client.client_id().to_string() , lease_id.clone() , expires_at.clone() , fencing_token.to_string()] , EXIT_OK),
+                AcquireResponse::Queued { position , estimated_wait } =>
+                    (json!({ "granted" : false , "client_id" : client.client_id() , "position" : position , "estimated_wait" : estimated_wait }) , vec![format!("queued at {}" , position) , client.client_id().to_string() , "-".to_string() , "-".to_string() , "-".to_string()] , EXIT_LOCK_BUSY),
+                AcquireResponse::Error { error_type , message } => return Err(ClientError::Rejected { error_type: error_type.clone(), message: message.clone() })
+            };
+            Output::new(json , vec!["RESULT" , "CLIENT" , "LEASE" , "EXPIRES" , "FENCING TOKEN"] , vec![row]).print(cli.output);
+            return Ok(code)
+        }
+        Command::Release { lock_id , lease } => {
+            client.release(lock_id , lease).await?;
+            Output::new(json!({ "released" : true }) , vec!["RESULT"] , vec![vec!["released".to_string()]])
+        }
+        Command::Transfer { lock_id , lease , to , ttl } => {
+            let (lease_id , fencing_token) = client.transfer(lock_id , lease , to , *ttl).await?;
+            Output::new(json!({ "client_id" : to , "lease_id" : lease_id , "fencing_token" : fencing_token }) , vec!["CLIENT" , "LEASE" , "FENCING TOKEN"] , vec![vec![to.clone() , lease_id , fencing_token.to_string()]])
+        }
+        Command::Renew { lock_id , lease , ttl , meta } => {
+            let new_expiry = if meta.is_empty() {
+                client.renew(lock_id , lease , *ttl).await?
+            } else {
+                client.renew_with_metadata(lock_id , lease , *ttl , meta.iter().cloned().collect()).await?
+            };
+            Output::new(json!({ "new_expiry" : new_expiry }) , vec!["EXPIRES"] , vec![vec![new_expiry]])
+        }
+        Command::Status { lock_id } => status_output(lock_id , &client.status(lock_id).await?),
+        Command::List => {
+            let locks = client.list().await?;
+            let rows = locks.iter().map(|lock| vec![lock.lock_id.clone() , optional(&lock.holder) , optional(&lock.lease_id) , optional(&lock.expires_at) , lock.queue_length.to_string()]).collect();
+            Output::new(json!(locks) , vec!["LOCK" , "HOLDER" , "LEASE" , "EXPIRES" , "QUEUE"] , rows)
+        }
+        Command::Watch { lock_id , interval_ms } => return watch(cli , client , lock_id , Duration::from_millis(*interval_ms)).await,
+        Command::Members => {
+            let (leader_id , members) = client.members().await?;
+            let rows = members.iter().map(|member| vec![
+                member.id.to_string(),
+                if member.leader { "leader" } else if member.voter { "voter" } else { "learner" }.to_string(),
+                optional(&member.addr),
+                optional(&member.client_addr)
+            ]).collect();
+            Output::new(json!({ "leader_id" : leader_id , "members" : members }) , vec!["ID" , "ROLE" , "ADDR" , "CLIENT ADDR"] , rows)
+        }
+        Command::AddNode { id , addr , client_addr } => voters_output(client.add_node(*id , addr , client_addr.as_deref()).await?),
+        Command::RemoveNode { id } => voters_output(client.remove_node(*id).await?),
+        Command::TransferLeader { to , timeout_ms } => {
+            let (from , to) = client.transfer_leader(*to , *timeout_ms).await?;
+            Output::new(json!({ "from" : from , "to" : to }) , vec!["FROM" , "TO"] , vec![vec![from.to_string() , to.to_string()]])
+        }
+        Command::Snapshot(SnapshotCommand::Save { file }) => {
+            let snapshot = client.save_snapshot().await?;
+            let data = serde_json::to_vec_pretty(&snapshot).unwrap_or_default();
+            if let Err(e) = std::fs::write(file , data) {
+                eprintln!("error: failed to write {} : {}" , file.display() , e);
+                return Ok(EXIT_USAGE)
+            }
+            Output::new(json!({ "file" : file , "locks" : snapshot.locks.len() , "taken_at" : snapshot.taken_at }) , vec!["FILE" , "LOCKS"] , vec![vec![file.display().to_string() , snapshot.locks.len().to_string()]])
+        }
+        Command::Snapshot(SnapshotCommand::Restore { file }) => {
+            let snapshot = match std::fs::read(file).map_err(|e| e.to_string()).and_then(|data| serde_json::from_slice::<LockSnapshot>(&data).map_err(|e| e.to_string())) {
+                Ok(snapshot) => snapshot,
+                Err(e) => {
+                    eprintln!("error: invalid snapshot {} : {}" , file.display() , e);
+                    return Ok(EXIT_USAGE)
+                }
+            };
+            let restored = client.restore_snapshot(&snapshot).await?;
+            Output::new(json!({ "restored" : restored }) , vec!["RESTORED"] , vec![vec![restored.to_string()]])
+        }
+        Command::Acl(AclCommand::Get) => {
+            let acl = client.acl().await?;
+            let rows = acl.rules.iter().map(|rule| {
+                let permissions = rule.permissions.iter().map(|permission| format!("{:?}" , permission).to_lowercase()).collect::<Vec<_>>().join(",");
+                vec![rule.principal.clone() , if rule.prefix.is_empty() { "*".to_string() } else { rule.prefix.clone() } , permissions]
+            }).collect();
+            Output::new(json!(acl) , vec!["PRINCIPAL" , "PREFIX" , "PERMISSIONS"] , rows)
+        }
+        Command::Acl(AclCommand::Set { file }) => {
+            let acl = match std::fs::read(file).map_err(|e| e.to_string()).and_then(|data| serde_json::from_slice::<AclRequest>(&data).map_err(|e| e.to_string())) {
+                Ok(acl) => acl,
+                Err(e) => {
+                    eprintln!("error: invalid ACL file {} : {}" , file.display() , e);
+                    return Ok(EXIT_USAGE)
+                }
+            };
+            let (principals , rules) = client.set_acl(&acl).await?;
+            Output::new(json!({ "principals" : principals , "rules" : rules }) , vec!["PRINCIPALS" , "RULES"] , vec![vec![principals.to_string() , rules.to_string()]])
+        }
+        Command::Quotas(QuotasCommand::Get) => quotas_output(client.quotas().await?),
+        Command::Quotas(QuotasCommand::Set { max_locks , max_locks_per_namespace , max_locks_per_client , max_waiters_per_lock }) => {
+            let current = client.quotas().await?;
+            let quotas = LockQuotas {
+                max_locks: max_locks.unwrap_or(current.max_locks),
+                max_locks_per_namespace: max_locks_per_namespace.unwrap_or(current.max_locks_per_namespace),
+                max_locks_per_client: max_locks_per_client.unwrap_or(current.max_locks_per_client),
+                max_waiters_per_lock: max_waiters_per_lock.unwrap_or(current.max_waiters_per_lock)
+            };
+            quotas_output(client.set_quotas(&quotas).await?)
+        }
+        Command::Locks(LocksCommand::List { prefix , after , limit }) => {
+            let query = AdminLocksQuery { prefix: prefix.clone(), after: after.clone(), limit: Some(*limit) };
+            let page = client.admin_locks(&query).await?;
+            // On stderr, so that JSON output stays parseable.
+            if let Some(next_after) = &page.next_after {
+                eprintln!("more locks follow, continue with --after {}" , next_after);
+            }
+            locks_output(&page.locks , json!(page))
+        }
+        Command::Locks(LocksCommand::Show { lock_id }) => {
+            let lock = client.lock_details(lock_id).await?;
+            let mut rows = vec![vec!["holder".to_string() , lock.holder.as_ref().map_or("-".to_string() , |holder| holder.client_id.0.clone()) , lock.holder.as_ref().map_or("-".to_string() , |holder| holder.expires_at.to_rfc3339())]];
+            rows.extend(lock.waiters.iter().enumerate().map(|(position , waiter)| vec![format!("waiter {}" , position) , waiter.client_id.0.clone() , waiter.requested_at.to_rfc3339()]));
+            Output::new(json!(lock) , vec!["ROLE" , "CLIENT" , "SINCE/EXPIRES"] , rows)
+        }
+        Command::Locks(LocksCommand::Stats { lock_id }) => {
+            let stats = client.lock_stats(lock_id).await?;
+            let mut rows = vec![
+                vec!["acquires".to_string() , stats.acquires.to_string()],
+                vec!["queued".to_string() , format!("{} ({:.1}%)" , stats.queued , stats.contention_rate * 100.0)],
+                vec!["mean hold".to_string() , format!("{:.3}s" , stats.mean_hold_seconds)],
+                vec!["p99 hold".to_string() , format!("{:.3}s" , stats.p99_hold_seconds)],
+                vec!["mean wait".to_string() , format!("{:.3}s" , stats.mean_wait_seconds)],
+                vec!["max queue depth".to_string() , stats.max_queue_depth.to_string()],
+                vec!["released / expired / forced / transferred".to_string() , format!("{} / {} / {} / {}" , stats.releases , stats.expirations , stats.force_releases , stats.transfers)]
+            ];
+            rows.extend(stats.top_holders.iter().map(|holder| vec![format!("holder {}" , holder.client_id) , holder.acquisitions.to_string()]));
+            Output::new(json!(stats) , vec!["STAT" , "VALUE"] , rows)
+        }
+        Command::Locks(LocksCommand::Top { prefix , limit }) => {
+            let locks = client.contended_locks(&ContendedLocksQuery { prefix: prefix.clone(), limit: Some(*limit) }).await?;
+            contended_output(&locks)
+        }
+        Command::Locks(LocksCommand::History { lock_id , since , until , limit }) => {
+            let query = HistoryQuery { lock_id: lock_id.clone(), since: *since, until: *until, limit: Some(*limit) };
+            let history = client.history(&query).await?;
+            if history.truncated && let Some(last) = history.events.last() {
+                eprintln!("more events follow, continue with --since {}" , last.at.to_rfc3339());
+            }
+            let rows = history.events.iter().map(|event| vec![
+                event.at.to_rfc3339(),
+                event.index.to_string(),
+                event.lock_id.0.clone(),
+                event.kind.as_str().to_string(),
+                event.client_id.0.clone(),
+                event.lease_id.as_ref().map_or("-".to_string() , |lease_id| lease_id.0.clone()),
+                optional(&event.actor)
+            ]).collect();
+            Output::new(json!(history) , vec!["TIME" , "INDEX" , "LOCK" , "EVENT" , "CLIENT" , "LEASE" , "ACTOR"] , rows)
+        }
+        Command::Locks(LocksCommand::ForceRelease { lock_id , lease_id }) => {
+            let (previous , promoted) = client.force_release(lock_id , lease_id.as_deref()).await?;
+            Output::new(json!({ "previous_holder" : previous , "promoted" : promoted }) , vec!["PREVIOUS HOLDER" , "PROMOTED"] , vec![vec![previous , optional(&promoted)]])
+        }
+        Command::Locks(LocksCommand::Evict { lock_id , client: client_id }) => {
+            let removed = client.evict_waiter(lock_id , client_id).await?;
+            Output::new(json!({ "removed" : removed }) , vec!["REMOVED"] , vec![vec![removed.to_string()]])
+        }
+        Command::Locks(LocksCommand::Revoke { client: client_id }) => {
+            let (released , evicted) = client.revoke_client(client_id).await?;
+            Output::new(json!({ "released" : released , "evicted" : evicted }) , vec!["RELEASED" , "EVICTED"] , vec![vec![released.to_string() , evicted.to_string()]])
+        }
+        Command::Kv(KvCommand::Get { key }) => match client.kv_get(key).await? {
+            Some(entry) => Output::new(json!(entry) , vec!["KEY" , "VERSION" , "VALUE"] , vec![vec![entry.key.clone() , entry.version.to_string() , entry.value.clone()]]),
+            None => return Err(ClientError::Rejected { error_type: "NotFound".to_string(), message: format!("No key '{}'" , key) })
+        },
+        Command::Kv(KvCommand::Put { key , value , expect_version , lease , lock }) => {
+            let lease = lock.as_deref().zip(lease.as_deref());
+            let version = client.kv_put(key , value , *expect_version , lease).await?;
+            Output::new(json!({ "key" : key , "version" : version }) , vec!["KEY" , "VERSION"] , vec![vec![key.clone() , version.to_string()]])
+        }
+        Command::Kv(KvCommand::Delete { key , expect_version }) => {
+            let version = client.kv_delete(key , *expect_version).await?;
+            Output::new(json!({ "key" : key , "deleted_version" : version }) , vec!["KEY" , "DELETED VERSION"] , vec![vec![key.clone() , version.to_string()]])
+        }
+        Command::Barrier(BarrierCommand::Create { name , count , latch }) => {
+            let kind = if *latch { BarrierKind::Latch } else { BarrierKind::Barrier };
+            remaining_output(name , client.create_barrier(name , kind , *count).await?)
+        }
+        Command::Barrier(BarrierCommand::Join { name , ttl }) => remaining_output(name , client.join_barrier(name , *ttl).await?),
+        Command::Barrier(BarrierCommand::Arrive { name }) => remaining_output(name , client.arrive(name).await?),
+        Command::Barrier(BarrierCommand::Status { name }) => barrier_output(&client.barrier(name).await?),
+        Command::Barrier(BarrierCommand::Wait { name , wait_timeout_ms }) => barrier_output(&client.wait_barrier(name , wait_timeout_ms.map(Duration::from_millis)).await?),
+        Command::Barrier(BarrierCommand::Delete { name }) => remaining_output(name , client.delete_barrier(name).await?),
+        Command::Exec { lock , ttl , wait_timeout_ms , meta , command } => {
+            let opts = LockOptions { ttl_seconds: *ttl, wait_timeout: wait_timeout_ms.map(Duration::from_millis), metadata: meta.iter().cloned().collect(), ..Default::default() };
+            return exec(client , lock , opts , command).await
+        }
+    };
+    output.print(cli.output);
+    Ok(EXIT_OK)
+}
+
+fn status_output(lock_id : &str , status : &StatusResponse) -> Output {
+    let row = match status {
+        StatusResponse::InUse { client_id , expires_at , lease_id , queue_length , metadata , fencing_token , .. } => {
+            let metadata = metadata.iter().map(|(key , value)| format!("{}={}" , key , value)).collect::<Vec<_>>().join(",");
+            vec![lock_id.to_string() , "held".to_string() , client_id.clone() , lease_id.clone() , expires_at.clone() , fencing_token.to_string() , queue_length.to_string() , if metadata.is_empty() { "-".to_string() } else { metadata }]
+        }
+        StatusResponse::Free => vec![lock_id.to_string() , "free".to_string() , "-".to_string() , "-".to_string() , "-".to_string() , "-".to_string() , "0".to_string() , "-".to_string()],
+        StatusResponse::NotFound => vec![lock_id.to_string() , "not found".to_string() , "-".to_string() , "-".to_string() , "-".to_string() , "-".to_string() , "0".to_string() , "-".to_string()]
+    };
+    Output::new(json!({ "lock_id" : lock_id , "status" : status }) , vec!["LOCK" , "STATE" , "HOLDER" , "LEASE" , "EXPIRES" , "FENCING TOKEN" , "QUEUE" , "METADATA"] , vec![row])
+}
+
+fn remaining_output(name : &str , remaining : u32) -> Output {
+    Output::new(json!({ "name" : name , "remaining" : remaining }) , vec!["BARRIER" , "REMAINING"] , vec![vec![name.to_string() , remaining.to_string()]])
+}
+
+fn barrier_output(barrier : &BarrierResponse) -> Output {
+    let list = |names : Vec<&String>| if names.is_empty() { "-".to_string() } else { names.into_iter().cloned().collect::<Vec<_>>().join(",") };
+    let row = vec![
+        barrier.name.clone(),
+        barrier.kind.as_str().to_string(),
+        format!("{}/{}" , barrier.count - barrier.remaining , barrier.count),
+        if barrier.released { "released".to_string() } else if barrier.degraded { "degraded".to_string() } else { "waiting".to_string() },
+        list(barrier.participants.iter().map(|participant| &participant.client_id).collect()),
+        list(barrier.expired.iter().collect())
+    ];
+    Output::new(json!(barrier) , vec!["BARRIER" , "KIND" , "ARRIVED" , "STATE" , "JOINED" , "EXPIRED"] , vec![row])
+}
+
+/// 0 is shown as `-`, no limit.
+fn quotas_output(quotas : LockQuotas) -> Output {
+    let limit = |value : usize| if value == 0 { "-".to_string() } else { value.to_string() };
+    let row = vec![limit(quotas.max_locks) , limit(quotas.max_locks_per_namespace) , limit(quotas.max_locks_per_client) , limit(quotas.max_waiters_per_lock)];
+    Output::new(json!(quotas) , vec!["LOCKS" , "PER_NAMESPACE" , "PER_CLIENT" , "WAITERS_PER_LOCK"] , vec![row])
+}
+
+fn locks_output(locks : &[LockDetails] , json : serde_json::Value) -> Output {
+    let rows = locks.iter().map(|lock| {
+        let holder = lock.holder.as_ref();
+        vec![
+            lock.lock_id.clone(),
+            holder.map_or("-".to_string() , |holder| holder.client_id.0.clone()),
+            holder.map_or("-".to_string() , |holder| holder.expires_at.to_rfc3339()) + if lock.expired { " (expired)" } else { "" },
+            holder.map_or("-".to_string() , |holder| holder.renewal_count.to_string()),
+            lock.waiters.len().to_string()
+        ]
+    }).collect();
+    Output::new(json , vec!["LOCK" , "HOLDER" , "EXPIRES" , "RENEWALS" , "WAITERS"] , rows)
+}
+
+fn contended_output(locks : &[LockStatsResponse]) -> Output {
+    let rows = locks.iter().map(|lock| vec![
+        lock.lock_id.clone(),
+        lock.acquires.to_string(),
+        lock.queued.to_string(),
+        format!("{:.1}%" , lock.contention_rate * 100.0),
+        format!("{:.3}s" , lock.mean_wait_seconds),
+        format!("{:.3}s" , lock.p99_hold_seconds),
+        lock.max_queue_depth.to_string()
+    ]).collect();
+    Output::new(json!({ "locks" : locks }) , vec!["LOCK" , "ACQUIRES" , "QUEUED" , "CONTENTION" , "MEAN WAIT" , "P99 HOLD" , "MAX QUEUE"] , rows)
+}
+
+fn voters_output(voters : Vec<u64>) -> Output {
+    let listed = voters.iter().map(u64::to_string).collect::<Vec<_>>().join(",");
+    Output::new(json!({ "voters" : voters }) , vec!["VOTERS"] , vec![vec![listed]])
+}
+
+/// Polls the lock and prints its status whenever it changes.
+async fn watch(cli : &Cli , client : &DistlockClient , lock_id : &str , interval : Duration) -> Result<i32 , ClientError> {
+    let mut last : Option<String> = None;
+    loop {
+        let status = client.status(lock_id).await?;
+        let current = serde_json::to_string(&status).unwrap_or_default();
+        if last.as_ref() != Some(&current) {
+            status_output(lock_id , &status).print(cli.output);
+            last = Some(current);
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = tokio::signal::ctrl_c() => return Ok(EXIT_OK)
+        }
+    }
+}
+
+/// Holds `lock_id` while `command` runs and exits with the command's exit
+/// code. The lock is released however the command ends.
+async fn exec(client : &DistlockClient , lock_id : &str , opts : LockOptions , command : &[String]) -> Result<i32 , ClientError> {
+    let guard = client.lock(lock_id , opts).await?;
+
+    let mut child = match tokio::process::Command::new(&command[0]).args(&command[1..]).kill_on_drop(true).spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("error: failed to run {} : {}" , command[0] , e);
+            let _ = guard.unlock().await;
+            return Ok(EXIT_ERROR)
+        }
+    };
+
+    let code = tokio::select! {
+        status = child.wait() => match status {
+            Ok(status) => status.code().unwrap_or(EXIT_ERROR),
+            Err(e) => {
+                eprintln!("error: failed to wait for {} : {}" , command[0] , e);
+                EXIT_ERROR
+            }
+        },
+        lost = guard.lost() => {
+            eprintln!("error: {} , stopping {}" , lost , command[0]);
+            let _ = child.kill().await;
+            EXIT_LEASE_LOST
+        }
+        _ = tokio::signal::ctrl_c() => {
+            let _ = child.kill().await;
+            130
+        }
+    };
+
+    let lost = guard.is_lost();
+    if let Err(e) = guard.unlock().await
+        && !lost {
+        eprintln!("warning: failed to release {} : {}" , lock_id , e);
+    }
+    Ok(code)
+}
@@ -0,0 +1,52 @@
+use clap::ValueEnum;
+use serde_json::Value;
+
+
+#[derive(Debug , Clone , Copy , PartialEq , ValueEnum)]
+pub enum OutputFormat{
+    Table,
+    Json
+}
+
+/// Result of a command, printable as JSON or as a table.
+pub struct Output{
+    pub json : Value,
+    pub headers : Vec<&'static str>,
+    pub rows : Vec<Vec<String>>
+}
+
+impl Output{
+    pub fn new(json : Value , headers : Vec<&'static str> , rows : Vec<Vec<String>>) -> Self {
+        Self { json, headers, rows }
+    }
+
+    pub fn print(&self , format : OutputFormat) {
+        match format {
+            OutputFormat::Json => println!("{}" , serde_json::to_string_pretty(&self.json).unwrap_or_default()),
+            OutputFormat::Table => print!("{}" , table(&self.headers , &self.rows))
+        }
+    }
+}
+
+/// Left aligned columns separated by two spaces.
+pub fn table(headers : &[&str] , rows : &[Vec<String>]) -> String {
+    let mut widths : Vec<usize> = headers.iter().map(|header| header.len()).collect();
+    for row in rows {
+        for (width , cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let line = |cells : Vec<&str>| -> String {
+        let padded : Vec<String> = cells.iter().zip(&widths).map(|(cell , width)| format!("{:<width$}" , cell , width = width)).collect();
+        format!("{}\n" , padded.join("  ").trim_end())
+    };
+    let mut out = line(headers.to_vec());
+    for row in rows {
+        out.push_str(&line(row.iter().map(String::as_str).collect()));
+    }
+    out
+}
+
+pub fn optional(value : &Option<String>) -> String {
+    value.clone().unwrap_or_else(|| "-".to_string())
+}
@@ -0,0 +1,145 @@
+use std::{convert::Infallible, sync::Arc};
+
+use axum::{Json, async_trait, extract::{FromRequestParts, Request, State}, http::{StatusCode, header, request::Parts}, middleware::Next, response::{IntoResponse, Response}};
+use distlock::{api::models::ApiError, auth::acl::{AclTable, Permission, ROOT_PRINCIPAL, hash_token, is_client_id_of, owns_client_id}, tls::https::ClientCertificate};
+use tokio::sync::RwLock;
+
+use crate::AppState;
+
+/// Checks bearer tokens against the root token and the replicated ACL.
+#[derive(Clone)]
+pub struct Authenticator{
+    root_token_sha256 : String,
+    acl : Arc<RwLock<AclTable>>
+}
+
+impl Authenticator{
+    pub fn new(root_token : &str , acl : Arc<RwLock<AclTable>>) -> Self {
+        Self { root_token_sha256: hash_token(root_token), acl }
+    }
+
+    pub async fn authenticate(&self , token : &str) -> Option<String> {
+        if hash_token(token) == self.root_token_sha256 {
+            return Some(ROOT_PRINCIPAL.to_string())
+        }
+        self.acl.read().await.authenticate(token).map(str::to_string)
+    }
+
+    pub fn acl(&self) -> &Arc<RwLock<AclTable>> {
+        &self.acl
+    }
+}
+
+/// Principal the request was authenticated as, set by `require_token`.
+#[derive(Clone , Debug)]
+struct Principal(String);
+
+/// Rejects requests without a valid bearer token, except the health check.
+pub async fn require_token(State(state) : State<AppState> , mut request : Request , next : Next) -> Response {
+    let Some(auth) = &state.auth else { return next.run(request).await };
+    if request.uri().path() == "/" {
+        return next.run(request).await
+    }
+
+    let token = request.headers().get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim);
+    let principal = match token {
+        Some(token) => auth.authenticate(token).await,
+        None => None
+    };
+    match principal {
+        Some(principal) => {
+            request.extensions_mut().insert(Principal(principal));
+            next.run(request).await
+        }
+        None => (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE , "Bearer")],
+            Json(ApiError::unauthenticated("A valid bearer token is required"))
+        ).into_response()
+    }
+}
+
+/// The caller of a handler: its principal, `None` when auth is disabled,
+/// and the certificate its client ids are bound to, if any.
+#[derive(Clone , Debug)]
+pub struct Caller{
+    principal : Option<String>,
+    certificate : Option<ClientCertificate>
+}
+
+#[async_trait]
+impl<S : Send + Sync> FromRequestParts<S> for Caller{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts : &mut Parts , _state : &S) -> Result<Self , Self::Rejection> {
+        Ok(Caller {
+            principal: parts.extensions.get::<Principal>().map(|principal| principal.0.clone()),
+            certificate: parts.extensions.get::<ClientCertificate>().cloned()
+        })
+    }
+}
+
+impl Caller{
+    pub fn principal(&self) -> Option<&str> {
+        self.principal.as_deref()
+    }
+
+    /// Who to record as having performed an operation: the principal, else
+    /// the certificate subject, else `anonymous`.
+    pub fn actor(&self) -> String {
+        match (&self.principal , &self.certificate) {
+            (Some(principal) , _) => principal.clone(),
+            (None , Some(ClientCertificate(Some(subject)))) => subject.clone(),
+            _ => "anonymous".to_string()
+        }
+    }
+
+    /// An empty client id stands for the certificate's subject.
+    pub fn bind_client_id(&self , client_id : &mut String) {
+        if client_id.is_empty()
+            && let Some(ClientCertificate(Some(subject))) = &self.certificate {
+            *client_id = subject.clone();
+        }
+    }
+
+    pub async fn allowed(&self , state : &AppState , permission : Permission , lock_id : &str) -> bool {
+        match (&self.principal , &state.auth) {
+            (Some(principal) , Some(auth)) => auth.acl().read().await.allows(principal , permission , lock_id),
+            _ => true
+        }
+    }
+
+    /// `None` when the caller may do this, otherwise the 403 to send.
+    pub async fn deny(&self , state : &AppState , permission : Permission , lock_id : &str) -> Option<Response> {
+        if self.allowed(state , permission , lock_id).await {
+            return None
+        }
+        let message = match permission {
+            Permission::Admin => format!("'{}' may not administer the cluster" , self.principal().unwrap_or_default()),
+            _ => format!("'{}' lacks the {:?} permission on '{}'" , self.principal().unwrap_or_default() , permission , lock_id)
+        };
+        Some((StatusCode::FORBIDDEN , Json(ApiError::permission_denied(&message))).into_response())
+    }
+
+    /// Like `deny`, and also refuses client ids that belong to another
+    /// principal or certificate.
+    pub async fn deny_client(&self , state : &AppState , permission : Permission , lock_id : &str , client_id : &str) -> Option<Response> {
+        if let Some(ClientCertificate(subject)) = &self.certificate
+            && !subject.as_deref().is_some_and(|subject| is_client_id_of(subject , client_id)) {
+            let message = match subject {
+                Some(subject) => format!("client_id must be '{}' or start with '{}/'" , subject , subject),
+                None => "The client certificate has no subject to bind client ids to".to_string()
+            };
+            return Some((StatusCode::FORBIDDEN , Json(ApiError::bad_request("ClientIdNotBound" , &message))).into_response())
+        }
+        if let Some(principal) = self.principal()
+            && !owns_client_id(principal , client_id) {
+            let message = format!("client_id must be '{}' or start with '{}/'" , principal , principal);
+            return Some((StatusCode::FORBIDDEN , Json(ApiError::bad_request("ClientIdNotBound" , &message))).into_response())
+        }
+        self.deny(state , permission , lock_id).await
+    }
+}
@@ -0,0 +1,151 @@
+use std::time::Duration;
+
+use axum::{Json, Router, extract::{Path, Query, State}, http::StatusCode, response::{IntoResponse, Response}, routing::{get, post}};
+use chrono::Utc;
+use distlock::{api::models::{ApiError, BarrierArriveRequest, BarrierCreateRequest, BarrierJoinRequest, BarrierParticipant, BarrierQuery, BarrierResponse, BarrierUpdateResponse}, auth::acl::Permission, lock::{barrier::{Barrier, INVALID_BARRIER}, quota::QUOTA_EXCEEDED}, raft::raft_commands::CommandResponse};
+use tokio::time::Instant;
+
+use crate::{AppState, auth::Caller, route_handlers::{invalid_ttl, is_retryable, quota_exceeded, resolve_ttl, unavailable}};
+
+/// How often a waiting read checks whether the barrier was released.
+const WAIT_POLL_INTERVAL : Duration = Duration::from_millis(100);
+/// Longest a single read may wait. Clients wait longer by asking again.
+const MAX_WAIT_MS : u64 = 30_000;
+
+/// Barriers and countdown latches. Names share the ACL namespace of locks:
+/// reading one needs the status permission, everything else the acquire
+/// one.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/barriers" , post(create_handler))
+        .route("/barriers/:name" , get(get_handler).delete(delete_handler))
+        .route("/barriers/:name/join" , post(join_handler))
+        .route("/barriers/:name/arrive" , post(arrive_handler))
+}
+
+async fn create_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(payload): Json<BarrierCreateRequest>,
+) -> Response {
+    if let Some(denied) = caller.deny(&state , Permission::Acquire , &payload.name).await {
+        return denied
+    }
+    match state.raft_client.propose_barrier_create(payload.name , payload.kind , payload.count).await {
+        Ok(response) => update_response(&state , response),
+        Err(message) => unavailable(&state , "Unavailable" , &message)
+    }
+}
+
+async fn join_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(name): Path<String>,
+    Json(mut payload): Json<BarrierJoinRequest>,
+) -> Response {
+    caller.bind_client_id(&mut payload.client_id);
+    if let Some(denied) = caller.deny_client(&state , Permission::Acquire , &name , &payload.client_id).await {
+        return denied
+    }
+    let ttl_seconds = match resolve_ttl(&state.ttl , payload.time_to_live) {
+        Ok(ttl_seconds) => ttl_seconds,
+        Err(message) => return invalid_ttl(&message)
+    };
+    match state.raft_client.propose_barrier_join(name , payload.client_id , ttl_seconds , payload.seq).await {
+        Ok(response) => update_response(&state , response),
+        Err(message) => unavailable(&state , "Unavailable" , &message)
+    }
+}
+
+async fn arrive_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(name): Path<String>,
+    Json(mut payload): Json<BarrierArriveRequest>,
+) -> Response {
+    caller.bind_client_id(&mut payload.client_id);
+    if let Some(denied) = caller.deny_client(&state , Permission::Acquire , &name , &payload.client_id).await {
+        return denied
+    }
+    match state.raft_client.propose_barrier_arrive(name , payload.client_id , payload.seq).await {
+        Ok(response) => update_response(&state , response),
+        Err(message) => unavailable(&state , "Unavailable" , &message)
+    }
+}
+
+/// Reads a barrier from this node. With `wait_ms`, answers as soon as it is
+/// released or once the wait is over, whichever comes first.
+async fn get_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(name): Path<String>,
+    Query(query): Query<BarrierQuery>,
+) -> Response {
+    if let Some(denied) = caller.deny(&state , Permission::Status , &name).await {
+        return denied
+    }
+    let deadline = Instant::now() + Duration::from_millis(query.wait_ms.unwrap_or(0).min(MAX_WAIT_MS));
+    loop {
+        let Some(barrier) = state.state_machine.barrier(&name).await else {
+            return (StatusCode::NOT_FOUND , Json(ApiError::not_found(&format!("No barrier '{}'" , name)))).into_response()
+        };
+        let response = barrier_response(&name , &barrier);
+        if response.released || Instant::now() >= deadline {
+            return Json(response).into_response()
+        }
+        tokio::time::sleep(WAIT_POLL_INTERVAL).await;
+    }
+}
+
+async fn delete_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(name): Path<String>,
+) -> Response {
+    if let Some(denied) = caller.deny(&state , Permission::Acquire , &name).await {
+        return denied
+    }
+    match state.raft_client.propose_barrier_delete(name).await {
+        Ok(response) => update_response(&state , response),
+        Err(message) => unavailable(&state , "Unavailable" , &message)
+    }
+}
+
+/// Leases that ran out show as expired even before the log notices, so
+/// waiters see the barrier degrade on time on every node.
+fn barrier_response(name : &str , barrier : &Barrier) -> BarrierResponse {
+    let now = Utc::now();
+    let (participants , lapsed) : (Vec<_> , Vec<_>) = barrier.participants.iter().partition(|(_ , expires_at)| **expires_at >= now);
+    BarrierResponse {
+        name: name.to_string(),
+        kind: barrier.kind,
+        count: barrier.count,
+        remaining: barrier.remaining(),
+        released: barrier.released_at.is_some(),
+        degraded: barrier.degraded_at(now),
+        participants: participants.into_iter()
+            .map(|(client_id , expires_at)| BarrierParticipant { client_id: client_id.clone(), expires_at: expires_at.to_rfc3339() })
+            .collect(),
+        arrived: barrier.arrived.iter().cloned().collect(),
+        expired: barrier.expired.iter().chain(lapsed.into_iter().map(|(client_id , _)| client_id)).cloned().collect(),
+        created_at: barrier.created_at.to_rfc3339()
+    }
+}
+
+fn update_response(state : &AppState , response : CommandResponse) -> Response {
+    match response {
+        CommandResponse::BarrierUpdated { remaining } | CommandResponse::BarrierDeleted { remaining } => Json(BarrierUpdateResponse { remaining }).into_response(),
+        CommandResponse::Error { error_type, message } if is_retryable(&error_type) => unavailable(state , &error_type , &message),
+        CommandResponse::Error { error_type, message } if error_type == QUOTA_EXCEEDED => quota_exceeded(&message),
+        CommandResponse::Error { error_type, message } => {
+            let status = match error_type.as_str() {
+                INVALID_BARRIER => StatusCode::BAD_REQUEST,
+                "NotFound" => StatusCode::NOT_FOUND,
+                "Conflict" | "Full" => StatusCode::CONFLICT,
+                _ => StatusCode::INTERNAL_SERVER_ERROR
+            };
+            (status , Json(ApiError::bad_request(&error_type , &message))).into_response()
+        }
+        other => (StatusCode::INTERNAL_SERVER_ERROR , Json(ApiError::bad_request("ServerError" , &format!("Unexpected response {:?}" , other)))).into_response()
+    }
+}
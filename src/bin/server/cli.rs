@@ -0,0 +1,143 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use distlock::config::server_config::{PeerConfig, ServerConfig};
+
+/// Command line flags. Every flag can also be set through the environment
+/// variable next to it; both take precedence over the config file.
+#[derive(Parser , Debug)]
+#[command(name = "distlock-server" , version , about = "Raft backed distributed lock server")]
+pub struct Cli{
+    /// Path to a TOML config file
+    #[arg(short , long , env = "DISTLOCK_CONFIG")]
+    pub config : Option<PathBuf>,
+
+    #[arg(long , env = "DISTLOCK_NODE_ID")]
+    pub node_id : Option<u64>,
+
+    /// Listen address of the client HTTP API
+    #[arg(long , env = "DISTLOCK_CLIENT_ADDR")]
+    pub client_addr : Option<String>,
+
+    /// Listen address of the gRPC API, disabled when unset
+    #[arg(long , env = "DISTLOCK_GRPC_ADDR")]
+    pub grpc_addr : Option<String>,
+
+    /// Listen address of the Redis protocol front end, disabled when unset
+    #[arg(long , env = "DISTLOCK_RESP_ADDR")]
+    pub resp_addr : Option<String>,
+
+    /// Listen address for raft traffic from peers
+    #[arg(long , env = "DISTLOCK_PEER_ADDR")]
+    pub peer_addr : Option<String>,
+
+    /// Peer as id=host:port, repeat the flag or separate with commas
+    #[arg(long = "peer" , env = "DISTLOCK_PEERS" , value_delimiter = ',')]
+    pub peers : Vec<PeerConfig>,
+
+    #[arg(long , env = "DISTLOCK_DATA_DIR")]
+    pub data_dir : Option<PathBuf>,
+
+    #[arg(long , env = "DISTLOCK_TICK_INTERVAL_MS")]
+    pub tick_interval_ms : Option<u64>,
+
+    #[arg(long , env = "DISTLOCK_ELECTION_TICK")]
+    pub election_tick : Option<usize>,
+
+    #[arg(long , env = "DISTLOCK_HEARTBEAT_TICK")]
+    pub heartbeat_tick : Option<usize>,
+
+    #[arg(long , env = "DISTLOCK_DEFAULT_TTL")]
+    pub default_ttl : Option<u64>,
+
+    #[arg(long , env = "DISTLOCK_MIN_TTL")]
+    pub min_ttl : Option<u64>,
+
+    #[arg(long , env = "DISTLOCK_MAX_TTL")]
+    pub max_ttl : Option<u64>,
+
+    /// Require bearer tokens on the HTTP API
+    #[arg(long , env = "DISTLOCK_AUTH")]
+    pub auth : bool,
+
+    /// Token of the root principal
+    #[arg(long , env = "DISTLOCK_ROOT_TOKEN" , hide_env_values = true)]
+    pub root_token : Option<String>,
+
+    /// PEM certificate chain of the node; enables TLS on the client API
+    #[arg(long , env = "DISTLOCK_TLS_CERT")]
+    pub tls_cert : Option<PathBuf>,
+
+    #[arg(long , env = "DISTLOCK_TLS_KEY")]
+    pub tls_key : Option<PathBuf>,
+
+    /// CA of the cluster; enables mutual TLS between peers
+    #[arg(long , env = "DISTLOCK_TLS_PEER_CA")]
+    pub tls_peer_ca : Option<PathBuf>,
+
+    /// CA of client certificates; clients must then present one
+    #[arg(long , env = "DISTLOCK_TLS_CLIENT_CA")]
+    pub tls_client_ca : Option<PathBuf>,
+
+    /// Bind client ids to the common name of the client certificate
+    #[arg(long , env = "DISTLOCK_CLIENT_ID_FROM_CERT")]
+    pub client_id_from_cert : bool,
+
+    /// Acquires and renewals per second one client may send to this node
+    #[arg(long , env = "DISTLOCK_REQUESTS_PER_SECOND")]
+    pub requests_per_second : Option<u32>,
+
+    #[arg(long , env = "DISTLOCK_REQUEST_BURST")]
+    pub request_burst : Option<u32>,
+
+    /// Ownership history events kept across every lock, 0 to record none
+    #[arg(long , env = "DISTLOCK_HISTORY_MAX_EVENTS")]
+    pub history_max_events : Option<usize>,
+
+    /// Age after which history events are dropped, 0 for no limit
+    #[arg(long , env = "DISTLOCK_HISTORY_RETENTION_SECONDS")]
+    pub history_retention_seconds : Option<u64>,
+
+    #[arg(long , env = "DISTLOCK_LOG_LEVEL")]
+    pub log_level : Option<String>,
+}
+
+impl Cli{
+    /// Builds the effective config: defaults, then the config file, then
+    /// flags and environment variables.
+    pub fn load_config(self) -> Result<ServerConfig , String> {
+        let mut config = match &self.config {
+            Some(path) => ServerConfig::from_file(path)?,
+            None => ServerConfig::default()
+        };
+
+        if let Some(node_id) = self.node_id { config.node_id = node_id }
+        if let Some(client_addr) = self.client_addr { config.client_addr = client_addr }
+        if let Some(grpc_addr) = self.grpc_addr { config.grpc_addr = Some(grpc_addr) }
+        if let Some(resp_addr) = self.resp_addr { config.resp_addr = Some(resp_addr) }
+        if let Some(peer_addr) = self.peer_addr { config.peer_addr = peer_addr }
+        if !self.peers.is_empty() { config.peers = self.peers }
+        if let Some(data_dir) = self.data_dir { config.data_dir = data_dir }
+        if let Some(tick_interval_ms) = self.tick_interval_ms { config.raft.tick_interval_ms = tick_interval_ms }
+        if let Some(election_tick) = self.election_tick { config.raft.election_tick = election_tick }
+        if let Some(heartbeat_tick) = self.heartbeat_tick { config.raft.heartbeat_tick = heartbeat_tick }
+        if let Some(default_ttl) = self.default_ttl { config.ttl.default_seconds = default_ttl }
+        if let Some(min_ttl) = self.min_ttl { config.ttl.min_seconds = min_ttl }
+        if let Some(max_ttl) = self.max_ttl { config.ttl.max_seconds = max_ttl }
+        if self.auth { config.auth.enabled = true }
+        if let Some(root_token) = self.root_token { config.auth.root_token = Some(root_token) }
+        if let Some(tls_cert) = self.tls_cert { config.tls.cert_file = Some(tls_cert) }
+        if let Some(tls_key) = self.tls_key { config.tls.key_file = Some(tls_key) }
+        if let Some(tls_peer_ca) = self.tls_peer_ca { config.tls.peer_ca_file = Some(tls_peer_ca) }
+        if let Some(tls_client_ca) = self.tls_client_ca { config.tls.client_ca_file = Some(tls_client_ca) }
+        if self.client_id_from_cert { config.tls.client_id_from_cert = true }
+        if let Some(requests_per_second) = self.requests_per_second { config.quotas.requests_per_second = requests_per_second }
+        if let Some(request_burst) = self.request_burst { config.quotas.burst = request_burst }
+        if let Some(history_max_events) = self.history_max_events { config.history.max_events = history_max_events }
+        if let Some(history_retention_seconds) = self.history_retention_seconds { config.history.retention_seconds = history_retention_seconds }
+        if let Some(log_level) = self.log_level { config.log_level = log_level }
+
+        config.validate()?;
+        Ok(config)
+    }
+}
@@ -0,0 +1,441 @@
+use std::time::Duration;
+
+use axum::{Json, Router, async_trait, body::{Body, Bytes}, extract::{FromRequest, Request, State}, http::StatusCode, response::{IntoResponse, Response}, routing::post};
+use chrono::Utc;
+use distlock::{api::etcd::{CampaignRequest, CampaignResponse, EtcdError, HeaderResponse, KeyValue, LeaderKey, LeaderRequest, LeaderResponse, LeaseGrantRequest, LeaseGrantResponse, LeaseHolder, LeaseIdRequest, LeaseKeepAliveResponse, LeaseKeepAliveResult, LeaseTimeToLiveResponse, LockRequest, LockResponse, ObserveResponse, ResignRequest, ResponseHeader, UnlockRequest, lease_client_id, lease_lock_id, lock_key, parse_lock_key}, auth::acl::Permission, lock::{quota::QUOTA_EXCEEDED, types::{LockHolder, LockId}}, raft::raft_commands::CommandResponse};
+use serde::de::DeserializeOwned;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::{AppState, auth::Caller, route_handlers::is_retryable};
+
+/// How often a blocked lock or campaign call checks whether it was promoted.
+const WAIT_POLL_INTERVAL : Duration = Duration::from_millis(100);
+
+// gRPC status codes used in gateway error bodies.
+const CODE_INVALID_ARGUMENT : i32 = 3;
+const CODE_NOT_FOUND : i32 = 5;
+const CODE_PERMISSION_DENIED : i32 = 7;
+const CODE_RESOURCE_EXHAUSTED : i32 = 8;
+const CODE_FAILED_PRECONDITION : i32 = 9;
+const CODE_INTERNAL : i32 = 13;
+const CODE_UNAVAILABLE : i32 = 14;
+
+/// The subset of the etcd v3 JSON gateway used by lock and election
+/// clients: leases, `/v3/lock` and `/v3/election`. `/v3/kv` is not served.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/v3/lease/grant" , post(lease_grant_handler))
+        .route("/v3/lease/keepalive" , post(lease_keepalive_handler))
+        .route("/v3/lease/revoke" , post(lease_revoke_handler))
+        .route("/v3/lease/timetolive" , post(lease_timetolive_handler))
+        .route("/v3/lock/lock" , post(lock_handler))
+        .route("/v3/lock/unlock" , post(unlock_handler))
+        .route("/v3/election/campaign" , post(campaign_handler))
+        .route("/v3/election/leader" , post(leader_handler))
+        .route("/v3/election/observe" , post(observe_handler))
+        .route("/v3/election/resign" , post(resign_handler))
+}
+
+/// JSON body read whatever the content type: the gateway accepts that, and
+/// etcd's own examples post with a bare `curl -d`.
+pub struct GatewayJson<T>(pub T);
+
+#[async_trait]
+impl<T : DeserializeOwned , S : Send + Sync> FromRequest<S> for GatewayJson<T>{
+    type Rejection = Response;
+
+    async fn from_request(request : Request , state : &S) -> Result<Self , Self::Rejection> {
+        let body = Bytes::from_request(request , state).await.map_err(IntoResponse::into_response)?;
+        serde_json::from_slice(&body).map(GatewayJson)
+            .map_err(|e| etcd_error(StatusCode::BAD_REQUEST , CODE_INVALID_ARGUMENT , &e.to_string()))
+    }
+}
+
+fn header(state : &AppState) -> ResponseHeader {
+    ResponseHeader { member_id: state.node_id as i64, ..Default::default() }
+}
+
+fn etcd_error(status : StatusCode , code : i32 , message : &str) -> Response {
+    (status , Json(EtcdError::new(code , message))).into_response()
+}
+
+/// ACL rules apply to the lock ids behind etcd names: lease locks live
+/// under `__etcd/lease/`, lock and election names are used as they are.
+async fn deny(caller : &Caller , state : &AppState , permission : Permission , lock_id : &str) -> Option<Response> {
+    if caller.allowed(state , permission , lock_id).await {
+        return None
+    }
+    Some(etcd_error(StatusCode::FORBIDDEN , CODE_PERMISSION_DENIED , "etcdserver: permission denied"))
+}
+
+fn lease_not_found() -> Response {
+    etcd_error(StatusCode::NOT_FOUND , CODE_NOT_FOUND , "etcdserver: requested lease not found")
+}
+
+fn command_error(error_type : &str , message : &str) -> Response {
+    if is_retryable(error_type) {
+        etcd_error(StatusCode::SERVICE_UNAVAILABLE , CODE_UNAVAILABLE , &format!("etcdserver: {} : {}" , error_type , message))
+    } else if error_type == QUOTA_EXCEEDED {
+        etcd_error(StatusCode::TOO_MANY_REQUESTS , CODE_RESOURCE_EXHAUSTED , &format!("etcdserver: {} : {}" , error_type , message))
+    } else {
+        etcd_error(StatusCode::BAD_REQUEST , CODE_FAILED_PRECONDITION , &format!("{} : {}" , error_type , message))
+    }
+}
+
+fn unexpected(response : CommandResponse) -> Response {
+    etcd_error(StatusCode::INTERNAL_SERVER_ERROR , CODE_INTERNAL , &format!("Unexpected response {:?}" , response))
+}
+
+/// Lock names must be text and must not collide with the lease locks.
+fn lock_name(name : Vec<u8>) -> Option<String> {
+    String::from_utf8(name).ok().filter(|name| !name.is_empty() && !name.starts_with("__etcd/"))
+}
+
+fn invalid_name() -> Response {
+    etcd_error(StatusCode::BAD_REQUEST , CODE_INVALID_ARGUMENT , "lock and election names must be non-empty UTF-8 outside __etcd/")
+}
+
+async fn unexpired_holder(state : &AppState , lock_id : &str) -> Option<LockHolder> {
+    let lock_manager = &state.state_machine;
+    lock_manager.status(&LockId(lock_id.to_string())).await.and_then(|lock| lock.holder).filter(|holder| holder.expires_at >= Utc::now())
+}
+
+/// The lease lock of a live lease and its TTL.
+async fn lease(state : &AppState , lease : i64) -> Option<(LockHolder , u64)> {
+    let holder = unexpired_holder(state , &lease_lock_id(lease)).await?;
+    let ttl = LeaseHolder::parse(&holder.client_id.0)?.ttl;
+    Some((holder , ttl))
+}
+
+/// Locks, other than the lease lock itself, held under `lease`.
+async fn locks_held_by(state : &AppState , lease : i64) -> Vec<(LockId , LockHolder)> {
+    let lease_lock = lease_lock_id(lease);
+    let lock_manager = &state.state_machine;
+    lock_manager.list().await.into_iter()
+        .filter(|(lock_id , _)| lock_id.0 != lease_lock)
+        .filter_map(|(lock_id , lock)| lock.holder.map(|holder| (lock_id , holder)))
+        .filter(|(_ , holder)| LeaseHolder::parse(&holder.client_id.0).is_some_and(|parsed| parsed.lease == lease))
+        .collect()
+}
+
+/// Like etcd, a TTL below the minimum is raised to it.
+fn granted_ttl(state : &AppState , requested : i64) -> u64 {
+    (requested.max(0) as u64).clamp(state.ttl.min_seconds , state.ttl.max_seconds)
+}
+
+pub async fn lease_grant_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    GatewayJson(payload): GatewayJson<LeaseGrantRequest>,
+) -> Response {
+    if payload.id < 0 {
+        return etcd_error(StatusCode::BAD_REQUEST , CODE_INVALID_ARGUMENT , "etcdserver: lease ID must be positive")
+    }
+    let id = match payload.id {
+        0 => rand::random::<i64>().checked_abs().unwrap_or(1).max(1),
+        id => id
+    };
+    let ttl = granted_ttl(&state , payload.ttl);
+    if let Some(denied) = deny(&caller , &state , Permission::Acquire , &lease_lock_id(id)).await {
+        return denied
+    }
+
+    match state.raft_client.propose_try_acquire(lease_lock_id(id), lease_client_id(id , ttl , None), ttl).await {
+        Ok(CommandResponse::AcquireGranted { .. }) => Json(LeaseGrantResponse { header: header(&state), id, ttl: ttl as i64 }).into_response(),
+        Ok(CommandResponse::Error { error_type, .. }) if error_type == "LockHeld" => {
+            etcd_error(StatusCode::BAD_REQUEST , CODE_FAILED_PRECONDITION , "etcdserver: lease already exists")
+        }
+        Ok(CommandResponse::Error { error_type, message }) => command_error(&error_type , &message),
+        Ok(other) => unexpected(other),
+        Err(message) => command_error("Unavailable" , &message)
+    }
+}
+
+/// Renews the lease and every lock held under it. An expired lease is
+/// answered with a TTL of 0, as etcd does.
+pub async fn lease_keepalive_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    GatewayJson(payload): GatewayJson<LeaseIdRequest>,
+) -> Response {
+    if let Some(denied) = deny(&caller , &state , Permission::Acquire , &lease_lock_id(payload.id)).await {
+        return denied
+    }
+    let reply = |ttl : u64| Json(LeaseKeepAliveResponse { result: LeaseKeepAliveResult { header: header(&state), id: payload.id, ttl: ttl as i64 } }).into_response();
+    let Some((holder , ttl)) = lease(&state , payload.id).await else { return reply(0) };
+
+    match state.raft_client.propose_renew(holder.lease_id.0, lease_lock_id(payload.id), holder.client_id.0, ttl, None).await {
+        Ok(CommandResponse::RenewSuccess { .. }) => {}
+        Ok(CommandResponse::Error { error_type, message }) if is_retryable(&error_type) => return command_error(&error_type , &message),
+        Ok(CommandResponse::Error { .. }) => return reply(0),
+        Ok(other) => return unexpected(other),
+        Err(message) => return command_error("Unavailable" , &message)
+    }
+    for (lock_id , holder) in locks_held_by(&state , payload.id).await {
+        if let Ok(CommandResponse::Error { error_type, message }) = state.raft_client.propose_renew(holder.lease_id.0, lock_id.0.clone(), holder.client_id.0, ttl, None).await {
+            tracing::warn!("Failed to renew {} under etcd lease {:x} : {} {}" , lock_id.0 , payload.id , error_type , message);
+        }
+    }
+    reply(ttl)
+}
+
+/// Releases every lock held under the lease, then the lease. Requests still
+/// queued under the lease leave their queue once they see it is gone.
+pub async fn lease_revoke_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    GatewayJson(payload): GatewayJson<LeaseIdRequest>,
+) -> Response {
+    if let Some(denied) = deny(&caller , &state , Permission::Release , &lease_lock_id(payload.id)).await {
+        return denied
+    }
+    let Some((holder , _)) = lease(&state , payload.id).await else { return lease_not_found() };
+
+    for (lock_id , held) in locks_held_by(&state , payload.id).await {
+        if let Ok(CommandResponse::Error { error_type, message }) = state.raft_client.propose_release(held.lease_id.0, lock_id.0.clone(), held.client_id.0, None).await
+            && is_retryable(&error_type) {
+            return command_error(&error_type , &message)
+        }
+    }
+    match state.raft_client.propose_release(holder.lease_id.0, lease_lock_id(payload.id), holder.client_id.0, None).await {
+        Ok(CommandResponse::ReleaseSuccess) => Json(HeaderResponse { header: header(&state) }).into_response(),
+        Ok(CommandResponse::Error { error_type, message }) if is_retryable(&error_type) => command_error(&error_type , &message),
+        Ok(CommandResponse::Error { .. }) => lease_not_found(),
+        Ok(other) => unexpected(other),
+        Err(message) => command_error("Unavailable" , &message)
+    }
+}
+
+pub async fn lease_timetolive_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    GatewayJson(payload): GatewayJson<LeaseIdRequest>,
+) -> Response {
+    if let Some(denied) = deny(&caller , &state , Permission::Status , &lease_lock_id(payload.id)).await {
+        return denied
+    }
+    let (ttl , granted_ttl) = match lease(&state , payload.id).await {
+        Some((holder , ttl)) => ((holder.expires_at - Utc::now()).num_seconds().max(0) , ttl as i64),
+        None => (-1 , 0)
+    };
+    Json(LeaseTimeToLiveResponse { header: header(&state), id: payload.id, ttl, granted_ttl }).into_response()
+}
+
+/// Acquires `name` for the lease, waiting in the queue while it is held.
+/// Returns once the lease holds the lock, or fails if the lease expires
+/// first. A request that ends any other way, the caller disconnecting
+/// included, leaves the queue like a cancelled etcd lock call.
+async fn acquire_for_lease(state : &AppState , name : &str , lease_id : i64 , value : Option<&[u8]>) -> Result<() , Response> {
+    let Some((_ , ttl)) = lease(state , lease_id).await else { return Err(lease_not_found()) };
+    let client_id = lease_client_id(lease_id , ttl , value);
+    let holds = |holder : &Option<LockHolder>| holder.as_ref().is_some_and(|holder| LeaseHolder::parse(&holder.client_id.0).is_some_and(|parsed| parsed.lease == lease_id));
+    let mut waiter = Waiter { state: state.clone(), name: name.to_string(), client_id: client_id.clone(), done: false };
+
+    let mut current = unexpired_holder(state , name).await;
+    loop {
+        if holds(&current) {
+            waiter.done = true;
+            return Ok(())
+        }
+        // Free or expired: (re)join. The manager does not promote waiters
+        // when a holder expires, so waiting alone would never end. An entry
+        // left from losing an earlier race goes first, or it would get the
+        // lock again after unlock.
+        if current.is_none() {
+            if is_queued(state , name , &client_id).await {
+                leave_queue(state , name , &client_id).await;
+            }
+            match state.raft_client.propose_acquire(name.to_string(), client_id.clone(), ttl, None).await {
+                Ok(CommandResponse::AcquireGranted { .. }) => {
+                    waiter.done = true;
+                    return Ok(())
+                }
+                Ok(CommandResponse::AcquireQueued { .. }) => {}
+                Ok(CommandResponse::Error { error_type, message }) => return Err(command_error(&error_type , &message)),
+                Ok(other) => return Err(unexpected(other)),
+                Err(message) => return Err(command_error("Unavailable" , &message))
+            }
+        }
+        tokio::time::sleep(WAIT_POLL_INTERVAL).await;
+        if lease(state , lease_id).await.is_none() {
+            return Err(lease_not_found())
+        }
+        current = unexpired_holder(state , name).await;
+    }
+}
+
+/// A lock request of `client_id` for `name`. Dropped before it is `done`,
+/// it takes the request out of the queue, and gives the lock back if it
+/// was granted in the meantime.
+struct Waiter{
+    state : AppState,
+    name : String,
+    client_id : String,
+    done : bool
+}
+
+impl Drop for Waiter{
+    fn drop(&mut self) {
+        if self.done {
+            return
+        }
+        let (state , name , client_id) = (self.state.clone() , std::mem::take(&mut self.name) , std::mem::take(&mut self.client_id));
+        tokio::spawn(async move {
+            if is_queued(&state , &name , &client_id).await {
+                leave_queue(&state , &name , &client_id).await;
+            }
+            if let Some(holder) = unexpired_holder(&state , &name).await
+                && holder.client_id.0 == client_id
+                && let Err(message) = state.raft_client.propose_release(holder.lease_id.0, name.clone(), client_id, None).await {
+                tracing::warn!("Failed to release abandoned lock {} : {}" , name , message);
+            }
+        });
+    }
+}
+
+async fn is_queued(state : &AppState , name : &str , client_id : &str) -> bool {
+    let lock_manager = &state.state_machine;
+    lock_manager.status(&LockId(name.to_string())).await.is_some_and(|lock| lock.wait_queue.iter().any(|waiter| waiter.client_id.0 == client_id))
+}
+
+async fn leave_queue(state : &AppState , name : &str , client_id : &str) {
+    if let Err(message) = state.raft_client.propose_evict_waiter(name.to_string(), client_id.to_string(), client_id.to_string(), None).await {
+        tracing::warn!("Failed to leave the queue of {} : {}" , name , message);
+    }
+}
+
+/// Releases `key` if its lease still holds the lock. Like deleting an etcd
+/// key, releasing a lock that is already gone succeeds.
+async fn release_key(caller : &Caller , state : &AppState , key : Vec<u8>) -> Response {
+    let key = String::from_utf8(key).unwrap_or_default();
+    let Some((name , lease_id)) = parse_lock_key(&key) else {
+        return etcd_error(StatusCode::BAD_REQUEST , CODE_INVALID_ARGUMENT , "key is not a lock key")
+    };
+    if let Some(denied) = deny(caller , state , Permission::Release , name).await {
+        return denied
+    }
+    if let Some(holder) = unexpired_holder(state , name).await
+        && LeaseHolder::parse(&holder.client_id.0).is_some_and(|parsed| parsed.lease == lease_id) {
+        match state.raft_client.propose_release(holder.lease_id.0, name.to_string(), holder.client_id.0, None).await {
+            Ok(CommandResponse::Error { error_type, message }) if is_retryable(&error_type) => return command_error(&error_type , &message),
+            Err(message) => return command_error("Unavailable" , &message),
+            Ok(_) => {}
+        }
+    }
+    Json(HeaderResponse { header: header(state) }).into_response()
+}
+
+pub async fn lock_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    GatewayJson(payload): GatewayJson<LockRequest>,
+) -> Response {
+    let Some(name) = lock_name(payload.name) else { return invalid_name() };
+    if let Some(denied) = deny(&caller , &state , Permission::Acquire , &name).await {
+        return denied
+    }
+    match acquire_for_lease(&state , &name , payload.lease , None).await {
+        Ok(()) => Json(LockResponse { header: header(&state), key: lock_key(&name , payload.lease).into_bytes() }).into_response(),
+        Err(response) => response
+    }
+}
+
+pub async fn unlock_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    GatewayJson(payload): GatewayJson<UnlockRequest>,
+) -> Response {
+    release_key(&caller , &state , payload.key).await
+}
+
+/// Blocks until the lease leads the election. The value is kept with the
+/// holder and cannot be changed without campaigning again.
+pub async fn campaign_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    GatewayJson(payload): GatewayJson<CampaignRequest>,
+) -> Response {
+    let Some(name) = lock_name(payload.name) else { return invalid_name() };
+    if let Some(denied) = deny(&caller , &state , Permission::Acquire , &name).await {
+        return denied
+    }
+    match acquire_for_lease(&state , &name , payload.lease , Some(&payload.value)).await {
+        Ok(()) => {
+            let leader = LeaderKey { name: name.clone().into_bytes(), key: lock_key(&name , payload.lease).into_bytes(), rev: 0, lease: payload.lease };
+            Json(CampaignResponse { header: header(&state), leader }).into_response()
+        }
+        Err(response) => response
+    }
+}
+
+async fn current_leader(state : &AppState , name : &str) -> Option<KeyValue> {
+    let holder = unexpired_holder(state , name).await?;
+    let parsed = LeaseHolder::parse(&holder.client_id.0)?;
+    Some(KeyValue { key: lock_key(name , parsed.lease).into_bytes(), value: parsed.value.unwrap_or_default(), lease: parsed.lease })
+}
+
+pub async fn leader_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    GatewayJson(payload): GatewayJson<LeaderRequest>,
+) -> Response {
+    let Some(name) = lock_name(payload.name) else { return invalid_name() };
+    if let Some(denied) = deny(&caller , &state , Permission::Status , &name).await {
+        return denied
+    }
+    match current_leader(&state , &name).await {
+        Some(kv) => Json(LeaderResponse { header: header(&state), kv }).into_response(),
+        None => etcd_error(StatusCode::NOT_FOUND , CODE_NOT_FOUND , "election: no leader")
+    }
+}
+
+/// Streams the leader as newline separated JSON messages: the current one,
+/// then every new one as commands applied on this node change it.
+pub async fn observe_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    GatewayJson(payload): GatewayJson<LeaderRequest>,
+) -> Response {
+    let Some(name) = lock_name(payload.name) else { return invalid_name() };
+    if let Some(denied) = deny(&caller , &state , Permission::Status , &name).await {
+        return denied
+    }
+    let mut applied = state.applied.subscribe();
+    let (tx , rx) = tokio::sync::mpsc::channel::<Result<String , std::io::Error>>(16);
+
+    tokio::spawn(async move {
+        let mut last : Option<KeyValue> = None;
+        loop {
+            let leader = current_leader(&state , &name).await;
+            if let Some(kv) = &leader
+                && last.as_ref() != Some(kv) {
+                let message = ObserveResponse { result: LeaderResponse { header: header(&state), kv: kv.clone() } };
+                if tx.send(Ok(format!("{}\n" , serde_json::to_string(&message).unwrap_or_default()))).await.is_err() {
+                    return
+                }
+            }
+            last = leader;
+
+            loop {
+                tokio::select! {
+                    entry = applied.recv() => match entry {
+                        Ok(entry) if entry.command.lock_id().is_some_and(|id| id != name) => continue,
+                        Ok(_) | Err(RecvError::Lagged(_)) => break,
+                        Err(RecvError::Closed) => return
+                    },
+                    _ = tx.closed() => return
+                }
+            }
+        }
+    });
+    Body::from_stream(ReceiverStream::new(rx)).into_response()
+}
+
+pub async fn resign_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    GatewayJson(payload): GatewayJson<ResignRequest>,
+) -> Response {
+    release_key(&caller , &state , payload.leader.key).await
+}
@@ -0,0 +1,114 @@
+#[cfg(test)]
+mod tests{
+    use std::time::Duration;
+
+    use distlock::{api::etcd::{LeaseGrantRequest, LeaseGrantResponse, LockRequest, LockResponse, UnlockRequest, lease_client_id}, config::server_config::TtlConfig, lock::types::LockId};
+    use serde::{Serialize, de::DeserializeOwned};
+
+    use crate::{AppState, etcd_handlers};
+
+    async fn serve(state : AppState) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}" , listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener , etcd_handlers::router().with_state(state)).await.unwrap() });
+        endpoint
+    }
+
+    async fn post<T : DeserializeOwned>(endpoint : &str , path : &str , body : &impl Serialize) -> T {
+        let response = reqwest::Client::new().post(format!("{}{}" , endpoint , path)).json(body).send().await.unwrap();
+        assert!(response.status().is_success() , "{} answered {}" , path , response.status());
+        response.json().await.unwrap()
+    }
+
+    async fn grant(endpoint : &str , id : i64 , ttl : i64) {
+        let granted : LeaseGrantResponse = post(endpoint , "/v3/lease/grant" , &LeaseGrantRequest { ttl, id }).await;
+        assert_eq!(granted.id , id);
+    }
+
+    async fn lock(endpoint : &str , lease : i64) -> Vec<u8> {
+        let locked : LockResponse = post(endpoint , "/v3/lock/lock" , &LockRequest { name: b"job".to_vec(), lease }).await;
+        locked.key
+    }
+
+    /// Queues a request of `lease` for "job", as one left behind by a lock
+    /// call of the lease that lost a race for an expired lock.
+    async fn leave_waiter(state : &AppState , lease : i64 , ttl : u64) {
+        state.raft_client.propose_acquire("job".to_string() , lease_client_id(lease , ttl , None) , ttl , None).await.unwrap();
+        assert_eq!(queue_length(state).await , 1);
+    }
+
+    async fn queue_length(state : &AppState) -> usize {
+        state.state_machine.status(&LockId("job".to_string())).await.map_or(0 , |lock| lock.wait_queue.len())
+    }
+
+    /// Waits until the queue of "job" is empty, as a request that gave up
+    /// leaves it in the background.
+    async fn wait_for_empty_queue(state : &AppState) {
+        for _ in 0..50 {
+            if queue_length(state).await == 0 {
+                return
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("The queue of 'job' still has {} entries" , queue_length(state).await)
+    }
+
+    #[tokio::test]
+
+    async fn test_lock_and_unlock_under_a_lease(){
+        let state = AppState::single_node(TtlConfig::default()).await;
+        let endpoint = serve(state.clone()).await;
+        grant(&endpoint , 0x1a , 10).await;
+
+        let key = lock(&endpoint , 0x1a).await;
+        assert_eq!(key , b"job/1a");
+        let holder = state.state_machine.status(&LockId("job".to_string())).await.unwrap().holder;
+        assert!(holder.is_some());
+
+        let _ : serde_json::Value = post(&endpoint , "/v3/lock/unlock" , &UnlockRequest { key }).await;
+        let holder = state.state_machine.status(&LockId("job".to_string())).await.unwrap().holder;
+        assert!(holder.is_none());
+    }
+
+    #[tokio::test]
+
+    async fn test_abandoned_lock_call_leaves_the_queue(){
+        let state = AppState::single_node(TtlConfig::default()).await;
+        let endpoint = serve(state.clone()).await;
+        grant(&endpoint , 1 , 10).await;
+        grant(&endpoint , 2 , 10).await;
+        let key = lock(&endpoint , 1).await;
+        leave_waiter(&state , 2 , 10).await;
+
+        let request = reqwest::Client::new().post(format!("{}/v3/lock/lock" , endpoint))
+            .json(&LockRequest { name: b"job".to_vec(), lease: 2 })
+            .timeout(Duration::from_millis(500));
+        assert!(request.send().await.unwrap_err().is_timeout());
+        wait_for_empty_queue(&state).await;
+
+        // Nobody is left to hand the lock to.
+        let _ : serde_json::Value = post(&endpoint , "/v3/lock/unlock" , &UnlockRequest { key }).await;
+        let holder = state.state_machine.status(&LockId("job".to_string())).await.unwrap().holder;
+        assert!(holder.is_none());
+    }
+
+    #[tokio::test]
+
+    async fn test_expired_holder_is_taken_over_without_stale_waiters(){
+        let state = AppState::single_node(TtlConfig::default()).await;
+        let endpoint = serve(state.clone()).await;
+        // Never kept alive, so the lock it holds expires after a second.
+        grant(&endpoint , 1 , 1).await;
+        grant(&endpoint , 2 , 10).await;
+        lock(&endpoint , 1).await;
+        leave_waiter(&state , 2 , 10).await;
+
+        let key = tokio::time::timeout(Duration::from_secs(5) , lock(&endpoint , 2)).await.unwrap();
+        assert_eq!(key , b"job/2");
+        assert_eq!(queue_length(&state).await , 0);
+
+        let _ : serde_json::Value = post(&endpoint , "/v3/lock/unlock" , &UnlockRequest { key }).await;
+        let holder = state.state_machine.status(&LockId("job".to_string())).await.unwrap().holder;
+        assert!(holder.is_none());
+    }
+}
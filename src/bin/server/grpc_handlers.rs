@@ -0,0 +1,259 @@
+use std::{pin::Pin, sync::atomic::Ordering, time::Duration};
+
+use chrono::{DateTime, Utc};
+use distlock::{api::{grpc::{self, cluster_admin_server::ClusterAdmin, distlock_server::Distlock}, utils::change_to_lock_id}, config::server_config::PeerConfig, lock::{metadata::INVALID_METADATA, quota::QUOTA_EXCEEDED, types::LockState}, raft::raft_commands::{AdminResponse, CommandResponse}};
+use tokio::{sync::{broadcast::error::RecvError, mpsc}, time::{Instant, sleep_until}};
+use tokio_stream::{Stream, wrappers::ReceiverStream};
+use tonic::{Code, Request, Response, Status, metadata::{MetadataMap, MetadataValue}};
+
+use crate::{AppState, route_handlers::{DEFAULT_TRANSFER_TIMEOUT_MS, is_retryable, member_list, resolve_ttl}};
+
+/// Serves the gRPC API. Requests take the same `RaftClient` path as the
+/// HTTP handlers; only the encoding of results and errors differs.
+#[derive(Clone)]
+pub struct GrpcService{
+    state : AppState
+}
+
+// tonic hands `Status` around by value; the helpers follow its signatures.
+#[allow(clippy::result_large_err)]
+impl GrpcService{
+    pub fn new(state : AppState) -> Self {
+        Self { state }
+    }
+
+    /// Maps a server error type to a gRPC status. The error type itself goes
+    /// into the `distlock-error-type` metadata, and retryable errors name
+    /// the leader when this node knows it.
+    fn error(&self , error_type : &str , message : &str) -> Status {
+        let code = if is_retryable(error_type) {
+            Code::Unavailable
+        } else {
+            match error_type {
+                "NotFound" => Code::NotFound,
+                "InvalidTtl" | "InvalidTarget" | "InvalidChange" | "NoTransferee" | INVALID_METADATA => Code::InvalidArgument,
+                "TransferInProgress" | "ChangeInProgress" | "LeadershipLost" => Code::Aborted,
+                "Timeout" => Code::DeadlineExceeded,
+                QUOTA_EXCEEDED => Code::ResourceExhausted,
+                "ServerError" => Code::Internal,
+                _ => Code::FailedPrecondition
+            }
+        };
+
+        let mut metadata = MetadataMap::new();
+        if let Ok(value) = MetadataValue::try_from(error_type) {
+            metadata.insert("distlock-error-type" , value);
+        }
+        let leader = self.state.known_leader.load(Ordering::Relaxed);
+        if code == Code::Unavailable && leader != 0 && leader != self.state.node_id {
+            metadata.insert("distlock-leader-id" , MetadataValue::from(leader));
+        }
+        Status::with_metadata(code , message , metadata)
+    }
+
+    fn unexpected<T : std::fmt::Debug>(&self , response : T) -> Status {
+        self.error("ServerError" , &format!("Unexpected response {:?}" , response))
+    }
+
+    /// Confirms through a ReadIndex that this node leads and is caught up
+    /// before a read is served from its state machine.
+    async fn read_barrier(&self) -> Result<() , Status> {
+        match self.state.raft_client.read_index().await {
+            Ok(AdminResponse::ReadIndex { .. }) => Ok(()),
+            Ok(AdminResponse::Error { error_type, message }) => Err(self.error(&error_type , &message)),
+            Ok(other) => Err(self.unexpected(other)),
+            Err(message) => Err(self.error("Unavailable" , &message))
+        }
+    }
+
+    fn ttl(&self , requested : u64) -> Result<u64 , Status> {
+        resolve_ttl(&self.state.ttl , requested).map_err(|message| self.error("InvalidTtl" , &message))
+    }
+
+    fn membership(&self , result : Result<AdminResponse , String>) -> Result<Response<grpc::MembershipResponse> , Status> {
+        match result {
+            Ok(AdminResponse::MembershipChanged { voters }) => Ok(Response::new(grpc::MembershipResponse { voters })),
+            Ok(AdminResponse::Error { error_type, message }) => Err(self.error(&error_type , &message)),
+            Ok(other) => Err(self.unexpected(other)),
+            Err(message) => Err(self.error("Unavailable" , &message))
+        }
+    }
+}
+
+/// A holder whose lease ran out at `now` is reported as free, as the next
+/// acquire would find it.
+fn lock_status(lock_id : &str , lock : Option<LockState> , now : DateTime<Utc>) -> grpc::StatusResponse {
+    use grpc::status_response::{Free, Held, NotFound, State};
+    let state = match lock {
+        Some(lock) => match lock.holder.filter(|holder| holder.expires_at >= now) {
+            Some(holder) => State::Held(Held {
+                client_id: holder.client_id.0,
+                lease_id: holder.lease_id.0,
+                expires_at: holder.expires_at.to_rfc3339(),
+                queue_length: lock.wait_queue.len() as u64,
+                created_at: lock.created_at.to_rfc3339(),
+                metadata: holder.metadata.into_iter().collect(),
+                fencing_token: holder.fencing_token
+            }),
+            None => State::Free(Free {})
+        },
+        None => State::NotFound(NotFound {})
+    };
+    grpc::StatusResponse { lock_id: lock_id.to_string(), state: Some(state) }
+}
+
+type WatchStream = Pin<Box<dyn Stream<Item = Result<grpc::StatusResponse , Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Distlock for GrpcService{
+    async fn acquire(&self , request : Request<grpc::AcquireRequest>) -> Result<Response<grpc::AcquireResponse> , Status> {
+        use grpc::acquire_response::{Granted, Queued, Result as AcquireResult};
+        let request = request.into_inner();
+        let ttl_seconds = self.ttl(request.time_to_live)?;
+
+        let result = match self.state.raft_client.propose_acquire_with_metadata(request.lock_id, request.client_id, ttl_seconds, request.metadata.into_iter().collect(), request.seq).await {
+            Ok(CommandResponse::AcquireGranted { lease_id, expires_at, fencing_token }) => AcquireResult::Granted(Granted { lease_id, expires_at, fencing_token }),
+            Ok(CommandResponse::AcquireQueued { position, estimated_wait }) => AcquireResult::Queued(Queued { position: position as u64, estimated_wait }),
+            Ok(CommandResponse::Error { error_type, message }) => return Err(self.error(&error_type , &message)),
+            Ok(other) => return Err(self.unexpected(other)),
+            Err(message) => return Err(self.error("Unavailable" , &message))
+        };
+        Ok(Response::new(grpc::AcquireResponse { result: Some(result) }))
+    }
+
+    async fn release(&self , request : Request<grpc::ReleaseRequest>) -> Result<Response<grpc::ReleaseResponse> , Status> {
+        let request = request.into_inner();
+        match self.state.raft_client.propose_release(request.lease_id, request.lock_id, request.client_id, request.seq).await {
+            Ok(CommandResponse::ReleaseSuccess) => Ok(Response::new(grpc::ReleaseResponse {})),
+            Ok(CommandResponse::Error { error_type, message }) => Err(self.error(&error_type , &message)),
+            Ok(other) => Err(self.unexpected(other)),
+            Err(message) => Err(self.error("Unavailable" , &message))
+        }
+    }
+
+    async fn renew(&self , request : Request<grpc::RenewRequest>) -> Result<Response<grpc::RenewResponse> , Status> {
+        let request = request.into_inner();
+        let ttl_seconds = self.ttl(request.time_to_live)?;
+        let metadata = (!request.metadata.is_empty()).then(|| request.metadata.into_iter().collect());
+        match self.state.raft_client.propose_renew_with_metadata(request.lease_id, request.lock_id, request.client_id, ttl_seconds, metadata, request.seq).await {
+            Ok(CommandResponse::RenewSuccess { new_expiry }) => Ok(Response::new(grpc::RenewResponse { new_expiry })),
+            Ok(CommandResponse::Error { error_type, message }) => Err(self.error(&error_type , &message)),
+            Ok(other) => Err(self.unexpected(other)),
+            Err(message) => Err(self.error("Unavailable" , &message))
+        }
+    }
+
+    async fn status(&self , request : Request<grpc::StatusRequest>) -> Result<Response<grpc::StatusResponse> , Status> {
+        let lock_id = request.into_inner().lock_id;
+        self.read_barrier().await?;
+        let lock = self.state.state_machine.status(&change_to_lock_id(&lock_id)).await;
+        Ok(Response::new(lock_status(&lock_id , lock , Utc::now())))
+    }
+
+    async fn list(&self , _request : Request<grpc::ListRequest>) -> Result<Response<grpc::ListResponse> , Status> {
+        self.read_barrier().await?;
+        let lock_manager = &self.state.state_machine;
+        let locks = lock_manager.list().await.into_iter().map(|(lock_id , lock)| {
+            let holder = lock.holder.as_ref();
+            grpc::LockSummary {
+                lock_id: lock_id.0,
+                holder: holder.map(|holder| holder.client_id.0.clone()),
+                lease_id: holder.map(|holder| holder.lease_id.0.clone()),
+                expires_at: holder.map(|holder| holder.expires_at.to_rfc3339()),
+                queue_length: lock.wait_queue.len() as u64,
+                created_at: lock.created_at.to_rfc3339()
+            }
+        }).collect();
+        Ok(Response::new(grpc::ListResponse { locks }))
+    }
+
+    type WatchStream = WatchStream;
+
+    /// Re-reads the lock after every applied command that touches it, and
+    /// when the holder's lease runs out, and sends the status when it
+    /// differs from the last one sent.
+    async fn watch(&self , request : Request<grpc::WatchRequest>) -> Result<Response<Self::WatchStream> , Status> {
+        let lock_id = request.into_inner().lock_id;
+        let mut applied = self.state.applied.subscribe();
+        let state_machine = self.state.state_machine.clone();
+        let (tx , rx) = mpsc::channel(16);
+
+        tokio::spawn(async move {
+            let mut last = None;
+            loop {
+                let lock = state_machine.status(&change_to_lock_id(&lock_id)).await;
+                let now = Utc::now();
+                let expires_at = lock.as_ref().and_then(|lock| lock.holder.as_ref()).map(|holder| holder.expires_at).filter(|expires_at| *expires_at >= now);
+                let current = lock_status(&lock_id , lock , now);
+                if last.as_ref() != Some(&current) {
+                    if tx.send(Ok(current.clone())).await.is_err() {
+                        return
+                    }
+                    last = Some(current);
+                }
+
+                // Expiry is not a log entry, so nothing is applied when it happens.
+                let expiry = expires_at.and_then(|expires_at| (expires_at - now).to_std().ok())
+                    .map(|left| Instant::now() + left + Duration::from_millis(1));
+                loop {
+                    tokio::select! {
+                        _ = sleep_until(expiry.unwrap_or_else(Instant::now)) , if expiry.is_some() => break,
+                        entry = applied.recv() => match entry {
+                            Ok(entry) if entry.command.lock_id().is_some_and(|id| id != lock_id) => continue,
+                            // Missed entries may have touched the lock.
+                            Ok(_) | Err(RecvError::Lagged(_)) => break,
+                            Err(RecvError::Closed) => return
+                        },
+                        _ = tx.closed() => return
+                    }
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+}
+
+#[tonic::async_trait]
+impl ClusterAdmin for GrpcService{
+    async fn members(&self , _request : Request<grpc::MembersRequest>) -> Result<Response<grpc::MembersResponse> , Status> {
+        match self.state.raft_client.members().await {
+            Ok(AdminResponse::Members { leader_id, voters, learners, added }) => {
+                let members = member_list(&self.state , leader_id , &voters , &learners , &added).into_iter()
+                    .map(|member| grpc::Member { id: member.id, addr: member.addr, client_addr: member.client_addr, voter: member.voter, leader: member.leader })
+                    .collect();
+                Ok(Response::new(grpc::MembersResponse { node_id: self.state.node_id, leader_id, members }))
+            }
+            Ok(other) => Err(self.unexpected(other)),
+            Err(message) => Err(self.error("Unavailable" , &message))
+        }
+    }
+
+    async fn add_node(&self , request : Request<grpc::AddNodeRequest>) -> Result<Response<grpc::MembershipResponse> , Status> {
+        let request = request.into_inner();
+        let peer = PeerConfig { id: request.id, addr: request.addr, client_addr: request.client_addr };
+        self.membership(self.state.raft_client.add_node(peer).await)
+    }
+
+    async fn remove_node(&self , request : Request<grpc::RemoveNodeRequest>) -> Result<Response<grpc::MembershipResponse> , Status> {
+        self.membership(self.state.raft_client.remove_node(request.into_inner().id).await)
+    }
+
+    async fn transfer_leader(&self , request : Request<grpc::TransferLeaderRequest>) -> Result<Response<grpc::TransferLeaderResponse> , Status> {
+        let request = request.into_inner();
+        let timeout_ms = request.timeout_ms.unwrap_or(DEFAULT_TRANSFER_TIMEOUT_MS);
+        match self.state.raft_client.transfer_leader(request.target, timeout_ms).await {
+            Ok(AdminResponse::LeaderTransferred { from, to, term }) => Ok(Response::new(grpc::TransferLeaderResponse { from, to, term })),
+            Ok(AdminResponse::Error { error_type, message }) => Err(self.error(&error_type , &message)),
+            Ok(other) => Err(self.unexpected(other)),
+            Err(message) => Err(self.error("Unavailable" , &message))
+        }
+    }
+
+    async fn drain(&self , request : Request<grpc::DrainRequest>) -> Result<Response<grpc::DrainResponse> , Status> {
+        match self.state.raft_client.set_draining(request.into_inner().enabled).await {
+            Ok(AdminResponse::DrainUpdated { draining, in_flight }) => Ok(Response::new(grpc::DrainResponse { draining, in_flight: in_flight as u64 })),
+            Ok(other) => Err(self.unexpected(other)),
+            Err(message) => Err(self.error("Unavailable" , &message))
+        }
+    }
+}
@@ -0,0 +1,110 @@
+#[cfg(test)]
+mod tests{
+    use std::{collections::HashMap, sync::{Arc, atomic::AtomicU64}, time::Duration};
+
+    use distlock::{api::grpc::{self, acquire_response, distlock_client::DistlockClient, distlock_server::DistlockServer, status_response}, config::server_config::TtlConfig, raft::raft_client::RaftClient};
+    use tokio::sync::mpsc;
+    use tokio_stream::{StreamExt, wrappers::TcpListenerStream};
+    use tonic::{Code, Status, transport::{Channel, Server}};
+
+    use crate::{AppState, grpc_handlers::GrpcService};
+
+    async fn start_node() -> AppState {
+        AppState::single_node(TtlConfig { default_seconds: 30, min_seconds: 1, max_seconds: 60 }).await
+    }
+
+    async fn serve(state : AppState) -> DistlockClient<Channel> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}" , listener.local_addr().unwrap());
+        let server = Server::builder().add_service(DistlockServer::new(GrpcService::new(state)));
+        tokio::spawn(server.serve_with_incoming(TcpListenerStream::new(listener)));
+        DistlockClient::connect(endpoint).await.unwrap()
+    }
+
+    fn acquire_request(lock_id : &str , client_id : &str , time_to_live : u64) -> grpc::AcquireRequest {
+        grpc::AcquireRequest { lock_id: lock_id.to_string(), client_id: client_id.to_string(), time_to_live, seq: None, metadata: HashMap::new() }
+    }
+
+    fn error_type(status : &Status) -> &str {
+        status.metadata().get("distlock-error-type").unwrap().to_str().unwrap()
+    }
+
+    #[tokio::test]
+
+    async fn test_acquire_release_and_status(){
+        let mut client = serve(start_node().await).await;
+
+        let granted = client.acquire(acquire_request("lock" , "a" , 0)).await.unwrap().into_inner();
+        let Some(acquire_response::Result::Granted(granted)) = granted.result else { panic!("Expected granted") };
+        let queued = client.acquire(acquire_request("lock" , "b" , 10)).await.unwrap().into_inner();
+        // The waiter learns how long the 30 second lease has left.
+        assert!(matches!(queued.result , Some(acquire_response::Result::Queued(grpc::acquire_response::Queued { position : 0 , estimated_wait : 25..=30 }))));
+
+        let status = client.status(grpc::StatusRequest { lock_id: "lock".to_string() }).await.unwrap().into_inner();
+        let Some(status_response::State::Held(held)) = status.state else { panic!("Expected held") };
+        assert_eq!((held.client_id.as_str() , held.lease_id.as_str() , held.queue_length) , ("a" , granted.lease_id.as_str() , 1));
+        // 0 selects the default TTL of 30 seconds.
+        let expires_in = chrono::DateTime::parse_from_rfc3339(&held.expires_at).unwrap().with_timezone(&chrono::Utc) - chrono::Utc::now();
+        assert!(expires_in > chrono::Duration::seconds(25) && expires_in <= chrono::Duration::seconds(30));
+
+        client.release(grpc::ReleaseRequest { lock_id: "lock".to_string(), client_id: "a".to_string(), lease_id: granted.lease_id, seq: None }).await.unwrap();
+        let status = client.status(grpc::StatusRequest { lock_id: "lock".to_string() }).await.unwrap().into_inner();
+        assert!(matches!(status.state , Some(status_response::State::Held(ref held)) if held.client_id == "b"));
+
+        let status = client.status(grpc::StatusRequest { lock_id: "missing".to_string() }).await.unwrap().into_inner();
+        assert!(matches!(status.state , Some(status_response::State::NotFound(_))));
+    }
+
+    #[tokio::test]
+
+    async fn test_errors_map_to_codes_and_metadata(){
+        let state = start_node().await;
+        let mut client = serve(state.clone()).await;
+
+        let status = client.acquire(acquire_request("lock" , "a" , 61)).await.unwrap_err();
+        assert_eq!((status.code() , error_type(&status)) , (Code::InvalidArgument , "InvalidTtl"));
+
+        client.acquire(acquire_request("lock" , "a" , 10)).await.unwrap();
+        let status = client.release(grpc::ReleaseRequest { lock_id: "lock".to_string(), client_id: "b".to_string(), lease_id: "stale".to_string(), seq: None }).await.unwrap_err();
+        assert_eq!((status.code() , error_type(&status)) , (Code::FailedPrecondition , "NotHolder"));
+        assert!(status.metadata().get("distlock-leader-id").is_none());
+
+        // A node that cannot reach raft and knows another leader names it.
+        let (command_tx , _) = mpsc::channel(1);
+        let (admin_tx , _) = mpsc::channel(1);
+        let follower = AppState { raft_client: Arc::new(RaftClient::new(command_tx , admin_tx)), known_leader: Arc::new(AtomicU64::new(2)), ..state };
+        let mut client = serve(follower).await;
+        let status = client.acquire(acquire_request("lock" , "a" , 10)).await.unwrap_err();
+        assert_eq!((status.code() , error_type(&status)) , (Code::Unavailable , "Unavailable"));
+        assert_eq!(status.metadata().get("distlock-leader-id").unwrap().to_str().unwrap() , "2");
+    }
+
+    #[tokio::test]
+
+    async fn test_watch_streams_each_change(){
+        let mut client = serve(start_node().await).await;
+        let mut watch = client.watch(grpc::WatchRequest { lock_id: "lock".to_string() }).await.unwrap().into_inner();
+        let mut next = async || tokio::time::timeout(Duration::from_secs(5) , watch.next()).await.unwrap().unwrap().unwrap().state.unwrap();
+
+        assert!(matches!(next().await , status_response::State::NotFound(_)));
+        let granted = client.acquire(acquire_request("lock" , "a" , 10)).await.unwrap().into_inner();
+        let Some(acquire_response::Result::Granted(granted)) = granted.result else { panic!("Expected granted") };
+        assert!(matches!(next().await , status_response::State::Held(ref held) if held.client_id == "a"));
+
+        client.release(grpc::ReleaseRequest { lock_id: "lock".to_string(), client_id: "a".to_string(), lease_id: granted.lease_id, seq: None }).await.unwrap();
+        assert!(matches!(next().await , status_response::State::Free(_)));
+    }
+
+    #[tokio::test]
+
+    async fn test_watch_reports_an_expired_lease(){
+        let mut client = serve(start_node().await).await;
+        client.acquire(acquire_request("lock" , "a" , 1)).await.unwrap();
+        let mut watch = client.watch(grpc::WatchRequest { lock_id: "lock".to_string() }).await.unwrap().into_inner();
+        let mut next = async || tokio::time::timeout(Duration::from_secs(5) , watch.next()).await.unwrap().unwrap().unwrap().state.unwrap();
+
+        assert!(matches!(next().await , status_response::State::Held(ref held) if held.client_id == "a"));
+        // Nothing is applied when the lease runs out.
+        assert!(matches!(next().await , status_response::State::Free(_)));
+    }
+}
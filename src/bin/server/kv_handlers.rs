@@ -0,0 +1,85 @@
+use axum::{Json, Router, extract::{Path, Query, State}, http::StatusCode, response::{IntoResponse, Response}, routing::get};
+use distlock::{api::models::{ApiError, KvDeleteQuery, KvPutRequest, KvWriteResponse}, auth::acl::Permission, lock::{kv::{INVALID_KV, KvLease, VERSION_MISMATCH}, quota::QUOTA_EXCEEDED, types::{LeaseId, LockId}}, raft::raft_commands::CommandResponse};
+
+use crate::{AppState, auth::Caller, route_handlers::{is_retryable, quota_exceeded, unavailable}};
+
+/// The key-value store. Keys share the ACL namespace of locks: reading a
+/// key needs the status permission on it, writing it the acquire one.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/kv/*key" , get(get_handler).put(put_handler).delete(delete_handler))
+}
+
+async fn get_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(key): Path<String>,
+) -> Response {
+    if let Some(denied) = caller.deny(&state , Permission::Status , &key).await {
+        return denied
+    }
+    match state.raft_client.propose_kv_get(key.clone()).await {
+        Ok(CommandResponse::KvValue { entry: Some(entry) }) => Json(entry).into_response(),
+        Ok(CommandResponse::KvValue { entry: None }) => (StatusCode::NOT_FOUND , Json(ApiError::not_found(&format!("No key '{}'" , key)))).into_response(),
+        Ok(response) => write_response(&state , response),
+        Err(message) => unavailable(&state , "Unavailable" , &message)
+    }
+}
+
+async fn put_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(key): Path<String>,
+    Json(mut payload): Json<KvPutRequest>,
+) -> Response {
+    caller.bind_client_id(&mut payload.client_id);
+    if let Some(denied) = caller.deny_client(&state , Permission::Acquire , &key , &payload.client_id).await {
+        return denied
+    }
+    let lease = match (payload.lock_id , payload.lease_id) {
+        (Some(lock_id) , Some(lease_id)) => Some(KvLease { lock_id: LockId(lock_id), lease_id: LeaseId(lease_id) }),
+        (None , None) => None,
+        _ => return (StatusCode::BAD_REQUEST , Json(ApiError::bad_request(INVALID_KV , "lock_id and lease_id must be given together"))).into_response()
+    };
+    match state.raft_client.propose_kv_put(key , payload.value , payload.client_id , payload.expected_version , lease , payload.seq).await {
+        Ok(response) => write_response(&state , response),
+        Err(message) => unavailable(&state , "Unavailable" , &message)
+    }
+}
+
+async fn delete_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(key): Path<String>,
+    Query(mut query): Query<KvDeleteQuery>,
+) -> Response {
+    caller.bind_client_id(&mut query.client_id);
+    if let Some(denied) = caller.deny_client(&state , Permission::Acquire , &key , &query.client_id).await {
+        return denied
+    }
+    match state.raft_client.propose_kv_delete(key , query.client_id , query.expected_version , query.seq).await {
+        Ok(response) => write_response(&state , response),
+        Err(message) => unavailable(&state , "Unavailable" , &message)
+    }
+}
+
+/// A failed compare-and-swap is 412 so clients can tell it from the lease
+/// errors, which are 409.
+fn write_response(state : &AppState , response : CommandResponse) -> Response {
+    match response {
+        CommandResponse::KvWritten { version } | CommandResponse::KvDeleted { version } => Json(KvWriteResponse { version }).into_response(),
+        CommandResponse::Error { error_type, message } if is_retryable(&error_type) => unavailable(state , &error_type , &message),
+        CommandResponse::Error { error_type, message } if error_type == QUOTA_EXCEEDED => quota_exceeded(&message),
+        CommandResponse::Error { error_type, message } => {
+            let status = match error_type.as_str() {
+                INVALID_KV => StatusCode::BAD_REQUEST,
+                VERSION_MISMATCH => StatusCode::PRECONDITION_FAILED,
+                "NotFound" => StatusCode::NOT_FOUND,
+                "NotHolder" | "Expired" => StatusCode::CONFLICT,
+                _ => StatusCode::INTERNAL_SERVER_ERROR
+            };
+            (status , Json(ApiError::bad_request(&error_type , &message))).into_response()
+        }
+        other => (StatusCode::INTERNAL_SERVER_ERROR , Json(ApiError::bad_request("ServerError" , &format!("Unexpected response {:?}" , other)))).into_response()
+    }
+}
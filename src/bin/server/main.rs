@@ -1,48 +1,258 @@
 
 
+pub mod auth;
+pub mod barrier_handlers;
+pub mod cli;
+pub mod etcd_handlers;
+pub mod etcd_handlers_test;
+pub mod grpc_handlers;
+pub mod grpc_handlers_test;
+pub mod kv_handlers;
+pub mod resp_handlers;
 pub mod route_handlers;
-use std::sync::{Arc};
+use std::{collections::HashMap, str::FromStr, sync::{Arc, atomic::{AtomicU64, Ordering}}};
 
-use axum::{Router, routing::{get, post}};
-use distlock::{lock::manager::InMemoryLockManager, raft::{node::RaftNode, raft_client::RaftClient, raft_commands::{CommandResponse, LockCommand}}};
+use axum::{Router, middleware, routing::{get, post}};
+use clap::Parser;
+use distlock::{api::grpc::{cluster_admin_server::ClusterAdminServer, distlock_server::DistlockServer}, config::server_config::{PeerConfig, TtlConfig}, lock::{backend::SharedBackend, manager::InMemoryLockManager, quota::RateLimiter}, raft::{node::RaftNode, raft_client::RaftClient, raft_commands::{AdminCommand, AdminResponse, AppliedEntry, CommandResponse, LockCommand}, storage::DistlockStorage, transport::{TcpTransport, serve_peers, serve_peers_tls}}, tls::{https::{accept_tls, serve_https}, material::TlsMaterial}};
 
-use route_handlers::{acquire_handler, health_check, release_handler, renew_handler, status_handler};
-use tokio::sync::{mpsc, oneshot::{ Sender, channel}};
+use route_handlers::{acl_handler, acquire_handler, add_node_handler, admin_lock_handler, cancel_wait_handler, admin_locks_handler, contended_locks_handler, drain_handler, evict_waiter_handler, force_release_handler, health_check, history_handler, list_handler, lock_stats_handler, members_handler, metrics_handler, quotas_handler, release_handler, remove_node_handler, renew_handler, restore_handler, revoke_client_handler, set_acl_handler, set_quotas_handler, snapshot_handler, status_handler, track_requests, transfer_handler, transfer_leader_handler};
+use metrics_exporter_prometheus::PrometheusHandle;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio_stream::{StreamExt, wrappers::{ReceiverStream, TcpListenerStream}};
+
+use crate::{auth::Authenticator, cli::Cli, grpc_handlers::GrpcService, resp_handlers::{serve_resp, serve_resp_tls}};
 
 #[derive(Clone)]
 pub struct AppState {
-    pub lock_manager : Arc<InMemoryLockManager>
+    pub raft_client : Arc<RaftClient>,
+    pub state_machine : SharedBackend,
+    pub ttl : TtlConfig,
+    pub node_id : u64,
+    pub known_leader : Arc<AtomicU64>,
+    /// Addresses of every configured node, this one included.
+    pub nodes : Arc<HashMap<u64 , PeerConfig>>,
+    /// Commands applied by the local node, for watchers.
+    pub applied : broadcast::Sender<AppliedEntry>,
+    /// Token checks of the HTTP API, `None` when auth is disabled.
+    pub auth : Option<Authenticator>,
+    pub metrics : PrometheusHandle
+}
+
+impl AppState {
+    /// Where clients should send writes instead, if it is another node.
+    pub fn leader_hint(&self) -> Option<String> {
+        let leader = self.known_leader.load(Ordering::Relaxed);
+        if leader == self.node_id {
+            return None
+        }
+        self.nodes.get(&leader).and_then(|node| node.client_addr.clone())
+    }
 }
+#[cfg(test)]
+impl AppState {
+    /// A single-node cluster for handler tests, returned once it leads.
+    pub async fn single_node(ttl : TtlConfig) -> Self {
+        let (command_tx , command_rx) = mpsc::channel(100);
+        let (admin_tx , admin_rx) = mpsc::channel(10);
+        let node = RaftNode::new(1 , vec![1] , command_rx , admin_rx);
+        let state = AppState {
+            raft_client: Arc::new(RaftClient::new(command_tx , admin_tx)),
+            state_machine: node.state_machine(),
+            ttl,
+            node_id: 1,
+            known_leader: node.known_leader(),
+            nodes: Arc::new(HashMap::new()),
+            applied: node.applied_notifier(),
+            auth: None,
+            metrics: distlock::telemetry::prometheus::recorder().handle()
+        };
+        tokio::spawn(node.run());
+        for _ in 0..100 {
+            if state.known_leader.load(Ordering::Relaxed) == 1 {
+                return state
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        panic!("No leader elected")
+    }
+}
+
 #[tokio::main]
 
 async fn main(){
-    tracing_subscriber::fmt::init();
+    let config = match Cli::parse().load_config(){
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}" , e);
+            std::process::exit(2);
+        }
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::from_str(&config.log_level).unwrap())
+        .init();
+
+    let metrics = match distlock::telemetry::prometheus::install(){
+        Ok(metrics) => metrics,
+        Err(e) => {
+            tracing::error!("{}" , e);
+            std::process::exit(1);
+        }
+    };
+
+    let tls = match config.tls.enabled().then(|| TlsMaterial::load(&config.tls)).transpose(){
+        Ok(tls) => tls.map(Arc::new),
+        Err(e) => {
+            tracing::error!("Failed to load TLS material: {}" , e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(tls) = &tls {
+        tokio::spawn(reload_on_sighup(tls.clone()));
+    }
+
     let (command_tx  , command_rx)= mpsc::channel::<(LockCommand , oneshot::Sender<CommandResponse>)>(100);
-    // let (command_tx , command_rx) = m
-    let lock_manager = Arc::new(InMemoryLockManager::new());
+    let (admin_tx , admin_rx) = mpsc::channel::<(AdminCommand , oneshot::Sender<AdminResponse>)>(10);
 
-    let peers = vec![];
-    let raft_client = RaftClient::new(command_tx);
+    let mut raft_client = RaftClient::new(command_tx , admin_tx);
+    if config.quotas.requests_per_second > 0 {
+        raft_client = raft_client.with_rate_limiter(RateLimiter::new(config.quotas.requests_per_second , config.quotas.burst));
+    }
+    let raft_client = Arc::new(raft_client);
 
-    let raft_node = RaftNode::new(1, peers , command_rx);
+    let lock_manager = InMemoryLockManager::new()
+        .with_history(config.history.clone());
+    let peer_addrs : HashMap<u64 , String> = config.peers.iter().map(|peer| (peer.id , peer.addr.clone())).collect();
+    let mut voters = vec![config.node_id];
+    voters.extend(config.peer_ids().into_iter().filter(|peer| *peer != config.node_id));
+    let storage = match DistlockStorage::open(&config.data_dir , voters){
+        Ok(storage) => storage,
+        Err(e) => {
+            tracing::error!("{}" , e);
+            std::process::exit(1);
+        }
+    };
+    let raft_node = RaftNode::with_storage(config.node_id, &config.raft , storage , lock_manager , command_rx , admin_rx)
+        .with_transport(Arc::new(match &tls {
+            Some(tls) => TcpTransport::with_tls(&peer_addrs , tls.clone()),
+            None => TcpTransport::new(&peer_addrs)
+        }));
+    let state_machine = raft_node.state_machine();
+    let known_leader = raft_node.known_leader();
+    let applied = raft_node.applied_notifier();
+    let auth = config.auth.enabled.then(|| Authenticator::new(config.auth.root_token.as_deref().unwrap_or_default() , raft_node.acl()));
+    let mut nodes : HashMap<u64 , PeerConfig> = config.peers.iter().map(|peer| (peer.id , peer.clone())).collect();
+    nodes.insert(config.node_id , PeerConfig { id: config.node_id, addr: config.peer_addr.clone(), client_addr: Some(config.client_addr.clone()) });
+
+    // A node started alone still listens, so nodes added later can reach it.
+    let peer_listener = tokio::net::TcpListener::bind(&config.peer_addr).await.unwrap();
+    tracing::info!("Listening for peers on {}",config.peer_addr);
+    match &tls {
+        Some(tls) => tokio::spawn(serve_peers_tls(peer_listener , raft_node.message_sender() , tls.clone())),
+        None => tokio::spawn(serve_peers(peer_listener , raft_node.message_sender()))
+    };
 
     tokio::spawn(async move {
         raft_node.run().await
     });
 
     let state = AppState{
-        lock_manager 
+        raft_client , 
+        state_machine ,
+        ttl : config.ttl.clone() ,
+        node_id : config.node_id ,
+        known_leader ,
+        nodes : Arc::new(nodes) ,
+        applied ,
+        auth ,
+        metrics
     };
+
+    if let Some(grpc_addr) = &config.grpc_addr {
+        let grpc_listener = tokio::net::TcpListener::bind(grpc_addr).await.unwrap();
+        tracing::info!("Serving gRPC on {}",grpc_addr);
+        let service = GrpcService::new(state.clone());
+        let server = tonic::transport::Server::builder()
+            .add_service(DistlockServer::new(service.clone()))
+            .add_service(ClusterAdminServer::new(service));
+        match &tls {
+            Some(tls) => {
+                let incoming = ReceiverStream::new(accept_tls(grpc_listener , tls.clone())).map(|(stream , _)| Ok::<_ , std::io::Error>(stream));
+                tokio::spawn(server.serve_with_incoming(incoming))
+            }
+            None => tokio::spawn(server.serve_with_incoming(TcpListenerStream::new(grpc_listener)))
+        };
+    }
+
+    if let Some(resp_addr) = &config.resp_addr {
+        let resp_listener = tokio::net::TcpListener::bind(resp_addr).await.unwrap();
+        tracing::info!("Serving the Redis protocol on {}",resp_addr);
+        match &tls {
+            Some(tls) => tokio::spawn(serve_resp_tls(resp_listener , state.clone() , tls.clone())),
+            None => tokio::spawn(serve_resp(resp_listener , state.clone()))
+        };
+    }
+
     let app = Router::new()
     .route("/",get(health_check))
     .route("/acquire",post(acquire_handler))
     .route("/release",post(release_handler))
+    .route("/cancel",post(cancel_wait_handler))
     .route("/renew",post(renew_handler))
+    .route("/transfer",post(transfer_handler))
     .route("/status/:lock_id",get(status_handler))
+    .route("/locks",get(list_handler))
+    .route("/locks/contended",get(contended_locks_handler))
+    .route("/locks/:lock_id/stats",get(lock_stats_handler))
+    .route("/history",get(history_handler))
+    .route("/cluster/members",get(members_handler))
+    .route("/admin/transfer-leader",post(transfer_leader_handler))
+    .route("/admin/drain",post(drain_handler))
+    .route("/admin/add-node",post(add_node_handler))
+    .route("/admin/remove-node",post(remove_node_handler))
+    .route("/admin/snapshot",get(snapshot_handler).post(restore_handler))
+    .route("/admin/acl",get(acl_handler).put(set_acl_handler))
+    .route("/admin/quotas",get(quotas_handler).put(set_quotas_handler))
+    .route("/admin/locks",get(admin_locks_handler))
+    .route("/admin/locks/*lock_id",get(admin_lock_handler))
+    .route("/admin/force-release",post(force_release_handler))
+    .route("/admin/evict-waiter",post(evict_waiter_handler))
+    .route("/admin/revoke-client",post(revoke_client_handler))
+    .route("/metrics",get(metrics_handler))
+    .merge(etcd_handlers::router())
+    .merge(kv_handlers::router())
+    .merge(barrier_handlers::router())
+    .route_layer(middleware::from_fn_with_state(state.clone() , auth::require_token))
+    .route_layer(middleware::from_fn(track_requests))
     .with_state(state);
-    let addr = "0.0.0.0:3000";
 
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    tracing::info!("Listening on {}",addr);
-    axum::serve(listener , app).await.unwrap();
+    let listener = tokio::net::TcpListener::bind(&config.client_addr).await.unwrap();
+    match tls {
+        Some(tls) => {
+            tracing::info!("Node {} listening on {} with TLS",config.node_id , config.client_addr);
+            serve_https(listener , app , tls).await
+        }
+        None => {
+            tracing::info!("Node {} listening on {}",config.node_id , config.client_addr);
+            axum::serve(listener , app).await.unwrap()
+        }
+    }
+}
+
+/// Re-reads certificates and keys on SIGHUP. New connections use them;
+/// established ones keep what they were set up with.
+async fn reload_on_sighup(tls : Arc<TlsMaterial>) {
+    let mut hangups = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            tracing::warn!("Cannot reload TLS material on SIGHUP: {}" , e);
+            return
+        }
+    };
+    while hangups.recv().await.is_some() {
+        match tls.reload() {
+            Ok(()) => tracing::info!("Reloaded TLS material"),
+            Err(e) => tracing::error!("Failed to reload TLS material, keeping the current one: {}" , e)
+        }
+    }
 }
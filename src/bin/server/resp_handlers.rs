@@ -0,0 +1,188 @@
+use std::sync::{Arc, atomic::Ordering};
+
+use chrono::Utc;
+use distlock::{api::resp::{RespCommand, RespValue, parse_command}, lock::types::{LockHolder, LockId}, raft::raft_commands::CommandResponse, tls::{https::accept_tls, material::TlsMaterial}};
+use tokio::{io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt}, net::TcpListener};
+
+use crate::{AppState, route_handlers::{is_retryable, resolve_ttl}};
+
+/// Accepts Redis protocol connections until the listener fails.
+pub async fn serve_resp(listener : TcpListener , state : AppState) {
+    loop {
+        match listener.accept().await {
+            Ok((stream , addr)) => {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream , state).await {
+                        tracing::debug!("RESP connection from {} closed : {}" , addr , e);
+                    }
+                });
+            }
+            Err(e) => tracing::warn!("Failed to accept RESP connection : {}" , e)
+        }
+    }
+}
+
+/// Like `serve_resp`, over TLS.
+pub async fn serve_resp_tls(listener : TcpListener , state : AppState , tls : Arc<TlsMaterial>) {
+    let mut accepted = accept_tls(listener , tls);
+    while let Some((stream , addr)) = accepted.recv().await {
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream , state).await {
+                tracing::debug!("RESP connection from {} closed : {}" , addr , e);
+            }
+        });
+    }
+}
+
+async fn handle_connection<S : AsyncRead + AsyncWrite + Unpin>(mut stream : S , state : AppState) -> std::io::Result<()> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8 ; 4096];
+    loop {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(())
+        }
+        buf.extend_from_slice(&chunk[..read]);
+
+        let mut out = Vec::new();
+        let mut quit = false;
+        while !quit {
+            let args = match parse_command(&buf) {
+                Ok(Some((args , used))) => {
+                    buf.drain(..used);
+                    args
+                }
+                Ok(None) => break,
+                Err(message) => {
+                    RespValue::Error(format!("ERR {}" , message)).encode(&mut out);
+                    stream.write_all(&out).await?;
+                    return Ok(())
+                }
+            };
+            if args.is_empty() {
+                continue
+            }
+            let reply = match RespCommand::parse(&args) {
+                Ok(RespCommand::Quit) => {
+                    quit = true;
+                    RespValue::ok()
+                }
+                Ok(command) => execute(&state , command).await,
+                Err(message) => RespValue::Error(message)
+            };
+            reply.encode(&mut out);
+        }
+        stream.write_all(&out).await?;
+        if quit {
+            return Ok(())
+        }
+    }
+}
+
+/// TTLs arrive in milliseconds but leases are granted in whole seconds, so
+/// they are rounded up: the lock never expires before the client expects.
+fn ttl_seconds(state : &AppState , ttl_ms : Option<u64>) -> Result<u64 , RespValue> {
+    let requested = ttl_ms.map_or(0 , |ttl_ms| ttl_ms.div_ceil(1000));
+    resolve_ttl(&state.ttl , requested).map_err(|message| RespValue::Error(format!("ERR {}" , message)))
+}
+
+/// Redis has no leader redirect; TRYAGAIN tells clients the write can be
+/// retried, against the leader when the message names one.
+fn error_reply(state : &AppState , error_type : &str , message : &str) -> RespValue {
+    if !is_retryable(error_type) {
+        return RespValue::Error(format!("ERR {} : {}" , error_type , message))
+    }
+    let leader = state.known_leader.load(Ordering::Relaxed);
+    if leader != 0 && leader != state.node_id {
+        RespValue::Error(format!("TRYAGAIN {} : {} , leader is node {}" , error_type , message , leader))
+    } else {
+        RespValue::Error(format!("TRYAGAIN {} : {}" , error_type , message))
+    }
+}
+
+/// Compare-and-set commands read the holder locally before proposing, so
+/// they only run where the state is current.
+fn not_leader(state : &AppState) -> Option<RespValue> {
+    (state.known_leader.load(Ordering::Relaxed) != state.node_id)
+        .then(|| error_reply(state , "NotLeader" , "Not the leader"))
+}
+
+/// The unexpired holder of `key` on this node, which is what a Redis key
+/// holding the client's token corresponds to.
+async fn holder(state : &AppState , key : &str) -> Option<LockHolder> {
+    let lock_manager = &state.state_machine;
+    lock_manager.status(&LockId(key.to_string())).await.and_then(|lock| lock.holder).filter(|holder| holder.expires_at >= Utc::now())
+}
+
+/// Renews the lease of `holder` and answers like `PEXPIRE`: 1 when renewed,
+/// 0 when the lease is gone.
+async fn renew(state : &AppState , key : String , holder : LockHolder , ttl_ms : u64) -> RespValue {
+    let ttl_seconds = match ttl_seconds(state , Some(ttl_ms)) {
+        Ok(ttl_seconds) => ttl_seconds,
+        Err(reply) => return reply
+    };
+    match state.raft_client.propose_renew(holder.lease_id.0, key, holder.client_id.0, ttl_seconds, None).await {
+        Ok(CommandResponse::RenewSuccess { .. }) => RespValue::Integer(1),
+        Ok(CommandResponse::Error { error_type, message }) if is_retryable(&error_type) => error_reply(state , &error_type , &message),
+        Ok(CommandResponse::Error { .. }) => RespValue::Integer(0),
+        Ok(other) => RespValue::Error(format!("ERR Unexpected response {:?}" , other)),
+        Err(message) => error_reply(state , "Unavailable" , &message)
+    }
+}
+
+async fn execute(state : &AppState , command : RespCommand) -> RespValue {
+    match command {
+        RespCommand::SetNx { key, token, ttl_ms } => {
+            let ttl_seconds = match ttl_seconds(state , ttl_ms) {
+                Ok(ttl_seconds) => ttl_seconds,
+                Err(reply) => return reply
+            };
+            match state.raft_client.propose_try_acquire(key, token, ttl_seconds).await {
+                Ok(CommandResponse::AcquireGranted { .. }) => RespValue::ok(),
+                Ok(CommandResponse::Error { error_type, .. }) if error_type == "LockHeld" => RespValue::Null,
+                Ok(CommandResponse::Error { error_type, message }) => error_reply(state , &error_type , &message),
+                Ok(other) => RespValue::Error(format!("ERR Unexpected response {:?}" , other)),
+                Err(message) => error_reply(state , "Unavailable" , &message)
+            }
+        }
+        RespCommand::Get { key } => match holder(state , &key).await {
+            Some(holder) => RespValue::Bulk(holder.client_id.0.into_bytes()),
+            None => RespValue::Null
+        },
+        RespCommand::Pttl { key } => match holder(state , &key).await {
+            Some(holder) => RespValue::Integer((holder.expires_at - Utc::now()).num_milliseconds().max(0)),
+            None => RespValue::Integer(-2)
+        },
+        RespCommand::Token { key } => match holder(state , &key).await {
+            Some(holder) => RespValue::Integer(holder.fencing_token as i64),
+            None => RespValue::Null
+        },
+        RespCommand::PExpire { .. } | RespCommand::Extend { .. } | RespCommand::Release { .. } if let Some(reply) = not_leader(state) => reply,
+        RespCommand::PExpire { key, ttl_ms } => match holder(state , &key).await {
+            Some(holder) => renew(state , key , holder , ttl_ms).await,
+            None => RespValue::Integer(0)
+        },
+        RespCommand::Extend { key, token, ttl_ms } => match holder(state , &key).await {
+            Some(holder) if holder.client_id.0 == token => renew(state , key , holder , ttl_ms).await,
+            _ => RespValue::Integer(0)
+        },
+        RespCommand::Release { key, token } => {
+            let Some(holder) = holder(state , &key).await.filter(|holder| holder.client_id.0 == token) else {
+                return RespValue::Integer(0)
+            };
+            // The lease id pins the release to the lease read above.
+            match state.raft_client.propose_release(holder.lease_id.0, key, token, None).await {
+                Ok(CommandResponse::ReleaseSuccess) => RespValue::Integer(1),
+                Ok(CommandResponse::Error { error_type, message }) if is_retryable(&error_type) => error_reply(state , &error_type , &message),
+                Ok(CommandResponse::Error { .. }) => RespValue::Integer(0),
+                Ok(other) => RespValue::Error(format!("ERR Unexpected response {:?}" , other)),
+                Err(message) => error_reply(state , "Unavailable" , &message)
+            }
+        }
+        RespCommand::Ping(None) => RespValue::Simple("PONG".to_string()),
+        RespCommand::Ping(Some(message)) | RespCommand::Echo(message) => RespValue::Bulk(message),
+        RespCommand::Quit | RespCommand::Ignored => RespValue::ok()
+    }
+}
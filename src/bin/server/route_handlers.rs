@@ -1,103 +1,718 @@
-use std::time::Duration;
+use std::time::Instant;
 
-use axum::{Json, extract::{Path, State}, response::IntoResponse};
-use chrono::format::parse;
-use distlock::{api::{models::{AcquireRequest, AcquireResponse, ReleaseRequest, ReleaseResponse, RenewRequest, RenewResponse, StatusResponse}, utils::{change_to_client_id, change_to_lease_id, change_to_lock_id}}, 
-lock::{self, types::{ClientId, LeaseId, LockId, LockManager, ReleaseResult, RenewResult}}};
-use distlock::lock::types::{AcquireResult};
-use crate::AppState;
+use axum::{Json, extract::{MatchedPath, Path, Query, Request, State}, http::{StatusCode, header}, middleware::Next, response::{IntoResponse, Response}};
+use chrono::Utc;
+use distlock::{api::{models::{AclRequest, AclResponse, AcquireRequest, AcquireResponse, AddNodeRequest, AdminLockResponse, CancelWaitRequest, CancelWaitResponse, ContendedLocksQuery, ContendedLocksResponse, HolderCount, LockStatsResponse, AdminLocksQuery, AdminLocksResponse, ApiError, EvictWaiterRequest, ForceReleaseRequest, HistoryQuery, HistoryResponse, LockDetails, RevokeClientRequest, DrainRequest, DrainResponse, ListLocksResponse, LockSnapshot, QuotasResponse, LockSummary, MemberInfo, MembersResponse, MembershipResponse, ReleaseRequest, ReleaseResponse, RemoveNodeRequest, RenewRequest, RenewResponse, RestoreResponse, StatusResponse, TransferRequest, TransferResponse, TransferLeaderRequest, TransferLeaderResponse}, utils::change_to_lock_id}, 
+auth::acl::{AclTable, Permission}, config::server_config::{PeerConfig, TtlConfig}, lock::{metadata::INVALID_METADATA, quota::{LockQuotas, QUOTA_EXCEEDED}, stats::LockStats, types::{LockId, LockState}}, raft::raft_commands::{AdminResponse, CommandResponse, IdempotencyKey}, telemetry::prometheus::{GRANT_LATENCY, HTTP_REQUESTS, HTTP_REQUEST_DURATION}};
+use metrics::{counter, histogram};
+use crate::{AppState, auth::Caller};
+
+pub const DEFAULT_TRANSFER_TIMEOUT_MS : u64 = 10_000;
 
 pub async fn health_check() -> &'static str {
     "Ok"
 }
+
+/// Counts requests by route rather than by path, so lock ids do not become
+/// label values.
+pub async fn track_requests(request : Request , next : Next) -> Response {
+    let endpoint = request.extensions().get::<MatchedPath>().map_or("unmatched".to_string() , |path| path.as_str().to_string());
+    let started = Instant::now();
+    let response = next.run(request).await;
+    histogram!(HTTP_REQUEST_DURATION , "endpoint" => endpoint.clone()).record(started.elapsed().as_secs_f64());
+    counter!(HTTP_REQUESTS , "endpoint" => endpoint , "status" => response.status().as_str().to_string()).increment(1);
+    response
+}
+
+/// Prometheus text format. Needs a token like every other endpoint when
+/// auth is enabled.
+pub async fn metrics_handler(State(state) : State<AppState>) -> Response {
+    state.state_machine.record_gauges(Utc::now()).await;
+    ([(header::CONTENT_TYPE , "text/plain; version=0.0.4")] , state.metrics.render()).into_response()
+}
+
+/// Leadership and drain errors are transient: the same request will succeed
+/// once it reaches the current leader.
+pub fn is_retryable(error_type : &str) -> bool {
+    matches!(error_type , "NotLeader" | "Draining" | "ProposalFailed" | "ProposalDropped" | "Unavailable")
+}
+
+pub fn unavailable(state : &AppState , error_type : &str , message : &str) -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::RETRY_AFTER , "1")],
+        Json(ApiError::unavailable(error_type , message).with_leader_hint(state.leader_hint()))
+    ).into_response()
+}
+
+/// Waits until this node is known to lead and applied every write committed
+/// before the read, so local reads are not stale after a failover.
+pub async fn read_barrier(state : &AppState) -> Result<() , Response> {
+    match state.raft_client.read_index().await {
+        Ok(AdminResponse::ReadIndex { .. }) => Ok(()),
+        Ok(AdminResponse::Error { error_type, message }) => Err(unavailable(state , &error_type , &message)),
+        Ok(other) => Err((StatusCode::INTERNAL_SERVER_ERROR , Json(ApiError::unavailable("ServerError" , &format!("Unexpected response {:?}" , other)))).into_response()),
+        Err(message) => Err(unavailable(state , "Unavailable" , &message))
+    }
+}
+
+/// A TTL of 0 selects the configured default; anything else must be within
+/// the configured limits.
+pub fn resolve_ttl(ttl : &TtlConfig , requested : u64) -> Result<u64 , String> {
+    if requested == 0 {
+        return Ok(ttl.default_seconds)
+    }
+    if requested < ttl.min_seconds || requested > ttl.max_seconds {
+        return Err(format!("time_to_live must be between {} and {} seconds" , ttl.min_seconds , ttl.max_seconds))
+    }
+    Ok(requested)
+}
+
+/// The request would exceed a quota. Nothing changed.
+pub fn quota_exceeded(message : &str) -> Response {
+    (StatusCode::TOO_MANY_REQUESTS , Json(ApiError::bad_request(QUOTA_EXCEEDED , message))).into_response()
+}
+
+pub fn invalid_ttl(message : &str) -> Response {
+    (StatusCode::BAD_REQUEST , Json(ApiError::bad_request("InvalidTtl" , message))).into_response()
+}
+
+fn invalid_metadata(message : &str) -> Response {
+    (StatusCode::BAD_REQUEST , Json(ApiError::bad_request(INVALID_METADATA , message))).into_response()
+}
+
 pub async fn acquire_handler(
     State(state): State<AppState>,
-    Json(payload): Json<AcquireRequest>,
-) -> impl IntoResponse {
-    let lock_id = change_to_lock_id(&payload.lock_id);
-    let client_id = change_to_client_id(&payload.client_id);
-    let ttl = Duration::from_secs(payload.time_to_live);
-
-    let lock_manager = state.lock_manager;
+    caller: Caller,
+    Json(mut payload): Json<AcquireRequest>,
+) -> Response {
+    caller.bind_client_id(&mut payload.client_id);
+    if let Some(denied) = caller.deny_client(&state , Permission::Acquire , &payload.lock_id , &payload.client_id).await {
+        return denied
+    }
+    let ttl_seconds = match resolve_ttl(&state.ttl , payload.time_to_live){
+        Ok(ttl_seconds) => ttl_seconds,
+        Err(message) => return invalid_ttl(&message)
+    };
+    let started = Instant::now();
+    let result = state.raft_client.propose_acquire_with_metadata(payload.lock_id, payload.client_id, ttl_seconds, payload.metadata, payload.seq).await;
 
-    match lock_manager.try_acquire(&lock_id, &client_id, ttl){
-        AcquireResult::Granted { lease_id, expires_at } => {
-             Json(AcquireResponse::Granted {lease_id : lease_id.0, expires_at : expires_at.to_rfc3339() })
+    match result{
+        Ok(CommandResponse::AcquireGranted { lease_id, expires_at, fencing_token }) => {
+             histogram!(GRANT_LATENCY).record(started.elapsed().as_secs_f64());
+             Json(AcquireResponse::Granted {lease_id , expires_at , fencing_token }).into_response()
         }
-        AcquireResult::Queued { position, estimated_wait }=> Json(AcquireResponse::Queued { position, estimated_wait : estimated_wait.as_secs()}),
-        AcquireResult::Error(message) => Json(AcquireResponse::Error { error_type: "AcquireFailure".to_string(), message })
+        Ok(CommandResponse::AcquireQueued { position, estimated_wait }) => Json(AcquireResponse::Queued { position, estimated_wait }).into_response(),
+        Ok(CommandResponse::Error { error_type, message }) if is_retryable(&error_type) => unavailable(&state , &error_type , &message),
+        Ok(CommandResponse::Error { error_type, message }) if error_type == QUOTA_EXCEEDED => quota_exceeded(&message),
+        Ok(CommandResponse::Error { error_type, message }) if error_type == INVALID_METADATA => invalid_metadata(&message),
+        Ok(CommandResponse::Error { error_type, message }) => Json(AcquireResponse::Error { error_type, message }).into_response(),
+        Ok(other) => Json(AcquireResponse::Error { error_type: "AcquireFailure".to_string(), message: format!("Unexpected response {:?}" , other) }).into_response(),
+        Err(message) => unavailable(&state , "Unavailable" , &message)
     }
-    
-   
 }
 pub async fn release_handler(
     State(state): State<AppState>,
-    Json(payload): Json<ReleaseRequest>,
-) -> impl IntoResponse {
-    let lock_manager = state.lock_manager;
-
-    let lock_id = change_to_lock_id(&payload.lock_id);
-    let client_id = change_to_client_id(&payload.client_id);
-    let lease_id = change_to_lease_id(&payload.lease_id);
+    caller: Caller,
+    Json(mut payload): Json<ReleaseRequest>,
+) -> Response {
+    caller.bind_client_id(&mut payload.client_id);
+    if let Some(denied) = caller.deny_client(&state , Permission::Release , &payload.lock_id , &payload.client_id).await {
+        return denied
+    }
+    let result = state.raft_client.propose_release(payload.lease_id, payload.lock_id, payload.client_id, payload.seq).await;
 
-    match lock_manager.release(&lock_id, &client_id, &lease_id){
-        ReleaseResult::Success => {
-            Json(ReleaseResponse::Success)
-        }
-        ReleaseResult::NotFound => {
-            Json(ReleaseResponse::Error { error_type: "NotFound".to_string(), message: "This lock does not exist".to_string() })
+    match result{
+        Ok(CommandResponse::ReleaseSuccess) => {
+            Json(ReleaseResponse::Success).into_response()
         }
-        ReleaseResult::NotHolder => {
-            Json(ReleaseResponse::Error { error_type: "NotHolder".to_string(), message: "This lock does not exist".to_string() })
+        Ok(CommandResponse::Error { error_type, message }) if is_retryable(&error_type) => unavailable(&state , &error_type , &message),
+        Ok(CommandResponse::Error { error_type, message }) => {
+            Json(ReleaseResponse::Error { error_type, message }).into_response()
         }
-        ReleaseResult::Error(message) =>{
-            Json(ReleaseResponse::Error { error_type: "ServerError".to_string(), message })
+        Ok(other) =>{
+            Json(ReleaseResponse::Error { error_type: "ServerError".to_string(), message: format!("Unexpected response {:?}" , other) }).into_response()
         }
+        Err(message) => unavailable(&state , "Unavailable" , &message)
     }
 }
-pub async fn renew_handler(
+/// Takes the caller out of the queue of a lock it stopped waiting for, so
+/// the lock is not handed to a client that is gone.
+pub async fn cancel_wait_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(mut payload): Json<CancelWaitRequest>,
+) -> Response {
+    caller.bind_client_id(&mut payload.client_id);
+    if let Some(denied) = caller.deny_client(&state , Permission::Acquire , &payload.lock_id , &payload.client_id).await {
+        return denied
+    }
+    let actor = payload.client_id.clone();
+    match state.raft_client.propose_evict_waiter(payload.lock_id , payload.client_id , actor , None).await {
+        Ok(CommandResponse::WaiterEvicted { removed }) => Json(CancelWaitResponse::Cancelled { removed }).into_response(),
+        Ok(CommandResponse::Error { error_type, .. }) if error_type == "NotFound" => Json(CancelWaitResponse::Cancelled { removed: 0 }).into_response(),
+        Ok(CommandResponse::Error { error_type, message }) if is_retryable(&error_type) => unavailable(&state , &error_type , &message),
+        Ok(CommandResponse::Error { error_type, message }) => Json(CancelWaitResponse::Error { error_type, message }).into_response(),
+        Ok(other) => Json(CancelWaitResponse::Error { error_type: "ServerError".to_string(), message: format!("Unexpected response {:?}" , other) }).into_response(),
+        Err(message) => unavailable(&state , "Unavailable" , &message)
+    }
+}
+
+/// Hands the caller's lock to another client in one step. The caller needs
+/// to be allowed both to release the lock and to acquire it.
+pub async fn transfer_handler(
     State(state): State<AppState>,
-    Json(payload): Json<RenewRequest>,
-) -> impl IntoResponse {
-    let lock_manager = state.lock_manager;
-    let lock_id = change_to_lock_id(&payload.lock_id);
-    let lease_id = change_to_lease_id(&payload.lease_id);
-    let client_id = change_to_client_id(&payload.client_id);
-    let ttl = Duration::from_secs(payload.time_to_live);
+    caller: Caller,
+    Json(mut payload): Json<TransferRequest>,
+) -> Response {
+    caller.bind_client_id(&mut payload.client_id);
+    if let Some(denied) = caller.deny_client(&state , Permission::Release , &payload.lock_id , &payload.client_id).await {
+        return denied
+    }
+    if let Some(denied) = caller.deny(&state , Permission::Acquire , &payload.lock_id).await {
+        return denied
+    }
+    let ttl_seconds = match resolve_ttl(&state.ttl , payload.time_to_live){
+        Ok(ttl_seconds) => ttl_seconds,
+        Err(message) => return invalid_ttl(&message)
+    };
+    let result = state.raft_client.propose_transfer(payload.lease_id, payload.lock_id, payload.client_id, payload.target_client_id, ttl_seconds, payload.seq).await;
 
-    match lock_manager.renew(&lock_id, &client_id, &lease_id, ttl){
-        RenewResult::Success { new_expiry } => {
-            Json(RenewResponse::Success{new_expiry : new_expiry.to_rfc3339()})
+    match result{
+        Ok(CommandResponse::Transferred { lease_id, expires_at, fencing_token }) => {
+            Json(TransferResponse::Transferred { lease_id, expires_at, fencing_token }).into_response()
         }
-        RenewResult::Expired => {
-            Json(RenewResponse::Error { error_type: "Expired".to_string(), message: "Lease expired".to_string() })
+        Ok(CommandResponse::Error { error_type, message }) if is_retryable(&error_type) => unavailable(&state , &error_type , &message),
+        Ok(CommandResponse::Error { error_type, message }) if error_type == QUOTA_EXCEEDED => quota_exceeded(&message),
+        Ok(CommandResponse::Error { error_type, message }) => {
+            Json(TransferResponse::Error { error_type, message }).into_response()
         }
-        RenewResult::NotFound => {
-            Json(RenewResponse::Error { error_type: "NotFound".to_string(), message: "Lock not found".to_string() })
+        Ok(other) => {
+            Json(TransferResponse::Error { error_type: "ServerError".to_string(), message: format!("Unexpected response {:?}" , other) }).into_response()
         }
-        RenewResult::NotHolder => {
-            Json(RenewResponse::Error { error_type: "NotHolder".to_string(), message: "Lock not Holder".to_string() })
+        Err(message) => unavailable(&state , "Unavailable" , &message)
+    }
+}
+
+pub async fn renew_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(mut payload): Json<RenewRequest>,
+) -> Response {
+    caller.bind_client_id(&mut payload.client_id);
+    if let Some(denied) = caller.deny_client(&state , Permission::Acquire , &payload.lock_id , &payload.client_id).await {
+        return denied
+    }
+    let ttl_seconds = match resolve_ttl(&state.ttl , payload.time_to_live){
+        Ok(ttl_seconds) => ttl_seconds,
+        Err(message) => return invalid_ttl(&message)
+    };
+    let result = state.raft_client.propose_renew_with_metadata(payload.lease_id, payload.lock_id, payload.client_id, ttl_seconds, payload.metadata, payload.seq).await;
+
+    match result{
+        Ok(CommandResponse::RenewSuccess { new_expiry }) => {
+            Json(RenewResponse::Success{new_expiry}).into_response()
         }
-        RenewResult::Error(message) => {
-            Json(RenewResponse::Error { error_type: "ServerError".to_string(), message })
+        Ok(CommandResponse::Error { error_type, message }) if is_retryable(&error_type) => unavailable(&state , &error_type , &message),
+        Ok(CommandResponse::Error { error_type, message }) if error_type == QUOTA_EXCEEDED => quota_exceeded(&message),
+        Ok(CommandResponse::Error { error_type, message }) if error_type == INVALID_METADATA => invalid_metadata(&message),
+        Ok(CommandResponse::Error { error_type, message }) => {
+            Json(RenewResponse::Error { error_type, message }).into_response()
         }
+        Ok(other) => {
+            Json(RenewResponse::Error { error_type: "ServerError".to_string(), message: format!("Unexpected response {:?}" , other) }).into_response()
+        }
+        Err(message) => unavailable(&state , "Unavailable" , &message)
     }
 }
 pub async fn status_handler(
     State(state): State<AppState>,
+    caller: Caller,
     Path(lock_id): Path<String>,
-) -> impl IntoResponse {
-    let lock_manager = state.lock_manager;
+) -> Response {
+    if let Some(denied) = caller.deny(&state , Permission::Status , &lock_id).await {
+        return denied
+    }
+    if let Err(response) = read_barrier(&state).await {
+        return response
+    }
+    let lock_manager = &state.state_machine;
 
-    match lock_manager.status(&LockId(lock_id)){
+    match lock_manager.status(&change_to_lock_id(&lock_id)).await{
         Some(state) => {
             if let Some(holder) = state.holder{
-                return Json( StatusResponse::InUse { client_id: holder.client_id.0, expires_at: holder.expires_at.to_rfc3339(), lease_id: holder.lease_id.0, queue_length: state.wait_queue.len(), created_at: state.created_at.to_rfc3339() }
-)            }
-            return Json(StatusResponse::Free)
+                return Json( StatusResponse::InUse { client_id: holder.client_id.0, expires_at: holder.expires_at.to_rfc3339(), lease_id: holder.lease_id.0, queue_length: state.wait_queue.len(), created_at: state.created_at.to_rfc3339(), metadata: holder.metadata, fencing_token: holder.fencing_token }
+).into_response()            }
+            Json(StatusResponse::Free).into_response()
         },
         None => {
-            return Json(StatusResponse::NotFound)
+            Json(StatusResponse::NotFound).into_response()
+        }
+    }
+}
+
+pub async fn transfer_leader_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(payload): Json<TransferLeaderRequest>,
+) -> Response {
+    if let Some(denied) = caller.deny(&state , Permission::Admin , "").await {
+        return denied
+    }
+    let timeout_ms = payload.timeout_ms.unwrap_or(DEFAULT_TRANSFER_TIMEOUT_MS);
+
+    match state.raft_client.transfer_leader(payload.target, timeout_ms).await{
+        Ok(AdminResponse::LeaderTransferred { from, to, term }) => {
+            Json(TransferLeaderResponse::Transferred { from, to, term }).into_response()
+        }
+        Ok(AdminResponse::Error { error_type, message }) => {
+            let status = match error_type.as_str(){
+                "NotLeader" | "TransferInProgress" => StatusCode::CONFLICT,
+                "InvalidTarget" | "NoTransferee" => StatusCode::BAD_REQUEST,
+                _ => StatusCode::GATEWAY_TIMEOUT
+            };
+            (status , Json(TransferLeaderResponse::Error { error_type, message })).into_response()
+        }
+        Ok(other) => {
+            (StatusCode::INTERNAL_SERVER_ERROR , Json(TransferLeaderResponse::Error { error_type: "ServerError".to_string(), message: format!("Unexpected response {:?}" , other) })).into_response()
+        }
+        Err(message) => unavailable(&state , "Unavailable" , &message)
+    }
+}
+
+pub async fn drain_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(payload): Json<DrainRequest>,
+) -> Response {
+    if let Some(denied) = caller.deny(&state , Permission::Admin , "").await {
+        return denied
+    }
+    match state.raft_client.set_draining(payload.enabled).await{
+        Ok(AdminResponse::DrainUpdated { draining, in_flight }) => {
+            Json(DrainResponse::Updated { draining, in_flight }).into_response()
+        }
+        Ok(other) => {
+            (StatusCode::INTERNAL_SERVER_ERROR , Json(DrainResponse::Error { error_type: "ServerError".to_string(), message: format!("Unexpected response {:?}" , other) })).into_response()
+        }
+        Err(message) => unavailable(&state , "Unavailable" , &message)
+    }
+}
+
+/// Lists the locks once this node confirmed it leads, leaving out those the
+/// caller may not read.
+pub async fn list_handler(State(state): State<AppState> , caller: Caller) -> Response {
+    if let Err(response) = read_barrier(&state).await {
+        return response
+    }
+    let locks = state.state_machine.list().await;
+    let mut visible = Vec::with_capacity(locks.len());
+    for (lock_id , lock) in locks {
+        if caller.allowed(&state , Permission::Status , &lock_id.0).await {
+            visible.push((lock_id , lock));
+        }
+    }
+    let locks = visible.into_iter().map(|(lock_id , lock)| {
+        let holder = lock.holder.as_ref();
+        LockSummary {
+            lock_id: lock_id.0,
+            holder: holder.map(|holder| holder.client_id.0.clone()),
+            lease_id: holder.map(|holder| holder.lease_id.0.clone()),
+            expires_at: holder.map(|holder| holder.expires_at.to_rfc3339()),
+            queue_length: lock.wait_queue.len(),
+            created_at: lock.created_at.to_rfc3339()
+        }
+    }).collect();
+    Json(ListLocksResponse { locks }).into_response()
+}
+
+const TOP_HOLDERS : usize = 10;
+const DEFAULT_CONTENDED : usize = 10;
+
+fn stats_response(lock_id : LockId , stats : &LockStats) -> LockStatsResponse {
+    LockStatsResponse {
+        lock_id: lock_id.0,
+        acquires: stats.acquires,
+        acquisitions: stats.grants,
+        queued: stats.queued,
+        contention_rate: stats.contention_rate(),
+        mean_hold_seconds: stats.mean_hold().as_secs_f64(),
+        p99_hold_seconds: stats.p99_hold().as_secs_f64(),
+        mean_wait_seconds: stats.mean_wait().as_secs_f64(),
+        max_queue_depth: stats.max_queue_depth,
+        releases: stats.releases,
+        expirations: stats.expirations,
+        force_releases: stats.force_releases,
+        transfers: stats.transfers,
+        top_holders: stats.top_holders(TOP_HOLDERS).into_iter().map(|(client_id , acquisitions)| HolderCount { client_id: client_id.0, acquisitions }).collect()
+    }
+}
+
+/// Statistics kept by this node since it started.
+pub async fn lock_stats_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(lock_id): Path<String>,
+) -> Response {
+    if let Some(denied) = caller.deny(&state , Permission::Status , &lock_id).await {
+        return denied
+    }
+    if let Err(response) = read_barrier(&state).await {
+        return response
+    }
+    let lock_id = LockId(lock_id);
+    match state.state_machine.stats(&lock_id).await {
+        Some(stats) => Json(stats_response(lock_id , &stats)).into_response(),
+        None => (StatusCode::NOT_FOUND , Json(ApiError::not_found(&format!("'{}' was never acquired" , lock_id.0)))).into_response()
+    }
+}
+
+/// The locks clients wait for most, among those the caller may read.
+pub async fn contended_locks_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(query): Query<ContendedLocksQuery>,
+) -> Response {
+    if let Err(response) = read_barrier(&state).await {
+        return response
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_CONTENDED).clamp(1 , MAX_PAGE_SIZE);
+    let contended = state.state_machine.most_contended().await;
+    let mut locks = Vec::new();
+    for (lock_id , stats) in contended.into_iter().filter(|(lock_id , _)| lock_id.0.starts_with(&query.prefix)) {
+        if locks.len() == limit {
+            break
+        }
+        if caller.allowed(&state , Permission::Status , &lock_id.0).await {
+            locks.push(stats_response(lock_id , &stats));
+        }
+    }
+    Json(ContendedLocksResponse { locks }).into_response()
+}
+
+/// Joins the raft membership with the addresses from the config and from
+/// nodes added at runtime.
+pub fn member_list(state : &AppState , leader_id : u64 , voters : &[u64] , learners : &[u64] , added : &[PeerConfig]) -> Vec<MemberInfo> {
+    let addresses = |id : u64| -> (Option<String> , Option<String>) {
+        match added.iter().find(|peer| peer.id == id).or_else(|| state.nodes.get(&id)) {
+            Some(peer) => (Some(peer.addr.clone()) , peer.client_addr.clone()),
+            None => (None , None)
         }
+    };
+    voters.iter().map(|id| (*id , true)).chain(learners.iter().map(|id| (*id , false)))
+        .map(|(id , voter)| {
+            let (addr , client_addr) = addresses(id);
+            MemberInfo { id, addr, client_addr, voter, leader: id == leader_id }
+        })
+        .collect()
+}
+
+pub async fn members_handler(State(state): State<AppState> , caller: Caller) -> Response {
+    if let Some(denied) = caller.deny(&state , Permission::Admin , "").await {
+        return denied
+    }
+    match state.raft_client.members().await{
+        Ok(AdminResponse::Members { leader_id, voters, learners, added }) => {
+            let members = member_list(&state , leader_id , &voters , &learners , &added);
+            Json(MembersResponse::Members { node_id: state.node_id, leader_id, members }).into_response()
+        }
+        Ok(other) => {
+            (StatusCode::INTERNAL_SERVER_ERROR , Json(MembersResponse::Error { error_type: "ServerError".to_string(), message: format!("Unexpected response {:?}" , other) })).into_response()
+        }
+        Err(message) => unavailable(&state , "Unavailable" , &message)
+    }
+}
+
+fn membership_response(state : &AppState , result : Result<AdminResponse , String>) -> Response {
+    match result{
+        Ok(AdminResponse::MembershipChanged { voters }) => Json(MembershipResponse::Changed { voters }).into_response(),
+        Ok(AdminResponse::Error { error_type, message }) if matches!(error_type.as_str() , "NotLeader" | "ProposalDropped") => unavailable(state , &error_type , &message),
+        Ok(AdminResponse::Error { error_type, message }) => {
+            let status = match error_type.as_str(){
+                "ChangeInProgress" => StatusCode::CONFLICT,
+                _ => StatusCode::BAD_REQUEST
+            };
+            (status , Json(MembershipResponse::Error { error_type, message })).into_response()
+        }
+        Ok(other) => {
+            (StatusCode::INTERNAL_SERVER_ERROR , Json(MembershipResponse::Error { error_type: "ServerError".to_string(), message: format!("Unexpected response {:?}" , other) })).into_response()
+        }
+        Err(message) => unavailable(state , "Unavailable" , &message)
+    }
+}
+
+/// Adds a voter. The new node should already be running, started with the
+/// full member list so it can reach the others.
+pub async fn add_node_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(payload): Json<AddNodeRequest>,
+) -> Response {
+    if let Some(denied) = caller.deny(&state , Permission::Admin , "").await {
+        return denied
+    }
+    let peer = PeerConfig { id: payload.id, addr: payload.addr, client_addr: payload.client_addr };
+    let result = state.raft_client.add_node(peer).await;
+    membership_response(&state , result)
+}
+
+pub async fn remove_node_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(payload): Json<RemoveNodeRequest>,
+) -> Response {
+    if let Some(denied) = caller.deny(&state , Permission::Admin , "").await {
+        return denied
+    }
+    let result = state.raft_client.remove_node(payload.id).await;
+    membership_response(&state , result)
+}
+
+/// Dumps this node's lock table. Ask the leader for the latest state.
+pub async fn snapshot_handler(State(state): State<AppState> , caller: Caller) -> Response {
+    if let Some(denied) = caller.deny(&state , Permission::Admin , "").await {
+        return denied
+    }
+    let lock_manager = &state.state_machine;
+    let now = Utc::now();
+    Json(LockSnapshot { taken_at: now.to_rfc3339(), locks: lock_manager.list().await, history: lock_manager.history_events().await, kv: lock_manager.kv_entries(now).await, barriers: lock_manager.barriers().await }).into_response()
+}
+
+/// Replaces the lock table of every replica. The snapshot goes through the
+/// log like any other write.
+pub async fn restore_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(payload): Json<LockSnapshot>,
+) -> Response {
+    if let Some(denied) = caller.deny(&state , Permission::Admin , "").await {
+        return denied
+    }
+    match state.raft_client.propose_restore(payload.locks , payload.history , payload.kv , payload.barriers).await{
+        Ok(CommandResponse::Restored { locks }) => Json(RestoreResponse::Restored { locks }).into_response(),
+        Ok(CommandResponse::Error { error_type, message }) if is_retryable(&error_type) => unavailable(&state , &error_type , &message),
+        Ok(CommandResponse::Error { error_type, message }) => {
+            (StatusCode::BAD_REQUEST , Json(RestoreResponse::Error { error_type, message })).into_response()
+        }
+        Ok(other) => {
+            (StatusCode::INTERNAL_SERVER_ERROR , Json(RestoreResponse::Error { error_type: "ServerError".to_string(), message: format!("Unexpected response {:?}" , other) })).into_response()
+        }
+        Err(message) => unavailable(&state , "Unavailable" , &message)
+    }
+}
+
+/// The replicated ACL table. Tokens only appear as hashes.
+pub async fn acl_handler(State(state): State<AppState> , caller: Caller) -> Response {
+    if let Some(denied) = caller.deny(&state , Permission::Admin , "").await {
+        return denied
+    }
+    match &state.auth {
+        Some(auth) => Json(auth.acl().read().await.clone()).into_response(),
+        None => Json(AclTable::default()).into_response()
+    }
+}
+
+/// Replaces the ACL table of every replica through the log.
+pub async fn set_acl_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(payload): Json<AclRequest>,
+) -> Response {
+    if let Some(denied) = caller.deny(&state , Permission::Admin , "").await {
+        return denied
+    }
+    let acl = match payload.into_table() {
+        Ok(acl) => acl,
+        Err(message) => return (StatusCode::BAD_REQUEST , Json(AclResponse::Error { error_type: "InvalidAcl".to_string(), message })).into_response()
+    };
+    match state.raft_client.propose_acl(acl).await{
+        Ok(CommandResponse::AclUpdated { principals, rules }) => Json(AclResponse::Updated { principals, rules }).into_response(),
+        Ok(CommandResponse::Error { error_type, message }) if is_retryable(&error_type) => unavailable(&state , &error_type , &message),
+        Ok(CommandResponse::Error { error_type, message }) => {
+            (StatusCode::BAD_REQUEST , Json(AclResponse::Error { error_type, message })).into_response()
+        }
+        Ok(other) => {
+            (StatusCode::INTERNAL_SERVER_ERROR , Json(AclResponse::Error { error_type: "ServerError".to_string(), message: format!("Unexpected response {:?}" , other) })).into_response()
+        }
+        Err(message) => unavailable(&state , "Unavailable" , &message)
+    }
+}
+
+/// The limits on the lock table, as replicated to this node.
+pub async fn quotas_handler(State(state): State<AppState> , caller: Caller) -> Response {
+    if let Some(denied) = caller.deny(&state , Permission::Admin , "").await {
+        return denied
+    }
+    if let Err(response) = read_barrier(&state).await {
+        return response
+    }
+    Json(state.state_machine.quotas().await).into_response()
+}
+
+/// Replaces the limits on the lock table of every replica through the log.
+pub async fn set_quotas_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(quotas): Json<LockQuotas>,
+) -> Response {
+    if let Some(denied) = caller.deny(&state , Permission::Admin , "").await {
+        return denied
+    }
+    match state.raft_client.propose_quotas(quotas).await{
+        Ok(CommandResponse::QuotasUpdated { quotas }) => Json(QuotasResponse::Updated { quotas }).into_response(),
+        Ok(CommandResponse::Error { error_type, message }) if is_retryable(&error_type) => unavailable(&state , &error_type , &message),
+        Ok(CommandResponse::Error { error_type, message }) => {
+            (StatusCode::BAD_REQUEST , Json(QuotasResponse::Error { error_type, message })).into_response()
+        }
+        Ok(other) => {
+            (StatusCode::INTERNAL_SERVER_ERROR , Json(QuotasResponse::Error { error_type: "ServerError".to_string(), message: format!("Unexpected response {:?}" , other) })).into_response()
+        }
+        Err(message) => unavailable(&state , "Unavailable" , &message)
+    }
+}
+
+const DEFAULT_PAGE_SIZE : usize = 100;
+const MAX_PAGE_SIZE : usize = 1000;
+
+fn lock_details(lock_id : LockId , lock : LockState) -> LockDetails {
+    let now = Utc::now();
+    LockDetails {
+        lock_id: lock_id.0,
+        expired: lock.holder.as_ref().is_some_and(|holder| holder.expires_at < now),
+        holder: lock.holder,
+        waiters: lock.wait_queue,
+        created_at: lock.created_at.to_rfc3339()
+    }
+}
+
+/// A page of locks with their holders and waiters, read from the leader.
+pub async fn admin_locks_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(query): Query<AdminLocksQuery>,
+) -> Response {
+    if let Some(denied) = caller.deny(&state , Permission::Admin , "").await {
+        return denied
+    }
+    if let Err(response) = read_barrier(&state).await {
+        return response
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1 , MAX_PAGE_SIZE);
+    let page = state.state_machine.list_page(&query.prefix , query.after.as_deref() , limit).await;
+    let next_after = (page.len() == limit).then(|| page.last().map(|(lock_id , _)| lock_id.0.clone())).flatten();
+    let locks = page.into_iter().map(|(lock_id , lock)| lock_details(lock_id , lock)).collect();
+    Json(AdminLocksResponse { locks, next_after }).into_response()
+}
+
+pub async fn admin_lock_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(lock_id): Path<String>,
+) -> Response {
+    if let Some(denied) = caller.deny(&state , Permission::Admin , "").await {
+        return denied
+    }
+    if let Err(response) = read_barrier(&state).await {
+        return response
+    }
+    let lock_id = LockId(lock_id);
+    match state.state_machine.status(&lock_id).await {
+        Some(lock) => Json(lock_details(lock_id , lock)).into_response(),
+        None => (StatusCode::NOT_FOUND , Json(ApiError::not_found(&format!("No lock '{}'" , lock_id.0)))).into_response()
+    }
+}
+
+/// Key of an admin write, so a retry by the client is answered from the
+/// dedup table instead of taking the lock from the next holder.
+fn admin_key(caller : &Caller , mut requester : String , seq : Option<u64>) -> Option<IdempotencyKey> {
+    caller.bind_client_id(&mut requester);
+    seq.filter(|_| !requester.is_empty()).map(|seq| IdempotencyKey { client_id: requester, seq })
+}
+
+fn admin_lock_response(state : &AppState , result : Result<CommandResponse , String>) -> Response {
+    match result {
+        Ok(CommandResponse::ForceReleased { previous_holder, promoted }) => Json(AdminLockResponse::ForceReleased { previous_holder, promoted }).into_response(),
+        Ok(CommandResponse::WaiterEvicted { removed }) => Json(AdminLockResponse::WaiterEvicted { removed }).into_response(),
+        Ok(CommandResponse::ClientRevoked { released, evicted }) => Json(AdminLockResponse::ClientRevoked { released, evicted }).into_response(),
+        Ok(CommandResponse::Error { error_type, message }) if is_retryable(&error_type) => unavailable(state , &error_type , &message),
+        Ok(CommandResponse::Error { error_type, message }) => {
+            let status = match error_type.as_str() {
+                "NotFound" | "NotHeld" => StatusCode::NOT_FOUND,
+                "NotHolder" => StatusCode::CONFLICT,
+                _ => StatusCode::BAD_REQUEST
+            };
+            (status , Json(AdminLockResponse::Error { error_type, message })).into_response()
+        }
+        Ok(other) => {
+            (StatusCode::INTERNAL_SERVER_ERROR , Json(AdminLockResponse::Error { error_type: "ServerError".to_string(), message: format!("Unexpected response {:?}" , other) })).into_response()
+        }
+        Err(message) => unavailable(state , "Unavailable" , &message)
+    }
+}
+
+/// Ownership history as applied on the leader, for postmortems. Reading one
+/// lock needs the status permission on it, reading every lock needs admin.
+pub async fn history_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(query): Query<HistoryQuery>,
+) -> Response {
+    let denied = match &query.lock_id {
+        Some(lock_id) => caller.deny(&state , Permission::Status , lock_id).await,
+        None => caller.deny(&state , Permission::Admin , "").await
+    };
+    if let Some(denied) = denied {
+        return denied
+    }
+    if let Err(response) = read_barrier(&state).await {
+        return response
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1 , MAX_PAGE_SIZE);
+    let lock_id = query.lock_id.map(LockId);
+    let mut events = state.state_machine.history(lock_id.as_ref() , query.since , query.until , limit + 1).await;
+    let truncated = events.len() > limit;
+    events.truncate(limit);
+    Json(HistoryResponse { events, truncated }).into_response()
+}
+
+/// Takes a lock from its holder, e.g. after the holder's host died with a
+/// long lease. The next waiter is granted the lock.
+pub async fn force_release_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(payload): Json<ForceReleaseRequest>,
+) -> Response {
+    if let Some(denied) = caller.deny(&state , Permission::Admin , "").await {
+        return denied
+    }
+    let idempotency_key = admin_key(&caller , payload.requester , payload.seq);
+    let result = state.raft_client.propose_force_release(payload.lock_id , payload.lease_id , caller.actor() , idempotency_key).await;
+    admin_lock_response(&state , result)
+}
+
+pub async fn evict_waiter_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(payload): Json<EvictWaiterRequest>,
+) -> Response {
+    if let Some(denied) = caller.deny(&state , Permission::Admin , "").await {
+        return denied
+    }
+    let idempotency_key = admin_key(&caller , payload.requester , payload.seq);
+    let result = state.raft_client.propose_evict_waiter(payload.lock_id , payload.client_id , caller.actor() , idempotency_key).await;
+    admin_lock_response(&state , result)
+}
+
+/// Releases every lock of a client and drops it from every queue.
+pub async fn revoke_client_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(payload): Json<RevokeClientRequest>,
+) -> Response {
+    if let Some(denied) = caller.deny(&state , Permission::Admin , "").await {
+        return denied
     }
+    let idempotency_key = admin_key(&caller , payload.requester , payload.seq);
+    let result = state.raft_client.propose_revoke_client(payload.client_id , caller.actor() , idempotency_key).await;
+    admin_lock_response(&state , result)
 }
@@ -0,0 +1,266 @@
+
+
+#[cfg(test)]
+mod tests{
+    use std::{sync::{Arc, atomic::{AtomicUsize, Ordering}}, time::Duration};
+
+    use axum::{Json, Router, extract::{Path, State}, http::StatusCode, response::IntoResponse, routing::{get, post}};
+    use tokio::time::Instant;
+
+    use crate::{api::{models::{AcquireRequest, AcquireResponse, ApiError, BarrierResponse, CancelWaitRequest, CancelWaitResponse, ReleaseRequest, ReleaseResponse, RenewRequest, RenewResponse, StatusResponse}, utils::{change_to_client_id, change_to_lease_id, change_to_lock_id}}, client::{distlock_client::{DistlockClient, LockOptions}, error::ClientError, retry::RetryPolicy}, lock::{barrier::BarrierKind, manager::InMemoryLockManager, types::{AcquireResult, ApplyContext, ClientId, LeaseId, LockId, LockManager, ReleaseResult, RenewResult}}};
+
+    type Manager = Arc<InMemoryLockManager>;
+
+    async fn acquire(State(manager) : State<Manager> , Json(request) : Json<AcquireRequest>) -> Json<AcquireResponse> {
+        let ttl = Duration::from_secs(request.time_to_live);
+        Json(match manager.try_acquire(&change_to_lock_id(&request.lock_id) , &change_to_client_id(&request.client_id) , ttl) {
+            AcquireResult::Granted { lease_id , expires_at , fencing_token } => AcquireResponse::Granted { lease_id: lease_id.0, expires_at: expires_at.to_rfc3339(), fencing_token },
+            AcquireResult::Queued { position , .. } => AcquireResponse::Queued { position, estimated_wait: 0 },
+            AcquireResult::QuotaExceeded(message) => AcquireResponse::Error { error_type: "QuotaExceeded".to_string(), message },
+            AcquireResult::Error(message) => AcquireResponse::Error { error_type: "AcquireError".to_string(), message }
+        })
+    }
+
+    async fn renew(State(manager) : State<Manager> , Json(request) : Json<RenewRequest>) -> Json<RenewResponse> {
+        let ttl = Duration::from_secs(request.time_to_live);
+        let error = |error_type : &str| RenewResponse::Error { error_type: error_type.to_string(), message: String::new() };
+        Json(match manager.renew(&change_to_lock_id(&request.lock_id) , &change_to_client_id(&request.client_id) , &change_to_lease_id(&request.lease_id) , ttl) {
+            RenewResult::Success { new_expiry } => RenewResponse::Success { new_expiry: new_expiry.to_rfc3339() },
+            RenewResult::NotHolder => error("NotHolder"),
+            RenewResult::NotFound => error("NotFound"),
+            RenewResult::Expired => error("Expired"),
+            RenewResult::Error(_) => error("RenewError")
+        })
+    }
+
+    async fn release(State(manager) : State<Manager> , Json(request) : Json<ReleaseRequest>) -> Json<ReleaseResponse> {
+        Json(match manager.release(&change_to_lock_id(&request.lock_id) , &change_to_client_id(&request.client_id) , &change_to_lease_id(&request.lease_id)) {
+            ReleaseResult::Success => ReleaseResponse::Success,
+            other => ReleaseResponse::Error { error_type: format!("{:?}" , other), message: String::new() }
+        })
+    }
+
+    async fn cancel(State(manager) : State<Manager> , Json(request) : Json<CancelWaitRequest>) -> Json<CancelWaitResponse> {
+        let removed = manager.evict_waiter_at(&change_to_lock_id(&request.lock_id) , &change_to_client_id(&request.client_id) , &request.client_id , &ApplyContext::local());
+        Json(CancelWaitResponse::Cancelled { removed })
+    }
+
+    async fn status(State(manager) : State<Manager> , Path(lock_id) : Path<String>) -> Json<StatusResponse> {
+        Json(match manager.status(&change_to_lock_id(&lock_id)) {
+            Some(state) => match state.holder {
+                Some(holder) => StatusResponse::InUse { client_id: holder.client_id.0, expires_at: holder.expires_at.to_rfc3339(), lease_id: holder.lease_id.0, fencing_token: holder.fencing_token, queue_length: state.wait_queue.len(), created_at: state.created_at.to_rfc3339(), metadata: holder.metadata },
+                None => StatusResponse::Free
+            },
+            None => StatusResponse::NotFound
+        })
+    }
+
+    /// A barrier nobody arrives at.
+    async fn barrier(Path(name) : Path<String>) -> Json<BarrierResponse> {
+        Json(BarrierResponse { name, kind: BarrierKind::Barrier, count: 2, remaining: 2, released: false, degraded: false, participants: Vec::new(), arrived: Vec::new(), expired: Vec::new(), created_at: String::new() })
+    }
+
+    /// Serves the lock endpoints straight from a lock manager, without raft.
+    async fn start_server() -> (String , Manager) {
+        let manager = Arc::new(InMemoryLockManager::new());
+        let app = Router::new()
+            .route("/acquire" , post(acquire))
+            .route("/renew" , post(renew))
+            .route("/release" , post(release))
+            .route("/cancel" , post(cancel))
+            .route("/status/:lock_id" , get(status))
+            .route("/barriers/:name" , get(barrier))
+            .with_state(manager.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}" , listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener , app).await.unwrap() });
+        (endpoint , manager)
+    }
+
+    /// A node that is not the leader: every request is refused with a hint
+    /// pointing at `leader`. Returns its URL and a request counter.
+    async fn start_follower(leader : Option<String>) -> (String , Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let app = Router::new().fallback(move || {
+            counter.fetch_add(1 , Ordering::SeqCst);
+            let error = ApiError::unavailable("NotLeader" , "Not the leader").with_leader_hint(leader.clone());
+            async move { (StatusCode::SERVICE_UNAVAILABLE , Json(error)).into_response() }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}" , listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener , app).await.unwrap() });
+        (endpoint , hits)
+    }
+
+    async fn unused_endpoint() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        format!("http://{}" , listener.local_addr().unwrap())
+    }
+
+    fn short_lease() -> LockOptions {
+        LockOptions { ttl_seconds : 1 , poll_interval : Duration::from_millis(20) , ..Default::default() }
+    }
+
+    #[tokio::test]
+
+    async fn test_lock_renews_until_unlocked(){
+        let (endpoint , _) = start_server().await;
+        let client = DistlockClient::new(endpoint.clone());
+        let other = DistlockClient::new(endpoint);
+
+        let guard = client.lock("lock" , short_lease()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(2_500)).await;
+
+        let status = other.status("lock").await.unwrap();
+        assert!(matches!(status , StatusResponse::InUse { ref lease_id , fencing_token , .. } if lease_id == guard.lease_id() && fencing_token == guard.fencing_token()));
+        assert!(!guard.is_lost());
+
+        guard.unlock().await.unwrap();
+        assert!(matches!(other.status("lock").await.unwrap() , StatusResponse::Free));
+    }
+
+    #[tokio::test]
+
+    async fn test_queued_lock_is_granted_on_release(){
+        let (endpoint , _) = start_server().await;
+        let first = DistlockClient::new(endpoint.clone());
+        let second = DistlockClient::new(endpoint);
+
+        let guard = first.lock("lock" , short_lease()).await.unwrap();
+        let waiter = tokio::spawn(async move { second.lock("lock" , short_lease()).await });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!waiter.is_finished());
+
+        guard.unlock().await.unwrap();
+        let promoted = waiter.await.unwrap().unwrap();
+        assert_ne!(promoted.lease_id() , "");
+        promoted.unlock().await.unwrap();
+    }
+
+    #[tokio::test]
+
+    async fn test_lock_times_out_while_queued(){
+        let (endpoint , manager) = start_server().await;
+        let holder = DistlockClient::new(endpoint.clone());
+        let waiter = DistlockClient::new(endpoint);
+
+        let _guard = holder.lock("lock" , short_lease()).await.unwrap();
+        let opts = LockOptions { wait_timeout : Some(Duration::from_millis(100)) , ..short_lease() };
+        let result = waiter.lock("lock" , opts).await;
+        assert!(matches!(result , Err(ClientError::Timeout(ref lock_id)) if lock_id == "lock"));
+        assert_eq!(manager.queue_length(&LockId("lock".to_string())) , 0);
+    }
+
+    #[tokio::test]
+
+    async fn test_holder_expiring_while_queued_leaves_no_stale_waiter(){
+        let (endpoint , manager) = start_server().await;
+        let lock_id = LockId("lock".to_string());
+        // Never renewed, so it expires while the client waits.
+        manager.try_acquire(&lock_id , &ClientId("crashed".to_string()) , Duration::from_secs(1));
+
+        let client = DistlockClient::new(endpoint);
+        let guard = client.lock("lock" , short_lease()).await.unwrap();
+        assert_eq!(manager.queue_length(&lock_id) , 0);
+
+        guard.unlock().await.unwrap();
+        assert!(manager.status(&lock_id).unwrap().holder.is_none());
+    }
+
+    #[tokio::test]
+
+    async fn test_barrier_wait_times_out_with_its_own_error(){
+        let (endpoint , _) = start_server().await;
+        let client = DistlockClient::new(endpoint);
+
+        let result = client.wait_barrier("deploy" , Some(Duration::from_millis(100))).await;
+        assert!(matches!(result , Err(ClientError::BarrierTimeout(ref name)) if name == "deploy"));
+    }
+
+    #[tokio::test]
+
+    async fn test_lost_fires_when_lease_is_taken_away(){
+        let (endpoint , manager) = start_server().await;
+        let client = DistlockClient::new(endpoint);
+
+        let guard = client.lock("lock" , short_lease()).await.unwrap();
+        let lease_id = LeaseId(guard.lease_id().to_string());
+        let released = manager.release(&change_to_lock_id(&"lock".to_string()) , &change_to_client_id(&client.client_id().to_string()) , &lease_id);
+        assert!(matches!(released , ReleaseResult::Success));
+
+        let lost = tokio::time::timeout(Duration::from_secs(2) , guard.lost()).await.unwrap();
+        assert!(matches!(lost , ClientError::LeaseLost { .. }));
+        assert!(guard.is_lost());
+    }
+
+    #[tokio::test]
+
+    async fn test_dropped_guard_releases_lock(){
+        let (endpoint , _) = start_server().await;
+        let client = DistlockClient::new(endpoint);
+
+        let guard = client.lock("lock" , LockOptions::default()).await.unwrap();
+        drop(guard);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        assert!(matches!(client.status("lock").await.unwrap() , StatusResponse::Free));
+    }
+
+    #[tokio::test]
+
+    async fn test_client_follows_leader_hint(){
+        let (leader , _) = start_server().await;
+        let (follower , follower_hits) = start_follower(Some(leader.trim_start_matches("http://").to_string())).await;
+        let client = DistlockClient::with_endpoints(vec![follower] , "client_1");
+
+        let guard = client.lock("lock" , short_lease()).await.unwrap();
+        assert_eq!(client.endpoint() , leader);
+
+        guard.unlock().await.unwrap();
+        assert_eq!(follower_hits.load(Ordering::SeqCst) , 1);
+    }
+
+    #[tokio::test]
+
+    async fn test_client_fails_over_to_next_seed(){
+        let (leader , _) = start_server().await;
+        let (follower , _) = start_follower(None).await;
+        let client = DistlockClient::with_endpoints(vec![unused_endpoint().await , follower , leader.clone()] , "client_1");
+
+        let guard = client.lock("lock" , short_lease()).await.unwrap();
+        assert_eq!(client.endpoint() , leader);
+        guard.unlock().await.unwrap();
+    }
+
+    #[tokio::test]
+
+    async fn test_client_gives_up_at_deadline(){
+        let (follower , hits) = start_follower(None).await;
+        let retry = RetryPolicy { deadline : Duration::from_millis(300) , ..Default::default() };
+        let client = DistlockClient::new(follower).with_retry_policy(retry);
+
+        let started = Instant::now();
+        let result = client.acquire("lock" , 1).await;
+        assert!(matches!(result , Err(ClientError::Unavailable { ref error_type , .. }) if error_type == "NotLeader"));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(hits.load(Ordering::SeqCst) > 1);
+
+        let client = client.with_retry_policy(RetryPolicy::none());
+        let before = hits.load(Ordering::SeqCst);
+        assert!(client.acquire("lock" , 1).await.is_err());
+        assert_eq!(hits.load(Ordering::SeqCst) , before + 1);
+    }
+
+    #[test]
+
+    fn test_backoff_grows_and_is_capped(){
+        let retry = RetryPolicy { initial_backoff : Duration::from_millis(10) , max_backoff : Duration::from_millis(50) , ..Default::default() };
+        assert_eq!(retry.backoff(0) , Duration::from_millis(10));
+        assert_eq!(retry.backoff(2) , Duration::from_millis(40));
+        assert_eq!(retry.backoff(10) , Duration::from_millis(50));
+
+        let mut rng = rand::thread_rng();
+        assert!((0..100).all(|attempt| retry.jittered_backoff(attempt , &mut rng) <= Duration::from_millis(50)));
+    }
+}
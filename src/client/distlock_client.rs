@@ -0,0 +1,649 @@
+use std::{sync::{Arc, Mutex, atomic::{AtomicU64, Ordering}}, time::Duration};
+
+use chrono::{DateTime, Utc};
+use reqwest::StatusCode;
+use serde::{Serialize, de::DeserializeOwned};
+use tokio::time::Instant;
+
+use crate::{api::models::{AclRequest, AclResponse, AcquireRequest, AcquireResponse, AddNodeRequest, AdminLockResponse, AdminLocksQuery, CancelWaitRequest, CancelWaitResponse, AdminLocksResponse, ApiError, BarrierArriveRequest, BarrierCreateRequest, BarrierJoinRequest, BarrierQuery, BarrierResponse, BarrierUpdateResponse, ContendedLocksQuery, ContendedLocksResponse, EvictWaiterRequest, ForceReleaseRequest, HistoryQuery, HistoryResponse, KvDeleteQuery, KvPutRequest, KvWriteResponse, ListLocksResponse, LockDetails, LockSnapshot, LockStatsResponse, LockSummary, MemberInfo, MembersResponse, MembershipResponse, QuotasResponse, ReleaseRequest, ReleaseResponse, RemoveNodeRequest, RenewRequest, RenewResponse, RestoreResponse, RevokeClientRequest, StatusResponse, TransferRequest, TransferResponse, TransferLeaderRequest, TransferLeaderResponse}, auth::acl::AclTable, client::{error::ClientError, guard::LockGuard, retry::RetryPolicy}, lock::{barrier::BarrierKind, kv::KvEntry, metadata::Metadata, quota::LockQuotas}};
+
+
+#[derive(Debug , Clone)]
+pub struct LockOptions{
+    /// Lease TTL requested from the server. Must be above 0: the guard
+    /// schedules renewals from it.
+    pub ttl_seconds : u64,
+    /// Share of the remaining lease time to wait before each renewal.
+    pub renew_fraction : f64,
+    /// How long `lock` waits for a queued request to be granted, forever
+    /// when `None`.
+    pub wait_timeout : Option<Duration>,
+    /// Interval between status checks while queued.
+    pub poll_interval : Duration,
+    /// Attached to the lease, see `acquire_with_metadata`.
+    pub metadata : Metadata
+}
+
+impl Default for LockOptions{
+    fn default() -> Self {
+        Self { ttl_seconds: 30, renew_fraction: 1.0 / 3.0, wait_timeout: None, poll_interval: Duration::from_millis(200), metadata: Metadata::new() }
+    }
+}
+
+/// PEM material for clusters that serve TLS.
+#[derive(Debug , Clone , Default)]
+pub struct TlsOptions{
+    /// CA that signed the nodes' certificates. The system roots are used
+    /// when unset.
+    pub ca_pem : Option<Vec<u8>>,
+    /// Client certificate chain followed by its private key, for nodes that
+    /// require client certificates.
+    pub identity_pem : Option<Vec<u8>>
+}
+
+/// Handle to a distlock cluster. Cloning is cheap and clones share the
+/// client id, the connection pool and what was learned about the leader.
+#[derive(Clone)]
+pub struct DistlockClient{
+    inner : Arc<ClientInner>,
+    retry : RetryPolicy,
+    token : Option<Arc<str>>
+}
+
+struct ClientInner{
+    http : reqwest::Client,
+    endpoints : Mutex<Endpoints>,
+    client_id : String,
+    // Sequence numbers for idempotency keys. Seeded from the clock so a
+    // restarted process does not reuse the keys of its previous run.
+    next_seq : AtomicU64
+}
+
+/// Known nodes and the one requests currently go to, which is the leader
+/// as far as the client knows.
+struct Endpoints{
+    urls : Vec<String>,
+    current : usize
+}
+
+impl Endpoints{
+    fn current(&self) -> (usize , String) {
+        (self.current , self.urls[self.current].clone())
+    }
+
+    /// Moves away from a node that failed. Follows `leader_hint` when there
+    /// is one, learning the address if it was not a seed. Returns whether
+    /// the hint was followed.
+    fn on_failure(&mut self , failed : usize , leader_hint : Option<&str>) -> bool {
+        if let Some(hint) = leader_hint {
+            let scheme = self.urls[failed].split_once("://").map_or("http" , |(scheme , _)| scheme).to_string();
+            let index = match self.urls.iter().position(|url| authority(url) == hint) {
+                Some(index) => index,
+                None => {
+                    self.urls.push(format!("{}://{}" , scheme , hint));
+                    self.urls.len() - 1
+                }
+            };
+            if index != failed {
+                self.current = index;
+                return true
+            }
+        }
+        // Another request may already have moved on.
+        if self.current == failed {
+            self.current = (failed + 1) % self.urls.len();
+        }
+        false
+    }
+}
+
+fn authority(url : &str) -> &str {
+    url.split_once("://").map_or(url , |(_ , rest)| rest)
+}
+
+impl DistlockClient{
+    /// Client with a random client id. `endpoint` is the base URL of a
+    /// node, e.g. `http://127.0.0.1:3000`.
+    pub fn new(endpoint : impl Into<String>) -> Self {
+        Self::with_client_id(endpoint , uuid::Uuid::new_v4().to_string())
+    }
+
+    /// The client id must not be shared with another live client: the
+    /// server deduplicates requests per client id.
+    pub fn with_client_id(endpoint : impl Into<String> , client_id : impl Into<String>) -> Self {
+        Self::with_endpoints(vec![endpoint.into()] , client_id)
+    }
+
+    /// Client for a cluster. `endpoints` are seeds: any reachable node is
+    /// enough to find the leader.
+    pub fn with_endpoints(endpoints : Vec<String> , client_id : impl Into<String>) -> Self {
+        assert!(!endpoints.is_empty() , "DistlockClient needs at least one endpoint");
+        let urls = endpoints.into_iter().map(|endpoint| endpoint.trim_end_matches('/').to_string()).collect();
+        Self {
+            inner : Arc::new(ClientInner {
+                http : reqwest::Client::new(),
+                endpoints : Mutex::new(Endpoints { urls, current: 0 }),
+                client_id : client_id.into(),
+                next_seq : AtomicU64::new(Utc::now().timestamp_micros().max(0) as u64)
+            }),
+            retry : RetryPolicy::default(),
+            token : None
+        }
+    }
+
+    /// Trusts the CA and presents the identity in `tls`. Endpoints must then
+    /// be `https://` URLs.
+    pub fn with_tls(self , tls : &TlsOptions) -> Result<Self , String> {
+        let mut builder = reqwest::Client::builder().use_rustls_tls();
+        if let Some(ca_pem) = &tls.ca_pem {
+            builder = builder.add_root_certificate(reqwest::Certificate::from_pem(ca_pem).map_err(|e| format!("Invalid CA certificate : {}" , e))?);
+        }
+        if let Some(identity_pem) = &tls.identity_pem {
+            builder = builder.identity(reqwest::Identity::from_pem(identity_pem).map_err(|e| format!("Invalid client certificate or key : {}" , e))?);
+        }
+        let http = builder.build().map_err(|e| e.to_string())?;
+
+        let urls = self.inner.endpoints.lock().unwrap().urls.clone();
+        let inner = ClientInner {
+            http,
+            endpoints : Mutex::new(Endpoints { urls, current: 0 }),
+            client_id : self.inner.client_id.clone(),
+            next_seq : AtomicU64::new(self.inner.next_seq.load(Ordering::Relaxed))
+        };
+        Ok(Self { inner: Arc::new(inner), ..self })
+    }
+
+    pub fn with_retry_policy(mut self , retry : RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Bearer token sent with every request, for servers with auth enabled.
+    /// The client id must then be the token's principal or start with
+    /// `<principal>/`.
+    pub fn with_token(mut self , token : impl Into<String>) -> Self {
+        self.token = Some(Arc::from(token.into()));
+        self
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.inner.client_id
+    }
+
+    /// Endpoint requests are currently sent to.
+    pub fn endpoint(&self) -> String {
+        self.inner.endpoints.lock().unwrap().current().1
+    }
+
+    /// Acquires `lock_id`, waiting in the queue if it is held, and starts
+    /// renewing the lease in the background.
+    pub async fn lock(&self , lock_id : &str , opts : LockOptions) -> Result<LockGuard , ClientError> {
+        if opts.ttl_seconds == 0 {
+            return Err(ClientError::Rejected { error_type: "InvalidTtl".to_string(), message: "ttl_seconds must be greater than 0".to_string() })
+        }
+        let deadline = opts.wait_timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let sent_at = Instant::now();
+            match self.acquire_with_metadata(lock_id , opts.ttl_seconds , opts.metadata.clone()).await? {
+                AcquireResponse::Granted { lease_id , fencing_token , .. } => {
+                    let valid_until = sent_at + Duration::from_secs(opts.ttl_seconds);
+                    return Ok(LockGuard::start(self.clone() , lock_id.to_string() , lease_id , fencing_token , valid_until , opts))
+                }
+                AcquireResponse::Queued { .. } => {
+                    let granted = match self.wait_for_grant(lock_id , deadline , &opts).await {
+                        Ok(Some(granted)) => Some(granted),
+                        // Asking again while still queued would leave an entry
+                        // behind that gets the lock after we release it.
+                        Ok(None) => self.leave_queue(lock_id).await?,
+                        Err(e) => {
+                            if let Ok(Some((lease_id , _ , _))) = self.leave_queue(lock_id).await {
+                                let _ = self.release(lock_id , &lease_id).await;
+                            }
+                            return Err(e)
+                        }
+                    };
+                    if let Some((lease_id , expires_at , fencing_token)) = granted {
+                        let remaining = (expires_at - Utc::now()).to_std().unwrap_or_default();
+                        return Ok(LockGuard::start(self.clone() , lock_id.to_string() , lease_id , fencing_token , Instant::now() + remaining , opts))
+                    }
+                }
+                AcquireResponse::Error { error_type , message } => return Err(ClientError::Rejected { error_type, message })
+            }
+        }
+    }
+
+    /// Leaves the queue of `lock_id`. Returns the lease if the lock was
+    /// handed to this client before it left.
+    async fn leave_queue(&self , lock_id : &str) -> Result<Option<(String , DateTime<Utc> , u64)> , ClientError> {
+        self.cancel_wait(lock_id).await?;
+        match self.status(lock_id).await? {
+            StatusResponse::InUse { client_id , lease_id , expires_at , fencing_token , .. } if client_id == self.inner.client_id => Ok(Some((lease_id , parse_expiry(&expires_at)? , fencing_token))),
+            _ => Ok(None)
+        }
+    }
+
+    /// Polls the lock until this client is promoted from the queue. Returns
+    /// `None` if the lock became free without being handed over, which
+    /// happens when the holder's lease expires instead of being released.
+    async fn wait_for_grant(&self , lock_id : &str , deadline : Option<Instant> , opts : &LockOptions) -> Result<Option<(String , DateTime<Utc> , u64)> , ClientError> {
+        loop {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(ClientError::Timeout(lock_id.to_string()))
+            }
+            tokio::time::sleep(opts.poll_interval).await;
+            match self.status(lock_id).await? {
+                StatusResponse::InUse { client_id , lease_id , expires_at , fencing_token , .. } if client_id == self.inner.client_id => {
+                    return Ok(Some((lease_id , parse_expiry(&expires_at)? , fencing_token)))
+                }
+                StatusResponse::InUse { expires_at , .. } if is_expired(&expires_at) => return Ok(None),
+                StatusResponse::InUse { .. } => {}
+                StatusResponse::Free | StatusResponse::NotFound => return Ok(None)
+            }
+        }
+    }
+
+    pub async fn acquire(&self , lock_id : &str , ttl_seconds : u64) -> Result<AcquireResponse , ClientError> {
+        self.acquire_with_metadata(lock_id , ttl_seconds , Metadata::new()).await
+    }
+
+    /// Acquires with `metadata` attached to the lease, e.g. the host and
+    /// PID of the holder. It shows in the lock's status and history.
+    pub async fn acquire_with_metadata(&self , lock_id : &str , ttl_seconds : u64 , metadata : Metadata) -> Result<AcquireResponse , ClientError> {
+        let request = AcquireRequest { lock_id: lock_id.to_string(), client_id: self.inner.client_id.clone(), time_to_live: ttl_seconds, seq: Some(self.next_seq()), metadata };
+        match self.post("/acquire" , &request , self.deadline()).await? {
+            AcquireResponse::Error { error_type , message } => Err(ClientError::Rejected { error_type, message }),
+            response => Ok(response)
+        }
+    }
+
+    /// Returns the new expiry time reported by the server.
+    pub async fn renew(&self , lock_id : &str , lease_id : &str , ttl_seconds : u64) -> Result<String , ClientError> {
+        self.renew_within(lock_id , lease_id , ttl_seconds , None , self.deadline()).await
+    }
+
+    /// Renews and replaces the lease's metadata in the same request.
+    pub async fn renew_with_metadata(&self , lock_id : &str , lease_id : &str , ttl_seconds : u64 , metadata : Metadata) -> Result<String , ClientError> {
+        self.renew_within(lock_id , lease_id , ttl_seconds , Some(metadata) , self.deadline()).await
+    }
+
+    /// Renews, retrying no later than `deadline` instead of the policy's.
+    pub(crate) async fn renew_within(&self , lock_id : &str , lease_id : &str , ttl_seconds : u64 , metadata : Option<Metadata> , deadline : Instant) -> Result<String , ClientError> {
+        let request = RenewRequest { lock_id: lock_id.to_string(), client_id: self.inner.client_id.clone(), lease_id: lease_id.to_string(), time_to_live: ttl_seconds, seq: Some(self.next_seq()), metadata };
+        match self.post("/renew" , &request , deadline).await? {
+            RenewResponse::Success { new_expiry } => Ok(new_expiry),
+            RenewResponse::Error { error_type , message } => Err(ClientError::Rejected { error_type, message })
+        }
+    }
+
+    /// Hands a lock this client holds to `target` and returns the target's
+    /// lease id and fencing token, which the caller must pass on. 0 as
+    /// `ttl_seconds` uses the server's default.
+    pub async fn transfer(&self , lock_id : &str , lease_id : &str , target : &str , ttl_seconds : u64) -> Result<(String , u64) , ClientError> {
+        let request = TransferRequest { lock_id: lock_id.to_string(), client_id: self.inner.client_id.clone(), lease_id: lease_id.to_string(), target_client_id: target.to_string(), time_to_live: ttl_seconds, seq: Some(self.next_seq()) };
+        match self.post("/transfer" , &request , self.deadline()).await? {
+            TransferResponse::Transferred { lease_id , fencing_token , .. } => Ok((lease_id , fencing_token)),
+            TransferResponse::Error { error_type , message } => Err(ClientError::Rejected { error_type, message })
+        }
+    }
+
+    pub async fn release(&self , lock_id : &str , lease_id : &str) -> Result<() , ClientError> {
+        let request = ReleaseRequest { lock_id: lock_id.to_string(), lease_id: lease_id.to_string(), client_id: self.inner.client_id.clone(), seq: Some(self.next_seq()) };
+        match self.post("/release" , &request , self.deadline()).await? {
+            ReleaseResponse::Success => Ok(()),
+            ReleaseResponse::Error { error_type , message } => Err(ClientError::Rejected { error_type, message })
+        }
+    }
+
+    /// Leaves the wait queue of `lock_id`. Returns the number of entries
+    /// removed, 0 if this client was not waiting.
+    pub async fn cancel_wait(&self , lock_id : &str) -> Result<usize , ClientError> {
+        let request = CancelWaitRequest { lock_id: lock_id.to_string(), client_id: self.inner.client_id.clone() };
+        match self.post("/cancel" , &request , self.deadline()).await? {
+            CancelWaitResponse::Cancelled { removed } => Ok(removed),
+            CancelWaitResponse::Error { error_type , message } => Err(ClientError::Rejected { error_type, message })
+        }
+    }
+
+    pub async fn status(&self , lock_id : &str) -> Result<StatusResponse , ClientError> {
+        self.get(&format!("/status/{}" , lock_id)).await
+    }
+
+    pub async fn list(&self) -> Result<Vec<LockSummary> , ClientError> {
+        let response : ListLocksResponse = self.get("/locks").await?;
+        Ok(response.locks)
+    }
+
+    /// Returns the leader id and every member of the cluster.
+    pub async fn members(&self) -> Result<(u64 , Vec<MemberInfo>) , ClientError> {
+        match self.get("/cluster/members").await? {
+            MembersResponse::Members { leader_id , members , .. } => Ok((leader_id , members)),
+            MembersResponse::Error { error_type , message } => Err(ClientError::Rejected { error_type, message })
+        }
+    }
+
+    /// Returns the voters once the change is applied.
+    pub async fn add_node(&self , id : u64 , addr : &str , client_addr : Option<&str>) -> Result<Vec<u64> , ClientError> {
+        let request = AddNodeRequest { id, addr: addr.to_string(), client_addr: client_addr.map(str::to_string) };
+        membership(self.post("/admin/add-node" , &request , self.deadline()).await?)
+    }
+
+    pub async fn remove_node(&self , id : u64) -> Result<Vec<u64> , ClientError> {
+        membership(self.post("/admin/remove-node" , &RemoveNodeRequest { id } , self.deadline()).await?)
+    }
+
+    /// Returns the old and the new leader.
+    pub async fn transfer_leader(&self , target : Option<u64> , timeout_ms : Option<u64>) -> Result<(u64 , u64) , ClientError> {
+        let request = TransferLeaderRequest { target, timeout_ms };
+        match self.post("/admin/transfer-leader" , &request , self.deadline()).await? {
+            TransferLeaderResponse::Transferred { from , to , .. } => Ok((from , to)),
+            TransferLeaderResponse::Error { error_type , message } => Err(ClientError::Rejected { error_type, message })
+        }
+    }
+
+    pub async fn save_snapshot(&self) -> Result<LockSnapshot , ClientError> {
+        self.get("/admin/snapshot").await
+    }
+
+    /// Returns the number of locks restored.
+    pub async fn restore_snapshot(&self , snapshot : &LockSnapshot) -> Result<usize , ClientError> {
+        match self.post("/admin/snapshot" , snapshot , self.deadline()).await? {
+            RestoreResponse::Restored { locks } => Ok(locks),
+            RestoreResponse::Error { error_type , message } => Err(ClientError::Rejected { error_type, message })
+        }
+    }
+
+    pub async fn acl(&self) -> Result<AclTable , ClientError> {
+        self.get("/admin/acl").await
+    }
+
+    /// Replaces the cluster's principals and ACL rules. Returns how many of
+    /// each the new table has.
+    pub async fn set_acl(&self , acl : &AclRequest) -> Result<(usize , usize) , ClientError> {
+        let response = self.call(|http , endpoint| http.put(format!("{}/admin/acl" , endpoint)).json(acl) , self.deadline()).await?;
+        match response {
+            AclResponse::Updated { principals , rules } => Ok((principals , rules)),
+            AclResponse::Error { error_type , message } => Err(ClientError::Rejected { error_type, message })
+        }
+    }
+
+    pub async fn quotas(&self) -> Result<LockQuotas , ClientError> {
+        self.get("/admin/quotas").await
+    }
+
+    /// Replaces the limits on the cluster's lock table. Returns them as
+    /// applied.
+    pub async fn set_quotas(&self , quotas : &LockQuotas) -> Result<LockQuotas , ClientError> {
+        let response = self.call(|http , endpoint| http.put(format!("{}/admin/quotas" , endpoint)).json(quotas) , self.deadline()).await?;
+        match response {
+            QuotasResponse::Updated { quotas } => Ok(quotas),
+            QuotasResponse::Error { error_type , message } => Err(ClientError::Rejected { error_type, message })
+        }
+    }
+
+    /// One page of locks with holders and waiters. Pass the page's
+    /// `next_after` as `query.after` for the next one.
+    pub async fn admin_locks(&self , query : &AdminLocksQuery) -> Result<AdminLocksResponse , ClientError> {
+        self.call(|http , endpoint| http.get(format!("{}/admin/locks" , endpoint)).query(query) , self.deadline()).await
+    }
+
+    pub async fn lock_stats(&self , lock_id : &str) -> Result<LockStatsResponse , ClientError> {
+        self.get(&format!("/locks/{}/stats" , lock_id)).await
+    }
+
+    pub async fn contended_locks(&self , query : &ContendedLocksQuery) -> Result<Vec<LockStatsResponse> , ClientError> {
+        let response : ContendedLocksResponse = self.call(|http , endpoint| http.get(format!("{}/locks/contended" , endpoint)).query(query) , self.deadline()).await?;
+        Ok(response.locks)
+    }
+
+    /// Ownership history of one lock or of every lock, oldest first.
+    pub async fn history(&self , query : &HistoryQuery) -> Result<HistoryResponse , ClientError> {
+        self.call(|http , endpoint| http.get(format!("{}/history" , endpoint)).query(query) , self.deadline()).await
+    }
+
+    pub async fn lock_details(&self , lock_id : &str) -> Result<LockDetails , ClientError> {
+        self.get(&format!("/admin/locks/{}" , lock_id)).await
+    }
+
+    /// Returns the previous holder and the waiter promoted in its place.
+    /// With `lease_id`, the lock is only taken from a holder of that lease.
+    pub async fn force_release(&self , lock_id : &str , lease_id : Option<&str>) -> Result<(String , Option<String>) , ClientError> {
+        let request = ForceReleaseRequest { lock_id: lock_id.to_string(), lease_id: lease_id.map(str::to_string), requester: self.inner.client_id.clone(), seq: Some(self.next_seq()) };
+        match admin_lock(self.post("/admin/force-release" , &request , self.deadline()).await?)? {
+            AdminLockResponse::ForceReleased { previous_holder , promoted } => Ok((previous_holder , promoted)),
+            other => Err(unexpected(other))
+        }
+    }
+
+    /// Returns how many queue entries the client had.
+    pub async fn evict_waiter(&self , lock_id : &str , client_id : &str) -> Result<usize , ClientError> {
+        let request = EvictWaiterRequest { lock_id: lock_id.to_string(), client_id: client_id.to_string(), requester: self.inner.client_id.clone(), seq: Some(self.next_seq()) };
+        match admin_lock(self.post("/admin/evict-waiter" , &request , self.deadline()).await?)? {
+            AdminLockResponse::WaiterEvicted { removed } => Ok(removed),
+            other => Err(unexpected(other))
+        }
+    }
+
+    /// Returns the number of locks released and of queue entries removed.
+    pub async fn revoke_client(&self , client_id : &str) -> Result<(usize , usize) , ClientError> {
+        let request = RevokeClientRequest { client_id: client_id.to_string(), requester: self.inner.client_id.clone(), seq: Some(self.next_seq()) };
+        match admin_lock(self.post("/admin/revoke-client" , &request , self.deadline()).await?)? {
+            AdminLockResponse::ClientRevoked { released , evicted } => Ok((released , evicted)),
+            other => Err(unexpected(other))
+        }
+    }
+
+    /// `None` when the key does not exist.
+    pub async fn kv_get(&self , key : &str) -> Result<Option<KvEntry> , ClientError> {
+        match self.get(&format!("/kv/{}" , key)).await {
+            Err(ClientError::Rejected { error_type , .. }) if error_type == "NotFound" => Ok(None),
+            result => result.map(Some)
+        }
+    }
+
+    /// Writes `key` and returns its new version. With `expected_version`,
+    /// 0 meaning absent, the write fails with `VersionMismatch` if the key
+    /// is at another version. With `lease`, a lock id and a lease of this
+    /// client, the key is deleted once that lease ends.
+    pub async fn kv_put(&self , key : &str , value : &str , expected_version : Option<u64> , lease : Option<(&str , &str)>) -> Result<u64 , ClientError> {
+        let request = KvPutRequest {
+            client_id: self.inner.client_id.clone(),
+            value: value.to_string(),
+            expected_version,
+            lock_id: lease.map(|(lock_id , _)| lock_id.to_string()),
+            lease_id: lease.map(|(_ , lease_id)| lease_id.to_string()),
+            seq: Some(self.next_seq())
+        };
+        let response : KvWriteResponse = self.call(|http , endpoint| http.put(format!("{}/kv/{}" , endpoint , key)).json(&request) , self.deadline()).await?;
+        Ok(response.version)
+    }
+
+    /// Returns the version of the deleted entry.
+    pub async fn kv_delete(&self , key : &str , expected_version : Option<u64>) -> Result<u64 , ClientError> {
+        let query = KvDeleteQuery { client_id: self.inner.client_id.clone(), expected_version, seq: Some(self.next_seq()) };
+        let response : KvWriteResponse = self.call(|http , endpoint| http.delete(format!("{}/kv/{}" , endpoint , key)).query(&query) , self.deadline()).await?;
+        Ok(response.version)
+    }
+
+    /// Creates a barrier or latch and returns the arrivals it still needs.
+    /// Creating it again with the same kind and count is not an error, so
+    /// every participant may do it.
+    pub async fn create_barrier(&self , name : &str , kind : BarrierKind , count : u32) -> Result<u32 , ClientError> {
+        let request = BarrierCreateRequest { name: name.to_string(), kind, count };
+        let response : BarrierUpdateResponse = self.post("/barriers" , &request , self.deadline()).await?;
+        Ok(response.remaining)
+    }
+
+    /// Reserves one of the arrivals for this client. If the lease of
+    /// `ttl_seconds` runs out before it arrives, the arrival is open to
+    /// others and the barrier shows as degraded. Joining again renews the
+    /// lease, or takes an open arrival after it ran out.
+    pub async fn join_barrier(&self , name : &str , ttl_seconds : u64) -> Result<u32 , ClientError> {
+        let request = BarrierJoinRequest { client_id: self.inner.client_id.clone(), time_to_live: ttl_seconds, seq: Some(self.next_seq()) };
+        let response : BarrierUpdateResponse = self.post(&format!("/barriers/{}/join" , name) , &request , self.deadline()).await?;
+        Ok(response.remaining)
+    }
+
+    /// Arrives at a barrier, or counts a latch down once.
+    pub async fn arrive(&self , name : &str) -> Result<u32 , ClientError> {
+        let request = BarrierArriveRequest { client_id: self.inner.client_id.clone(), seq: Some(self.next_seq()) };
+        let response : BarrierUpdateResponse = self.post(&format!("/barriers/{}/arrive" , name) , &request , self.deadline()).await?;
+        Ok(response.remaining)
+    }
+
+    pub async fn barrier(&self , name : &str) -> Result<BarrierResponse , ClientError> {
+        self.get(&format!("/barriers/{}" , name)).await
+    }
+
+    /// Waits until the barrier is released, forever without a `timeout`.
+    /// The server holds each request until the release or for a while,
+    /// then the client asks again.
+    pub async fn wait_barrier(&self , name : &str , timeout : Option<Duration>) -> Result<BarrierResponse , ClientError> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        // Each wait ends before the attempt times out.
+        let longest = self.retry.attempt_timeout.saturating_sub(Duration::from_millis(500));
+        loop {
+            let wait = deadline.map_or(longest , |deadline| deadline.saturating_duration_since(Instant::now()).min(longest));
+            let query = BarrierQuery { wait_ms: Some(wait.as_millis() as u64) };
+            let response : BarrierResponse = self.call(|http , endpoint| http.get(format!("{}/barriers/{}" , endpoint , name)).query(&query) , self.deadline()).await?;
+            if response.released {
+                return Ok(response)
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(ClientError::BarrierTimeout(name.to_string()))
+            }
+        }
+    }
+
+    /// Returns the arrivals the barrier still needed.
+    pub async fn delete_barrier(&self , name : &str) -> Result<u32 , ClientError> {
+        let response : BarrierUpdateResponse = self.call(|http , endpoint| http.delete(format!("{}/barriers/{}" , endpoint , name)) , self.deadline()).await?;
+        Ok(response.remaining)
+    }
+
+    async fn get<Resp : DeserializeOwned>(&self , path : &str) -> Result<Resp , ClientError> {
+        self.call(|http , endpoint| http.get(format!("{}{}" , endpoint , path)) , self.deadline()).await
+    }
+
+    fn next_seq(&self) -> u64 {
+        self.inner.next_seq.fetch_add(1 , Ordering::Relaxed)
+    }
+
+    fn deadline(&self) -> Instant {
+        Instant::now() + self.retry.deadline
+    }
+
+    /// Retries keep the request body, idempotency key included, so a write
+    /// that reached the log before its response was lost is applied once.
+    async fn post<Req : Serialize , Resp : DeserializeOwned>(&self , path : &str , request : &Req , deadline : Instant) -> Result<Resp , ClientError> {
+        self.call(|http , endpoint| http.post(format!("{}{}" , endpoint , path)).json(request) , deadline).await
+    }
+
+    /// Sends the request to the current endpoint, switching endpoints and
+    /// backing off on transient failures until `deadline`.
+    async fn call<Resp : DeserializeOwned>(&self , request : impl Fn(&reqwest::Client , &str) -> reqwest::RequestBuilder , deadline : Instant) -> Result<Resp , ClientError> {
+        let mut attempt = 0;
+        let mut followed_hint = false;
+        loop {
+            let (index , endpoint) = self.inner.endpoints.lock().unwrap().current();
+            let mut builder = request(&self.inner.http , &endpoint).timeout(self.retry.attempt_timeout);
+            if let Some(token) = &self.token {
+                builder = builder.bearer_auth(token);
+            }
+            let result = match builder.send().await {
+                Ok(response) => decode(response).await,
+                Err(e) => Err(ClientError::Transport(e.to_string()))
+            };
+            let error = match result {
+                Err(error) if error.is_retryable() => error,
+                result => return result
+            };
+
+            let leader_hint = match &error {
+                ClientError::Unavailable { leader_hint , .. } => leader_hint.as_deref(),
+                _ => None
+            };
+            let followed = self.inner.endpoints.lock().unwrap().on_failure(index , leader_hint);
+            // A fresh hint is worth trying at once. Hints that keep moving
+            // mean an election is under way, so those back off too.
+            let delay = if followed && !followed_hint {
+                Duration::ZERO
+            } else {
+                let delay = self.retry.jittered_backoff(attempt , &mut rand::thread_rng());
+                attempt += 1;
+                delay
+            };
+            followed_hint = followed;
+            if Instant::now() + delay >= deadline {
+                return Err(error)
+            }
+            tracing::debug!("Request to {} failed, retrying in {:?} : {}" , endpoint , delay , error);
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+fn membership(response : MembershipResponse) -> Result<Vec<u64> , ClientError> {
+    match response {
+        MembershipResponse::Changed { voters } => Ok(voters),
+        MembershipResponse::Error { error_type , message } => Err(ClientError::Rejected { error_type, message })
+    }
+}
+
+fn admin_lock(response : AdminLockResponse) -> Result<AdminLockResponse , ClientError> {
+    match response {
+        AdminLockResponse::Error { error_type , message } => Err(ClientError::Rejected { error_type, message }),
+        response => Ok(response)
+    }
+}
+
+/// A success of the wrong kind, which only a mismatched server sends.
+fn unexpected(response : AdminLockResponse) -> ClientError {
+    ClientError::Transport(format!("Unexpected response {:?}" , response))
+}
+
+/// Successful responses carry the endpoint's response type, failures an
+/// `ApiError` or the endpoint's own `Error` variant. 503 marks the errors
+/// worth retrying.
+async fn decode<Resp : DeserializeOwned>(response : reqwest::Response) -> Result<Resp , ClientError> {
+    let status = response.status();
+    let body = response.text().await.map_err(|e| ClientError::Transport(e.to_string()))?;
+    if status.is_success() {
+        return serde_json::from_str(&body)
+            .map_err(|e| ClientError::Transport(format!("Invalid response body '{}' : {}" , body , e)))
+    }
+    let (error_type , message , leader_hint) = match serde_json::from_str::<ApiError>(&body) {
+        Ok(error) => (error.error , error.message , error.leader_hint),
+        Err(_) => match serde_json::from_str::<VariantError>(&body) {
+            Ok(VariantError { error }) => (error.error_type , error.message , None),
+            Err(_) => (status.to_string() , body , None)
+        }
+    };
+    if status == StatusCode::SERVICE_UNAVAILABLE {
+        Err(ClientError::Unavailable { error_type, message, leader_hint })
+    } else {
+        Err(ClientError::Rejected { error_type, message })
+    }
+}
+
+/// The `Error { error_type , message }` variant shared by the response enums.
+#[derive(serde::Deserialize)]
+struct VariantError{
+    #[serde(rename = "Error")]
+    error : VariantErrorFields
+}
+
+#[derive(serde::Deserialize)]
+struct VariantErrorFields{
+    error_type : String,
+    message : String
+}
+
+fn is_expired(expires_at : &str) -> bool {
+    DateTime::parse_from_rfc3339(expires_at).is_ok_and(|expires_at| expires_at < Utc::now())
+}
+
+fn parse_expiry(expires_at : &str) -> Result<DateTime<Utc> , ClientError> {
+    DateTime::parse_from_rfc3339(expires_at)
+        .map(|expires_at| expires_at.with_timezone(&Utc))
+        .map_err(|e| ClientError::Transport(format!("Invalid expires_at '{}' : {}" , expires_at , e)))
+}
@@ -0,0 +1,30 @@
+use thiserror::Error;
+
+
+/// Errors returned by `DistlockClient` and `LockGuard`.
+#[derive(Debug , Clone , PartialEq , Error)]
+pub enum ClientError{
+    /// The server refused the request, e.g. `NotHolder` or `InvalidTtl`.
+    #[error("{error_type}: {message}")]
+    Rejected { error_type : String , message : String },
+    /// Transient failure: the same request may succeed later or on another
+    /// node, e.g. `NotLeader` or `Draining`. `leader_hint` is the client
+    /// address of the leader if the node knew it.
+    #[error("{error_type}: {message}")]
+    Unavailable { error_type : String , message : String , leader_hint : Option<String> },
+    /// The request or its response was lost on the way.
+    #[error("transport error: {0}")]
+    Transport(String),
+    #[error("timed out waiting for lock {0}")]
+    Timeout(String),
+    #[error("timed out waiting for barrier {0}")]
+    BarrierTimeout(String),
+    #[error("lease on {lock_id} was lost: {reason}")]
+    LeaseLost { lock_id : String , reason : String }
+}
+
+impl ClientError{
+    pub fn is_retryable(&self) -> bool {
+        matches!(self , ClientError::Unavailable { .. } | ClientError::Transport(_))
+    }
+}
@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use tokio::{sync::watch, task::JoinHandle, time::Instant};
+
+use crate::client::{distlock_client::{DistlockClient, LockOptions}, error::ClientError};
+
+
+/// A held lock. A background task renews the lease until the guard is
+/// unlocked or dropped; dropping it releases the lock in the background.
+pub struct LockGuard{
+    client : DistlockClient,
+    lock_id : String,
+    lease_id : String,
+    fencing_token : u64,
+    renewer : JoinHandle<()>,
+    lost : watch::Receiver<Option<ClientError>>,
+    released : bool
+}
+
+impl LockGuard{
+    pub(crate) fn start(client : DistlockClient , lock_id : String , lease_id : String , fencing_token : u64 , valid_until : Instant , opts : LockOptions) -> Self {
+        let (lost_tx , lost) = watch::channel(None);
+        let renewer = tokio::spawn(renew_loop(client.clone() , lock_id.clone() , lease_id.clone() , valid_until , opts , lost_tx));
+        Self { client, lock_id, lease_id, fencing_token, renewer, lost, released: false }
+    }
+
+    pub fn lock_id(&self) -> &str {
+        &self.lock_id
+    }
+
+    pub fn lease_id(&self) -> &str {
+        &self.lease_id
+    }
+
+    /// Token of this grant. Pass it to the guarded resource so it can
+    /// reject writes from a holder whose lease has since been taken over.
+    pub fn fencing_token(&self) -> u64 {
+        self.fencing_token
+    }
+
+    pub fn is_lost(&self) -> bool {
+        self.lost.borrow().is_some()
+    }
+
+    /// Resolves once the lease can no longer be trusted: a renewal was
+    /// refused, or none succeeded before the lease ran out. Work guarded by
+    /// the lock should stop when this fires.
+    pub fn lost(&self) -> impl Future<Output = ClientError> + Send + 'static {
+        let mut lost = self.lost.clone();
+        let lock_id = self.lock_id.clone();
+        async move {
+            match lost.wait_for(|error| error.is_some()).await {
+                Ok(error) => error.clone().unwrap(),
+                Err(_) => ClientError::LeaseLost { lock_id, reason: "renewal stopped".to_string() }
+            }
+        }
+    }
+
+    /// Stops renewing and releases the lock.
+    pub async fn unlock(mut self) -> Result<() , ClientError> {
+        self.renewer.abort();
+        self.released = true;
+        self.client.release(&self.lock_id , &self.lease_id).await
+    }
+}
+
+impl Drop for LockGuard{
+    fn drop(&mut self) {
+        if self.released {
+            return
+        }
+        self.renewer.abort();
+        // Without a runtime the lease is left to expire on the server.
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let (client , lock_id , lease_id) = (self.client.clone() , self.lock_id.clone() , self.lease_id.clone());
+            runtime.spawn(async move {
+                if let Err(e) = client.release(&lock_id , &lease_id).await {
+                    tracing::warn!("Failed to release {} on drop : {}" , lock_id , e);
+                }
+            });
+        }
+    }
+}
+
+/// Renews once `renew_fraction` of the remaining lease time has passed.
+/// Transient failures are retried, across nodes, until the lease runs out
+/// locally.
+async fn renew_loop(client : DistlockClient , lock_id : String , lease_id : String , mut valid_until : Instant , opts : LockOptions , lost : watch::Sender<Option<ClientError>>) {
+    let ttl = Duration::from_secs(opts.ttl_seconds);
+    loop {
+        let remaining = valid_until.saturating_duration_since(Instant::now());
+        tokio::time::sleep(remaining.mul_f64(opts.renew_fraction)).await;
+
+        let sent_at = Instant::now();
+        let renewal = tokio::time::timeout_at(valid_until , client.renew_within(&lock_id , &lease_id , opts.ttl_seconds , None , valid_until)).await;
+        let error = match renewal {
+            Ok(Ok(_)) => {
+                valid_until = sent_at + ttl;
+                continue
+            }
+            Ok(Err(e)) if e.is_retryable() => ClientError::LeaseLost { lock_id: lock_id.clone(), reason: format!("lease expired while renewal kept failing : {}" , e) },
+            Ok(Err(e)) => ClientError::LeaseLost { lock_id: lock_id.clone(), reason: e.to_string() },
+            Err(_) => ClientError::LeaseLost { lock_id: lock_id.clone(), reason: "lease expired before a renewal completed".to_string() }
+        };
+        tracing::warn!("{}" , error);
+        let _ = lost.send(Some(error));
+        return
+    }
+}
@@ -0,0 +1,15 @@
+//! Async client for the distlock HTTP API.
+//!
+//! `DistlockClient::lock` returns a `LockGuard` that keeps its lease alive
+//! in the background and releases it on `unlock` or when dropped. Requests
+//! and responses are the `api::models` types the server uses.
+//!
+//! A client is given one or more seed endpoints. It follows the leader
+//! hints of nodes that cannot serve a write, moves on to the next endpoint
+//! when one fails, and retries transient errors under its `RetryPolicy`.
+
+pub mod error;
+pub mod distlock_client;
+pub mod guard;
+pub mod retry;
+pub mod client_test;
@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+
+/// Backoff between retries of transient failures. Each call gives up once
+/// `deadline` has passed since its first attempt.
+#[derive(Debug , Clone)]
+pub struct RetryPolicy{
+    pub initial_backoff : Duration,
+    pub max_backoff : Duration,
+    pub multiplier : f64,
+    /// Total time a call may spend retrying, including the attempts.
+    pub deadline : Duration,
+    /// Time limit of a single attempt, so that an unresponsive node is
+    /// given up on.
+    pub attempt_timeout : Duration
+}
+
+impl Default for RetryPolicy{
+    fn default() -> Self {
+        Self { initial_backoff: Duration::from_millis(50), max_backoff: Duration::from_secs(2), multiplier: 2.0, deadline: Duration::from_secs(10), attempt_timeout: Duration::from_secs(2) }
+    }
+}
+
+impl RetryPolicy{
+    /// A policy that tries once.
+    pub fn none() -> Self {
+        Self { deadline: Duration::ZERO, ..Default::default() }
+    }
+
+    /// Upper bound of the delay before retry number `attempt`, starting at 0.
+    pub fn backoff(&self , attempt : u32) -> Duration {
+        let factor = self.multiplier.max(1.0).powi(attempt.min(64) as i32);
+        self.initial_backoff.mul_f64(factor).min(self.max_backoff)
+    }
+
+    /// Full jitter: a uniform delay up to the backoff, so that clients that
+    /// failed together do not retry together.
+    pub fn jittered_backoff(&self , attempt : u32 , rng : &mut impl Rng) -> Duration {
+        let backoff = self.backoff(attempt);
+        if backoff.is_zero() {
+            return backoff
+        }
+        rng.gen_range(Duration::ZERO..=backoff)
+    }
+}
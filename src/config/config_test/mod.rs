@@ -0,0 +1,3 @@
+
+
+pub mod test;
\ No newline at end of file
@@ -0,0 +1,101 @@
+
+
+#[cfg(test)]
+mod tests{
+    use crate::config::server_config::{PeerConfig, ServerConfig};
+
+    #[test]
+
+    fn test_parse_config_file(){
+        let config = ServerConfig::from_toml(r#"
+            node_id = 2
+            client_addr = "127.0.0.1:3002"
+            grpc_addr = "127.0.0.1:5002"
+            peer_addr = "127.0.0.1:4002"
+            peers = [
+                { id = 1 , addr = "127.0.0.1:4001" },
+                { id = 3 , addr = "node3:4003" , client_addr = "node3:3003" },
+            ]
+
+            [raft]
+            election_tick = 20
+
+            [ttl]
+            max_seconds = 600
+
+            [quotas]
+            requests_per_second = 50
+            burst = 100
+
+            [history]
+            retention_seconds = 3600
+        "#).unwrap();
+
+        assert_eq!(config.node_id , 2);
+        assert_eq!(config.grpc_addr.as_deref() , Some("127.0.0.1:5002"));
+        assert_eq!(config.peer_ids() , vec![1 , 3]);
+        assert_eq!(config.peers[1].client_addr.as_deref() , Some("node3:3003"));
+        assert_eq!(config.raft.election_tick , 20);
+        assert_eq!(config.raft.heartbeat_tick , 3);
+        assert_eq!(config.ttl.max_seconds , 600);
+        assert_eq!((config.quotas.requests_per_second , config.quotas.burst) , (50 , 100));
+        assert_eq!((config.history.max_events , config.history.retention_seconds) , (100_000 , 3600));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+
+    fn test_reject_invalid_config(){
+        assert!(ServerConfig::from_toml("unknown_key = 1").is_err());
+
+        let config = ServerConfig { peers : vec![PeerConfig { id : 1 , addr : "127.0.0.1:4001".to_string() , client_addr : None }] , ..Default::default() };
+        assert!(config.validate().is_err());
+
+        let config = ServerConfig { grpc_addr : Some("no-port".to_string()) , ..Default::default() };
+        assert!(config.validate().is_err());
+
+        let mut config = ServerConfig::default();
+        config.raft.election_tick = 2;
+        assert!(config.validate().is_err());
+
+        let config = ServerConfig { data_dir : Default::default() , ..Default::default() };
+        assert!(config.validate().is_err());
+
+        let mut config = ServerConfig::default();
+        config.ttl.default_seconds = 0;
+        assert!(config.validate().is_err());
+
+        let mut config = ServerConfig::default();
+        config.ttl.max_seconds = u64::MAX;
+        assert!(config.validate().is_err());
+
+        let mut config = ServerConfig::default();
+        config.auth.enabled = true;
+        assert!(config.validate().is_err());
+        config.auth.root_token = Some("secret".to_string());
+        assert!(config.validate().is_ok());
+        config.resp_addr = Some("127.0.0.1:6379".to_string());
+        assert!(config.validate().is_err());
+
+        let mut config = ServerConfig::default();
+        config.tls.cert_file = Some("node.pem".into());
+        assert!(config.validate().is_err());
+        config.tls.key_file = Some("node-key.pem".into());
+        assert!(config.validate().is_ok());
+        config.tls.client_id_from_cert = true;
+        assert!(config.validate().is_err());
+        config.tls.client_ca_file = Some("clients.pem".into());
+        assert!(config.validate().is_ok());
+
+        let mut config = ServerConfig::default();
+        config.quotas.burst = 10;
+        assert!(config.validate().is_err());
+        config.quotas.requests_per_second = 5;
+        assert!(config.validate().is_ok());
+
+        assert!("2=node2:4002".parse::<PeerConfig>().is_ok());
+        let peer = "2=node2:4002@node2:3002".parse::<PeerConfig>().unwrap();
+        assert_eq!((peer.addr.as_str() , peer.client_addr.as_deref()) , ("node2:4002" , Some("node2:3002")));
+        assert!("node2:4002".parse::<PeerConfig>().is_err());
+    }
+}
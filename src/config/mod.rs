@@ -0,0 +1,4 @@
+
+
+pub mod server_config;
+pub mod config_test;
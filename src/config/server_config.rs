@@ -0,0 +1,311 @@
+use std::{net::SocketAddr, path::{Path, PathBuf}, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// Longest lease a node will grant, one year.
+pub const MAX_TTL_SECONDS : u64 = 365 * 24 * 3600;
+
+
+/// Configuration of one node.
+#[derive(Debug , Clone , PartialEq , Serialize , Deserialize)]
+#[serde(default , deny_unknown_fields)]
+pub struct ServerConfig{
+    pub node_id : u64,
+    /// Address of the HTTP API used by clients.
+    pub client_addr : String,
+    /// Address of the gRPC API. Not served when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grpc_addr : Option<String>,
+    /// Address of the Redis protocol listener. Not served when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resp_addr : Option<String>,
+    /// Address other nodes use to send raft messages to this node.
+    pub peer_addr : String,
+    pub peers : Vec<PeerConfig>,
+    /// Holds the raft log and state. A restarted node rebuilds its lock
+    /// table by re-applying the log found there.
+    pub data_dir : PathBuf,
+    pub raft : RaftConfig,
+    pub ttl : TtlConfig,
+    pub auth : AuthConfig,
+    pub tls : TlsConfig,
+    pub quotas : QuotaConfig,
+    pub history : HistoryConfig,
+    pub log_level : String
+}
+
+#[derive(Debug , Clone , PartialEq , Serialize , Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PeerConfig{
+    pub id : u64,
+    pub addr : String,
+    /// Client API address of the peer. Followers send it to clients as a
+    /// hint when they are asked to do the leader's work.
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub client_addr : Option<String>
+}
+
+#[derive(Debug , Clone , PartialEq , Serialize , Deserialize)]
+#[serde(default , deny_unknown_fields)]
+pub struct RaftConfig{
+    pub tick_interval_ms : u64,
+    pub election_tick : usize,
+    /// Election timeouts are drawn from [election_tick, max_election_tick).
+    /// 0 uses twice the election tick.
+    pub max_election_tick : usize,
+    pub heartbeat_tick : usize,
+    pub max_size_per_msg : u64,
+    pub max_inflight_msgs : usize
+}
+
+#[derive(Debug , Clone , PartialEq , Serialize , Deserialize)]
+#[serde(default , deny_unknown_fields)]
+pub struct TtlConfig{
+    /// TTL given to waiters promoted to holder on release.
+    pub default_seconds : u64,
+    pub min_seconds : u64,
+    /// At most `MAX_TTL_SECONDS`.
+    pub max_seconds : u64
+}
+
+/// Bearer token authentication of the HTTP API. Principals other than root
+/// and their ACL rules are kept in the replicated state, see `auth::acl`.
+#[derive(Debug , Clone , PartialEq , Default , Serialize , Deserialize)]
+#[serde(default , deny_unknown_fields)]
+pub struct AuthConfig{
+    pub enabled : bool,
+    /// Token of the root principal, which may do everything. Needed to set
+    /// up the first ACL; prefer passing it through the environment.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root_token : Option<String>
+}
+
+/// PEM files of the node. The client API is served over TLS when
+/// `cert_file` is set; SIGHUP reloads every file.
+#[derive(Debug , Clone , PartialEq , Default , Serialize , Deserialize)]
+#[serde(default , deny_unknown_fields)]
+pub struct TlsConfig{
+    /// Certificate chain of the node, used for clients and peers alike.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cert_file : Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_file : Option<PathBuf>,
+    /// CA of the cluster. When set, raft traffic uses mutual TLS and peers
+    /// must present a certificate it signed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer_ca_file : Option<PathBuf>,
+    /// When set, clients must present a certificate this CA signed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_ca_file : Option<PathBuf>,
+    /// Bind client ids to the common name of the client certificate: a
+    /// request's client id must be the name or start with `<name>/`, and an
+    /// empty one is replaced by the name.
+    pub client_id_from_cert : bool
+}
+
+/// Request rate limits of one node. 0 means unlimited. The limits on the
+/// lock table are cluster state, set with `PUT /admin/quotas`, so that
+/// every replica enforces the same ones.
+#[derive(Debug , Clone , PartialEq , Default , Serialize , Deserialize)]
+#[serde(default , deny_unknown_fields)]
+pub struct QuotaConfig{
+    /// Acquires and renewals one client id may send to a node per second.
+    /// Releases are never limited.
+    pub requests_per_second : u32,
+    /// Requests a client may send at once after being idle. 0 uses
+    /// `requests_per_second`.
+    pub burst : u32
+}
+
+/// Retention of the ownership history of locks, see `lock::history`. Each
+/// replica prunes its own copy, so nodes given different limits only
+/// remember more or less of it.
+#[derive(Debug , Clone , PartialEq , Serialize , Deserialize)]
+#[serde(default , deny_unknown_fields)]
+pub struct HistoryConfig{
+    /// Events kept across every lock, 0 to record none.
+    pub max_events : usize,
+    /// Events older than this are dropped, 0 to keep them until
+    /// `max_events` pushes them out.
+    pub retention_seconds : u64
+}
+
+impl TlsConfig{
+    pub fn enabled(&self) -> bool {
+        self.cert_file.is_some()
+    }
+}
+
+impl Default for ServerConfig{
+    fn default() -> Self {
+        Self {
+            node_id: 1,
+            client_addr: "0.0.0.0:3000".to_string(),
+            grpc_addr: None,
+            resp_addr: None,
+            peer_addr: "0.0.0.0:4000".to_string(),
+            peers: Vec::new(),
+            data_dir: PathBuf::from("./data"),
+            raft: RaftConfig::default(),
+            ttl: TtlConfig::default(),
+            auth: AuthConfig::default(),
+            tls: TlsConfig::default(),
+            quotas: QuotaConfig::default(),
+            history: HistoryConfig::default(),
+            log_level: "info".to_string()
+        }
+    }
+}
+
+impl Default for RaftConfig{
+    fn default() -> Self {
+        Self { tick_interval_ms: 100, election_tick: 10, max_election_tick: 0, heartbeat_tick: 3, max_size_per_msg: 1024 * 1024, max_inflight_msgs: 256 }
+    }
+}
+
+impl Default for HistoryConfig{
+    fn default() -> Self {
+        Self { max_events: 100_000, retention_seconds: 7 * 24 * 3600 }
+    }
+}
+
+impl Default for TtlConfig{
+    fn default() -> Self {
+        Self { default_seconds: 30, min_seconds: 1, max_seconds: 3600 }
+    }
+}
+
+/// Peers are written `id=host:port` on the command line and in env vars,
+/// optionally followed by `@host:port` for the peer's client API.
+impl FromStr for PeerConfig{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (id , addrs) = s.split_once('=')
+            .ok_or_else(|| format!("Invalid peer '{}', expected id=host:port[@client_host:port]" , s))?;
+        let id = id.trim().parse::<u64>()
+            .map_err(|e| format!("Invalid peer id in '{}': {}" , s , e))?;
+        let (addr , client_addr) = match addrs.split_once('@') {
+            Some((addr , client_addr)) => (addr , Some(client_addr.trim().to_string())),
+            None => (addrs , None)
+        };
+        Ok(Self { id, addr: addr.trim().to_string(), client_addr })
+    }
+}
+
+impl ServerConfig{
+    pub fn from_file(path : &Path) -> Result<Self , String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file {} : {}" , path.display() , e))?;
+        Self::from_toml(&contents)
+            .map_err(|e| format!("Invalid config file {} : {}" , path.display() , e))
+    }
+
+    pub fn from_toml(contents : &str) -> Result<Self , String> {
+        toml::from_str(contents).map_err(|e| e.to_string())
+    }
+
+    pub fn peer_ids(&self) -> Vec<u64> {
+        self.peers.iter().map(|peer| peer.id).collect()
+    }
+
+    pub fn validate(&self) -> Result<() , String> {
+        if self.node_id == 0 {
+            return Err("node_id must be greater than 0".to_string())
+        }
+        validate_addr("client_addr" , &self.client_addr)?;
+        validate_addr("peer_addr" , &self.peer_addr)?;
+        if let Some(grpc_addr) = &self.grpc_addr {
+            validate_addr("grpc_addr" , grpc_addr)?;
+        }
+        if let Some(resp_addr) = &self.resp_addr {
+            validate_addr("resp_addr" , resp_addr)?;
+        }
+
+        let mut seen = vec![self.node_id];
+        for peer in &self.peers {
+            if peer.id == 0 {
+                return Err("peer ids must be greater than 0".to_string())
+            }
+            if seen.contains(&peer.id) {
+                return Err(format!("peer id {} is listed twice or equals node_id" , peer.id))
+            }
+            seen.push(peer.id);
+            validate_addr(&format!("peer {}" , peer.id) , &peer.addr)?;
+            if let Some(client_addr) = &peer.client_addr {
+                validate_addr(&format!("peer {} client_addr" , peer.id) , client_addr)?;
+            }
+        }
+
+        if self.data_dir.as_os_str().is_empty() {
+            return Err("data_dir must not be empty".to_string())
+        }
+
+        if self.raft.tick_interval_ms == 0 {
+            return Err("raft.tick_interval_ms must be greater than 0".to_string())
+        }
+        if self.raft.heartbeat_tick == 0 || self.raft.election_tick <= self.raft.heartbeat_tick {
+            return Err(format!("raft.election_tick ({}) must be greater than raft.heartbeat_tick ({}) and both above 0" , self.raft.election_tick , self.raft.heartbeat_tick))
+        }
+        if self.raft.max_election_tick != 0 && self.raft.max_election_tick <= self.raft.election_tick {
+            return Err("raft.max_election_tick must be greater than raft.election_tick".to_string())
+        }
+        if self.raft.max_inflight_msgs == 0 {
+            return Err("raft.max_inflight_msgs must be greater than 0".to_string())
+        }
+
+        if self.ttl.min_seconds == 0 {
+            return Err("ttl.min_seconds must be greater than 0".to_string())
+        }
+        if self.ttl.max_seconds > MAX_TTL_SECONDS {
+            return Err(format!("ttl.max_seconds must be at most {}" , MAX_TTL_SECONDS))
+        }
+        if !(self.ttl.min_seconds..=self.ttl.max_seconds).contains(&self.ttl.default_seconds) {
+            return Err(format!("ttl.default_seconds ({}) must be between ttl.min_seconds ({}) and ttl.max_seconds ({})" , self.ttl.default_seconds , self.ttl.min_seconds , self.ttl.max_seconds))
+        }
+
+        if self.auth.enabled {
+            if self.auth.root_token.as_deref().is_none_or(str::is_empty) {
+                return Err("auth.root_token must be set when auth is enabled".to_string())
+            }
+            // Only the HTTP API checks tokens.
+            if self.grpc_addr.is_some() || self.resp_addr.is_some() {
+                return Err("grpc_addr and resp_addr cannot be used while auth is enabled".to_string())
+            }
+        }
+
+        if self.tls.cert_file.is_some() != self.tls.key_file.is_some() {
+            return Err("tls.cert_file and tls.key_file must be set together".to_string())
+        }
+        if !self.tls.enabled() && (self.tls.peer_ca_file.is_some() || self.tls.client_ca_file.is_some()) {
+            return Err("tls.peer_ca_file and tls.client_ca_file need tls.cert_file and tls.key_file".to_string())
+        }
+        if self.tls.client_id_from_cert && self.tls.client_ca_file.is_none() {
+            return Err("tls.client_id_from_cert needs tls.client_ca_file".to_string())
+        }
+        // Only the HTTP API binds client ids to certificates.
+        if self.tls.client_id_from_cert && (self.grpc_addr.is_some() || self.resp_addr.is_some()) {
+            return Err("grpc_addr and resp_addr cannot be used with tls.client_id_from_cert".to_string())
+        }
+
+        if self.quotas.burst > 0 && self.quotas.requests_per_second == 0 {
+            return Err("quotas.burst needs quotas.requests_per_second".to_string())
+        }
+
+        tracing::Level::from_str(&self.log_level)
+            .map_err(|_| format!("Invalid log_level '{}'" , self.log_level))?;
+
+        Ok(())
+    }
+}
+
+/// Accepts `ip:port` or `hostname:port`; hostnames are resolved on connect.
+fn validate_addr(name : &str , addr : &str) -> Result<() , String> {
+    if addr.parse::<SocketAddr>().is_ok() {
+        return Ok(())
+    }
+    match addr.rsplit_once(':') {
+        Some((host , port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(()),
+        _ => Err(format!("{} has an invalid address '{}', expected host:port" , name , addr))
+    }
+}
@@ -1,5 +1,17 @@
 pub mod api;
 
+pub mod auth;
+
+pub mod client;
+
+pub mod config;
+
 pub mod lock;
 
-pub mod raft;
\ No newline at end of file
+pub mod raft;
+
+pub mod sim;
+
+pub mod telemetry;
+
+pub mod tls;
\ No newline at end of file
@@ -0,0 +1,71 @@
+//! The state machine behind a node. `RaftNode` applies committed commands
+//! to a `LockBackend` and the HTTP, gRPC and etcd handlers read from it, so
+//! any implementation can stand in for `InMemoryLockManager`: a sharded
+//! table, one kept in an embedded database, or a test double.
+//!
+//! Methods are async so backends may do I/O. Writes are only called while
+//! applying the log, in log order, with the time and index of the entry in
+//! the `ApplyContext`; a backend must give the same results on every
+//! replica. Reads may be called at any time from any task, also while a
+//! write is applied, so a backend guards its own state.
+
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::lock::{barrier::{Barrier, BarrierKind, BarrierResult}, history::HistoryEvent, kv::{KvEntry, KvLease, KvResult}, metadata::Metadata, quota::LockQuotas, stats::LockStats, types::{AcquireResult, ApplyContext, ClientId, LeaseId, LockId, LockState, ReleaseResult, RenewResult, TransferResult}};
+
+/// The backend shared by a node and its handlers. Backends lock what they
+/// touch themselves, so reads are served while the node applies entries.
+pub type SharedBackend = Arc<dyn LockBackend>;
+
+#[async_trait]
+pub trait LockBackend : Send + Sync {
+    async fn acquire(&self , lock_id : &LockId , client_id : &ClientId , ttl : Duration , metadata : Metadata , ctx : &ApplyContext) -> AcquireResult;
+    async fn release(&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId , ctx : &ApplyContext) -> ReleaseResult;
+    /// `metadata` replaces the lease's metadata; `None` keeps it.
+    async fn renew(&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId , ttl : Duration , metadata : Option<Metadata> , ctx : &ApplyContext) -> RenewResult;
+    async fn transfer(&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId , target : &ClientId , ttl : Duration , ctx : &ApplyContext) -> TransferResult;
+    /// Returns the previous holder and the waiter promoted in its place.
+    /// With `lease_id`, a lock held under another lease is left alone.
+    /// `actor` is recorded in the history of the admin writes.
+    async fn force_release(&self , lock_id : &LockId , lease_id : Option<&LeaseId> , actor : &str , ctx : &ApplyContext) -> Option<(ClientId , Option<ClientId>)>;
+    /// Returns the number of queue entries removed.
+    async fn evict_waiter(&self , lock_id : &LockId , client_id : &ClientId , actor : &str , ctx : &ApplyContext) -> usize;
+    /// Returns the number of locks released and of queues left.
+    async fn revoke_client(&self , client_id : &ClientId , actor : &str , ctx : &ApplyContext) -> (usize , usize);
+    /// Replaces the whole state with a snapshot. Statistics start over.
+    async fn restore(&self , locks : Vec<(LockId , LockState)> , history : Vec<HistoryEvent> , kv : Vec<KvEntry> , barriers : Vec<(String , Barrier)> , ctx : &ApplyContext);
+    /// Replaces the limits checked by later acquires.
+    async fn set_quotas(&self , quotas : LockQuotas);
+
+    async fn kv_get(&self , key : &str , ctx : &ApplyContext) -> Option<KvEntry>;
+    async fn kv_put(&self , key : &str , value : String , client_id : &ClientId , expected_version : Option<u64> , lease : Option<KvLease> , ctx : &ApplyContext) -> KvResult;
+    async fn kv_delete(&self , key : &str , expected_version : Option<u64> , ctx : &ApplyContext) -> KvResult;
+
+    async fn barrier_create(&self , name : &str , kind : BarrierKind , count : u32 , ctx : &ApplyContext) -> BarrierResult;
+    async fn barrier_join(&self , name : &str , client_id : &ClientId , ttl : Duration , ctx : &ApplyContext) -> BarrierResult;
+    async fn barrier_arrive(&self , name : &str , client_id : &ClientId , ctx : &ApplyContext) -> BarrierResult;
+    async fn barrier_delete(&self , name : &str , ctx : &ApplyContext) -> BarrierResult;
+
+    async fn status(&self , lock_id : &LockId) -> Option<LockState>;
+    /// Every lock with its state, ordered by lock id.
+    async fn list(&self) -> Vec<(LockId , LockState)>;
+    /// Locks whose id starts with `prefix` and sorts after `after`, ordered
+    /// by lock id.
+    async fn list_page(&self , prefix : &str , after : Option<&str> , limit : usize) -> Vec<(LockId , LockState)>;
+    /// Events of `lock_id`, or of every lock, oldest first.
+    async fn history(&self , lock_id : Option<&LockId> , since : Option<DateTime<Utc>> , until : Option<DateTime<Utc>> , limit : usize) -> Vec<HistoryEvent>;
+    async fn history_events(&self) -> Vec<HistoryEvent>;
+    async fn stats(&self , lock_id : &LockId) -> Option<LockStats>;
+    /// Most contended first.
+    async fn most_contended(&self) -> Vec<(LockId , LockStats)>;
+    /// Keys whose lease ran out before `now` are left out.
+    async fn kv_entries(&self , now : DateTime<Utc>) -> Vec<KvEntry>;
+    async fn barrier(&self , name : &str) -> Option<Barrier>;
+    async fn barriers(&self) -> Vec<(String , Barrier)>;
+    async fn quotas(&self) -> LockQuotas;
+    /// Publishes the gauges of the lock table, as seen at `now`.
+    async fn record_gauges(&self , now : DateTime<Utc>);
+}
@@ -0,0 +1,143 @@
+//! Barriers and countdown latches, for workers that must rendezvous before
+//! moving on. Both are created with a count that arrivals bring down to
+//! zero, which releases everyone waiting on them. A barrier counts each
+//! participant once; a latch counts every count-down.
+//!
+//! Participants may join first with a lease, reserving one of the arrivals.
+//! If the lease runs out before they arrive, the reservation is given back
+//! and the barrier is degraded until someone takes the arrival: a crashed
+//! worker does not keep the slot, but it is not counted as arrived either.
+//! It may join again once it is back.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Error type of barrier names and counts that are not allowed.
+pub const INVALID_BARRIER : &str = "InvalidBarrier";
+
+pub const MAX_NAME_BYTES : usize = 256;
+
+#[derive(Debug , Clone , Copy , PartialEq , Eq , Serialize , Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BarrierKind{
+    Barrier,
+    Latch
+}
+
+impl BarrierKind{
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BarrierKind::Barrier => "barrier",
+            BarrierKind::Latch => "latch"
+        }
+    }
+}
+
+#[derive(Debug , Clone , PartialEq , Serialize , Deserialize)]
+pub struct Barrier{
+    pub kind : BarrierKind,
+    pub count : u32,
+    /// Count-downs of a latch, participants of a barrier.
+    pub arrivals : u32,
+    /// Participants that joined and have not arrived yet, with the expiry
+    /// of their lease.
+    pub participants : BTreeMap<String , DateTime<Utc>>,
+    /// Barrier participants that arrived. Latches do not keep them.
+    pub arrived : BTreeSet<String>,
+    /// Participants whose lease ran out before they arrived and that have
+    /// not joined or arrived since. Their arrival is open to anyone.
+    pub expired : Vec<String>,
+    pub created_at : DateTime<Utc>,
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub released_at : Option<DateTime<Utc>>
+}
+
+#[derive(Debug , Clone , PartialEq)]
+pub enum BarrierResult{
+    Updated { remaining : u32 },
+    NotFound,
+    /// Exists with another kind or count.
+    Conflict,
+    /// Every remaining arrival is reserved by a participant.
+    Full,
+}
+
+impl Barrier{
+    pub fn new(kind : BarrierKind , count : u32 , now : DateTime<Utc>) -> Self {
+        let mut barrier = Self { kind, count, arrivals: 0, participants: BTreeMap::new(), arrived: BTreeSet::new(), expired: Vec::new(), created_at: now, released_at: None };
+        barrier.check_released(now);
+        barrier
+    }
+
+    pub fn remaining(&self) -> u32 {
+        self.count.saturating_sub(self.arrivals)
+    }
+
+    /// Whether, as seen at `now`, a participant's lease ran out before it
+    /// arrived and it has not joined or arrived since.
+    pub fn degraded_at(&self , now : DateTime<Utc>) -> bool {
+        self.released_at.is_none() && (!self.expired.is_empty() || self.participants.values().any(|expires_at| *expires_at < now))
+    }
+
+    /// Registers `client_id` until `expires_at`, or extends its lease if it
+    /// already joined. A participant whose lease ran out joins like anyone
+    /// else.
+    pub fn join(&mut self , client_id : &str , expires_at : DateTime<Utc> , now : DateTime<Utc>) -> BarrierResult {
+        self.expire(now);
+        if self.released_at.is_none() && !self.arrived.contains(client_id) {
+            if !self.participants.contains_key(client_id) && self.participants.len() as u32 >= self.remaining() {
+                return BarrierResult::Full
+            }
+            self.expired.retain(|expired| expired != client_id);
+            self.participants.insert(client_id.to_string() , expires_at);
+        }
+        BarrierResult::Updated { remaining: self.remaining() }
+    }
+
+    /// Counts one arrival of `client_id`. A barrier participant arriving
+    /// again changes nothing.
+    pub fn arrive(&mut self , client_id : &str , now : DateTime<Utc>) -> BarrierResult {
+        self.expire(now);
+        if self.released_at.is_some() || self.arrived.contains(client_id) {
+            return BarrierResult::Updated { remaining: self.remaining() }
+        }
+        if self.participants.remove(client_id).is_none() && self.participants.len() as u32 >= self.remaining() {
+            return BarrierResult::Full
+        }
+        self.expired.retain(|expired| expired != client_id);
+        self.arrivals += 1;
+        if self.kind == BarrierKind::Barrier {
+            self.arrived.insert(client_id.to_string());
+        }
+        self.check_released(now);
+        BarrierResult::Updated { remaining: self.remaining() }
+    }
+
+    /// Gives back the arrivals reserved by participants whose lease ran
+    /// out before `now`.
+    pub fn expire(&mut self , now : DateTime<Utc>) {
+        let lapsed : Vec<String> = self.participants.iter().filter(|(_ , expires_at)| **expires_at < now).map(|(client_id , _)| client_id.clone()).collect();
+        for client_id in lapsed {
+            self.participants.remove(&client_id);
+            self.expired.push(client_id);
+        }
+    }
+
+    fn check_released(&mut self , now : DateTime<Utc>) {
+        if self.released_at.is_none() && self.remaining() == 0 {
+            self.released_at = Some(now);
+        }
+    }
+}
+
+pub fn validate(name : &str , count : u32) -> Result<() , String> {
+    if name.is_empty() || name.len() > MAX_NAME_BYTES {
+        return Err(format!("Names must take between 1 and {} bytes" , MAX_NAME_BYTES))
+    }
+    if count == 0 {
+        return Err("Count must be at least 1".to_string())
+    }
+    Ok(())
+}
@@ -0,0 +1,15 @@
+use chrono::{DateTime, Utc};
+
+
+/// Wall clock used by the leader to timestamp proposals.
+pub trait Clock : Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock{
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
@@ -0,0 +1,232 @@
+//! Ownership history of locks. Events are recorded by the state machine as
+//! entries are applied, so every replica keeps the same history, stamped
+//! with the raft index of the entry that caused it.
+
+use std::{collections::{HashMap, VecDeque}, sync::{Mutex, RwLock, RwLockWriteGuard, atomic::{AtomicU64, Ordering}}};
+
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::server_config::HistoryConfig, lock::{metadata::Metadata, types::{ClientId, LeaseId, LockId}}};
+
+#[derive(Debug , Clone , Copy , PartialEq , Eq , Serialize , Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryEventKind{
+    /// Granted on acquire or on promotion from the wait queue.
+    Granted,
+    Queued,
+    Renewed,
+    Released,
+    /// Stamped with the expiry time, recorded when the expiry is noticed.
+    Expired,
+    ForceReleased,
+    /// Handed to another client, which is granted the lock at the same index.
+    Transferred,
+    /// Released because every lock of the client was revoked.
+    Revoked,
+    /// Removed from the wait queue by an operator or a revoke.
+    Evicted
+}
+
+impl HistoryEventKind{
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HistoryEventKind::Granted => "granted",
+            HistoryEventKind::Queued => "queued",
+            HistoryEventKind::Renewed => "renewed",
+            HistoryEventKind::Released => "released",
+            HistoryEventKind::Expired => "expired",
+            HistoryEventKind::ForceReleased => "force_released",
+            HistoryEventKind::Transferred => "transferred",
+            HistoryEventKind::Revoked => "revoked",
+            HistoryEventKind::Evicted => "evicted"
+        }
+    }
+}
+
+#[derive(Debug , Clone , PartialEq , Serialize , Deserialize)]
+pub struct HistoryEvent{
+    pub lock_id : LockId,
+    pub kind : HistoryEventKind,
+    pub client_id : ClientId,
+    /// Lease the event is about. Waiters have none.
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub lease_id : Option<LeaseId>,
+    /// Raft index of the entry that caused the event, 0 outside of raft.
+    pub index : u64,
+    pub at : DateTime<Utc>,
+    /// Metadata of the lease when it was granted or renewed.
+    #[serde(default , skip_serializing_if = "Metadata::is_empty")]
+    pub metadata : Metadata,
+    /// Who force-released, evicted or revoked, as the server identified
+    /// them. The client itself when it stopped waiting.
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub actor : Option<String>
+}
+
+/// Events of every lock in the order they were recorded, bounded by a
+/// `HistoryConfig`.
+pub struct History{
+    events : VecDeque<HistoryEvent>,
+    config : HistoryConfig
+}
+
+impl History{
+    pub fn new(config : HistoryConfig) -> Self {
+        Self { events: VecDeque::new(), config }
+    }
+
+    pub fn record(&mut self , event : HistoryEvent , now : DateTime<Utc>) {
+        if self.config.max_events == 0 {
+            return
+        }
+        self.events.push_back(event);
+        self.prune(now);
+    }
+
+    /// Drops the oldest events beyond the limits. Only the front is looked
+    /// at, which is enough since events are recorded in apply order.
+    fn prune(&mut self , now : DateTime<Utc>) {
+        while self.events.len() > self.config.max_events {
+            self.events.pop_front();
+        }
+        if self.config.retention_seconds > 0 {
+            let cutoff = now - ChronoDuration::seconds(self.config.retention_seconds as i64);
+            while self.events.front().is_some_and(|event| event.at < cutoff) {
+                self.events.pop_front();
+            }
+        }
+    }
+
+    /// Events of `lock_id`, or of every lock, recorded between `since` and
+    /// `until` inclusive, oldest first and at most `limit` of them. A lease
+    /// still held at `since` is led by its last grant or renewal, so a
+    /// holder across the whole window shows up too.
+    pub fn query(&self , lock_id : Option<&LockId> , since : Option<DateTime<Utc>> , until : Option<DateTime<Utc>> , limit : usize) -> Vec<HistoryEvent> {
+        let of_lock = |event : &&HistoryEvent| lock_id.is_none_or(|lock_id| event.lock_id == *lock_id);
+        let held = since.map(|since| self.held_at(lock_id , since)).unwrap_or_default();
+        held.into_iter()
+            .chain(self.events.iter()
+                .filter(of_lock)
+                .filter(|event| since.is_none_or(|since| event.at >= since) && until.is_none_or(|until| event.at <= until)))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// The last grant or renewal of each lease that did not end before
+    /// `at`, in the order they were recorded.
+    fn held_at(&self , lock_id : Option<&LockId> , at : DateTime<Utc>) -> Vec<&HistoryEvent> {
+        let mut held : HashMap<&LockId , usize> = HashMap::new();
+        let before = self.events.iter().enumerate()
+            .filter(|(_ , event)| lock_id.is_none_or(|lock_id| event.lock_id == *lock_id) && event.at < at);
+        for (position , event) in before {
+            match event.kind {
+                HistoryEventKind::Granted | HistoryEventKind::Renewed => {
+                    held.insert(&event.lock_id , position);
+                }
+                HistoryEventKind::Released | HistoryEventKind::Expired | HistoryEventKind::ForceReleased | HistoryEventKind::Transferred | HistoryEventKind::Revoked => {
+                    if held.get(&event.lock_id).is_some_and(|&grant| self.events[grant].lease_id == event.lease_id) {
+                        held.remove(&event.lock_id);
+                    }
+                }
+                HistoryEventKind::Queued | HistoryEventKind::Evicted => {}
+            }
+        }
+        let mut positions : Vec<usize> = held.into_values().collect();
+        positions.sort_unstable();
+        positions.into_iter().map(|position| &self.events[position]).collect()
+    }
+
+    pub fn events(&self) -> Vec<HistoryEvent> {
+        self.events.iter().cloned().collect()
+    }
+
+    pub fn config(&self) -> &HistoryConfig {
+        &self.config
+    }
+
+    /// Replaces every event with the ones from a snapshot.
+    pub fn restore(&mut self , events : Vec<HistoryEvent> , now : DateTime<Utc>) {
+        self.events = events.into();
+        self.prune(now);
+    }
+}
+
+/// Events a shard recorded that are not in the history yet.
+const BUFFERED_EVENTS : usize = 1024;
+
+struct Buffered{
+    sequence : u64,
+    event : HistoryEvent,
+    now : DateTime<Utc>
+}
+
+/// A `History` fed through one buffer per shard of the lock table, so that
+/// recording an event only waits for operations on locks of the same shard.
+/// Buffered events are moved into the history in the order they were
+/// recorded before it is read, or once a buffer fills up.
+pub struct ShardedHistory{
+    history : RwLock<History>,
+    buffers : Vec<Mutex<Vec<Buffered>>>,
+    sequence : AtomicU64
+}
+
+impl ShardedHistory{
+    pub fn new(config : HistoryConfig , shards : usize) -> Self {
+        Self {
+            history: RwLock::new(History::new(config)),
+            buffers: (0..shards.max(1)).map(|_| Mutex::new(Vec::new())).collect(),
+            sequence: AtomicU64::new(0)
+        }
+    }
+
+    pub fn config(&self) -> HistoryConfig {
+        self.history.read().unwrap().config().clone()
+    }
+
+    /// Records an event of a lock in `shard`.
+    pub fn record(&self , shard : usize , event : HistoryEvent , now : DateTime<Utc>) {
+        let mut buffer = self.buffers[shard % self.buffers.len()].lock().unwrap();
+        // Taken under the buffer's lock, so a flush, which holds every
+        // buffer at once, never leaves out an event older than one it moves.
+        let sequence = self.sequence.fetch_add(1 , Ordering::Relaxed);
+        buffer.push(Buffered { sequence, event, now });
+        if buffer.len() >= BUFFERED_EVENTS {
+            drop(buffer);
+            drop(self.flush());
+        }
+    }
+
+    /// Moves every buffered event into the history and returns it.
+    fn flush(&self) -> RwLockWriteGuard<'_ , History> {
+        let mut history = self.history.write().unwrap();
+        let mut buffers : Vec<_> = self.buffers.iter().map(|buffer| buffer.lock().unwrap()).collect();
+        let mut buffered : Vec<Buffered> = buffers.iter_mut().flat_map(|buffer| buffer.drain(..)).collect();
+        drop(buffers);
+        buffered.sort_unstable_by_key(|buffered| buffered.sequence);
+        for buffered in buffered {
+            history.record(buffered.event , buffered.now);
+        }
+        history
+    }
+
+    /// See `History::query`.
+    pub fn query(&self , lock_id : Option<&LockId> , since : Option<DateTime<Utc>> , until : Option<DateTime<Utc>> , limit : usize) -> Vec<HistoryEvent> {
+        self.flush().query(lock_id , since , until , limit)
+    }
+
+    pub fn events(&self) -> Vec<HistoryEvent> {
+        self.flush().events()
+    }
+
+    /// Replaces every event, buffered ones included, with the ones from a
+    /// snapshot.
+    pub fn restore(&self , events : Vec<HistoryEvent> , now : DateTime<Utc>) {
+        let mut history = self.history.write().unwrap();
+        for buffer in &self.buffers {
+            buffer.lock().unwrap().clear();
+        }
+        history.restore(events , now);
+    }
+}
@@ -0,0 +1,172 @@
+//! A small key-value namespace kept next to the locks, for coordination
+//! state such as the endpoint of the current leader. Reads go through the
+//! log like writes, so every operation is linearizable.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::lock::types::{LeaseId, LockId};
+
+/// Error type of keys and values over the limits.
+pub const INVALID_KV : &str = "InvalidKv";
+/// Error type of writes whose expected version did not match.
+pub const VERSION_MISMATCH : &str = "VersionMismatch";
+
+pub const MAX_KEY_BYTES : usize = 256;
+pub const MAX_VALUE_BYTES : usize = 16 * 1024;
+
+/// Lock lease a key belongs to. The key is deleted once the lease ends.
+#[derive(Debug , Clone , PartialEq , Serialize , Deserialize)]
+pub struct KvLease{
+    pub lock_id : LockId,
+    pub lease_id : LeaseId
+}
+
+impl KvLease{
+    fn bound_key(&self) -> (String , String) {
+        (self.lock_id.0.clone() , self.lease_id.0.clone())
+    }
+}
+
+#[derive(Debug , Clone , PartialEq , Serialize , Deserialize)]
+pub struct KvEntry{
+    pub key : String,
+    pub value : String,
+    /// Revision of the store when the key was last written. Revisions grow
+    /// across every key, so a deleted and recreated key never reuses one.
+    pub version : u64,
+    pub updated_at : DateTime<Utc>,
+    #[serde(default , skip_serializing_if = "Option::is_none")]
+    pub lease : Option<KvLease>
+}
+
+#[derive(Debug , Clone , PartialEq)]
+pub enum KvResult{
+    Written { version : u64 },
+    Deleted { version : u64 },
+    NotFound,
+    /// `current` is 0 when the key does not exist.
+    VersionMismatch { current : u64 },
+    /// The lease to attach is not the current lease of its lock.
+    NotHolder,
+    Expired
+}
+
+#[derive(Default)]
+pub struct KvStore{
+    entries : BTreeMap<String , KvEntry>,
+    /// Keys bound to each lock and lease id, so ending a lease finds its
+    /// keys without a scan.
+    leased : BTreeMap<(String , String) , BTreeSet<String>>,
+    revision : u64
+}
+
+impl KvStore{
+    pub fn get(&self , key : &str) -> Option<&KvEntry> {
+        self.entries.get(key)
+    }
+
+    /// Writes `key` if it is at `expected_version`, 0 meaning absent, or
+    /// unconditionally without one.
+    pub fn put(&mut self , key : &str , value : String , expected_version : Option<u64> , lease : Option<KvLease> , now : DateTime<Utc>) -> KvResult {
+        if let Err(current) = self.check_version(key , expected_version) {
+            return KvResult::VersionMismatch { current }
+        }
+        self.revision += 1;
+        let entry = KvEntry { key: key.to_string(), value, version: self.revision, updated_at: now, lease };
+        self.insert(entry);
+        KvResult::Written { version: self.revision }
+    }
+
+    pub fn delete(&mut self , key : &str , expected_version : Option<u64>) -> KvResult {
+        if let Err(current) = self.check_version(key , expected_version) {
+            return KvResult::VersionMismatch { current }
+        }
+        match self.remove(key) {
+            Some(entry) => KvResult::Deleted { version: entry.version },
+            None => KvResult::NotFound
+        }
+    }
+
+    fn insert(&mut self , entry : KvEntry) {
+        if let Some(lease) = &entry.lease {
+            self.leased.entry(lease.bound_key()).or_default().insert(entry.key.clone());
+        }
+        if let Some(previous) = self.entries.insert(entry.key.clone() , entry) {
+            self.unbind(&previous);
+        }
+    }
+
+    fn remove(&mut self , key : &str) -> Option<KvEntry> {
+        let entry = self.entries.remove(key)?;
+        self.unbind(&entry);
+        Some(entry)
+    }
+
+    fn unbind(&mut self , entry : &KvEntry) {
+        let Some(lease) = &entry.lease else { return };
+        // The key may have been rewritten under the same lease.
+        if self.entries.get(&entry.key).is_some_and(|current| current.lease.as_ref() == Some(lease)) {
+            return
+        }
+        if let Some(keys) = self.leased.get_mut(&lease.bound_key()) {
+            keys.remove(&entry.key);
+            if keys.is_empty() {
+                self.leased.remove(&lease.bound_key());
+            }
+        }
+    }
+
+    fn check_version(&self , key : &str , expected_version : Option<u64>) -> Result<() , u64> {
+        let current = self.entries.get(key).map_or(0 , |entry| entry.version);
+        match expected_version {
+            Some(expected) if expected != current => Err(current),
+            _ => Ok(())
+        }
+    }
+
+    /// Whether any key is bound to `lease`.
+    pub fn binds(&self , lease : &KvLease) -> bool {
+        self.leased.contains_key(&lease.bound_key())
+    }
+
+    /// Every lease keys are bound to.
+    pub fn leases(&self) -> Vec<KvLease> {
+        self.leased.keys().map(|(lock_id , lease_id)| KvLease { lock_id: LockId(lock_id.clone()), lease_id: LeaseId(lease_id.clone()) }).collect()
+    }
+
+    /// Deletes every key bound to `lease`.
+    pub fn drop_lease(&mut self , lease : &KvLease) {
+        for key in self.leased.remove(&lease.bound_key()).unwrap_or_default() {
+            self.entries.remove(&key);
+        }
+    }
+
+    /// Every entry in key order.
+    pub fn entries(&self) -> Vec<KvEntry> {
+        self.entries.values().cloned().collect()
+    }
+
+    /// Replaces every entry with the ones from a snapshot.
+    pub fn restore(&mut self , entries : Vec<KvEntry>) {
+        // Revisions never go back, even to an older snapshot.
+        self.revision = entries.iter().map(|entry| entry.version).fold(self.revision , u64::max);
+        self.entries.clear();
+        self.leased.clear();
+        for entry in entries {
+            self.insert(entry);
+        }
+    }
+}
+
+pub fn validate(key : &str , value : &str) -> Result<() , String> {
+    if key.is_empty() || key.len() > MAX_KEY_BYTES {
+        return Err(format!("Keys must take between 1 and {} bytes" , MAX_KEY_BYTES))
+    }
+    if value.len() > MAX_VALUE_BYTES {
+        return Err(format!("Value takes {} bytes, at most {} are allowed" , value.len() , MAX_VALUE_BYTES))
+    }
+    Ok(())
+}
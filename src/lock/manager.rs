@@ -1,63 +1,579 @@
 
-use std::{collections::HashMap,sync::RwLock};
+use std::{collections::{BTreeMap, HashMap},sync::{Mutex, MutexGuard, RwLock, atomic::{AtomicBool, Ordering}}};
 use std::{time::Duration};
 
-use chrono::{Utc , Duration as ChronoDuration};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use metrics::{counter, gauge, histogram};
 
-use crate::lock::types::{AcquireResult, ClientId, LeaseId, LockHolder, LockId, LockManager, LockState, ReleaseResult, RenewResult, WaitRequest};
+use crate::{config::server_config::{HistoryConfig, MAX_TTL_SECONDS}, lock::{backend::LockBackend, barrier::{Barrier, BarrierKind, BarrierResult}, history::{HistoryEvent, HistoryEventKind, ShardedHistory}, kv::{KvEntry, KvLease, KvResult, KvStore}, metadata::Metadata, quota::{ClientLocks, LockQuotas, namespace}, stats::{LeaseEnd, LockStats}, table::{DEFAULT_SHARDS, LockTable}, types::{AcquireResult, ApplyContext, ClientId, LeaseId, LockHolder, LockId, LockManager, LockState, ReleaseResult, RenewResult, TransferResult, WaitRequest}}, telemetry::prometheus::{LOCKS, LOCKS_HELD, LOCK_EXPIRATIONS, LOCK_OPERATIONS, MAX_QUEUE_LENGTH, QUEUE_WAIT, WAITERS}};
 
 
+/// TTLs are clamped before they reach the log, but a longer one must not
+/// panic the apply loop on every replica either.
+fn lease_length(ttl : Duration) -> ChronoDuration {
+    ChronoDuration::from_std(ttl.min(Duration::from_secs(MAX_TTL_SECONDS))).unwrap_or(ChronoDuration::seconds(MAX_TTL_SECONDS as i64))
+}
+
 pub struct InMemoryLockManager{
-    locks : std::sync::RwLock<HashMap<LockId , LockState>>,
-     default_ttl : ChronoDuration
+    locks : LockTable,
+    // Replicated: set through the log, never from a node's config.
+    quotas : RwLock<LockQuotas>,
+    // Whether any quota is set, so unlimited acquires skip `quotas`.
+    limited : AtomicBool,
+    clients : ClientLocks,
+    history : ShardedHistory,
+    // Sharded like the lock table: the stats of a lock are in the shard of
+    // the same index.
+    stats : Vec<Mutex<HashMap<LockId , LockStats>>>,
+    kv : RwLock<KvStore>,
+    barriers : RwLock<BTreeMap<String , Barrier>>
+}
+
+impl Default for InMemoryLockManager{
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 
 impl InMemoryLockManager{
     pub fn new() -> Self{
-        InMemoryLockManager { locks: RwLock::new(HashMap::new()) ,
-            default_ttl : ChronoDuration::seconds(30)
+        InMemoryLockManager { locks: LockTable::new(DEFAULT_SHARDS) ,
+            quotas : RwLock::new(LockQuotas::default()) ,
+            limited : AtomicBool::new(false) ,
+            clients : ClientLocks::new(DEFAULT_SHARDS) ,
+            history : ShardedHistory::new(HistoryConfig::default() , DEFAULT_SHARDS) ,
+            stats : (0..DEFAULT_SHARDS).map(|_| Mutex::new(HashMap::new())).collect() ,
+            kv : RwLock::new(KvStore::default()) ,
+            barriers : RwLock::new(BTreeMap::new())
         }
     }
 
-    pub fn with_default_ttl(ttl : ChronoDuration) -> Self{
-        InMemoryLockManager { locks: RwLock::new(HashMap::new()), default_ttl: ttl }
+    /// Refuses acquires beyond the lock limits of `quotas`. The request rate
+    /// is not the manager's concern.
+    pub fn with_quotas(self , quotas : LockQuotas) -> Self{
+        self.set_quotas(quotas);
+        self
     }
-}
 
-impl LockManager for InMemoryLockManager{
-    fn try_acquire (&self , lock_id : &LockId , client_id : &ClientId , ttl : std::time::Duration ) -> AcquireResult {
-        let mut locks = self.locks.write().unwrap();
+    /// Counts the locks of every client again when `max_locks_per_client`
+    /// is set, which takes the whole table.
+    pub fn set_quotas(&self , quotas : LockQuotas) {
+        let mut table = self.locks.write();
+        *self.quotas.write().unwrap() = quotas;
+        self.limited.store(quotas != LockQuotas::default() , Ordering::Relaxed);
+        self.clients.reset(quotas.max_locks_per_client > 0 , table.iter_sorted_mut().into_iter().map(|(lock_id , state)| (lock_id , &*state)));
+    }
+
+    pub fn quotas(&self) -> LockQuotas {
+        *self.quotas.read().unwrap()
+    }
+
+    /// Splits the lock table into `shards` shards. One shard puts every lock
+    /// behind the same lock, which only makes sense for comparisons.
+    pub fn with_shards(mut self , shards : usize) -> Self{
+        self.locks = LockTable::new(shards);
+        self.stats = (0..self.locks.shard_count()).map(|_| Mutex::new(HashMap::new())).collect();
+        self.history = ShardedHistory::new(self.history.config() , shards);
+        self
+    }
+
+    /// Bounds the ownership history kept for postmortems.
+    pub fn with_history(mut self , config : HistoryConfig) -> Self{
+        self.history = ShardedHistory::new(config , self.locks.shard_count());
+        self
+    }
+
+    /// Replaces every lock, the ownership history, the key-value store and
+    /// the barriers with the ones from a snapshot. Statistics start over.
+    pub fn restore(&self , locks : Vec<(LockId , LockState)> , history : Vec<HistoryEvent> , kv : Vec<KvEntry> , barriers : Vec<(String , Barrier)> , ctx : &ApplyContext){
+        self.locks.replace(locks);
+        for stats in &self.stats {
+            stats.lock().unwrap().clear();
+        }
+        {
+            let mut table = self.locks.write();
+            self.clients.reset(self.clients.enabled() , table.iter_sorted_mut().into_iter().map(|(lock_id , state)| (lock_id , &*state)));
+        }
+        self.history.restore(history , ctx.now);
+        self.kv.write().unwrap().restore(kv);
+        self.drop_orphaned_keys(ctx);
+        *self.barriers.write().unwrap() = barriers.into_iter().collect();
+    }
+
+    /// Creates a barrier or latch. Creating it again with the same kind and
+    /// count changes nothing, so every participant may do it.
+    pub fn barrier_create_at(&self , name : &str , kind : BarrierKind , count : u32 , ctx : &ApplyContext) -> BarrierResult {
+        let mut barriers = self.barriers.write().unwrap();
+        match barriers.get_mut(name) {
+            Some(barrier) if barrier.kind != kind || barrier.count != count => BarrierResult::Conflict,
+            Some(barrier) => {
+                barrier.expire(ctx.now);
+                BarrierResult::Updated { remaining: barrier.remaining() }
+            }
+            None => {
+                barriers.insert(name.to_string() , Barrier::new(kind , count , ctx.now));
+                BarrierResult::Updated { remaining: count }
+            }
+        }
+    }
+
+    /// Reserves an arrival for `client_id` until its lease of `ttl` runs
+    /// out. Joining again renews the lease.
+    pub fn barrier_join_at(&self , name : &str , client_id : &ClientId , ttl : Duration , ctx : &ApplyContext) -> BarrierResult {
+        let expires_at = ctx.now + lease_length(ttl);
+        match self.barriers.write().unwrap().get_mut(name) {
+            Some(barrier) => barrier.join(&client_id.0 , expires_at , ctx.now),
+            None => BarrierResult::NotFound
+        }
+    }
+
+    pub fn barrier_arrive_at(&self , name : &str , client_id : &ClientId , ctx : &ApplyContext) -> BarrierResult {
+        match self.barriers.write().unwrap().get_mut(name) {
+            Some(barrier) => barrier.arrive(&client_id.0 , ctx.now),
+            None => BarrierResult::NotFound
+        }
+    }
+
+    /// Returns the arrivals the barrier still needed when it was deleted.
+    pub fn barrier_delete_at(&self , name : &str , _ctx : &ApplyContext) -> BarrierResult {
+        match self.barriers.write().unwrap().remove(name) {
+            Some(barrier) => BarrierResult::Updated { remaining: barrier.remaining() },
+            None => BarrierResult::NotFound
+        }
+    }
+
+    pub fn barrier(&self , name : &str) -> Option<Barrier> {
+        self.barriers.read().unwrap().get(name).cloned()
+    }
+
+    /// Every barrier and latch in name order.
+    pub fn barriers(&self) -> Vec<(String , Barrier)> {
+        self.barriers.read().unwrap().iter().map(|(name , barrier)| (name.clone() , barrier.clone())).collect()
+    }
+
+    /// Current entry of `key`. Applied through the log like writes, so
+    /// keys of leases that ran out are dropped first here too.
+    pub fn kv_get_at(&self , key : &str , ctx : &ApplyContext) -> Option<KvEntry> {
+        self.drop_orphaned_keys(ctx);
+        self.kv.read().unwrap().get(key).cloned()
+    }
+
+    /// Writes `key` if it is at `expected_version`, 0 meaning absent. With
+    /// `lease`, which must be `client_id`'s current lease of its lock, the
+    /// key is deleted once that lease ends.
+    pub fn kv_put_at(&self , key : &str , value : String , client_id : &ClientId , expected_version : Option<u64> , lease : Option<KvLease> , ctx : &ApplyContext) -> KvResult {
+        self.drop_orphaned_keys(ctx);
+        if let Some(lease) = &lease {
+            match self.locks.with(&lease.lock_id , |state| state.holder.clone()).flatten() {
+                Some(holder) if holder.client_id != *client_id || holder.lease_id != lease.lease_id => return KvResult::NotHolder,
+                Some(holder) if holder.expires_at < ctx.now => return KvResult::Expired,
+                Some(_) => {}
+                None => return KvResult::NotHolder
+            }
+        }
+        self.kv.write().unwrap().put(key , value , expected_version , lease , ctx.now)
+    }
+
+    pub fn kv_delete_at(&self , key : &str , expected_version : Option<u64> , ctx : &ApplyContext) -> KvResult {
+        self.drop_orphaned_keys(ctx);
+        self.kv.write().unwrap().delete(key , expected_version)
+    }
+
+    /// Every key, leaving out the ones whose lease ran out before `now`
+    /// but has not been noticed by a command yet.
+    pub fn kv_entries(&self , now : DateTime<Utc>) -> Vec<KvEntry> {
+        let mut entries = self.kv.read().unwrap().entries();
+        entries.retain(|entry| entry.lease.as_ref().is_none_or(|lease| self.lease_alive(lease , now)));
+        entries
+    }
+
+    fn lease_alive(&self , lease : &KvLease , now : DateTime<Utc>) -> bool {
+        self.locks.with(&lease.lock_id , |state| {
+            state.holder.as_ref().is_some_and(|holder| holder.lease_id == lease.lease_id && holder.expires_at >= now)
+        }).unwrap_or(false)
+    }
+
+    /// Deletes the keys of leases that ran out. Keys of leases that were
+    /// released, transferred or taken away are deleted when that happens.
+    fn drop_orphaned_keys(&self , ctx : &ApplyContext){
+        // The lock table is always taken before the store, never inside it.
+        let leases = self.kv.read().unwrap().leases();
+        let ended = leases.into_iter().filter(|lease| !self.lease_alive(lease , ctx.now)).collect::<Vec<_>>();
+        let mut kv = self.kv.write().unwrap();
+        for lease in ended {
+            kv.drop_lease(&lease);
+        }
+    }
+
+    /// Deletes the keys bound to `lease_id` of `lock_id`, which just ended.
+    fn drop_keys_of(&self , lock_id : &LockId , lease_id : &LeaseId) {
+        let lease = KvLease { lock_id: lock_id.clone(), lease_id: lease_id.clone() };
+        if self.kv.read().unwrap().binds(&lease) {
+            self.kv.write().unwrap().drop_lease(&lease);
+        }
+    }
+
+    /// Recorded events of `lock_id`, or of every lock, between `since` and
+    /// `until`, oldest first.
+    pub fn history(&self , lock_id : Option<&LockId> , since : Option<DateTime<Utc>> , until : Option<DateTime<Utc>> , limit : usize) -> Vec<HistoryEvent> {
+        self.history.query(lock_id , since , until , limit)
+    }
+
+    /// Every recorded event, for snapshots.
+    pub fn history_events(&self) -> Vec<HistoryEvent> {
+        self.history.events()
+    }
+
+    /// Usage statistics of `lock_id`, if it was ever acquired.
+    pub fn stats(&self , lock_id : &LockId) -> Option<LockStats> {
+        self.stats_of(lock_id).get(lock_id).cloned()
+    }
+
+    /// Statistics of every lock, the ones with the most queued acquires
+    /// first.
+    pub fn most_contended(&self) -> Vec<(LockId , LockStats)> {
+        let mut contended : Vec<(LockId , LockStats)> = self.stats.iter()
+            .flat_map(|shard| shard.lock().unwrap().iter().map(|(lock_id , stats)| (lock_id.clone() , stats.clone())).collect::<Vec<_>>())
+            .collect();
+        contended.sort_by(|(a_id , a) , (b_id , b)| {
+            b.queued.cmp(&a.queued)
+                .then_with(|| b.contention_rate().total_cmp(&a.contention_rate()))
+                .then_with(|| a_id.cmp(b_id))
+        });
+        contended
+    }
+
+    fn stats_of(&self , lock_id : &LockId) -> MutexGuard<'_ , HashMap<LockId , LockStats>> {
+        self.stats[self.locks.shard_of(lock_id)].lock().unwrap()
+    }
+
+    fn update_stats(&self , lock_id : &LockId , update : impl FnOnce(&mut LockStats)){
+        update(self.stats_of(lock_id).entry(lock_id.clone()).or_default());
+    }
+
+    fn record(&self , kind : HistoryEventKind , lock_id : &LockId , client_id : &ClientId , lease_id : Option<&LeaseId> , at : DateTime<Utc> , ctx : &ApplyContext){
+        let event = HistoryEvent { lock_id: lock_id.clone(), kind, client_id: client_id.clone(), lease_id: lease_id.cloned(), index: ctx.index, at, metadata: Metadata::new(), actor: None };
+        self.history.record(self.locks.shard_of(lock_id) , event , ctx.now);
+    }
+
+    /// Records an operator's doing, naming `actor` as the one who asked.
+    fn record_by(&self , kind : HistoryEventKind , lock_id : &LockId , client_id : &ClientId , lease_id : Option<&LeaseId> , actor : &str , ctx : &ApplyContext){
+        let event = HistoryEvent { lock_id: lock_id.clone(), kind, client_id: client_id.clone(), lease_id: lease_id.cloned(), index: ctx.index, at: ctx.now, metadata: Metadata::new(), actor: Some(actor.to_string()) };
+        self.history.record(self.locks.shard_of(lock_id) , event , ctx.now);
+    }
+
+    /// Records a grant or renewal with the lease's metadata.
+    fn record_lease(&self , kind : HistoryEventKind , lock_id : &LockId , holder : &LockHolder , ctx : &ApplyContext){
+        let event = HistoryEvent { lock_id: lock_id.clone(), kind, client_id: holder.client_id.clone(), lease_id: Some(holder.lease_id.clone()), index: ctx.index, at: ctx.now, metadata: holder.metadata.clone(), actor: None };
+        self.history.record(self.locks.shard_of(lock_id) , event , ctx.now);
+    }
+
+    /// Records the end of a lease that `actor` took from its holder.
+    fn record_end(&self , kind : HistoryEventKind , lock_id : &LockId , holder : &LockHolder , actor : &str , ctx : &ApplyContext){
+        self.update_stats(lock_id , |stats| stats.ended(LeaseEnd::ForceReleased , (ctx.now - holder.acquired_at).to_std().unwrap_or_default()));
+        self.record_by(kind , lock_id , &holder.client_id , Some(&holder.lease_id) , actor , ctx);
+    }
+
+    fn record_expiry(&self , lock_id : &LockId , holder : &LockHolder , ctx : &ApplyContext){
+        counter!(LOCK_EXPIRATIONS).increment(1);
+        self.update_stats(lock_id , |stats| stats.ended(LeaseEnd::Expired , (holder.expires_at - holder.acquired_at).to_std().unwrap_or_default()));
+        self.record(HistoryEventKind::Expired , lock_id , &holder.client_id , Some(&holder.lease_id) , holder.expires_at , ctx);
+    }
+
+    /// Grants a lock nobody holds to the first waiter, for the TTL it asked for.
+    fn promote_next_waiter(&self , lock_id : &LockId , lock_state : &mut LockState , ctx : &ApplyContext) -> Option<ClientId> {
+        if lock_state.wait_queue.is_empty() {
+            return None
+        }
+        let next_waiter = lock_state.wait_queue.remove(0);
+        let waited = (ctx.now - next_waiter.requested_at).to_std().unwrap_or_default();
+        histogram!(QUEUE_WAIT).record(waited.as_secs_f64());
+        self.update_stats(lock_id , |stats| stats.granted(&next_waiter.client_id , Some(waited)));
+        let holder = lock_state.holder.insert(LockHolder {
+            client_id: next_waiter.client_id.clone(),
+            lease_id: ctx.next_lease_id(),
+            acquired_at: ctx.now,
+            expires_at: ctx.now + lease_length(Duration::from_secs(next_waiter.ttl_seconds)),
+            renewal_count: 0,
+            fencing_token: ctx.index,
+            metadata: next_waiter.metadata
+        });
+        self.record_lease(HistoryEventKind::Granted , lock_id , holder , ctx);
+        Some(next_waiter.client_id)
+    }
+
+    /// Takes the lock from its holder, whoever it is, and grants it to the
+    /// next waiter. With `lease_id`, only a holder of that lease loses it.
+    /// Returns the previous holder and the promoted waiter, or `None` when
+    /// nobody holds the lock under the expected lease.
+    pub fn force_release_at(&self , lock_id : &LockId , lease_id : Option<&LeaseId> , actor : &str , ctx : &ApplyContext) -> Option<(ClientId , Option<ClientId>)> {
+        self.with_lock(lock_id , |lock_state| {
+            let previous = lock_state.holder.take_if(|holder| lease_id.is_none_or(|lease_id| holder.lease_id == *lease_id))?;
+            self.record_end(HistoryEventKind::ForceReleased , lock_id , &previous , actor , ctx);
+            let promoted = self.promote_next_waiter(lock_id , lock_state , ctx);
+            counter!(LOCK_OPERATIONS , "operation" => "force_release" , "result" => "success").increment(1);
+            Some((previous.client_id , promoted))
+        }).flatten()
+    }
+
+    /// Hands the lock from its holder, proven by `lease_id`, to `target`
+    /// with a new lease and the same metadata. Both happen in the same
+    /// entry, so no other client can take the lock in between. `target`
+    /// leaves the wait queue if it was in it; other waiters keep their place.
+    pub fn transfer_at(&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId , target : &ClientId , ttl : Duration , ctx : &ApplyContext) -> TransferResult {
+        let result = self.transfer(lock_id , client_id , lease_id , target , ttl , ctx);
+        let outcome = match &result {
+            TransferResult::Transferred { .. } => "success",
+            TransferResult::NotHolder => "not_holder",
+            TransferResult::NotFound => "not_found",
+            TransferResult::Expired => "expired"
+        };
+        counter!(LOCK_OPERATIONS , "operation" => "transfer" , "result" => outcome).increment(1);
+        result
+    }
+
+    fn transfer(&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId , target : &ClientId , ttl : Duration , ctx : &ApplyContext) -> TransferResult {
+        self.with_lock(lock_id , |lock_state| self.transfer_in(lock_state , lock_id , client_id , lease_id , target , ttl , ctx)).unwrap_or(TransferResult::NotFound)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn transfer_in(&self , lock_state : &mut LockState , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId , target : &ClientId , ttl : Duration , ctx : &ApplyContext) -> TransferResult {
+        let Some(holder) = lock_state.holder.as_ref().filter(|holder| holder.client_id == *client_id && holder.lease_id == *lease_id) else {
+            return TransferResult::NotHolder
+        };
+        if holder.expires_at < ctx.now {
+            return TransferResult::Expired
+        }
+        self.record(HistoryEventKind::Transferred , lock_id , client_id , Some(lease_id) , ctx.now , ctx);
+        self.update_stats(lock_id , |stats| stats.ended(LeaseEnd::Transferred , (ctx.now - holder.acquired_at).to_std().unwrap_or_default()));
+        let metadata = holder.metadata.clone();
+
+        lock_state.wait_queue.retain(|waiter| waiter.client_id != *target);
+        let expires_at = ctx.now + lease_length(ttl);
+        self.update_stats(lock_id , |stats| stats.granted(target , None));
+        let holder = lock_state.holder.insert(LockHolder {
+            client_id: target.clone(),
+            lease_id: ctx.next_lease_id(),
+            acquired_at: ctx.now,
+            expires_at,
+            renewal_count: 0,
+            fencing_token: ctx.index,
+            metadata
+        });
+        self.record_lease(HistoryEventKind::Granted , lock_id , holder , ctx);
+        TransferResult::Transferred { lease_id: holder.lease_id.clone(), expires_at, fencing_token: ctx.index }
+    }
+
+    /// Removes `client_id` from the lock's wait queue. Returns how many
+    /// entries it had there.
+    pub fn evict_waiter_at(&self , lock_id : &LockId , client_id : &ClientId , actor : &str , ctx : &ApplyContext) -> usize {
+        let removed = self.with_lock(lock_id , |lock_state| self.evict(lock_id , lock_state , client_id , actor , ctx)).unwrap_or(0);
+        counter!(LOCK_OPERATIONS , "operation" => "evict_waiter" , "result" => if removed > 0 { "success" } else { "not_found" }).increment(1);
+        removed
+    }
+
+    /// Drops `client_id` from every wait queue, then releases every lock it
+    /// holds so its waiters are promoted. Returns the number of locks
+    /// released and of queue entries removed.
+    pub fn revoke_client_at(&self , client_id : &ClientId , actor : &str , ctx : &ApplyContext) -> (usize , usize) {
+        // In lock id order, so promoted waiters get the same lease ids on
+        // every replica.
+        let mut table = self.locks.write();
+        let (mut released , mut evicted) = (0 , 0);
+        for (lock_id , lock_state) in table.iter_sorted_mut() {
+            self.tracked(lock_id , lock_state , |lock_state| {
+                evicted += self.evict(lock_id , lock_state , client_id , actor , ctx);
+                if let Some(holder) = lock_state.holder.take_if(|holder| holder.client_id == *client_id) {
+                    self.record_end(HistoryEventKind::Revoked , lock_id , &holder , actor , ctx);
+                    self.promote_next_waiter(lock_id , lock_state , ctx);
+                    released += 1;
+                }
+            });
+        }
+        counter!(LOCK_OPERATIONS , "operation" => "revoke_client" , "result" => "success").increment(1);
+        (released , evicted)
+    }
 
-        // let count = locks.keys().count();
-        let chrono_ttl = ChronoDuration::from_std(ttl).expect("TTL too large for chrono");
+    /// Drops every queue entry of `client_id` and returns how many there were.
+    fn evict(&self , lock_id : &LockId , lock_state : &mut LockState , client_id : &ClientId , actor : &str , ctx : &ApplyContext) -> usize {
+        let before = lock_state.wait_queue.len();
+        lock_state.wait_queue.retain(|waiter| waiter.client_id != *client_id);
+        let removed = before - lock_state.wait_queue.len();
+        for _ in 0..removed {
+            self.record_by(HistoryEventKind::Evicted , lock_id , client_id , None , actor , ctx);
+        }
+        removed
+    }
 
-        let lock_state = locks.entry(lock_id.clone()).or_insert(LockState { holder: None, wait_queue: Vec::new(), created_at: Utc::now() });
+    /// Locks whose id starts with `prefix` and sorts after `after`, in lock
+    /// id order, at most `limit` of them.
+    pub fn list_page(&self , prefix : &str , after : Option<&str> , limit : usize) -> Vec<(LockId , LockState)> {
+        let table = self.locks.read();
+        table.ids().into_iter()
+            .filter(|lock_id| lock_id.0.starts_with(prefix) && after.is_none_or(|after| lock_id.0.as_str() > after))
+            .take(limit)
+            .filter_map(|lock_id| Some((lock_id.clone() , table.get(lock_id)?)))
+            .collect()
+    }
 
-        if lock_state.holder.as_ref().map_or(false, |h| h.expires_at < Utc::now()){
-            lock_state.holder = None;
+    /// Sets the gauges describing the whole table. Called when metrics are
+    /// scraped rather than on every operation, since it walks every lock.
+    pub fn record_gauges(&self , now : DateTime<Utc>){
+        let (mut locks , mut held , mut waiters , mut max_queue) = (0 , 0 , 0 , 0);
+        self.locks.read().for_each(|_ , state| {
+            locks += 1;
+            held += state.holder.as_ref().is_some_and(|holder| holder.expires_at >= now) as usize;
+            waiters += state.wait_queue.len();
+            max_queue = max_queue.max(state.wait_queue.len());
+        });
+        gauge!(LOCKS).set(locks as f64);
+        gauge!(LOCKS_HELD).set(held as f64);
+        gauge!(WAITERS).set(waiters as f64);
+        gauge!(MAX_QUEUE_LENGTH).set(max_queue as f64);
+    }
 
+    /// Runs `f` on the state of `lock_id`, if it exists, keeping the
+    /// counts of `max_locks_per_client` up to date.
+    fn with_lock<R>(&self , lock_id : &LockId , f : impl FnOnce(&mut LockState) -> R) -> Option<R> {
+        self.locks.with(lock_id , |lock_state| self.tracked(lock_id , lock_state , f))
+    }
+
+    /// Runs `f` on a lock the caller holds, keeping the counts of
+    /// `max_locks_per_client` up to date and deleting the keys of a lease
+    /// that `f` ended.
+    fn tracked<R>(&self , lock_id : &LockId , lock_state : &mut LockState , f : impl FnOnce(&mut LockState) -> R) -> R {
+        let lease_id = lock_state.holder.as_ref().map(|holder| holder.lease_id.clone());
+        let before = self.clients.enabled().then(|| ClientLocks::takers(lock_state));
+        let result = f(lock_state);
+        if let Some(before) = before {
+            self.clients.update(lock_id , before , lock_state);
+        }
+        if let Some(lease_id) = lease_id.filter(|lease_id| lock_state.holder.as_ref().is_none_or(|holder| holder.lease_id != *lease_id)) {
+            self.drop_keys_of(lock_id , &lease_id);
+        }
+        result
+    }
+
+    /// Drops the entries nobody holds or waits for, which only remember
+    /// that the lock was once used. Takes the whole table, so it only runs
+    /// once the table or a namespace is full.
+    fn drop_idle_locks(&self , ctx : &ApplyContext) {
+        self.locks.write().retain(|lock_id , state| {
+            let keep = state.holder.as_ref().is_some_and(|holder| holder.expires_at >= ctx.now) || !state.wait_queue.is_empty();
+            if let Some(holder) = state.holder.as_ref().filter(|_| !keep) {
+                self.record_expiry(lock_id , holder , ctx);
+                self.drop_keys_of(lock_id , &holder.lease_id);
+            }
+            if !keep {
+                self.stats_of(lock_id).remove(lock_id);
+            }
+            keep
+        });
+    }
+
+    /// Why there was no room for a new entry of `lock_id`.
+    fn full(&self , quotas : &LockQuotas , lock_id : &LockId) -> String {
+        if quotas.max_locks > 0 && self.locks.len() >= quotas.max_locks {
+            return format!("The lock table is full ({} locks)" , self.locks.len())
+        }
+        format!("Namespace '{}' is full ({} locks)" , namespace(lock_id) , quotas.max_locks_per_namespace)
+    }
+
+    /// The only quota on a lock that already exists.
+    fn check_waiters(quotas : &LockQuotas , lock_id : &LockId , state : &LockState , ctx : &ApplyContext) -> Result<() , String> {
+        let live = state.holder.as_ref().is_some_and(|holder| holder.expires_at >= ctx.now);
+        if quotas.max_waiters_per_lock > 0 && live && state.wait_queue.len() >= quotas.max_waiters_per_lock {
+            return Err(format!("'{}' already has {} waiters" , lock_id.0 , state.wait_queue.len()))
+        }
+        Ok(())
+    }
+
+    /// Like `try_acquire_at`, giving `metadata` to the lease whether it is
+    /// granted now or when the client is promoted.
+    pub fn acquire_with_metadata_at(&self , lock_id : &LockId , client_id : &ClientId , ttl : Duration , metadata : Metadata , ctx : &ApplyContext) -> AcquireResult {
+        let result = self.acquire(lock_id , client_id , ttl , metadata , ctx);
+        let outcome = match &result {
+            AcquireResult::Granted { .. } => "granted",
+            AcquireResult::Queued { .. } => "queued",
+            AcquireResult::QuotaExceeded(_) => "quota_exceeded",
+            AcquireResult::Error(_) => "error"
+        };
+        counter!(LOCK_OPERATIONS , "operation" => "acquire" , "result" => outcome).increment(1);
+        result
+    }
+
+    /// Like `renew_at`, replacing the lease's metadata when `metadata` is
+    /// set.
+    pub fn renew_with_metadata_at(&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId , ttl : Duration , metadata : Option<Metadata> , ctx : &ApplyContext) -> RenewResult {
+        let result = self.renew_lock(lock_id , client_id , lease_id , ttl , metadata , ctx);
+        let outcome = match &result {
+            RenewResult::Success { .. } => "success",
+            RenewResult::NotHolder => "not_holder",
+            RenewResult::NotFound => "not_found",
+            RenewResult::Expired => "expired",
+            RenewResult::Error(_) => "error"
+        };
+        counter!(LOCK_OPERATIONS , "operation" => "renew" , "result" => outcome).increment(1);
+        result
+    }
+
+    fn acquire (&self , lock_id : &LockId , client_id : &ClientId , ttl : std::time::Duration , metadata : Metadata , ctx : &ApplyContext ) -> AcquireResult {
+        let quotas = if self.limited.load(Ordering::Relaxed) { *self.quotas.read().unwrap() } else { LockQuotas::default() };
+        if quotas.max_locks_per_client > 0 {
+            let taken = self.clients.count(client_id , ctx.now);
+            if taken >= quotas.max_locks_per_client {
+                return AcquireResult::QuotaExceeded(format!("'{}' already holds or waits for {} locks" , client_id.0 , taken))
+            }
+        }
+        let create = || LockState { holder: None, wait_queue: Vec::new(), created_at: ctx.now };
+        let mut metadata = Some(metadata);
+        let mut acquire = |lock_state : &mut LockState| self.tracked(lock_id , lock_state , |lock_state| match Self::check_waiters(&quotas , lock_id , lock_state , ctx) {
+            Ok(()) => self.acquire_in(lock_state , lock_id , client_id , ttl , metadata.take().unwrap_or_default() , ctx),
+            Err(message) => AcquireResult::QuotaExceeded(message)
+        });
+        if let Some(result) = self.locks.with_or_insert_within(lock_id , quotas.max_locks , quotas.max_locks_per_namespace , create , &mut acquire) {
+            return result
+        }
+        self.drop_idle_locks(ctx);
+        self.locks.with_or_insert_within(lock_id , quotas.max_locks , quotas.max_locks_per_namespace , create , &mut acquire)
+            .unwrap_or_else(|| AcquireResult::QuotaExceeded(self.full(&quotas , lock_id)))
+    }
+
+    fn acquire_in(&self , lock_state : &mut LockState , lock_id : &LockId , client_id : &ClientId , ttl : Duration , metadata : Metadata , ctx : &ApplyContext) -> AcquireResult {
+        let chrono_ttl = lease_length(ttl);
+
+        if let Some(expired) = lock_state.holder.take_if(|h| h.expires_at < ctx.now){
+            self.record_expiry(lock_id , &expired , ctx);
         }
       
         if lock_state.holder.is_none(){
 
-            let lease_id = LeaseId(
-                 uuid::Uuid::new_v4().to_string()
-            );
-            let expires_at = Utc::now() + chrono_ttl; 
+            let lease_id = ctx.next_lease_id();
+            let expires_at = ctx.now + chrono_ttl; 
 
-            lock_state.holder = Some(LockHolder { client_id: client_id.clone(), lease_id: lease_id.clone() , acquired_at: Utc::now(), expires_at, renewal_count: 0 });
+            self.update_stats(lock_id , |stats| {
+                stats.acquired(false , 0);
+                stats.granted(client_id , None);
+            });
+            let holder = lock_state.holder.insert(LockHolder { client_id: client_id.clone(), lease_id: lease_id.clone() , acquired_at: ctx.now, expires_at, renewal_count: 0, fencing_token: ctx.index, metadata });
+            self.record_lease(HistoryEventKind::Granted , lock_id , holder , ctx);
 
-            AcquireResult::Granted { lease_id, expires_at }
+            AcquireResult::Granted { lease_id, expires_at, fencing_token: ctx.index }
         }
         else {
             let position = lock_state.wait_queue.len();
             lock_state.wait_queue.push(WaitRequest {
                 client_id : client_id.clone() , 
-                requested_at : Utc::now()
+                requested_at : ctx.now ,
+                ttl_seconds : ttl.as_secs() ,
+                metadata
             });
+            self.record(HistoryEventKind::Queued , lock_id , client_id , None , ctx.now , ctx);
+            self.update_stats(lock_id , |stats| stats.acquired(true , position + 1));
             let estimated_wait = if let Some(holder) = &lock_state.holder{
-                let remaining = holder.expires_at - Utc::now();
+                let remaining = holder.expires_at - ctx.now;
                 std::time::Duration::from_secs(remaining.num_seconds().max(0) as u64)
             }else {
                 std::time::Duration::from_secs(0)
@@ -68,13 +584,12 @@ impl LockManager for InMemoryLockManager{
 
     }
 
-     fn release (&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId ) -> ReleaseResult {
+     fn release_lock (&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId , ctx : &ApplyContext ) -> ReleaseResult {
+        self.with_lock(lock_id , |lock_state| self.release_in(lock_state , lock_id , client_id , lease_id , ctx)).unwrap_or(ReleaseResult::NotFound)
+     }
+
+     fn release_in (&self , lock_state : &mut LockState , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId , ctx : &ApplyContext ) -> ReleaseResult {
         // verify identy 
-        let mut locks = self.locks.write().unwrap();
-       let lock_state = match locks.get_mut(&lock_id){
-         Some(state) => state , 
-         None => return ReleaseResult::NotFound
-       };
        let current_holder = match &lock_state.holder{
         Some(holder) =>holder, 
         None => return ReleaseResult::NotHolder
@@ -82,36 +597,19 @@ impl LockManager for InMemoryLockManager{
        if current_holder.client_id != *client_id || current_holder.lease_id != *lease_id{
         return  ReleaseResult::NotHolder;
        }
+       self.record(HistoryEventKind::Released , lock_id , client_id , Some(lease_id) , ctx.now , ctx);
+       self.update_stats(lock_id , |stats| stats.ended(LeaseEnd::Released , (ctx.now - current_holder.acquired_at).to_std().unwrap_or_default()));
        lock_state.holder = None;
-
-    
-
-       if let true  = !lock_state.wait_queue.is_empty(){
-
-           let next_waiter = lock_state.wait_queue.remove(0);
-            let new_lease_id = LeaseId(uuid::Uuid::new_v4().to_string());
-           
-            
-            let expires_at = Utc::now() + self.default_ttl;
-
-           lock_state.holder =Some( LockHolder{
-            client_id : next_waiter.client_id,
-            lease_id : new_lease_id , 
-            acquired_at:Utc::now() , expires_at , renewal_count : 0
-       });
-
-    }
+       self.promote_next_waiter(lock_id , lock_state , ctx);
     ReleaseResult::Success
 
      }
- fn renew(&self, lock_id: &LockId, client_id: &ClientId, lease_id: &LeaseId, ttl: Duration) -> RenewResult {
-    let mut locks = self.locks.write().unwrap();
-
-    let lock_state = match locks.get_mut(lock_id) {
-        Some(state) => state,
-        None => return RenewResult::NotFound,
-    };
+fn renew_lock(&self, lock_id: &LockId, client_id: &ClientId, lease_id: &LeaseId, ttl: Duration, metadata: Option<Metadata>, ctx: &ApplyContext) -> RenewResult {
+    self.with_lock(lock_id , |lock_state| self.renew_in(lock_state , lock_id , client_id , lease_id , ttl , metadata , ctx)).unwrap_or(RenewResult::NotFound)
+}
 
+#[allow(clippy::too_many_arguments)]
+fn renew_in(&self, lock_state: &mut LockState, lock_id: &LockId, client_id: &ClientId, lease_id: &LeaseId, ttl: Duration, metadata: Option<Metadata>, ctx: &ApplyContext) -> RenewResult {
     match lock_state.holder.as_mut() {
         Some(holder) => {
             // Verify ownership
@@ -120,14 +618,18 @@ impl LockManager for InMemoryLockManager{
             }
             
             // Check if already expired
-            if holder.expires_at < Utc::now() {
+            if holder.expires_at < ctx.now {
                 return RenewResult::Expired;
             }
             
             // Perform renewal
-            let new_expiry = Utc::now() + ChronoDuration::from_std(ttl).expect("TTL too large for Chrono");
+            let new_expiry = ctx.now + lease_length(ttl);
             holder.expires_at = new_expiry;
             holder.renewal_count += 1;
+            if let Some(metadata) = metadata {
+                holder.metadata = metadata;
+            }
+            self.record_lease(HistoryEventKind::Renewed , lock_id , holder , ctx);
             
             RenewResult::Success { new_expiry }
         }
@@ -136,31 +638,167 @@ impl LockManager for InMemoryLockManager{
         }
     }
 }
+}
+
+impl LockManager for InMemoryLockManager{
+    fn try_acquire_at (&self , lock_id : &LockId , client_id : &ClientId , ttl : std::time::Duration , ctx : &ApplyContext ) -> AcquireResult {
+        self.acquire_with_metadata_at(lock_id , client_id , ttl , Metadata::new() , ctx)
+    }
+
+    fn release_at (&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId , ctx : &ApplyContext ) -> ReleaseResult {
+        let result = self.release_lock(lock_id , client_id , lease_id , ctx);
+        let outcome = match &result {
+            ReleaseResult::Success => "success",
+            ReleaseResult::NotHolder => "not_holder",
+            ReleaseResult::NotFound => "not_found",
+            ReleaseResult::Error(_) => "error"
+        };
+        counter!(LOCK_OPERATIONS , "operation" => "release" , "result" => outcome).increment(1);
+        result
+    }
+
+    fn renew_at(&self, lock_id: &LockId, client_id: &ClientId, lease_id: &LeaseId, ttl: Duration, ctx: &ApplyContext) -> RenewResult {
+        self.renew_with_metadata_at(lock_id , client_id , lease_id , ttl , None , ctx)
+    }
+
 fn status(&self , lock_id : &LockId ) -> Option<LockState> {
 
-    let locks = self.locks.read().unwrap();
-    let lock_state = locks.get(lock_id).cloned();
-    lock_state
+    self.locks.get(lock_id)
 
         
     }
 
 
-    fn current_holder(&self , lock_id : &LockId) -> Option<ClientId> {
-    let locks = self.locks.read().unwrap();
+    fn list(&self) -> Vec<(LockId , LockState)> {
+        let table = self.locks.read();
+        table.ids().into_iter().filter_map(|lock_id| Some((lock_id.clone() , table.get(lock_id)?))).collect()
+    }
 
-    locks.get(&lock_id).and_then(|state| state.holder.as_ref()).map(|holder|holder.client_id.clone())
+    fn current_holder(&self , lock_id : &LockId) -> Option<ClientId> {
+    self.locks.with(lock_id , |state| state.holder.as_ref().map(|holder|holder.client_id.clone())).flatten()
 
 }
 fn queue_length(&self , lock_id : &LockId) -> usize {
-    let locks = self.locks.read().unwrap();
+    self.locks.with(lock_id , |state| state.wait_queue.len()).unwrap_or(0)
+}
+
+
 
-    match locks.get(&lock_id){
-        Some(state) => {return state.wait_queue.len()},
-        None => return 0
-    };
 }
 
+// Delegates to the methods above; the lock table is in memory, so nothing
+// ever waits.
+#[async_trait]
+impl LockBackend for InMemoryLockManager{
+    async fn acquire(&self , lock_id : &LockId , client_id : &ClientId , ttl : Duration , metadata : Metadata , ctx : &ApplyContext) -> AcquireResult {
+        self.acquire_with_metadata_at(lock_id , client_id , ttl , metadata , ctx)
+    }
 
+    async fn release(&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId , ctx : &ApplyContext) -> ReleaseResult {
+        self.release_at(lock_id , client_id , lease_id , ctx)
+    }
 
-}
\ No newline at end of file
+    async fn renew(&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId , ttl : Duration , metadata : Option<Metadata> , ctx : &ApplyContext) -> RenewResult {
+        self.renew_with_metadata_at(lock_id , client_id , lease_id , ttl , metadata , ctx)
+    }
+
+    async fn transfer(&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId , target : &ClientId , ttl : Duration , ctx : &ApplyContext) -> TransferResult {
+        self.transfer_at(lock_id , client_id , lease_id , target , ttl , ctx)
+    }
+
+    async fn force_release(&self , lock_id : &LockId , lease_id : Option<&LeaseId> , actor : &str , ctx : &ApplyContext) -> Option<(ClientId , Option<ClientId>)> {
+        self.force_release_at(lock_id , lease_id , actor , ctx)
+    }
+
+    async fn evict_waiter(&self , lock_id : &LockId , client_id : &ClientId , actor : &str , ctx : &ApplyContext) -> usize {
+        self.evict_waiter_at(lock_id , client_id , actor , ctx)
+    }
+
+    async fn revoke_client(&self , client_id : &ClientId , actor : &str , ctx : &ApplyContext) -> (usize , usize) {
+        self.revoke_client_at(client_id , actor , ctx)
+    }
+
+    async fn restore(&self , locks : Vec<(LockId , LockState)> , history : Vec<HistoryEvent> , kv : Vec<KvEntry> , barriers : Vec<(String , Barrier)> , ctx : &ApplyContext) {
+        InMemoryLockManager::restore(self , locks , history , kv , barriers , ctx)
+    }
+
+    async fn set_quotas(&self , quotas : LockQuotas) {
+        InMemoryLockManager::set_quotas(self , quotas)
+    }
+
+    async fn kv_get(&self , key : &str , ctx : &ApplyContext) -> Option<KvEntry> {
+        self.kv_get_at(key , ctx)
+    }
+
+    async fn kv_put(&self , key : &str , value : String , client_id : &ClientId , expected_version : Option<u64> , lease : Option<KvLease> , ctx : &ApplyContext) -> KvResult {
+        self.kv_put_at(key , value , client_id , expected_version , lease , ctx)
+    }
+
+    async fn kv_delete(&self , key : &str , expected_version : Option<u64> , ctx : &ApplyContext) -> KvResult {
+        self.kv_delete_at(key , expected_version , ctx)
+    }
+
+    async fn barrier_create(&self , name : &str , kind : BarrierKind , count : u32 , ctx : &ApplyContext) -> BarrierResult {
+        self.barrier_create_at(name , kind , count , ctx)
+    }
+
+    async fn barrier_join(&self , name : &str , client_id : &ClientId , ttl : Duration , ctx : &ApplyContext) -> BarrierResult {
+        self.barrier_join_at(name , client_id , ttl , ctx)
+    }
+
+    async fn barrier_arrive(&self , name : &str , client_id : &ClientId , ctx : &ApplyContext) -> BarrierResult {
+        self.barrier_arrive_at(name , client_id , ctx)
+    }
+
+    async fn barrier_delete(&self , name : &str , ctx : &ApplyContext) -> BarrierResult {
+        self.barrier_delete_at(name , ctx)
+    }
+
+    async fn status(&self , lock_id : &LockId) -> Option<LockState> {
+        LockManager::status(self , lock_id)
+    }
+
+    async fn list(&self) -> Vec<(LockId , LockState)> {
+        LockManager::list(self)
+    }
+
+    async fn list_page(&self , prefix : &str , after : Option<&str> , limit : usize) -> Vec<(LockId , LockState)> {
+        InMemoryLockManager::list_page(self , prefix , after , limit)
+    }
+
+    async fn history(&self , lock_id : Option<&LockId> , since : Option<DateTime<Utc>> , until : Option<DateTime<Utc>> , limit : usize) -> Vec<HistoryEvent> {
+        InMemoryLockManager::history(self , lock_id , since , until , limit)
+    }
+
+    async fn history_events(&self) -> Vec<HistoryEvent> {
+        InMemoryLockManager::history_events(self)
+    }
+
+    async fn stats(&self , lock_id : &LockId) -> Option<LockStats> {
+        InMemoryLockManager::stats(self , lock_id)
+    }
+
+    async fn most_contended(&self) -> Vec<(LockId , LockStats)> {
+        InMemoryLockManager::most_contended(self)
+    }
+
+    async fn kv_entries(&self , now : DateTime<Utc>) -> Vec<KvEntry> {
+        InMemoryLockManager::kv_entries(self , now)
+    }
+
+    async fn barrier(&self , name : &str) -> Option<Barrier> {
+        InMemoryLockManager::barrier(self , name)
+    }
+
+    async fn barriers(&self) -> Vec<(String , Barrier)> {
+        InMemoryLockManager::barriers(self)
+    }
+
+    async fn quotas(&self) -> LockQuotas {
+        InMemoryLockManager::quotas(self)
+    }
+
+    async fn record_gauges(&self , now : DateTime<Utc>) {
+        InMemoryLockManager::record_gauges(self , now)
+    }
+}
@@ -1,3 +1,4 @@
 
 
+#[allow(clippy::module_inception , clippy::empty_line_after_outer_attr , unused_imports)]
 pub mod test;
\ No newline at end of file
@@ -35,4 +35,729 @@ mod test{
         assert_eq!(manager.current_holder(&lock_id) , None);
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests{
+    use std::time::{Duration, Instant};
+    use chrono::{DateTime, Utc};
+    use crate::{config::server_config::{HistoryConfig, MAX_TTL_SECONDS}, telemetry::prometheus::recorder, lock::{barrier::{BarrierKind, BarrierResult}, history::HistoryEventKind, kv::{KvLease, KvResult}, manager::InMemoryLockManager, metadata::{self, Metadata}, quota::{LockQuotas, RateLimiter}, stats::{LeaseEnd, LockStats}, types::{AcquireResult, ApplyContext, ClientId, LeaseId, LockId, LockManager, ReleaseResult, TransferResult}}};
+
+    fn lock(id : &str) -> LockId {
+        LockId(id.to_string())
+    }
+
+    fn client(id : &str) -> ClientId {
+        ClientId(id.to_string())
+    }
+
+    #[test]
+
+    fn test_list_and_restore_round_trip(){
+        let manager = InMemoryLockManager::new();
+        let client1 = ClientId("client_1".to_string());
+        manager.try_acquire(&LockId("b".to_string()), &client1, Duration::from_secs(30));
+        manager.try_acquire(&LockId("a".to_string()), &client1, Duration::from_secs(30));
+        manager.try_acquire(&LockId("a".to_string()), &ClientId("client_2".to_string()), Duration::from_secs(30));
+
+        let locks = manager.list();
+        assert_eq!(locks.iter().map(|(lock_id , _)| lock_id.0.as_str()).collect::<Vec<_>>() , vec!["a" , "b"]);
+        assert_eq!(locks[0].1.wait_queue.len() , 1);
+
+        let restored = InMemoryLockManager::new();
+        restored.try_acquire(&LockId("stale".to_string()), &client1, Duration::from_secs(30));
+        restored.restore(locks.clone() , manager.history_events() , Vec::new() , Vec::new() , &ApplyContext::local());
+
+        assert_eq!(restored.list() , locks);
+        assert_eq!(restored.history_events() , manager.history_events());
+        assert_eq!(restored.current_holder(&LockId("a".to_string())) , Some(client1));
+    }
+
+    #[test]
+
+    fn test_quotas_refuse_acquires_beyond_the_limits(){
+        let ttl = Duration::from_secs(30);
+        let manager = InMemoryLockManager::new().with_quotas(LockQuotas { max_locks_per_client: 2, max_waiters_per_lock: 1, ..Default::default() });
+
+        assert!(matches!(manager.try_acquire(&lock("a") , &client("greedy") , ttl) , AcquireResult::Granted { .. }));
+        assert!(matches!(manager.try_acquire(&lock("b") , &client("greedy") , ttl) , AcquireResult::Granted { .. }));
+        assert!(matches!(manager.try_acquire(&lock("c") , &client("greedy") , ttl) , AcquireResult::QuotaExceeded(_)));
+        assert!(manager.status(&lock("c")).is_none());
+
+        assert!(matches!(manager.try_acquire(&lock("a") , &client("first") , ttl) , AcquireResult::Queued { .. }));
+        assert!(matches!(manager.try_acquire(&lock("a") , &client("second") , ttl) , AcquireResult::QuotaExceeded(_)));
+        assert_eq!(manager.queue_length(&lock("a")) , 1);
+    }
+
+    #[test]
+
+    fn test_full_table_drops_idle_locks(){
+        let ttl = Duration::from_secs(30);
+        let manager = InMemoryLockManager::new().with_quotas(LockQuotas { max_locks: 2, max_locks_per_namespace: 1, ..Default::default() });
+
+        let lease_id = match manager.try_acquire(&lock("jobs/a") , &client("worker") , ttl) {
+            AcquireResult::Granted { lease_id , .. } => lease_id,
+            other => panic!("Expected granted, got {:?}" , other)
+        };
+        assert!(matches!(manager.try_acquire(&lock("jobs/b") , &client("worker") , ttl) , AcquireResult::QuotaExceeded(_)));
+        assert!(matches!(manager.try_acquire(&lock("cron/a") , &client("worker") , ttl) , AcquireResult::Granted { .. }));
+        assert!(matches!(manager.try_acquire(&lock("mail") , &client("worker") , ttl) , AcquireResult::QuotaExceeded(_)));
+
+        // Once released, jobs/a only takes up room and makes way.
+        assert!(matches!(manager.release(&lock("jobs/a") , &client("worker") , &lease_id) , ReleaseResult::Success));
+        assert!(matches!(manager.try_acquire(&lock("jobs/b") , &client("worker") , ttl) , AcquireResult::Granted { .. }));
+        assert!(manager.status(&lock("jobs/a")).is_none());
+    }
+
+    #[test]
+
+    fn test_client_quota_counts_follow_the_locks(){
+        let ttl = Duration::from_secs(30);
+        let start = Utc::now();
+        let at = |seconds : i64| ApplyContext::new(start + chrono::Duration::seconds(seconds) , seconds as u64);
+        let manager = InMemoryLockManager::new();
+        let lease_id = match manager.try_acquire_at(&lock("a") , &client("worker") , ttl , &at(0)) {
+            AcquireResult::Granted { lease_id , .. } => lease_id,
+            other => panic!("Expected granted, got {:?}" , other)
+        };
+        assert!(matches!(manager.try_acquire_at(&lock("b") , &client("other") , ttl , &at(0)) , AcquireResult::Granted { .. }));
+        assert!(matches!(manager.try_acquire_at(&lock("b") , &client("worker") , ttl , &at(0)) , AcquireResult::Queued { .. }));
+
+        // Locks taken before the limit was set count too.
+        manager.set_quotas(LockQuotas { max_locks_per_client: 2, ..Default::default() });
+        assert!(matches!(manager.try_acquire_at(&lock("c") , &client("worker") , ttl , &at(0)) , AcquireResult::QuotaExceeded(_)));
+
+        assert!(matches!(manager.release_at(&lock("a") , &client("worker") , &lease_id , &at(1)) , ReleaseResult::Success));
+        assert!(matches!(manager.try_acquire_at(&lock("c") , &client("worker") , ttl , &at(1)) , AcquireResult::Granted { .. }));
+        assert!(matches!(manager.try_acquire_at(&lock("d") , &client("worker") , ttl , &at(1)) , AcquireResult::QuotaExceeded(_)));
+
+        // Promoted on b when other is revoked; both leases ran out by 40s.
+        assert_eq!(manager.revoke_client_at(&client("other") , "ops" , &at(2)) , (1 , 0));
+        assert!(matches!(manager.try_acquire_at(&lock("d") , &client("worker") , ttl , &at(40)) , AcquireResult::Granted { .. }));
+    }
+
+    #[test]
+
+    fn test_history_keeps_apply_order_across_shards(){
+        let manager = InMemoryLockManager::new();
+        let ctx = ApplyContext::local();
+        for i in 0..1_500 {
+            manager.try_acquire_at(&lock(&format!("lock-{}" , i)) , &client("worker") , Duration::from_secs(30) , &ctx);
+        }
+        let events = manager.history(None , None , None , usize::MAX);
+        let ids : Vec<String> = events.iter().map(|event| event.lock_id.0.clone()).collect();
+        assert_eq!(ids , (0..1_500).map(|i| format!("lock-{}" , i)).collect::<Vec<_>>());
+    }
+
+    #[test]
+
+    fn test_rate_limiter_refills_per_client(){
+        let limiter = RateLimiter::new(10 , 2);
+        let start = Instant::now();
+
+        assert!(limiter.check_at("a" , start).is_ok());
+        assert!(limiter.check_at("a" , start).is_ok());
+        let wait = limiter.check_at("a" , start).unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_millis(100));
+        assert!(limiter.check_at("b" , start).is_ok());
+
+        assert!(limiter.check_at("a" , start + Duration::from_millis(100)).is_ok());
+        assert!(limiter.check_at("a" , start + Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+
+    fn test_admin_operations_and_pages(){
+        let ttl = Duration::from_secs(30);
+        let manager = InMemoryLockManager::new();
+        for lock_id in ["jobs/a" , "jobs/b" , "jobs/c" , "mail"] {
+            manager.try_acquire(&lock(lock_id) , &client("holder") , ttl);
+        }
+        manager.try_acquire(&lock("jobs/a") , &client("first") , ttl);
+        manager.try_acquire(&lock("jobs/a") , &client("second") , ttl);
+        manager.try_acquire(&lock("jobs/b") , &client("first") , ttl);
+
+        let ids = |page : Vec<(LockId , _)>| page.into_iter().map(|(lock_id , _)| lock_id.0).collect::<Vec<_>>();
+        assert_eq!(ids(manager.list_page("jobs/" , None , 2)) , vec!["jobs/a" , "jobs/b"]);
+        assert_eq!(ids(manager.list_page("jobs/" , Some("jobs/b") , 2)) , vec!["jobs/c"]);
+
+        assert_eq!(manager.evict_waiter_at(&lock("jobs/a") , &client("first") , "oncall" , &ApplyContext::local()) , 1);
+        assert_eq!(manager.evict_waiter_at(&lock("jobs/a") , &client("first") , "oncall" , &ApplyContext::local()) , 0);
+
+        let stale = LeaseId("stale".to_string());
+        assert!(manager.force_release_at(&lock("jobs/a") , Some(&stale) , "oncall" , &ApplyContext::local()).is_none());
+        assert_eq!(manager.current_holder(&lock("jobs/a")) , Some(client("holder")));
+        let (previous , promoted) = manager.force_release_at(&lock("jobs/a") , None , "oncall" , &ApplyContext::local()).unwrap();
+        assert_eq!((previous , promoted) , (client("holder") , Some(client("second"))));
+
+        assert_eq!(manager.revoke_client_at(&client("holder") , "oncall" , &ApplyContext::local()) , (3 , 0));
+        assert_eq!(manager.current_holder(&lock("jobs/b")) , Some(client("first")));
+        assert_eq!(manager.current_holder(&lock("mail")) , None);
+        assert!(manager.force_release_at(&lock("mail") , None , "oncall" , &ApplyContext::local()).is_none());
+    }
+
+    #[test]
+
+    fn test_history_window_includes_leases_held_across_it(){
+        let start = Utc::now();
+        let at = |index : u64 , seconds : i64| ApplyContext::new(start + chrono::Duration::seconds(seconds) , index).with_index(index);
+        let seconds = |seconds : i64| Some(start + chrono::Duration::seconds(seconds));
+        let manager = InMemoryLockManager::new();
+
+        manager.try_acquire_at(&lock("jobs") , &client("a") , Duration::from_secs(3_600) , &at(1 , 0));
+        let AcquireResult::Granted { lease_id , .. } = manager.try_acquire_at(&lock("mail") , &client("b") , Duration::from_secs(30) , &at(2 , 5)) else { panic!("Expected granted") };
+        manager.release_at(&lock("mail") , &client("b") , &lease_id , &at(3 , 10));
+
+        // Nothing happened between 100 and 200, yet a held "jobs" all along.
+        let window = manager.history(None , seconds(100) , seconds(200) , 100);
+        assert_eq!(window.iter().map(|event| (event.index , event.kind , event.client_id.0.as_str())).collect::<Vec<_>>() , vec![(1 , HistoryEventKind::Granted , "a")]);
+        assert_eq!(manager.history(Some(&lock("mail")) , seconds(100) , seconds(200) , 100) , vec![]);
+        assert_eq!(manager.history(Some(&lock("jobs")) , seconds(100) , seconds(200) , 100).len() , 1);
+    }
+
+    #[test]
+
+    fn test_history_records_every_owner(){
+        let ttl = Duration::from_secs(30);
+        let start = Utc::now();
+        let at = |index : u64 , seconds : i64| ApplyContext::new(start + chrono::Duration::seconds(seconds) , index).with_index(index);
+        let manager = InMemoryLockManager::new();
+
+        let AcquireResult::Granted { lease_id , .. } = manager.try_acquire_at(&lock("jobs") , &client("a") , ttl , &at(1 , 0)) else { panic!("Expected granted") };
+        manager.renew_at(&lock("jobs") , &client("a") , &lease_id , ttl , &at(2 , 10));
+        manager.try_acquire_at(&lock("jobs") , &client("b") , ttl , &at(3 , 20));
+        manager.release_at(&lock("jobs") , &client("a") , &lease_id , &at(4 , 30));
+        manager.try_acquire_at(&lock("jobs") , &client("c") , ttl , &at(5 , 100));
+        manager.force_release_at(&lock("jobs") , None , "oncall" , &at(6 , 110));
+        manager.try_acquire_at(&lock("mail") , &client("a") , ttl , &at(7 , 120));
+
+        let events = manager.history(Some(&lock("jobs")) , None , None , 100);
+        let summary = events.iter().map(|event| (event.index , event.kind , event.client_id.0.as_str())).collect::<Vec<_>>();
+        assert_eq!(summary , vec![
+            (1 , HistoryEventKind::Granted , "a"),
+            (2 , HistoryEventKind::Renewed , "a"),
+            (3 , HistoryEventKind::Queued , "b"),
+            (4 , HistoryEventKind::Released , "a"),
+            (4 , HistoryEventKind::Granted , "b"),
+            (5 , HistoryEventKind::Expired , "b"),
+            (5 , HistoryEventKind::Granted , "c"),
+            (6 , HistoryEventKind::ForceReleased , "c")
+        ]);
+        // b's lease ran out 30 seconds after the promotion, not when it was noticed.
+        assert_eq!(events[5].at , start + chrono::Duration::seconds(60));
+        assert_eq!(events[4].lease_id , events[5].lease_id);
+        // Only what an operator did names them.
+        assert_eq!(events[7].actor.as_deref() , Some("oncall"));
+        assert!(events[..7].iter().all(|event| event.actor.is_none()));
+
+        let window = manager.history(Some(&lock("jobs")) , Some(start + chrono::Duration::seconds(30)) , Some(start + chrono::Duration::seconds(60)) , 100);
+        // a still held the lock at 30, under the lease it renewed at 10.
+        assert_eq!(window.iter().map(|event| (event.index , event.kind)).collect::<Vec<_>>() , vec![
+            (2 , HistoryEventKind::Renewed),
+            (4 , HistoryEventKind::Released),
+            (4 , HistoryEventKind::Granted),
+            (5 , HistoryEventKind::Expired)
+        ]);
+        assert_eq!(manager.history(None , None , None , 100).len() , 9);
+        assert_eq!(manager.history(None , None , None , 2).len() , 2);
+
+        let bounded = InMemoryLockManager::new().with_history(HistoryConfig { max_events: 3, retention_seconds: 60 });
+        for (index , seconds) in [(1 , 0) , (2 , 10) , (3 , 20) , (4 , 25)] {
+            bounded.try_acquire_at(&lock(&format!("lock_{}" , index)) , &client("a") , ttl , &at(index , seconds));
+        }
+        assert_eq!(bounded.history_events().iter().map(|event| event.index).collect::<Vec<_>>() , vec![2 , 3 , 4]);
+        bounded.try_acquire_at(&lock("late") , &client("a") , ttl , &at(5 , 86));
+        assert_eq!(bounded.history_events().iter().map(|event| event.index).collect::<Vec<_>>() , vec![5]);
+    }
+
+    #[test]
+
+    fn test_history_limits_and_window_edges(){
+        let ttl = Duration::from_secs(30);
+        let start = Utc::now();
+        let at = |index : u64 , seconds : i64| ApplyContext::new(start + chrono::Duration::seconds(seconds) , index).with_index(index);
+        let seconds = |seconds : i64| Some(start + chrono::Duration::seconds(seconds));
+        let indexes = |manager : &InMemoryLockManager| manager.history_events().iter().map(|event| event.index).collect::<Vec<_>>();
+
+        let silent = InMemoryLockManager::new().with_history(HistoryConfig { max_events: 0, retention_seconds: 0 });
+        silent.try_acquire_at(&lock("jobs") , &client("a") , ttl , &at(1 , 0));
+        assert!(silent.history_events().is_empty());
+
+        // An event exactly `retention_seconds` old is kept.
+        let retained = InMemoryLockManager::new().with_history(HistoryConfig { max_events: 100, retention_seconds: 60 });
+        for (index , seconds) in [(1 , 0) , (2 , 10) , (3 , 60)] {
+            retained.try_acquire_at(&lock(&format!("lock_{}" , index)) , &client("a") , ttl , &at(index , seconds));
+        }
+        assert_eq!(indexes(&retained) , vec![1 , 2 , 3]);
+        retained.try_acquire_at(&lock("lock_4") , &client("a") , ttl , &at(4 , 71));
+        assert_eq!(indexes(&retained) , vec![3 , 4]);
+
+        // A snapshot larger than `max_events` keeps its newest events.
+        let bounded = InMemoryLockManager::new().with_history(HistoryConfig { max_events: 2, retention_seconds: 0 });
+        bounded.restore(Vec::new() , retained.history_events() , Vec::new() , Vec::new() , &at(5 , 72));
+        assert_eq!(indexes(&bounded) , vec![3 , 4]);
+        let many = InMemoryLockManager::new();
+        for index in 1..=5 {
+            many.try_acquire_at(&lock(&format!("lock_{}" , index)) , &client("a") , ttl , &at(index , index as i64));
+        }
+        bounded.restore(Vec::new() , many.history_events() , Vec::new() , Vec::new() , &at(6 , 6));
+        assert_eq!(indexes(&bounded) , vec![4 , 5]);
+
+        // Both ends of a window are inclusive.
+        let manager = InMemoryLockManager::new();
+        let AcquireResult::Granted { lease_id , .. } = manager.try_acquire_at(&lock("jobs") , &client("a") , ttl , &at(1 , 10)) else { panic!("Expected granted") };
+        manager.release_at(&lock("jobs") , &client("a") , &lease_id , &at(2 , 20));
+        let window = |since : Option<DateTime<Utc>> , until : Option<DateTime<Utc>> , limit : usize| manager.history(Some(&lock("jobs")) , since , until , limit).iter().map(|event| (event.index , event.kind)).collect::<Vec<_>>();
+        assert_eq!(window(seconds(10) , seconds(20) , 100) , vec![(1 , HistoryEventKind::Granted) , (2 , HistoryEventKind::Released)]);
+        // Held up to 20, so a window starting there leads with the grant.
+        assert_eq!(window(seconds(20) , seconds(20) , 100) , vec![(1 , HistoryEventKind::Granted) , (2 , HistoryEventKind::Released)]);
+        assert_eq!(window(seconds(21) , None , 100) , vec![]);
+        assert_eq!(window(None , seconds(9) , 100) , vec![]);
+        assert_eq!(window(None , seconds(10) , 100) , vec![(1 , HistoryEventKind::Granted)]);
+        assert_eq!(window(None , None , 0) , vec![]);
+    }
+
+    #[test]
+
+    fn test_metadata_follows_the_lease(){
+        let ttl = Duration::from_secs(30);
+        let start = Utc::now();
+        let at = |index : u64| ApplyContext::new(start , index).with_index(index);
+        let meta = |pairs : &[(&str , &str)]| pairs.iter().map(|(key , value)| (key.to_string() , value.to_string())).collect::<Metadata>();
+        let manager = InMemoryLockManager::new();
+        let holder = |manager : &InMemoryLockManager| manager.status(&lock("deploy")).and_then(|state| state.holder).unwrap();
+
+        let AcquireResult::Granted { lease_id , .. } = manager.acquire_with_metadata_at(&lock("deploy") , &client("a") , ttl , meta(&[("host" , "web-1")]) , &at(1)) else { panic!("Expected granted") };
+        manager.acquire_with_metadata_at(&lock("deploy") , &client("b") , ttl , meta(&[("host" , "web-2")]) , &at(2));
+        assert_eq!(holder(&manager).metadata , meta(&[("host" , "web-1")]));
+
+        manager.renew_with_metadata_at(&lock("deploy") , &client("a") , &lease_id , ttl , None , &at(3));
+        assert_eq!(holder(&manager).metadata , meta(&[("host" , "web-1")]));
+        manager.renew_with_metadata_at(&lock("deploy") , &client("a") , &lease_id , ttl , Some(meta(&[("host" , "web-1") , ("step" , "migrate")])) , &at(4));
+        assert_eq!(holder(&manager).metadata , meta(&[("host" , "web-1") , ("step" , "migrate")]));
+
+        // The waiter's metadata is kept until it is promoted.
+        manager.release_at(&lock("deploy") , &client("a") , &lease_id , &at(5));
+        assert_eq!(holder(&manager).client_id , client("b"));
+        assert_eq!(holder(&manager).metadata , meta(&[("host" , "web-2")]));
+
+        let history = manager.history(Some(&lock("deploy")) , None , None , 100);
+        let recorded = history.iter().filter(|event| !event.metadata.is_empty()).map(|event| (event.index , event.kind , event.metadata.len())).collect::<Vec<_>>();
+        assert_eq!(recorded , vec![
+            (1 , HistoryEventKind::Granted , 1),
+            (3 , HistoryEventKind::Renewed , 1),
+            (4 , HistoryEventKind::Renewed , 2),
+            (5 , HistoryEventKind::Granted , 1)
+        ]);
+    }
+
+    #[test]
+
+    fn test_promoted_waiter_gets_the_ttl_it_asked_for(){
+        let start = Utc::now();
+        let at = |index : u64| ApplyContext::new(start , index).with_index(index);
+        let manager = InMemoryLockManager::new();
+
+        let AcquireResult::Granted { lease_id , .. } = manager.acquire_with_metadata_at(&lock("deploy") , &client("a") , Duration::from_secs(30) , Metadata::new() , &at(1)) else { panic!("Expected granted") };
+        manager.acquire_with_metadata_at(&lock("deploy") , &client("b") , Duration::from_secs(5) , Metadata::new() , &at(2));
+        manager.release_at(&lock("deploy") , &client("a") , &lease_id , &at(3));
+
+        let holder = manager.status(&lock("deploy")).and_then(|state| state.holder).unwrap();
+        assert_eq!(holder.client_id , client("b"));
+        assert_eq!(holder.expires_at - holder.acquired_at , chrono::Duration::seconds(5));
+    }
+
+    #[test]
+
+    fn test_transfer_to_a_waiter_and_with_a_wrong_lease(){
+        let ttl = Duration::from_secs(30);
+        let start = Utc::now();
+        let at = |index : u64 , seconds : i64| ApplyContext::new(start + chrono::Duration::seconds(seconds) , index).with_index(index);
+        let manager = InMemoryLockManager::new();
+        let queue = || manager.status(&lock("job")).unwrap().wait_queue.iter().map(|waiter| waiter.client_id.0.clone()).collect::<Vec<_>>();
+
+        let AcquireResult::Granted { lease_id , .. } = manager.try_acquire_at(&lock("job") , &client("a") , ttl , &at(1 , 0)) else { panic!("Expected granted") };
+        for (index , waiter) in [(2 , "b") , (3 , "c") , (4 , "d")] {
+            manager.try_acquire_at(&lock("job") , &client(waiter) , ttl , &at(index , 1));
+        }
+
+        // A wrong lease, or the right lease from another client, changes nothing.
+        assert!(matches!(manager.transfer_at(&lock("job") , &client("a") , &LeaseId("wrong".to_string()) , &client("c") , ttl , &at(5 , 2)) , TransferResult::NotHolder));
+        assert!(matches!(manager.transfer_at(&lock("job") , &client("b") , &lease_id , &client("c") , ttl , &at(6 , 2)) , TransferResult::NotHolder));
+        assert!(matches!(manager.transfer_at(&lock("none") , &client("a") , &lease_id , &client("c") , ttl , &at(7 , 2)) , TransferResult::NotFound));
+        assert_eq!(manager.current_holder(&lock("job")) , Some(client("a")));
+        assert_eq!(queue() , vec!["b" , "c" , "d"]);
+
+        // c was queued: it leaves the queue and the others keep their place.
+        let TransferResult::Transferred { lease_id : c_lease , fencing_token , .. } = manager.transfer_at(&lock("job") , &client("a") , &lease_id , &client("c") , ttl , &at(8 , 3)) else { panic!("Expected transferred") };
+        assert_eq!(fencing_token , 8);
+        assert_eq!(manager.current_holder(&lock("job")) , Some(client("c")));
+        assert_eq!(queue() , vec!["b" , "d"]);
+        // The old lease is spent, and an expired one cannot be handed on.
+        assert!(matches!(manager.transfer_at(&lock("job") , &client("a") , &lease_id , &client("a") , ttl , &at(9 , 4)) , TransferResult::NotHolder));
+        assert!(matches!(manager.transfer_at(&lock("job") , &client("c") , &c_lease , &client("d") , ttl , &at(10 , 40)) , TransferResult::Expired));
+        assert!(matches!(manager.release_at(&lock("job") , &client("c") , &c_lease , &at(11 , 41)) , ReleaseResult::Success));
+        assert_eq!(manager.current_holder(&lock("job")) , Some(client("b")));
+    }
+
+    #[test]
+
+    fn test_kv_keys_end_with_their_lease(){
+        let ttl = Duration::from_secs(30);
+        let start = Utc::now();
+        let at = |seconds : i64| ApplyContext::new(start + chrono::Duration::seconds(seconds) , 0);
+        let manager = InMemoryLockManager::new();
+
+        let AcquireResult::Granted { lease_id , .. } = manager.try_acquire_at(&lock("leader") , &client("a") , ttl , &at(0)) else { panic!("Expected granted") };
+        let lease = KvLease { lock_id: lock("leader"), lease_id };
+        assert_eq!(manager.kv_put_at("endpoint" , "10.0.0.1".to_string() , &client("b") , None , Some(lease.clone()) , &at(1)) , KvResult::NotHolder);
+        assert_eq!(manager.kv_put_at("endpoint" , "10.0.0.1".to_string() , &client("a") , None , Some(lease.clone()) , &at(1)) , KvResult::Written { version: 1 });
+        assert_eq!(manager.kv_put_at("shared" , "x".to_string() , &client("a") , None , None , &at(1)) , KvResult::Written { version: 2 });
+        assert_eq!(manager.kv_put_at("shared" , "y".to_string() , &client("a") , Some(1) , None , &at(2)) , KvResult::VersionMismatch { current: 2 });
+        assert!(manager.kv_get_at("endpoint" , &at(29)).is_some());
+
+        // The lease expires at 30 seconds; the key goes with it.
+        assert!(manager.kv_get_at("endpoint" , &at(31)).is_none());
+        assert_eq!(manager.kv_put_at("endpoint" , "10.0.0.2".to_string() , &client("a") , None , Some(lease) , &at(31)) , KvResult::Expired);
+        assert_eq!(manager.kv_delete_at("shared" , Some(2) , &at(32)) , KvResult::Deleted { version: 2 });
+        assert_eq!(manager.kv_delete_at("shared" , None , &at(32)) , KvResult::NotFound);
+        // Versions are never reused, even by a recreated key.
+        assert_eq!(manager.kv_put_at("shared" , "z".to_string() , &client("a") , Some(0) , None , &at(33)) , KvResult::Written { version: 3 });
+    }
+
+    #[test]
+
+    fn test_kv_keys_go_as_soon_as_their_lease_ends(){
+        let ttl = Duration::from_secs(30);
+        let start = Utc::now();
+        let at = |seconds : i64| ApplyContext::new(start + chrono::Duration::seconds(seconds) , seconds as u64);
+        let manager = InMemoryLockManager::new();
+        let keys = |seconds : i64| manager.kv_entries(start + chrono::Duration::seconds(seconds)).into_iter().map(|entry| entry.key).collect::<Vec<_>>();
+        let bind = |key : &str , lock_id : &str , holder : &str , seconds : i64| {
+            let lease_id = manager.status(&lock(lock_id)).and_then(|state| state.holder).unwrap().lease_id;
+            let lease = KvLease { lock_id: lock(lock_id), lease_id };
+            assert!(matches!(manager.kv_put_at(key , "x".to_string() , &client(holder) , None , Some(lease) , &at(seconds)) , KvResult::Written { .. }));
+        };
+
+        for lock_id in ["released" , "transferred" , "forced" , "revoked" , "expired"] {
+            manager.try_acquire_at(&lock(lock_id) , &client(if lock_id == "revoked" { "b" } else { "a" }) , ttl , &at(0));
+        }
+        bind("released" , "released" , "a" , 1);
+        bind("transferred" , "transferred" , "a" , 1);
+        bind("forced" , "forced" , "a" , 1);
+        bind("revoked" , "revoked" , "b" , 1);
+        bind("expired" , "expired" , "a" , 1);
+        assert_eq!(keys(1) , vec!["expired" , "forced" , "released" , "revoked" , "transferred"]);
+
+        let holder = |lock_id : &str| manager.status(&lock(lock_id)).and_then(|state| state.holder).unwrap();
+        manager.release_at(&lock("released") , &client("a") , &holder("released").lease_id , &at(2));
+        manager.transfer_at(&lock("transferred") , &client("a") , &holder("transferred").lease_id , &client("c") , ttl , &at(2));
+        manager.force_release_at(&lock("forced") , None , "oncall" , &at(2));
+        manager.revoke_client_at(&client("b") , "oncall" , &at(2));
+        // No key-value command ran, yet every ended lease took its key.
+        assert_eq!(keys(2) , vec!["expired"]);
+
+        // An expired lease nobody has noticed yet is left out of reads.
+        assert!(keys(31).is_empty());
+        manager.try_acquire_at(&lock("expired") , &client("c") , ttl , &at(31));
+        assert!(manager.kv_entries(start).is_empty());
+    }
+
+    #[test]
+
+    fn test_barriers_reopen_the_arrivals_of_expired_participants(){
+        let ttl = Duration::from_secs(30);
+        let start = Utc::now();
+        let at = |seconds : i64| ApplyContext::new(start + chrono::Duration::seconds(seconds) , 0);
+        let manager = InMemoryLockManager::new();
+
+        assert_eq!(manager.barrier_create_at("stage" , BarrierKind::Barrier , 3 , &at(0)) , BarrierResult::Updated { remaining: 3 });
+        assert_eq!(manager.barrier_create_at("stage" , BarrierKind::Barrier , 3 , &at(1)) , BarrierResult::Updated { remaining: 3 });
+        assert_eq!(manager.barrier_create_at("stage" , BarrierKind::Latch , 3 , &at(1)) , BarrierResult::Conflict);
+        assert_eq!(manager.barrier_join_at("stage" , &client("a") , ttl , &at(1)) , BarrierResult::Updated { remaining: 3 });
+        assert_eq!(manager.barrier_join_at("stage" , &client("b") , ttl , &at(1)) , BarrierResult::Updated { remaining: 3 });
+        assert_eq!(manager.barrier_join_at("stage" , &client("c") , ttl , &at(1)) , BarrierResult::Updated { remaining: 3 });
+        assert_eq!(manager.barrier_join_at("stage" , &client("d") , ttl , &at(1)) , BarrierResult::Full);
+
+        // A barrier counts each participant once.
+        assert_eq!(manager.barrier_arrive_at("stage" , &client("a") , &at(2)) , BarrierResult::Updated { remaining: 2 });
+        assert_eq!(manager.barrier_arrive_at("stage" , &client("a") , &at(3)) , BarrierResult::Updated { remaining: 2 });
+        assert_eq!(manager.barrier_arrive_at("stage" , &client("b") , &at(4)) , BarrierResult::Updated { remaining: 1 });
+
+        // c crashed: its lease ends at 31 seconds, which degrades the
+        // barrier without releasing the others.
+        let barrier = manager.barrier("stage").unwrap();
+        assert_eq!((barrier.degraded_at(at(30).now) , barrier.degraded_at(at(32).now)) , (false , true));
+        assert_eq!(manager.barrier_join_at("stage" , &client("d") , ttl , &at(32)) , BarrierResult::Updated { remaining: 1 });
+        assert_eq!(manager.barrier_join_at("stage" , &client("c") , ttl , &at(33)) , BarrierResult::Full);
+        let barrier = manager.barrier("stage").unwrap();
+        assert_eq!(barrier.expired , vec!["c".to_string()]);
+        assert_eq!(barrier.released_at , None);
+
+        // Once d's lease runs out too, c may join again.
+        assert_eq!(manager.barrier_join_at("stage" , &client("c") , ttl , &at(63)) , BarrierResult::Updated { remaining: 1 });
+        let barrier = manager.barrier("stage").unwrap();
+        assert_eq!(barrier.expired , vec!["d".to_string()]);
+        assert!(barrier.degraded_at(at(63).now));
+        assert_eq!(manager.barrier_arrive_at("stage" , &client("c") , &at(64)) , BarrierResult::Updated { remaining: 0 });
+        let barrier = manager.barrier("stage").unwrap();
+        assert_eq!(barrier.released_at , Some(at(64).now));
+        assert!(!barrier.degraded_at(at(64).now));
+
+        // A latch counts every count-down.
+        manager.barrier_create_at("loaded" , BarrierKind::Latch , 2 , &at(0));
+        assert_eq!(manager.barrier_arrive_at("loaded" , &client("a") , &at(1)) , BarrierResult::Updated { remaining: 1 });
+        assert_eq!(manager.barrier_arrive_at("loaded" , &client("a") , &at(2)) , BarrierResult::Updated { remaining: 0 });
+        assert_eq!(manager.barrier_delete_at("loaded" , &at(3)) , BarrierResult::Updated { remaining: 0 });
+        assert_eq!(manager.barrier_arrive_at("loaded" , &client("a") , &at(4)) , BarrierResult::NotFound);
+    }
+
+    #[test]
+
+    fn test_latch_and_barrier_count_differently(){
+        let ttl = Duration::from_secs(30);
+        let start = Utc::now();
+        let at = |seconds : i64| ApplyContext::new(start + chrono::Duration::seconds(seconds) , 0);
+        let manager = InMemoryLockManager::new();
+
+        manager.barrier_create_at("barrier" , BarrierKind::Barrier , 2 , &at(0));
+        manager.barrier_create_at("latch" , BarrierKind::Latch , 2 , &at(0));
+        assert_eq!(manager.barrier_create_at("latch" , BarrierKind::Latch , 3 , &at(0)) , BarrierResult::Conflict);
+        assert_eq!(manager.barrier_create_at("barrier" , BarrierKind::Latch , 2 , &at(0)) , BarrierResult::Conflict);
+        for name in ["barrier" , "latch"] {
+            assert_eq!(manager.barrier_join_at(name , &client("a") , ttl , &at(1)) , BarrierResult::Updated { remaining: 2 });
+            assert_eq!(manager.barrier_arrive_at(name , &client("a") , &at(2)) , BarrierResult::Updated { remaining: 1 });
+        }
+
+        // The same client counts once at a barrier and every time at a latch.
+        assert_eq!(manager.barrier_arrive_at("barrier" , &client("a") , &at(3)) , BarrierResult::Updated { remaining: 1 });
+        assert_eq!(manager.barrier_arrive_at("latch" , &client("a") , &at(3)) , BarrierResult::Updated { remaining: 0 });
+        let (barrier , latch) = (manager.barrier("barrier").unwrap() , manager.barrier("latch").unwrap());
+        assert_eq!(barrier.arrived.iter().collect::<Vec<_>>() , vec!["a"]);
+        assert!(latch.arrived.is_empty());
+        assert_eq!((barrier.released_at , latch.released_at) , (None , Some(at(3).now)));
+
+        // Arrivals after the release change nothing.
+        assert_eq!(manager.barrier_arrive_at("latch" , &client("b") , &at(4)) , BarrierResult::Updated { remaining: 0 });
+        assert_eq!(manager.barrier("latch").unwrap().arrivals , 2);
+    }
+
+    #[test]
+
+    fn test_deleting_a_barrier_others_wait_on(){
+        let ttl = Duration::from_secs(30);
+        let start = Utc::now();
+        let at = |seconds : i64| ApplyContext::new(start + chrono::Duration::seconds(seconds) , 0);
+        let manager = InMemoryLockManager::new();
+
+        manager.barrier_create_at("stage" , BarrierKind::Barrier , 3 , &at(0));
+        manager.barrier_join_at("stage" , &client("a") , ttl , &at(1));
+        manager.barrier_join_at("stage" , &client("b") , ttl , &at(1));
+        manager.barrier_arrive_at("stage" , &client("a") , &at(2));
+
+        // b is still waiting; the delete reports what was missing.
+        assert_eq!(manager.barrier_delete_at("stage" , &at(3)) , BarrierResult::Updated { remaining: 2 });
+        assert_eq!(manager.barrier_delete_at("stage" , &at(3)) , BarrierResult::NotFound);
+        assert_eq!(manager.barrier_arrive_at("stage" , &client("b") , &at(4)) , BarrierResult::NotFound);
+        assert_eq!(manager.barrier_join_at("stage" , &client("c") , ttl , &at(4)) , BarrierResult::NotFound);
+        assert!(manager.barrier("stage").is_none());
+
+        // Created again, it starts over without the old participants.
+        assert_eq!(manager.barrier_create_at("stage" , BarrierKind::Latch , 1 , &at(5)) , BarrierResult::Updated { remaining: 1 });
+        let barrier = manager.barrier("stage").unwrap();
+        assert!(barrier.participants.is_empty() && barrier.arrived.is_empty());
+        assert_eq!(manager.barrier_arrive_at("stage" , &client("b") , &at(6)) , BarrierResult::Updated { remaining: 0 });
+    }
+
+    #[test]
+
+    fn test_metadata_limits(){
+        let mut metadata = Metadata::new();
+        assert!(metadata::validate(&metadata).is_ok());
+        metadata.insert(String::new() , "x".to_string());
+        assert!(metadata::validate(&metadata).is_err());
+
+        let many = (0..=metadata::MAX_ENTRIES).map(|i| (format!("key_{}" , i) , String::new())).collect::<Metadata>();
+        assert!(metadata::validate(&many).is_err());
+
+        let mut large = Metadata::new();
+        large.insert("note".to_string() , "x".repeat(metadata::MAX_BYTES - 4));
+        assert!(metadata::validate(&large).is_ok());
+        large.insert("note".to_string() , "x".repeat(metadata::MAX_BYTES - 3));
+        assert!(metadata::validate(&large).is_err());
+    }
+
+    #[test]
+
+    fn test_stats_measure_contention(){
+        let ttl = Duration::from_secs(30);
+        let start = Utc::now();
+        let at = |seconds : i64| ApplyContext::new(start + chrono::Duration::seconds(seconds) , seconds as u64);
+        let manager = InMemoryLockManager::new();
+
+        let AcquireResult::Granted { lease_id , .. } = manager.try_acquire_at(&lock("hot") , &client("a") , ttl , &at(0)) else { panic!("Expected granted") };
+        manager.try_acquire_at(&lock("hot") , &client("b") , ttl , &at(1));
+        manager.try_acquire_at(&lock("hot") , &client("c") , ttl , &at(2));
+        manager.release_at(&lock("hot") , &client("a") , &lease_id , &at(10));
+        manager.try_acquire_at(&lock("hot") , &client("a") , ttl , &at(100));
+        manager.try_acquire_at(&lock("cold") , &client("a") , ttl , &at(0));
+
+        let stats = manager.stats(&lock("hot")).unwrap();
+        assert_eq!((stats.acquires , stats.queued , stats.grants) , (4 , 2 , 3));
+        assert_eq!(stats.contention_rate() , 0.5);
+        assert_eq!(stats.max_queue_depth , 2);
+        // a released after 10s, b expired after its 30s default lease.
+        assert_eq!((stats.releases , stats.expirations , stats.force_releases) , (1 , 1 , 0));
+        assert_eq!(stats.mean_hold() , Duration::from_secs(20));
+        assert_eq!(stats.p99_hold() , Duration::from_secs(30));
+        assert_eq!(stats.mean_wait() , Duration::from_secs(9));
+        assert_eq!(stats.top_holders(1) , vec![(client("a") , 2)]);
+
+        let ranked = manager.most_contended().into_iter().map(|(lock_id , _)| lock_id.0).collect::<Vec<_>>();
+        assert_eq!(ranked , vec!["hot" , "cold"]);
+        assert!(manager.stats(&lock("never")).is_none());
+    }
+
+    #[test]
+
+    fn test_stats_p99_and_holder_cap(){
+        let start = Utc::now();
+        let at = |index : u64 , seconds : i64| ApplyContext::new(start + chrono::Duration::seconds(seconds) , index).with_index(index);
+        let manager = InMemoryLockManager::new();
+
+        // Leases held 1 to 100 seconds: the p99 is the 99th.
+        let mut now = 0;
+        for held in 1..=100 {
+            let AcquireResult::Granted { lease_id , .. } = manager.try_acquire_at(&lock("jobs") , &client("a") , Duration::from_secs(3_600) , &at(held as u64 * 2 , now)) else { panic!("Expected granted") };
+            now += held;
+            manager.release_at(&lock("jobs") , &client("a") , &lease_id , &at(held as u64 * 2 + 1 , now));
+        }
+        let stats = manager.stats(&lock("jobs")).unwrap();
+        assert_eq!(stats.p99_hold() , Duration::from_secs(99));
+        assert_eq!(stats.mean_hold() , Duration::from_millis(50_500));
+        assert_eq!(LockStats::default().p99_hold() , Duration::ZERO);
+
+        // Only the latest 1000 leases count towards the p99.
+        let mut stats = LockStats::default();
+        for _ in 0..20 {
+            stats.ended(LeaseEnd::Released , Duration::from_secs(600));
+        }
+        assert_eq!(stats.p99_hold() , Duration::from_secs(600));
+        for _ in 0..1_000 {
+            stats.ended(LeaseEnd::Released , Duration::from_secs(1));
+        }
+        assert_eq!(stats.p99_hold() , Duration::from_secs(1));
+
+        // Past 100 holders the rarest, then lowest id, is forgotten.
+        let mut stats = LockStats::default();
+        stats.granted(&client("a") , None);
+        stats.granted(&client("a") , None);
+        for i in 0..100 {
+            stats.granted(&client(&format!("c{:03}" , i)) , None);
+        }
+        let holders = stats.top_holders(usize::MAX);
+        assert_eq!(holders.len() , 100);
+        assert_eq!(holders[0] , (client("a") , 2));
+        assert!(!holders.iter().any(|(client_id , _)| client_id.0 == "c000"));
+        assert_eq!(stats.top_holders(2) , vec![(client("a") , 2) , (client("c001") , 1)]);
+        assert_eq!(stats.top_holders(0) , vec![]);
+    }
+
+    #[test]
+
+    fn test_metrics_label_every_operation_and_result(){
+        let ttl = Duration::from_secs(30);
+        let start = Utc::now();
+        let at = |index : u64 , seconds : i64| ApplyContext::new(start + chrono::Duration::seconds(seconds) , index).with_index(index);
+        let recorder = recorder();
+        let handle = recorder.handle();
+        metrics::with_local_recorder(&recorder , || {
+            let manager = InMemoryLockManager::new().with_quotas(LockQuotas { max_waiters_per_lock: 1, ..LockQuotas::default() });
+            let AcquireResult::Granted { lease_id , .. } = manager.try_acquire_at(&lock("jobs") , &client("a") , ttl , &at(1 , 0)) else { panic!("Expected granted") };
+            manager.try_acquire_at(&lock("jobs") , &client("b") , ttl , &at(2 , 0));
+            manager.try_acquire_at(&lock("jobs") , &client("c") , ttl , &at(3 , 0));
+            manager.renew_at(&lock("jobs") , &client("a") , &lease_id , ttl , &at(4 , 1));
+            manager.renew_at(&lock("jobs") , &client("b") , &lease_id , ttl , &at(5 , 1));
+            manager.renew_at(&lock("none") , &client("a") , &lease_id , ttl , &at(6 , 1));
+            manager.release_at(&lock("jobs") , &client("b") , &lease_id , &at(7 , 1));
+            manager.record_gauges(start + chrono::Duration::seconds(1));
+            let TransferResult::Transferred { lease_id , .. } = manager.transfer_at(&lock("jobs") , &client("a") , &lease_id , &client("d") , ttl , &at(8 , 2)) else { panic!("Expected transferred") };
+            manager.transfer_at(&lock("jobs") , &client("d") , &lease_id , &client("a") , ttl , &at(9 , 100));
+            manager.renew_at(&lock("jobs") , &client("d") , &lease_id , ttl , &at(10 , 100));
+            manager.evict_waiter_at(&lock("jobs") , &client("b") , "oncall" , &at(11 , 100));
+            manager.evict_waiter_at(&lock("jobs") , &client("b") , "oncall" , &at(12 , 100));
+            manager.try_acquire_at(&lock("jobs") , &client("e") , ttl , &at(13 , 100));
+            manager.force_release_at(&lock("jobs") , None , "oncall" , &at(14 , 101));
+            manager.revoke_client_at(&client("e") , "oncall" , &at(15 , 102));
+        });
+
+        let rendered = handle.render();
+        for line in [
+            r#"distlock_lock_operations_total{operation="acquire",result="granted"} 2"#,
+            r#"distlock_lock_operations_total{operation="acquire",result="queued"} 1"#,
+            r#"distlock_lock_operations_total{operation="acquire",result="quota_exceeded"} 1"#,
+            r#"distlock_lock_operations_total{operation="renew",result="success"} 1"#,
+            r#"distlock_lock_operations_total{operation="renew",result="not_holder"} 1"#,
+            r#"distlock_lock_operations_total{operation="renew",result="not_found"} 1"#,
+            r#"distlock_lock_operations_total{operation="renew",result="expired"} 1"#,
+            r#"distlock_lock_operations_total{operation="release",result="not_holder"} 1"#,
+            r#"distlock_lock_operations_total{operation="transfer",result="success"} 1"#,
+            r#"distlock_lock_operations_total{operation="transfer",result="expired"} 1"#,
+            r#"distlock_lock_operations_total{operation="evict_waiter",result="success"} 1"#,
+            r#"distlock_lock_operations_total{operation="evict_waiter",result="not_found"} 1"#,
+            r#"distlock_lock_operations_total{operation="force_release",result="success"} 1"#,
+            r#"distlock_lock_operations_total{operation="revoke_client",result="success"} 1"#,
+            // d's lease ran out at 32 and was noticed when e asked at 100.
+            "distlock_lock_expirations_total 1",
+            "distlock_locks 1",
+            "distlock_max_queue_length 1"
+        ] {
+            assert!(rendered.lines().any(|rendered| rendered == line) , "missing '{}' in\n{}" , line , rendered);
+        }
+    }
+
+    #[test]
+
+    fn test_sharded_table_under_concurrent_clients(){
+        let ttl = Duration::from_secs(30);
+        let manager = std::sync::Arc::new(InMemoryLockManager::new());
+        let ids : Vec<LockId> = (0..200).map(|i| lock(&format!("lock-{:03}" , i))).collect();
+
+        std::thread::scope(|scope| {
+            for thread in 0..8 {
+                let (manager , ids) = (manager.clone() , &ids);
+                scope.spawn(move || {
+                    for lock_id in ids {
+                        manager.try_acquire(lock_id , &client(&format!("client-{}" , thread)) , ttl);
+                    }
+                });
+            }
+        });
+        let listed = manager.list();
+        assert_eq!(listed.iter().map(|(lock_id , _)| lock_id.clone()).collect::<Vec<_>>() , ids);
+        assert!(listed.iter().all(|(_ , state)| state.holder.is_some() && state.wait_queue.len() == 7));
+
+        // Promotions hand out lease ids in lock id order whatever the shard
+        // count, so replicas agree.
+        let promoted = |shards : usize| {
+            let manager = InMemoryLockManager::new().with_shards(shards);
+            let ctx = ApplyContext::new(Utc::now() , 7);
+            for lock_id in &ids {
+                manager.try_acquire_at(lock_id , &client("a") , ttl , &ctx);
+                manager.try_acquire_at(lock_id , &client("b") , ttl , &ctx);
+            }
+            assert_eq!(manager.revoke_client_at(&client("a") , "oncall" , &ctx) , (200 , 0));
+            manager.list().into_iter().map(|(_ , state)| state.holder.unwrap().lease_id).collect::<Vec<_>>()
+        };
+        assert_eq!(promoted(1) , promoted(64));
+    }
+
+    #[test]
+
+    fn test_huge_ttl_is_clamped_instead_of_panicking(){
+        let manager = InMemoryLockManager::new();
+        let ctx = ApplyContext::local();
+        let AcquireResult::Granted { expires_at , .. } = manager.try_acquire_at(&lock("forever") , &client("a") , Duration::MAX , &ctx) else { panic!("Expected granted") };
+        assert_eq!(expires_at , ctx.now + chrono::Duration::seconds(MAX_TTL_SECONDS as i64));
+    }
+}
@@ -0,0 +1,28 @@
+//! Small string maps attached to leases, so operators can see why a lock is
+//! held: a ticket, a hostname, a PID.
+
+use std::collections::BTreeMap;
+
+/// Error type of requests carrying metadata over the limits.
+pub const INVALID_METADATA : &str = "InvalidMetadata";
+
+pub const MAX_ENTRIES : usize = 16;
+/// Bytes of every key and value together.
+pub const MAX_BYTES : usize = 1024;
+
+/// Ordered, so every replica serializes it the same way.
+pub type Metadata = BTreeMap<String , String>;
+
+pub fn validate(metadata : &Metadata) -> Result<() , String> {
+    if metadata.len() > MAX_ENTRIES {
+        return Err(format!("Metadata has {} entries, at most {} are allowed" , metadata.len() , MAX_ENTRIES))
+    }
+    if metadata.keys().any(String::is_empty) {
+        return Err("Metadata keys must not be empty".to_string())
+    }
+    let size : usize = metadata.iter().map(|(key , value)| key.len() + value.len()).sum();
+    if size > MAX_BYTES {
+        return Err(format!("Metadata takes {} bytes, at most {} are allowed" , size , MAX_BYTES))
+    }
+    Ok(())
+}
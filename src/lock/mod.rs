@@ -2,5 +2,14 @@
 
 pub mod types ; 
 pub mod manager;
+pub mod table;
+pub mod backend;
 pub mod error;
+pub mod clock;
+pub mod quota;
+pub mod history;
+pub mod stats;
+pub mod metadata;
+pub mod kv;
+pub mod barrier;
 pub mod manager_test;
@@ -0,0 +1,174 @@
+//! Admission limits. The limits on the lock table itself are replicated
+//! state, set through the log like the ACL and checked by
+//! `InMemoryLockManager`; the request rate is checked per node, before a
+//! command is proposed, so it needs no agreement between replicas.
+
+use std::{collections::HashMap, sync::{Mutex, atomic::{AtomicBool, Ordering}}, time::{Duration, Instant}};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::lock::{table::hash_index, types::{ClientId, LockId, LockState}};
+
+/// Error type of every refused request, whichever limit refused it.
+pub const QUOTA_EXCEEDED : &str = "QuotaExceeded";
+
+// Buckets are only forgotten once this many clients are tracked.
+const MAX_TRACKED_CLIENTS : usize = 10_000;
+
+/// Limits on the lock table, the same on every replica. 0 means unlimited.
+#[derive(Debug , Clone , Copy , PartialEq , Eq , Default , Serialize , Deserialize)]
+#[serde(default , deny_unknown_fields)]
+pub struct LockQuotas{
+    /// Lock entries in the table. Idle entries are dropped to make room.
+    pub max_locks : usize,
+    /// Lock entries per namespace, the part of a lock id before its first `/`.
+    pub max_locks_per_namespace : usize,
+    /// Locks one client id may hold or be queued for.
+    pub max_locks_per_client : usize,
+    pub max_waiters_per_lock : usize
+}
+
+/// Part of the lock id before its first `/`, the whole id when it has none.
+pub fn namespace(lock_id : &LockId) -> &str {
+    lock_id.0.split('/').next().unwrap_or_default()
+}
+
+/// What a client takes of one lock: its lease and its places in the queue.
+#[derive(Debug , Clone , Copy , PartialEq)]
+pub struct Taken{
+    holds_until : Option<DateTime<Utc>>,
+    waits : usize
+}
+
+impl Taken{
+    fn at(&self , now : DateTime<Utc>) -> bool {
+        self.waits > 0 || self.holds_until.is_some_and(|expires_at| expires_at >= now)
+    }
+}
+
+/// Clients holding or waiting for a lock, with what each takes of it.
+pub type Takers = HashMap<ClientId , Taken>;
+
+/// The locks each client holds or waits for, for `max_locks_per_client`.
+/// Kept up to date as locks change, and only while that limit is set.
+/// Sharded by client id.
+pub struct ClientLocks{
+    enabled : AtomicBool,
+    shards : Vec<Mutex<HashMap<ClientId , HashMap<LockId , Taken>>>>
+}
+
+impl ClientLocks{
+    pub fn new(shards : usize) -> Self {
+        Self { enabled: AtomicBool::new(false), shards: (0..shards.max(1)).map(|_| Mutex::new(HashMap::new())).collect() }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Starts counting the locks of every client from scratch, or stops.
+    /// The caller keeps the lock table from changing meanwhile.
+    pub fn reset<'a>(&self , enabled : bool , locks : impl IntoIterator<Item = (&'a LockId , &'a LockState)>) {
+        for shard in &self.shards {
+            shard.lock().unwrap().clear();
+        }
+        if enabled {
+            for (lock_id , state) in locks {
+                self.update(lock_id , Takers::new() , state);
+            }
+        }
+        self.enabled.store(enabled , Ordering::Relaxed);
+    }
+
+    pub fn takers(state : &LockState) -> Takers {
+        let mut takers = Takers::new();
+        if let Some(holder) = &state.holder {
+            takers.entry(holder.client_id.clone()).or_insert(Taken { holds_until: None, waits: 0 }).holds_until = Some(holder.expires_at);
+        }
+        for waiter in &state.wait_queue {
+            takers.entry(waiter.client_id.clone()).or_insert(Taken { holds_until: None, waits: 0 }).waits += 1;
+        }
+        takers
+    }
+
+    /// Records the change of a lock from having `before` as takers to
+    /// `after`.
+    pub fn update(&self , lock_id : &LockId , before : Takers , after : &LockState) {
+        let after = Self::takers(after);
+        for client_id in before.keys().chain(after.keys().filter(|client_id| !before.contains_key(*client_id))) {
+            let taken = after.get(client_id);
+            if before.get(client_id) == taken {
+                continue
+            }
+            let mut shard = self.shards[hash_index(client_id , self.shards.len())].lock().unwrap();
+            match taken {
+                Some(taken) => { shard.entry(client_id.clone()).or_default().insert(lock_id.clone() , *taken); }
+                None => if let Some(locks) = shard.get_mut(client_id) {
+                    locks.remove(lock_id);
+                    if locks.is_empty() {
+                        shard.remove(client_id);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Locks `client_id` holds a live lease of or waits for at `now`.
+    /// Leases that ran out are forgotten on the way.
+    pub fn count(&self , client_id : &ClientId , now : DateTime<Utc>) -> usize {
+        let mut shard = self.shards[hash_index(client_id , self.shards.len())].lock().unwrap();
+        let Some(locks) = shard.get_mut(client_id) else { return 0 };
+        locks.retain(|_ , taken| taken.at(now));
+        locks.len()
+    }
+}
+
+/// Token bucket per client id.
+pub struct RateLimiter{
+    per_second : f64,
+    burst : f64,
+    buckets : Mutex<HashMap<String , Bucket>>
+}
+
+struct Bucket{
+    tokens : f64,
+    updated : Instant
+}
+
+impl RateLimiter{
+    /// A `burst` of 0 allows one second worth of requests at once.
+    pub fn new(requests_per_second : u32 , burst : u32) -> Self {
+        let burst = if burst == 0 { requests_per_second } else { burst };
+        Self { per_second: requests_per_second as f64, burst: burst as f64, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    /// Takes a token from the client's bucket. When it is empty, returns how
+    /// long until the next token.
+    pub fn check(&self , client_id : &str) -> Result<() , Duration> {
+        self.check_at(client_id , Instant::now())
+    }
+
+    pub fn check_at(&self , client_id : &str , now : Instant) -> Result<() , Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(client_id) {
+            // A full bucket is the same as no bucket.
+            buckets.retain(|_ , bucket| bucket.refilled(now , self) < self.burst);
+        }
+        let bucket = buckets.entry(client_id.to_string()).or_insert(Bucket { tokens: self.burst, updated: now });
+        bucket.tokens = bucket.refilled(now , self);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(())
+        }
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.per_second))
+    }
+}
+
+impl Bucket{
+    fn refilled(&self , now : Instant , limiter : &RateLimiter) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * limiter.per_second).min(limiter.burst)
+    }
+}
@@ -0,0 +1,123 @@
+//! Usage statistics of each lock, kept by the state machine to find the
+//! locks that slow their users down. They are derived from applied entries
+//! like everything else, but are not part of snapshots.
+
+use std::{collections::{HashMap, VecDeque}, time::Duration};
+
+use crate::lock::types::ClientId;
+
+// p99 hold time is computed over this many of the latest leases.
+const HOLD_SAMPLES : usize = 1000;
+// Past this many holders, the least frequent one is forgotten to make room.
+const MAX_TRACKED_HOLDERS : usize = 100;
+
+#[derive(Debug , Clone , Default)]
+pub struct LockStats{
+    /// Acquire requests that were granted or queued.
+    pub acquires : u64,
+    /// Acquire requests that had to wait in the queue.
+    pub queued : u64,
+    /// Leases granted, on acquire or on promotion from the queue.
+    pub grants : u64,
+    pub releases : u64,
+    pub expirations : u64,
+    /// Leases ended by an operator, with a force-release or a revoke.
+    pub force_releases : u64,
+    /// Leases handed to another client by their holder.
+    pub transfers : u64,
+    pub max_queue_depth : usize,
+    ended : u64,
+    total_hold : Duration,
+    recent_holds : VecDeque<Duration>,
+    promotions : u64,
+    total_wait : Duration,
+    holders : HashMap<ClientId , u64>
+}
+
+/// How a lease ended.
+#[derive(Debug , Clone , Copy , PartialEq)]
+pub enum LeaseEnd{
+    Released,
+    Transferred,
+    Expired,
+    ForceReleased
+}
+
+impl LockStats{
+    pub fn acquired(&mut self , queued : bool , queue_depth : usize) {
+        self.acquires += 1;
+        if queued {
+            self.queued += 1;
+        }
+        self.max_queue_depth = self.max_queue_depth.max(queue_depth);
+    }
+
+    /// `waited` is how long a promoted waiter spent in the queue.
+    pub fn granted(&mut self , client_id : &ClientId , waited : Option<Duration>) {
+        self.grants += 1;
+        if let Some(waited) = waited {
+            self.promotions += 1;
+            self.total_wait += waited;
+        }
+        if let Some(count) = self.holders.get_mut(client_id) {
+            *count += 1;
+            return
+        }
+        if self.holders.len() >= MAX_TRACKED_HOLDERS
+            && let Some(rarest) = self.holders.iter().min_by(|(a_id , a) , (b_id , b)| a.cmp(b).then_with(|| a_id.0.cmp(&b_id.0))).map(|(client_id , _)| client_id.clone()) {
+            self.holders.remove(&rarest);
+        }
+        self.holders.insert(client_id.clone() , 1);
+    }
+
+    pub fn ended(&mut self , end : LeaseEnd , held : Duration) {
+        match end {
+            LeaseEnd::Released => self.releases += 1,
+            LeaseEnd::Transferred => self.transfers += 1,
+            LeaseEnd::Expired => self.expirations += 1,
+            LeaseEnd::ForceReleased => self.force_releases += 1
+        }
+        self.ended += 1;
+        self.total_hold += held;
+        if self.recent_holds.len() == HOLD_SAMPLES {
+            self.recent_holds.pop_front();
+        }
+        self.recent_holds.push_back(held);
+    }
+
+    /// Share of acquires that were queued, 0 before the first one.
+    pub fn contention_rate(&self) -> f64 {
+        if self.acquires == 0 { 0.0 } else { self.queued as f64 / self.acquires as f64 }
+    }
+
+    pub fn mean_hold(&self) -> Duration {
+        mean(self.total_hold , self.ended)
+    }
+
+    /// Over the latest leases only.
+    pub fn p99_hold(&self) -> Duration {
+        let mut holds : Vec<Duration> = self.recent_holds.iter().copied().collect();
+        holds.sort();
+        let rank = (holds.len() * 99).div_ceil(100);
+        holds.get(rank.saturating_sub(1)).copied().unwrap_or_default()
+    }
+
+    /// Time promoted waiters spent in the queue. Waiters that gave up or
+    /// were evicted are not counted.
+    pub fn mean_wait(&self) -> Duration {
+        mean(self.total_wait , self.promotions)
+    }
+
+    /// Clients granted the lock most often, most frequent first. Counts are
+    /// approximate once more than `MAX_TRACKED_HOLDERS` clients held it.
+    pub fn top_holders(&self , limit : usize) -> Vec<(ClientId , u64)> {
+        let mut holders : Vec<(ClientId , u64)> = self.holders.iter().map(|(client_id , count)| (client_id.clone() , *count)).collect();
+        holders.sort_by(|(a_id , a) , (b_id , b)| b.cmp(a).then_with(|| a_id.0.cmp(&b_id.0)));
+        holders.truncate(limit);
+        holders
+    }
+}
+
+fn mean(total : Duration , count : u64) -> Duration {
+    if count == 0 { Duration::ZERO } else { total.div_f64(count as f64) }
+}
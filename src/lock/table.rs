@@ -0,0 +1,240 @@
+//! The lock table of `InMemoryLockManager`, split into shards by a hash of
+//! the lock id so operations on different locks rarely wait for each other.
+//! Each shard maps lock ids to their state behind a mutex of their own: an
+//! operation on one lock takes its shard's read lock, then the lock's mutex.
+//! Only creating and dropping entries takes a shard's write lock.
+//!
+//! Operations spanning the table take every shard in index order and visit
+//! locks in lock id order, so they never deadlock with each other and apply
+//! the same way on every replica.
+//!
+//! The table counts its entries, in total and per namespace, as they are
+//! inserted and dropped, so limits on its size are checked without looking
+//! at other shards.
+
+use std::{collections::HashMap, hash::{DefaultHasher, Hash, Hasher}, sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard, atomic::{AtomicUsize, Ordering}}};
+
+use crate::lock::{quota::namespace, types::{LockId, LockState}};
+
+pub const DEFAULT_SHARDS : usize = 64;
+
+type Shard = HashMap<LockId , Mutex<LockState>>;
+
+pub struct LockTable{
+    shards : Vec<RwLock<Shard>>,
+    len : AtomicUsize,
+    /// Entries per namespace, sharded by a hash of the namespace.
+    namespaces : Vec<Mutex<HashMap<String , usize>>>
+}
+
+impl LockTable{
+    /// A table of `shards` shards, at least one.
+    pub fn new(shards : usize) -> Self {
+        let shards = shards.max(1);
+        Self {
+            shards: (0..shards).map(|_| RwLock::new(HashMap::new())).collect(),
+            len: AtomicUsize::new(0),
+            namespaces: (0..shards).map(|_| Mutex::new(HashMap::new())).collect()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Entries whose id is in `namespace`.
+    pub fn namespace_len(&self , namespace : &str) -> usize {
+        self.namespace_counts(namespace).get(namespace).copied().unwrap_or(0)
+    }
+
+    fn namespace_counts(&self , namespace : &str) -> MutexGuard<'_ , HashMap<String , usize>> {
+        self.namespaces[hash_index(namespace , self.namespaces.len())].lock().unwrap()
+    }
+
+    fn count_removed(&self , lock_id : &LockId) {
+        self.len.fetch_sub(1 , Ordering::Relaxed);
+        let lock_namespace = namespace(lock_id);
+        let mut counts = self.namespace_counts(lock_namespace);
+        if let Some(count) = counts.get_mut(lock_namespace) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(lock_namespace);
+            }
+        }
+    }
+
+    /// Counts a new entry unless the table already has `max_locks` or its
+    /// namespace `max_in_namespace`, 0 meaning no limit.
+    fn count_inserted(&self , lock_id : &LockId , max_locks : usize , max_in_namespace : usize) -> bool {
+        let lock_namespace = namespace(lock_id);
+        let mut counts = self.namespace_counts(lock_namespace);
+        let in_namespace = counts.get(lock_namespace).copied().unwrap_or(0);
+        if max_in_namespace > 0 && in_namespace >= max_in_namespace {
+            return false
+        }
+        if self.len.fetch_add(1 , Ordering::Relaxed) >= max_locks && max_locks > 0 {
+            self.len.fetch_sub(1 , Ordering::Relaxed);
+            return false
+        }
+        *counts.entry(lock_namespace.to_string()).or_default() += 1;
+        true
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn shard_of(&self , lock_id : &LockId) -> usize {
+        shard_index(lock_id , self.shards.len())
+    }
+
+    pub fn get(&self , lock_id : &LockId) -> Option<LockState> {
+        self.with(lock_id , |state| state.clone())
+    }
+
+    /// Runs `f` on the state of `lock_id`, if it exists.
+    pub fn with<R>(&self , lock_id : &LockId , f : impl FnOnce(&mut LockState) -> R) -> Option<R> {
+        let shard = self.shards[self.shard_of(lock_id)].read().unwrap();
+        let mut state = shard.get(lock_id)?.lock().unwrap();
+        Some(f(&mut state))
+    }
+
+    /// Like `with`, inserting the state made by `create` first if the lock
+    /// does not exist.
+    pub fn with_or_insert<R>(&self , lock_id : &LockId , create : impl FnOnce() -> LockState , f : impl FnOnce(&mut LockState) -> R) -> R {
+        self.with_or_insert_within(lock_id , 0 , 0 , create , f).expect("an unbounded table has room")
+    }
+
+    /// Like `with_or_insert`, but a lock that does not exist is only
+    /// inserted while the table has fewer than `max_locks` entries and its
+    /// namespace fewer than `max_in_namespace`, 0 meaning no limit. `None`
+    /// when there was no room for it.
+    pub fn with_or_insert_within<R>(&self , lock_id : &LockId , max_locks : usize , max_in_namespace : usize , create : impl FnOnce() -> LockState , f : impl FnOnce(&mut LockState) -> R) -> Option<R> {
+        let index = self.shard_of(lock_id);
+        {
+            let shard = self.shards[index].read().unwrap();
+            if let Some(state) = shard.get(lock_id) {
+                return Some(f(&mut state.lock().unwrap()))
+            }
+        }
+        let mut shard = self.shards[index].write().unwrap();
+        if !shard.contains_key(lock_id) && !self.count_inserted(lock_id , max_locks , max_in_namespace) {
+            return None
+        }
+        let state = shard.entry(lock_id.clone()).or_insert_with(|| Mutex::new(create()));
+        Some(f(state.get_mut().unwrap()))
+    }
+
+    /// Takes every shard for reading, in index order.
+    pub fn read(&self) -> TableView<'_> {
+        TableView { shards: self.shards.iter().map(|shard| shard.read().unwrap()).collect() }
+    }
+
+    /// Takes every shard for writing, in index order.
+    pub fn write(&self) -> TableGuard<'_> {
+        TableGuard { table: self, shards: self.shards.iter().map(|shard| shard.write().unwrap()).collect() }
+    }
+
+    pub fn replace(&self , locks : Vec<(LockId , LockState)>) {
+        let mut table = self.write();
+        for shard in table.shards.iter_mut() {
+            shard.clear();
+        }
+        self.len.store(0 , Ordering::Relaxed);
+        for counts in &self.namespaces {
+            counts.lock().unwrap().clear();
+        }
+        for (lock_id , state) in locks {
+            table.insert(lock_id , state);
+        }
+    }
+}
+
+/// Every shard held for reading. Other operations on existing locks go on,
+/// so each lock is read under its own mutex.
+pub struct TableView<'a>{
+    shards : Vec<RwLockReadGuard<'a , Shard>>
+}
+
+impl TableView<'_>{
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Lock ids in order.
+    pub fn ids(&self) -> Vec<&LockId> {
+        let mut ids : Vec<&LockId> = self.shards.iter().flat_map(|shard| shard.keys()).collect();
+        ids.sort();
+        ids
+    }
+
+    pub fn get(&self , lock_id : &LockId) -> Option<LockState> {
+        self.shards[shard_index(lock_id , self.shards.len())].get(lock_id).map(|state| state.lock().unwrap().clone())
+    }
+
+    /// Calls `f` on every lock, in no particular order.
+    pub fn for_each(&self , mut f : impl FnMut(&LockId , &LockState)) {
+        for (lock_id , state) in self.shards.iter().flat_map(|shard| shard.iter()) {
+            f(lock_id , &state.lock().unwrap());
+        }
+    }
+}
+
+/// Every shard held for writing, which excludes every other operation.
+pub struct TableGuard<'a>{
+    table : &'a LockTable,
+    shards : Vec<RwLockWriteGuard<'a , Shard>>
+}
+
+impl TableGuard<'_>{
+    fn shard_mut(&mut self , lock_id : &LockId) -> &mut Shard {
+        let index = shard_index(lock_id , self.shards.len());
+        &mut self.shards[index]
+    }
+
+    pub fn insert(&mut self , lock_id : LockId , state : LockState) {
+        if self.shard_mut(&lock_id).insert(lock_id.clone() , Mutex::new(state)).is_none() {
+            self.table.count_inserted(&lock_id , 0 , 0);
+        }
+    }
+
+    /// Every lock in lock id order.
+    pub fn iter_sorted_mut(&mut self) -> Vec<(&LockId , &mut LockState)> {
+        let mut locks : Vec<(&LockId , &mut LockState)> = self.shards.iter_mut()
+            .flat_map(|shard| shard.iter_mut().map(|(lock_id , state)| (lock_id , state.get_mut().unwrap())))
+            .collect();
+        locks.sort_by_key(|(lock_id , _)| *lock_id);
+        locks
+    }
+
+    /// Keeps the locks `keep` accepts, visiting them in lock id order.
+    pub fn retain(&mut self , mut keep : impl FnMut(&LockId , &mut LockState) -> bool) {
+        let dropped : Vec<LockId> = self.iter_sorted_mut().into_iter()
+            .filter_map(|(lock_id , state)| (!keep(lock_id , state)).then(|| lock_id.clone()))
+            .collect();
+        for lock_id in dropped {
+            self.shard_mut(&lock_id).remove(&lock_id);
+            self.table.count_removed(&lock_id);
+        }
+    }
+}
+
+/// The hasher has fixed keys, so a lock lands in the same shard in every
+/// process.
+fn shard_index(lock_id : &LockId , shards : usize) -> usize {
+    hash_index(lock_id , shards)
+}
+
+pub(crate) fn hash_index(key : &(impl Hash + ?Sized) , shards : usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (hasher.finish() % shards as u64) as usize
+}
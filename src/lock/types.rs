@@ -1,5 +1,8 @@
-use std::{time::Duration};
+use std::{sync::atomic::{AtomicU64, Ordering}, time::Duration};
 use chrono::{DateTime, Utc };
+use serde::{Deserialize, Serialize};
+
+use crate::lock::{metadata::Metadata, stats::LockStats};
 
 
 #[derive(Clone)]
@@ -8,16 +11,24 @@ pub struct Lock {
     pub holder : Option<LockHolder> , 
     pub created_at : DateTime<Utc>, 
     pub wait_queue : Vec<LockRequest>, 
-    // stats : LockStats
+    pub stats : LockStats
 }
-#[derive(Clone)]
+#[derive(Debug , Clone , PartialEq , Serialize , Deserialize)]
 
 pub struct LockHolder {
     pub client_id : ClientId, 
     pub lease_id : LeaseId,
     pub acquired_at : DateTime<Utc> , 
     pub expires_at : DateTime<Utc> , 
-    pub renewal_count : u32
+    pub renewal_count : u32,
+    /// Raft index of the entry that granted the lease. It grows with every
+    /// grant, so a resource can refuse writes carrying an older token than
+    /// one it has seen. 0 outside of raft.
+    #[serde(default)]
+    pub fencing_token : u64,
+    /// What the holder said it holds the lock for.
+    #[serde(default , skip_serializing_if = "Metadata::is_empty")]
+    pub metadata : Metadata
 }
 
 #[derive(Clone)]
@@ -26,26 +37,39 @@ pub struct LockRequest{
     pub requested_at : DateTime<Utc> , 
     pub timeout : Duration
 }
-#[derive(Clone)]
+#[derive(Debug , Clone , PartialEq , Serialize , Deserialize)]
 pub struct LockState{
     pub holder : Option<LockHolder> , 
     pub wait_queue : Vec<WaitRequest>, 
     pub created_at : DateTime<Utc>
 }
-#[derive(Clone)]
+#[derive(Debug , Clone , PartialEq , Serialize , Deserialize)]
 
 pub struct WaitRequest{
     pub client_id : ClientId , 
-    pub requested_at : DateTime<Utc>
+    pub requested_at : DateTime<Utc>,
+    /// Lease length on promotion, as resolved when the acquire was proposed,
+    /// so every replica grants the same expiry.
+    #[serde(default = "default_wait_ttl")]
+    pub ttl_seconds : u64,
+    /// Given to the lease when the waiter is promoted.
+    #[serde(default , skip_serializing_if = "Metadata::is_empty")]
+    pub metadata : Metadata
 }
 
-#[derive(Debug , Clone , PartialEq)]
+/// Waiters in snapshots taken before their TTL was recorded were promoted
+/// with the old 30 second default.
+fn default_wait_ttl() -> u64 {
+    30
+}
+
+#[derive(Debug , Clone , PartialEq , Eq , Hash , Serialize , Deserialize)]
 
 pub struct ClientId(pub String);
-#[derive(Debug , Clone , PartialEq, Eq , Hash)]
+#[derive(Debug , Clone , PartialEq, Eq , Hash , PartialOrd , Ord , Serialize , Deserialize)]
 pub struct LockId (pub String);
 
-#[derive(Debug , Clone , PartialEq)]
+#[derive(Debug , Clone , PartialEq , Serialize , Deserialize)]
 pub struct LeaseId (pub String);
 
 
@@ -53,12 +77,14 @@ pub struct LeaseId (pub String);
 pub enum AcquireResult{
     Granted {
          lease_id : LeaseId , 
-         expires_at : DateTime<Utc>
+         expires_at : DateTime<Utc>,
+         fencing_token : u64
     } , 
     Queued {
          position : usize , 
          estimated_wait : Duration
     }
+    , QuotaExceeded(String)
     , Error(String)
 }
 
@@ -67,6 +93,18 @@ pub enum ReleaseResult{
     Success , NotHolder , NotFound , Error(String)
 }
 
+#[derive(Debug)]
+pub enum TransferResult{
+    Transferred {
+        lease_id : LeaseId,
+        expires_at : DateTime<Utc>,
+        fencing_token : u64
+    },
+    NotHolder,
+    NotFound,
+    Expired
+}
+
 #[derive(Debug)]
 
 pub enum RenewResult{
@@ -79,11 +117,65 @@ pub enum RenewResult{
     Error(String),
 }
 
+/// Time and lease id source for one state machine operation.
+///
+/// Replicas apply the same entry with the same context, so they agree on
+/// expiry times and on the lease ids they hand out.
+pub struct ApplyContext{
+    pub now : DateTime<Utc>,
+    /// Raft index of the entry being applied, 0 outside of raft.
+    pub index : u64,
+    seed : u64,
+    issued : AtomicU64
+}
+
+impl ApplyContext{
+    pub fn new(now : DateTime<Utc> , seed : u64) -> Self {
+        Self { now, index: 0, seed, issued: AtomicU64::new(0) }
+    }
+
+    pub fn with_index(mut self , index : u64) -> Self {
+        self.index = index;
+        self
+    }
+
+    /// Context for operations that are not replicated, such as direct use of
+    /// a manager in tests.
+    pub fn local() -> Self {
+        Self::new(Utc::now() , uuid::Uuid::new_v4().as_u64_pair().0)
+    }
+
+    pub fn next_lease_id(&self) -> LeaseId {
+        let issued = self.issued.fetch_add(1 , Ordering::Relaxed);
+        let high = splitmix64(self.seed ^ issued.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        LeaseId(uuid::Uuid::from_u64_pair(high , splitmix64(high)).to_string())
+    }
+}
+
+fn splitmix64(value : u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
 pub trait LockManager : Send + Sync  {
-    fn try_acquire (&self , lock_id : &LockId , client_id : &ClientId , ttl : Duration ) -> AcquireResult ;
-    fn release (&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId ) -> ReleaseResult ;
-    fn renew (&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId ,ttl: Duration ) ->RenewResult ;
+    fn try_acquire_at (&self , lock_id : &LockId , client_id : &ClientId , ttl : Duration , ctx : &ApplyContext ) -> AcquireResult ;
+    fn release_at (&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId , ctx : &ApplyContext ) -> ReleaseResult ;
+    fn renew_at (&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId ,ttl: Duration , ctx : &ApplyContext ) ->RenewResult ;
+
+    fn try_acquire (&self , lock_id : &LockId , client_id : &ClientId , ttl : Duration ) -> AcquireResult {
+        self.try_acquire_at(lock_id , client_id , ttl , &ApplyContext::local())
+    }
+    fn release (&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId ) -> ReleaseResult {
+        self.release_at(lock_id , client_id , lease_id , &ApplyContext::local())
+    }
+    fn renew (&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId ,ttl: Duration ) ->RenewResult {
+        self.renew_at(lock_id , client_id , lease_id , ttl , &ApplyContext::local())
+    }
     fn status(&self , lock_id : &LockId ) -> Option<LockState>;
+    /// Every lock with its state, ordered by lock id.
+    fn list(&self) -> Vec<(LockId , LockState)>;
     fn current_holder(&self , lock_id : &LockId) -> Option<ClientId>;
     fn queue_length(&self , lock_id : &LockId) -> usize;
 
@@ -0,0 +1,55 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::raft::raft_commands::{CommandResponse, IdempotencyKey};
+
+pub const DEFAULT_DEDUP_CAPACITY : usize = 10_000;
+
+/// Responses of already applied commands, keyed by their idempotency key.
+///
+/// Every replica fills the table while applying the log and evicts in apply
+/// order, so all replicas hold the same entries at the same index.
+#[derive(Debug , Clone , Serialize , Deserialize)]
+pub struct DedupTable{
+    responses : HashMap<IdempotencyKey , CommandResponse>,
+    order : VecDeque<IdempotencyKey>,
+    capacity : usize
+}
+
+impl DedupTable{
+    pub fn new(capacity : usize) -> Self {
+        Self { responses: HashMap::new(), order: VecDeque::new(), capacity }
+    }
+
+    pub fn get(&self , key : &IdempotencyKey) -> Option<&CommandResponse> {
+        self.responses.get(key)
+    }
+
+    pub fn insert(&mut self , key : IdempotencyKey , response : CommandResponse) {
+        if self.capacity == 0 || self.responses.contains_key(&key) {
+            return
+        }
+        while self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front(){
+                self.responses.remove(&oldest);
+            }
+        }
+        self.order.push_back(key.clone());
+        self.responses.insert(key , response);
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+}
+
+impl Default for DedupTable{
+    fn default() -> Self {
+        Self::new(DEFAULT_DEDUP_CAPACITY)
+    }
+}
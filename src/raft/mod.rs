@@ -3,4 +3,7 @@
 pub mod node;
 pub mod raft_commands;
 pub mod storage;
-pub mod raft_client;
\ No newline at end of file
+pub mod raft_client;
+pub mod transport;
+pub mod dedup;
+pub mod node_test;
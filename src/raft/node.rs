@@ -1,131 +1,705 @@
 
 
-use raft::{Config, RawNode, default_logger};
-use std::{collections::HashMap, sync::{Arc, Mutex}};
-use tokio::sync::{RwLock, mpsc, oneshot};
+use chrono::{DateTime, Utc};
+use metrics::{counter, gauge, histogram};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use protobuf::Message as _;
+use raft::{Config, RawNode, StateRole, Storage, default_logger, prelude::{ConfChange, ConfChangeType, Entry, EntryType, Message}};
+use std::{collections::{BTreeMap, HashMap}, sync::{Arc, Mutex, atomic::{AtomicU64, Ordering}}, time::Duration};
+use tokio::{sync::{RwLock, broadcast, mpsc, oneshot}, time::Instant};
 
-use crate::{lock::{manager::InMemoryLockManager, types::{AcquireResult, ClientId, LeaseId, LockId, LockManager, ReleaseResult, RenewResult}}, raft::{raft_client::RaftClient, raft_commands::{CommandResponse, LockCommand}, storage::DistlockStorage}};
+use crate::{auth::acl::AclTable, config::server_config::{PeerConfig, RaftConfig}, lock::{backend::{LockBackend, SharedBackend}, barrier::BarrierResult, clock::{Clock, SystemClock}, kv::{KvResult, VERSION_MISMATCH}, manager::InMemoryLockManager, quota::QUOTA_EXCEEDED, types::{AcquireResult, ApplyContext, ClientId, LeaseId, LockId, ReleaseResult, RenewResult, TransferResult}}, raft::{dedup::DedupTable, raft_commands::{AdminCommand, AdminResponse, AppliedEntry, CommandResponse, LockCommand, LogEntry, ReadResponse}, storage::DistlockStorage, transport::{NoopTransport, Transport}}, telemetry::prometheus::{PENDING_PROPOSALS, PROPOSAL_LATENCY, RAFT_APPLIED_INDEX, RAFT_COMMIT_INDEX, RAFT_LEADER_CHANGES, RAFT_TERM}};
+
+const APPLIED_CHANNEL_CAPACITY : usize = 1024;
 
 pub struct RaftNode {
 
     raft : RawNode<DistlockStorage>, 
     storage : DistlockStorage,
 
-    state_machine : Arc<RwLock<InMemoryLockManager>>, 
+    state_machine : SharedBackend, 
+    // Principals and ACL rules, replicated alongside the lock table.
+    acl : Arc<RwLock<AclTable>>,
 
     id : u64 , 
 
     peers : Vec<u64> , 
+    tick_interval : Duration,
     command_rx: mpsc::Receiver<(LockCommand , oneshot::Sender<CommandResponse>)>,
-    pending_maps :  Mutex<HashMap<u64 , oneshot::Sender<CommandResponse>>>
+    admin_rx: mpsc::Receiver<(AdminCommand , oneshot::Sender<AdminResponse>)>,
+    message_tx : mpsc::UnboundedSender<Message>,
+    message_rx : mpsc::UnboundedReceiver<Message>,
+    transport : Arc<dyn Transport>,
+    clock : Arc<dyn Clock>,
+    // Source of lease seeds stamped on proposals.
+    rng : StdRng,
+    // Time of the last applied entry. Entries never apply earlier than this,
+    // even if a new leader's clock is behind the old one.
+    last_applied_at : Mutex<DateTime<Utc>>,
+    applied_tx : broadcast::Sender<AppliedEntry>,
+    // Proposals of this node awaiting their response, with when they were
+    // proposed.
+    pending_maps :  Mutex<HashMap<u64 , (oneshot::Sender<CommandResponse> , Instant)>>,
+    // Log slot of each pending proposal: index -> (term , request id).
+    proposed : BTreeMap<u64 , (u64 , u64)>,
+    // Replicated alongside the state machine: filled while applying entries.
+    dedup : Mutex<DedupTable>,
+    // While draining the node refuses new client writes but keeps applying
+    // the ones already proposed.
+    draining : bool,
+    pending_transfer : Option<PendingTransfer>,
+    applied_index : u64,
+    // Linearizable reads waiting for their read index to be confirmed, then
+    // for the state machine to catch up with it.
+    pending_reads : HashMap<Vec<u8> , PendingRead>,
+    confirmed_reads : Vec<(u64 , Vec<u8>)>,
+    next_read_id : u64,
+    // Last leader this node heard of, 0 when unknown. Shared with the HTTP
+    // layer so it can point clients at the leader.
+    known_leader : Arc<AtomicU64>,
+    // Membership change waiting to be applied: log index -> response.
+    pending_conf_change : Option<(u64 , oneshot::Sender<AdminResponse>)>,
+    // Nodes added at runtime, with the addresses they were added with.
+    added_peers : BTreeMap<u64 , PeerConfig>
+}
+
+/// A read waiting for its ReadIndex: either a status read served by the
+/// node, or a caller that reads the state machine itself once it caught up.
+enum PendingRead{
+    Status(LockId , oneshot::Sender<ReadResponse>),
+    Index(oneshot::Sender<AdminResponse>)
+}
+
+impl PendingRead{
+    fn fail(self , error_type : &str , message : String){
+        match self {
+            PendingRead::Status(_ , sender) => { let _ = sender.send(ReadResponse::Error { error_type: error_type.to_string(), message }); }
+            PendingRead::Index(sender) => { let _ = sender.send(AdminResponse::Error { error_type: error_type.to_string(), message }); }
+        }
+    }
+}
+
+struct PendingTransfer{
+    target : u64,
+    deadline : Instant,
+    started : bool,
+    was_draining : bool,
+    response_sender : oneshot::Sender<AdminResponse>
 }
 
 impl RaftNode {
-    pub fn new(id : u64 , peers : Vec<u64> , command_rx :mpsc::Receiver<(LockCommand , oneshot::Sender<CommandResponse>)> ) -> Self {
+    pub fn new(id : u64 , peers : Vec<u64> , command_rx :mpsc::Receiver<(LockCommand , oneshot::Sender<CommandResponse>)> , admin_rx : mpsc::Receiver<(AdminCommand , oneshot::Sender<AdminResponse>)> ) -> Self {
+        Self::with_config(id , peers , &RaftConfig::default() , InMemoryLockManager::new() , command_rx , admin_rx)
+    }
+
+    pub fn with_config(id : u64 , peers : Vec<u64> , raft_config : &RaftConfig , state_machine : impl LockBackend + 'static , command_rx :mpsc::Receiver<(LockCommand , oneshot::Sender<CommandResponse>)> , admin_rx : mpsc::Receiver<(AdminCommand , oneshot::Sender<AdminResponse>)> ) -> Self {
+
+        let mut voters = vec![id];
+        voters.extend(peers.into_iter().filter(|peer| *peer != id));
+        let storage = DistlockStorage::new_with_voters(voters);
+
+        Self::with_storage(id , raft_config , storage , state_machine , command_rx , admin_rx)
+    }
+
+    /// Starts a node on top of existing raft storage, e.g. after a restart.
+    /// Peers are taken from the membership recorded in the storage, and the
+    /// state machine is rebuilt by re-applying the committed log.
+    pub fn with_storage(id : u64 , raft_config : &RaftConfig , storage : DistlockStorage , state_machine : impl LockBackend + 'static , command_rx :mpsc::Receiver<(LockCommand , oneshot::Sender<CommandResponse>)> , admin_rx : mpsc::Receiver<(AdminCommand , oneshot::Sender<AdminResponse>)> ) -> Self {
 
-        let storage = DistlockStorage::new();
+        let peers : Vec<u64> = storage.initial_state().unwrap().conf_state.voters
+            .into_iter().filter(|peer| *peer != id).collect();
 
         let config = Config{
             id , 
-            election_tick : 10 , 
-            heartbeat_tick : 3 , 
-            max_size_per_msg : 1024 * 1024, 
-            max_inflight_msgs : 256, 
+            election_tick : raft_config.election_tick , 
+            max_election_tick : raft_config.max_election_tick ,
+            heartbeat_tick : raft_config.heartbeat_tick , 
+            max_size_per_msg : raft_config.max_size_per_msg, 
+            max_inflight_msgs : raft_config.max_inflight_msgs, 
+            // A leader cut off from the majority steps down instead of
+            // accepting proposals it can never commit.
+            check_quorum : true ,
+            pre_vote : true ,
             ..Default::default()
         };
         let raft = RawNode::new(&config, storage.clone() , &default_logger()).unwrap();
-        let state_machine = Arc::new(RwLock::new(InMemoryLockManager::new()));
+        let state_machine : SharedBackend = Arc::new(state_machine);
+        let (message_tx , message_rx) = mpsc::unbounded_channel();
+        let (applied_tx , _) = broadcast::channel(APPLIED_CHANNEL_CAPACITY);
 
-        let node =    Self { storage , raft, state_machine, id, peers , command_rx , pending_maps : Mutex::new(HashMap::new()) };
+        Self { storage , raft, state_machine, acl : Arc::new(RwLock::new(AclTable::default())) , id, peers , command_rx , admin_rx , message_tx , message_rx ,
+            tick_interval : Duration::from_millis(raft_config.tick_interval_ms) ,
+            transport : Arc::new(NoopTransport) ,
+            clock : Arc::new(SystemClock) ,
+            rng : StdRng::from_entropy() ,
+            last_applied_at : Mutex::new(DateTime::<Utc>::MIN_UTC) ,
+            applied_tx ,
+            pending_maps : Mutex::new(HashMap::new()) ,
+            proposed : BTreeMap::new() ,
+            dedup : Mutex::new(DedupTable::default()) ,
+            draining : false ,
+            pending_transfer : None ,
+            applied_index : 0 ,
+            pending_reads : HashMap::new() ,
+            confirmed_reads : Vec::new() ,
+            next_read_id : 0 ,
+            known_leader : Arc::new(AtomicU64::new(0)) ,
+            pending_conf_change : None ,
+            added_peers : BTreeMap::new()
+        }
+
+    }
+
+    pub fn with_transport(mut self , transport : Arc<dyn Transport>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    pub fn with_clock(mut self , clock : Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
 
-     
-     node 
+    /// Makes the lease seeds of this node's proposals reproducible.
+    pub fn with_seed(mut self , seed : u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.raft.raft.state == StateRole::Leader
+    }
+
+    pub fn leader_id(&self) -> u64 {
+        self.raft.raft.leader_id
+    }
 
+    pub fn known_leader(&self) -> Arc<AtomicU64> {
+        self.known_leader.clone()
+    }
+
+    pub fn storage(&self) -> DistlockStorage {
+        self.storage.clone()
+    }
+
+    /// Stream of every command this node applies, in log order.
+    pub fn subscribe_applied(&self) -> broadcast::Receiver<AppliedEntry> {
+        self.applied_tx.subscribe()
+    }
+
+    /// Sender side of `subscribe_applied`, to subscribe once the node has
+    /// been moved into its task.
+    pub fn applied_notifier(&self) -> broadcast::Sender<AppliedEntry> {
+        self.applied_tx.clone()
+    }
+
+    /// Mailbox for raft messages coming from peers.
+    pub fn message_sender(&self) -> mpsc::UnboundedSender<Message> {
+        self.message_tx.clone()
+    }
+
+    pub fn state_machine(&self) -> SharedBackend {
+        self.state_machine.clone()
+    }
+
+    pub fn acl(&self) -> Arc<RwLock<AclTable>> {
+        self.acl.clone()
     }
 
     pub async fn run (mut self) {
 
+        let mut ticker = tokio::time::interval(self.tick_interval);
         loop { 
            tokio::select! {
             Some((command , response_sender)) = self.command_rx.recv() =>{
                 self.handle_command(command , response_sender).await
             }
-            _ = tokio::time::sleep(tokio::time::Duration::from_millis(100)) => {
+            Some((command , response_sender)) = self.admin_rx.recv() =>{
+                self.handle_admin(command , response_sender)
+            }
+            Some(message) = self.message_rx.recv() => {
+                self.step(message);
+            }
+            _ = ticker.tick() => {
                 self.tick();
-                self.process_raft_ready();
             }
            }
+           self.poll_transfer();
+           self.process_raft_ready().await;
+           self.record_metrics();
         }
     }
 
     pub async fn handle_command(&mut self , command : LockCommand , response_sender : oneshot::Sender<CommandResponse> ){
-        if self.raft.status().id != self.id {
+        if self.raft.raft.state != StateRole::Leader {
             let _ = response_sender.send(CommandResponse::Error{
-                error_type : "Not the leader".to_string(), 
-                message : "Failed to append log".to_string()
+                error_type : "NotLeader".to_string(), 
+                message : format!("Not the leader, current leader is {}" , self.raft.raft.leader_id)
             });
             return 
         }
-        let request_id = match &command{
-            LockCommand::Acquire { request_id,..   } => request_id,
-            LockCommand::Release { request_id, .. } => request_id,
-            LockCommand::Renew { request_id,.. } => request_id,
+        if self.draining {
+            let _ = response_sender.send(CommandResponse::Error{
+                error_type : "Draining".to_string(), 
+                message : "Node is draining, retry against another node".to_string()
+            });
+            return
+        }
+        // A retry of a command that is already applied is answered locally.
+        if let Some(key) = command.idempotency_key()
+            && let Some(response) = self.dedup.lock().unwrap().get(key){
+            let _ = response_sender.send(response.clone());
+            return
+        }
+        let request_id = command.request_id();
+
+        self.pending_maps.lock().unwrap().insert(request_id, (response_sender , Instant::now()));
+
+        match self.propose(command).await {
+            Ok(()) => {
+                let index = self.raft.raft.raft_log.last_index();
+                self.proposed.insert(index , (self.raft.raft.term , request_id));
+            }
+            Err(e) => if let Some((sender , _)) = self.pending_maps.lock().unwrap().remove(&request_id){
+                let _ = sender.send(CommandResponse::Error { error_type: "ProposalFailed".to_string(), message: e });
+            }
+        }
+
+
+    }
+
+    pub fn handle_admin(&mut self , command : AdminCommand , response_sender : oneshot::Sender<AdminResponse>){
+        match command {
+            AdminCommand::SetDraining(draining) => {
+                self.draining = draining;
+                let in_flight = self.pending_maps.lock().unwrap().len();
+                let _ = response_sender.send(AdminResponse::DrainUpdated { draining, in_flight });
+            }
+            AdminCommand::TransferLeader { target, timeout_ms } => {
+                self.start_transfer(target , Duration::from_millis(timeout_ms) , response_sender)
+            }
+            AdminCommand::Members => {
+                let conf = self.raft.raft.prs().conf().to_conf_state();
+                let _ = response_sender.send(AdminResponse::Members {
+                    leader_id: self.raft.raft.leader_id,
+                    voters: conf.voters,
+                    learners: conf.learners,
+                    added: self.added_peers.values().cloned().collect()
+                });
+            }
+            AdminCommand::AddNode(peer) => {
+                let context = serde_json::to_vec(&peer).unwrap_or_default();
+                self.propose_conf_change(ConfChangeType::AddNode , peer.id , context , response_sender)
+            }
+            AdminCommand::RemoveNode(id) => {
+                self.propose_conf_change(ConfChangeType::RemoveNode , id , Vec::new() , response_sender)
+            }
+            AdminCommand::ReadIndex => self.read_index(PendingRead::Index(response_sender))
+        }
+    }
+
+    /// Proposes a single membership change. It is answered once applied;
+    /// raft accepts no new change until then.
+    fn propose_conf_change(&mut self , change_type : ConfChangeType , node_id : u64 , context : Vec<u8> , response_sender : oneshot::Sender<AdminResponse>){
+        let error = |error_type : &str , message : String| AdminResponse::Error { error_type: error_type.to_string(), message };
+
+        if self.raft.raft.state != StateRole::Leader {
+            let _ = response_sender.send(error("NotLeader" , format!("Not the leader, current leader is {}" , self.raft.raft.leader_id)));
+            return
+        }
+        if self.pending_conf_change.is_some() || self.raft.raft.has_pending_conf() {
+            let _ = response_sender.send(error("ChangeInProgress" , "Another membership change is not applied yet".to_string()));
+            return
+        }
+        let mut change = ConfChange::default();
+        change.set_change_type(change_type);
+        change.node_id = node_id;
+        match self.raft.propose_conf_change(context , change) {
+            Ok(()) => {
+                let index = self.raft.raft.raft_log.last_index();
+                self.pending_conf_change = Some((index , response_sender));
+            }
+            Err(e) => {
+                let _ = response_sender.send(error("ProposalFailed" , format!("Proposal Error {}" , e)));
+            }
+        }
+    }
+
+    fn apply_conf_change(&mut self , entry : &Entry){
+        let result = ConfChange::parse_from_bytes(&entry.data)
+            .map_err(|e| e.to_string())
+            .and_then(|change| self.raft.apply_conf_change(&change).map(|conf| (change , conf)).map_err(|e| e.to_string()));
+        let (change , conf) = match result {
+            Ok(applied) => applied,
+            Err(e) => {
+                tracing::error!("Failed to apply membership change {}: {}" , entry.index , e);
+                self.answer_conf_change(entry.index , AdminResponse::Error { error_type: "InvalidChange".to_string(), message: e });
+                return
+            }
         };
 
-     self.propose(command.clone()).await;
-        self.pending_maps.lock().unwrap().insert(*request_id, response_sender);
-        let data = serde_json::to_vec(&command).unwrap();
+        match change.get_change_type() {
+            ConfChangeType::AddNode => {
+                if let Ok(peer) = serde_json::from_slice::<PeerConfig>(&entry.context)
+                    && peer.id != self.id {
+                    self.transport.add_peer(peer.id , &peer.addr);
+                    self.added_peers.insert(peer.id , peer);
+                }
+            }
+            ConfChangeType::RemoveNode => {
+                self.transport.remove_peer(change.node_id);
+                self.added_peers.remove(&change.node_id);
+            }
+            ConfChangeType::AddLearnerNode => {}
+        }
+        self.peers = conf.voters.iter().copied().filter(|id| *id != self.id).collect();
+        if let Err(e) = self.storage.set_conf_state(conf.clone()) {
+            tracing::error!("Failed to persist membership: {}" , e);
+        }
+        tracing::info!("Membership changed at index {}, voters {:?}" , entry.index , conf.voters);
+        self.answer_conf_change(entry.index , AdminResponse::MembershipChanged { voters: conf.voters });
+    }
+
+    fn answer_conf_change(&mut self , index : u64 , response : AdminResponse){
+        if let Some((pending_index , _)) = &self.pending_conf_change
+            && *pending_index == index
+            && let Some((_ , sender)) = self.pending_conf_change.take() {
+            let _ = sender.send(response);
+        }
+    }
 
-        if let Err(e) = self.raft.propose(vec![], data){
-            self.pending_maps.lock().unwrap().remove(request_id);
+    fn start_transfer(&mut self , target : Option<u64> , timeout : Duration , response_sender : oneshot::Sender<AdminResponse>){
+        let error = |error_type : &str , message : String| AdminResponse::Error { error_type: error_type.to_string(), message };
+
+        if self.pending_transfer.is_some(){
+            let _ = response_sender.send(error("TransferInProgress" , "A leadership transfer is already running".to_string()));
+            return
+        }
+        if self.raft.raft.state != StateRole::Leader {
+            let _ = response_sender.send(error("NotLeader" , format!("Not the leader, current leader is {}" , self.raft.raft.leader_id)));
+            return
         }
+        let target = match target.or_else(|| self.best_transferee()){
+            Some(target) if self.peers.contains(&target) => target,
+            Some(target) => {
+                let _ = response_sender.send(error("InvalidTarget" , format!("Node {} is not a peer of node {}" , target , self.id)));
+                return
+            }
+            None => {
+                let _ = response_sender.send(error("NoTransferee" , "No peer available to take over leadership".to_string()));
+                return
+            }
+        };
 
+        self.pending_transfer = Some(PendingTransfer {
+            target,
+            deadline: Instant::now() + timeout,
+            started: false,
+            was_draining: self.draining,
+            response_sender
+        });
+        self.draining = true;
+    }
 
+    /// The peer with the highest matched index needs the least catch-up
+    /// before it can take over.
+    fn best_transferee(&self) -> Option<u64> {
+        self.raft.raft.prs().iter()
+            .filter(|(id , _)| self.peers.contains(id))
+            .max_by_key(|(_ , progress)| progress.matched)
+            .map(|(id , _)| *id)
     }
 
-    pub async fn apply_entry (&self , entry : &raft::eraftpb::Entry){
+    fn poll_transfer(&mut self){
+        let Some(transfer) = self.pending_transfer.as_mut() else {
+            return
+        };
+        let is_leader = self.raft.raft.state == StateRole::Leader;
+        let leader_id = self.raft.raft.leader_id;
+
+        if !is_leader && leader_id == transfer.target {
+            let transfer = self.pending_transfer.take().unwrap();
+            let _ = transfer.response_sender.send(AdminResponse::LeaderTransferred { from: self.id, to: transfer.target, term: self.raft.raft.term });
+            return
+        }
+        if !is_leader && leader_id != 0 {
+            let transfer = self.pending_transfer.take().unwrap();
+            let _ = transfer.response_sender.send(AdminResponse::Error {
+                error_type: "LeadershipLost".to_string(),
+                message: format!("Leadership moved to node {} instead of node {}" , leader_id , transfer.target)
+            });
+            return
+        }
+        if Instant::now() >= transfer.deadline {
+            let transfer = self.pending_transfer.take().unwrap();
+            if is_leader {
+                self.raft.raft.abort_leader_transfer();
+                self.draining = transfer.was_draining;
+            }
+            let _ = transfer.response_sender.send(AdminResponse::Error {
+                error_type: "Timeout".to_string(),
+                message: format!("Node {} did not take over leadership in time" , transfer.target)
+            });
+            return
+        }
+        // Only hand over once every in-flight proposal has been answered.
+        if is_leader && !transfer.started && self.pending_maps.lock().unwrap().is_empty() {
+            transfer.started = true;
+            let target = transfer.target;
+            self.raft.transfer_leader(target);
+        }
+    }
+
+    pub fn step(&mut self , message : Message){
+        if let Err(e) = self.raft.step(message){
+            tracing::warn!("Failed to step raft message: {}" , e);
+        }
+    }
+
+    pub async fn process_raft_ready(&mut self){
+        if !self.raft.has_ready() {
+            return
+        }
+        let mut ready = self.raft.ready();
+
+        if let Some(soft_state) = ready.ss() {
+            let previous = self.known_leader.swap(soft_state.leader_id , Ordering::Relaxed);
+            if soft_state.leader_id != 0 && soft_state.leader_id != previous {
+                counter!(RAFT_LEADER_CHANGES).increment(1);
+            }
+            if soft_state.raft_state != StateRole::Leader {
+                self.fail_reads();
+            }
+        }
+
+        for read_state in ready.take_read_states() {
+            self.confirmed_reads.push((read_state.index , read_state.request_ctx));
+        }
+
+        self.send_messages(ready.take_messages());
+
+        if !ready.snapshot().is_empty()
+            && let Err(e) = self.storage.apply_snapshot(ready.snapshot().clone()){
+            tracing::error!("Failed to apply snapshot: {}" , e);
+        }
 
-        let command : LockCommand = serde_json::from_slice(entry.get_data()).unwrap();
+        self.apply_entries(ready.take_committed_entries()).await;
 
-        let request_id = match command{
-            LockCommand::Acquire {  request_id,.. } => request_id , 
-            LockCommand::Release { request_id, .. } => request_id, 
-            LockCommand::Renew { request_id, .. } => request_id
+        if !ready.entries().is_empty()
+            && let Err(e) = self.storage.append(ready.entries()){
+            tracing::error!("Failed to append entries: {}" , e);
+        }
+        if let Some(hard_state) = ready.hs()
+            && let Err(e) = self.storage.set_hardstate(hard_state.clone()){
+            tracing::error!("Failed to persist hard state: {}" , e);
+        }
+        self.send_messages(ready.take_persisted_messages());
+
+        let mut light_ready = self.raft.advance(ready);
+        if let Some(commit) = light_ready.commit_index()
+            && let Err(e) = self.storage.set_commit(commit){
+            tracing::error!("Failed to persist commit index: {}" , e);
+        }
+        self.send_messages(light_ready.take_messages());
+        self.apply_entries(light_ready.take_committed_entries()).await;
+        self.raft.advance_apply();
+        self.serve_reads().await;
+    }
+
+    fn record_metrics(&self){
+        gauge!(RAFT_TERM).set(self.raft.raft.term as f64);
+        gauge!(RAFT_COMMIT_INDEX).set(self.raft.raft.raft_log.committed as f64);
+        gauge!(RAFT_APPLIED_INDEX).set(self.applied_index as f64);
+        gauge!(PENDING_PROPOSALS).set(self.pending_maps.lock().unwrap().len() as f64);
+    }
+
+    fn send_messages(&self , messages : Vec<Message>){
+        for message in messages {
+            self.transport.send(message);
+        }
+    }
+
+    async fn apply_entries(&mut self , entries : Vec<Entry>){
+        for entry in entries {
+            self.applied_index = entry.index;
+            self.drop_overwritten(&entry);
+            if entry.get_entry_type() == EntryType::EntryConfChange {
+                self.apply_conf_change(&entry);
+                continue
+            }
+            // A pending membership change whose slot went to another entry
+            // was dropped by a new leader.
+            self.answer_conf_change(entry.index , AdminResponse::Error {
+                error_type: "ProposalDropped".to_string(),
+                message: format!("Entry {} was replaced by a new leader" , entry.index)
+            });
+            // Empty entries are appended by a new leader when it takes office.
+            if entry.data.is_empty() || entry.get_entry_type() != EntryType::EntryNormal {
+                continue
+            }
+            self.apply_entry(&entry).await;
+        }
+    }
+
+    /// Fails the proposals that can no longer commit: their log slot holds
+    /// another entry, or a later term's entry committed before them. Those
+    /// commands are known not to be applied, so clients can retry them right
+    /// away instead of waiting for a timeout.
+    fn drop_overwritten(&mut self , entry : &Entry){
+        let (settled , waiting) : (BTreeMap<_ , _> , BTreeMap<_ , _>) = std::mem::take(&mut self.proposed)
+            .into_iter()
+            .partition(|(index , (term , _))| *index <= entry.index || *term < entry.term);
+        self.proposed = waiting;
+        for (index , (term , request_id)) in settled {
+            if index == entry.index && term == entry.term {
+                continue
+            }
+            if let Some((sender , _)) = self.pending_maps.lock().unwrap().remove(&request_id) {
+                let _ = sender.send(CommandResponse::Error {
+                    error_type : "ProposalDropped".to_string(),
+                    message : format!("Entry {} was replaced by a new leader" , index)
+                });
+            }
+        }
+    }
+
+    /// Reads the status of a lock without going through the log. The read is
+    /// served once the leader confirmed it still leads and the state machine
+    /// applied everything committed before the read arrived (raft ReadIndex).
+    pub fn read_status(&mut self , lock_id : LockId , response_sender : oneshot::Sender<ReadResponse>){
+        self.read_index(PendingRead::Status(lock_id , response_sender))
+    }
+
+    fn read_index(&mut self , read : PendingRead){
+        if self.raft.raft.state != StateRole::Leader {
+            read.fail("NotLeader" , format!("Not the leader, current leader is {}" , self.raft.raft.leader_id));
+            return
+        }
+        self.next_read_id += 1;
+        let ctx = self.next_read_id.to_be_bytes().to_vec();
+        self.raft.read_index(ctx.clone());
+        self.pending_reads.insert(ctx , read);
+    }
+
+    /// Reads of a leader that stepped down are never confirmed.
+    fn fail_reads(&mut self){
+        self.confirmed_reads.clear();
+        let leader_id = self.raft.raft.leader_id;
+        for (_ , read) in self.pending_reads.drain() {
+            read.fail("NotLeader" , format!("Not the leader, current leader is {}" , leader_id));
+        }
+    }
+
+    async fn serve_reads(&mut self){
+        if self.confirmed_reads.is_empty() {
+            return
+        }
+        let applied_index = self.applied_index;
+        let (ready , waiting) : (Vec<_> , Vec<_>) = std::mem::take(&mut self.confirmed_reads)
+            .into_iter()
+            .partition(|(index , _)| *index <= applied_index);
+        self.confirmed_reads = waiting;
+
+        let manager = &self.state_machine;
+        for (index , ctx) in ready {
+            match self.pending_reads.remove(&ctx) {
+                Some(PendingRead::Status(lock_id , response_sender)) => {
+                    let _ = response_sender.send(ReadResponse::Status(manager.status(&lock_id).await));
+                }
+                Some(PendingRead::Index(response_sender)) => {
+                    let _ = response_sender.send(AdminResponse::ReadIndex { index });
+                }
+                None => {}
+            }
+        }
+    }
+
+    pub async fn apply_entry (&self , entry : &raft::eraftpb::Entry){
+
+        let log_entry : LogEntry = match serde_json::from_slice(entry.get_data()){
+            Ok(log_entry) => log_entry,
+            Err(e) => {
+                tracing::error!("Failed to decode entry {}: {}" , entry.index , e);
+                return
+            }
+        };
+        let command = log_entry.command;
+        let applied_at = {
+            let mut last_applied_at = self.last_applied_at.lock().unwrap();
+            *last_applied_at = (*last_applied_at).max(log_entry.issued_at);
+            *last_applied_at
         };
+        let ctx = ApplyContext::new(applied_at , log_entry.lease_seed).with_index(entry.index);
 
-        let result = self.apply_command_to_state(command.clone()).await;
+        let request_id = command.request_id();
+        let idempotency_key = command.idempotency_key().cloned();
 
-        if let Ok(mut pending) = self.pending_maps.lock(){
-            
-            if let Some(sender) = pending.remove(&request_id){
-                _ = sender.send(result)
+        let cached = idempotency_key.as_ref().and_then(|key| self.dedup.lock().unwrap().get(key).cloned());
+        let result = match cached {
+            Some(response) => response,
+            None => {
+                let response = self.apply_command_to_state(command.clone() , &ctx).await;
+                if let Some(key) = idempotency_key {
+                    self.dedup.lock().unwrap().insert(key , response.clone());
+                }
+                response
             }
+        };
+
+        let _ = self.applied_tx.send(AppliedEntry { index: entry.index, term: entry.term, applied_at, command, response: result.clone() });
+
+        // Request ids are only unique per proposer.
+        if log_entry.proposer == self.id
+            && let Ok(mut pending) = self.pending_maps.lock()
+            && let Some((sender , proposed_at)) = pending.remove(&request_id){
+            histogram!(PROPOSAL_LATENCY).record(proposed_at.elapsed().as_secs_f64());
+            _ = sender.send(result)
         }
 
     }
 
- pub async fn apply_command_to_state(&self, command: LockCommand) -> CommandResponse {
+ pub async fn apply_command_to_state(&self, command: LockCommand, ctx: &ApplyContext) -> CommandResponse {
     use std::time::Duration;
     
-    let manager = self.state_machine.write().await;
+    let manager = &self.state_machine;
     
     match command {
-        LockCommand::Acquire { lock_id, client_id, ttl_seconds, request_id } => {
-            let result = manager.try_acquire(
-                &LockId(lock_id),
+        LockCommand::Acquire { lock_id, client_id, ttl_seconds, queue, metadata, .. } => {
+            let lock_id = LockId(lock_id);
+            if !queue && manager.status(&lock_id).await.and_then(|state| state.holder).is_some_and(|holder| holder.expires_at >= ctx.now) {
+                return CommandResponse::Error {
+                    error_type: "LockHeld".to_string(),
+                    message: "Lock is already held".to_string(),
+                }
+            }
+            let result = manager.acquire(
+                &lock_id,
                 &ClientId(client_id),
                 Duration::from_secs(ttl_seconds),
-            );
+                metadata,
+                ctx,
+            ).await;
             
             match result {
-                AcquireResult::Granted { lease_id, expires_at } => {
+                AcquireResult::Granted { lease_id, expires_at, fencing_token } => {
                     CommandResponse::AcquireGranted {
                         lease_id: lease_id.0,  // Assuming .0 is public
                         expires_at: expires_at.to_rfc3339(),
+                        fencing_token,
                     }
                 }
-                AcquireResult::Queued { position, .. } => {
-                    CommandResponse::AcquireQueued { position }
+                AcquireResult::Queued { position, estimated_wait } => {
+                    CommandResponse::AcquireQueued { position , estimated_wait: estimated_wait.as_secs() }
+                }
+                AcquireResult::QuotaExceeded(message) => {
+                    CommandResponse::Error {
+                        error_type: QUOTA_EXCEEDED.to_string(),
+                        message,
+                    }
                 }
                 AcquireResult::Error(message) => {
                     CommandResponse::Error {
@@ -136,12 +710,13 @@ impl RaftNode {
             }
         }
         
-        LockCommand::Release { request_id, lock_id, client_id, lease_id } => {
+        LockCommand::Release { lock_id, client_id, lease_id, .. } => {
             let result = manager.release(
                 &LockId(lock_id),
                 &ClientId(client_id),
                 &LeaseId(lease_id),
-            );
+                ctx,
+            ).await;
             
             match result {
                 ReleaseResult::Success => CommandResponse::ReleaseSuccess,
@@ -160,13 +735,15 @@ impl RaftNode {
             }
         }
         
-        LockCommand::Renew { request_id, lock_id, client_id, ttl_seconds, lease_id } => {
+        LockCommand::Renew { lock_id, client_id, ttl_seconds, lease_id, metadata, .. } => {
             let result = manager.renew(
                 &LockId(lock_id),
                 &ClientId(client_id),
                 &LeaseId(lease_id),
                 Duration::from_secs(ttl_seconds),  // Fixed: was `ttl`
-            );
+                metadata,
+                ctx,
+            ).await;
             
             match result {
                 RenewResult::Success { new_expiry } => CommandResponse::RenewSuccess {
@@ -190,20 +767,179 @@ impl RaftNode {
                 },
             }
         }
+
+        LockCommand::Transfer { lock_id, client_id, lease_id, target, ttl_seconds, .. } => {
+            let result = manager.transfer(
+                &LockId(lock_id),
+                &ClientId(client_id),
+                &LeaseId(lease_id),
+                &ClientId(target),
+                Duration::from_secs(ttl_seconds),
+                ctx,
+            ).await;
+
+            match result {
+                TransferResult::Transferred { lease_id, expires_at, fencing_token } => CommandResponse::Transferred {
+                    lease_id: lease_id.0,
+                    expires_at: expires_at.to_rfc3339(),
+                    fencing_token,
+                },
+                TransferResult::Expired => CommandResponse::Error {
+                    error_type: "Expired".to_string(),
+                    message: "Lock already expired".to_string(),
+                },
+                TransferResult::NotFound => CommandResponse::Error {
+                    error_type: "NotFound".to_string(),
+                    message: "Lock not found".to_string(),
+                },
+                TransferResult::NotHolder => CommandResponse::Error {
+                    error_type: "NotHolder".to_string(),
+                    message: "Not the lock holder".to_string(),
+                },
+            }
+        }
+
+        LockCommand::Restore { locks, history, kv, barriers, .. } => {
+            let count = locks.len();
+            manager.restore(locks , history , kv , barriers , ctx).await;
+            CommandResponse::Restored { locks: count }
+        }
+
+        LockCommand::SetAcl { acl, .. } => {
+            // The leader's server validated the table; replicas check again
+            // so that a bad entry changes nothing anywhere.
+            if let Err(message) = acl.validate() {
+                return CommandResponse::Error { error_type: "InvalidAcl".to_string(), message }
+            }
+            let response = CommandResponse::AclUpdated { principals: acl.principals.len(), rules: acl.rules.len() };
+            *self.acl.write().await = acl;
+            response
+        }
+
+        LockCommand::SetQuotas { quotas, .. } => {
+            manager.set_quotas(quotas).await;
+            CommandResponse::QuotasUpdated { quotas }
+        }
+
+        LockCommand::ForceRelease { lock_id, lease_id, actor, .. } => {
+            match manager.force_release(&LockId(lock_id.clone()) , lease_id.clone().map(LeaseId).as_ref() , &actor , ctx).await {
+                Some((previous , promoted)) => {
+                    tracing::info!("{} force-released {} from {}" , actor , lock_id , previous.0);
+                    CommandResponse::ForceReleased { previous_holder: previous.0, promoted: promoted.map(|client_id| client_id.0) }
+                }
+                None => match lease_id {
+                    Some(lease_id) => CommandResponse::Error {
+                        error_type: "NotHolder".to_string(),
+                        message: format!("'{}' is not held under lease {}" , lock_id , lease_id),
+                    },
+                    None => CommandResponse::Error {
+                        error_type: "NotHeld".to_string(),
+                        message: format!("Nobody holds '{}'" , lock_id),
+                    }
+                }
+            }
+        }
+
+        LockCommand::EvictWaiter { lock_id, client_id, actor, .. } => {
+            match manager.evict_waiter(&LockId(lock_id.clone()) , &ClientId(client_id.clone()) , &actor , ctx).await {
+                0 => CommandResponse::Error {
+                    error_type: "NotFound".to_string(),
+                    message: format!("'{}' is not waiting for '{}'" , client_id , lock_id),
+                },
+                removed => {
+                    tracing::info!("{} evicted {} from the queue of {}" , actor , client_id , lock_id);
+                    CommandResponse::WaiterEvicted { removed }
+                }
+            }
+        }
+
+        LockCommand::RevokeClient { client_id, actor, .. } => {
+            let (released , evicted) = manager.revoke_client(&ClientId(client_id.clone()) , &actor , ctx).await;
+            tracing::info!("{} revoked {}: released {} locks , left {} queues" , actor , client_id , released , evicted);
+            CommandResponse::ClientRevoked { released, evicted }
+        }
+
+        LockCommand::KvGet { key, .. } => CommandResponse::KvValue { entry: manager.kv_get(&key , ctx).await },
+
+        LockCommand::KvPut { key, value, client_id, expected_version, lease, .. } => {
+            kv_response(manager.kv_put(&key , value , &ClientId(client_id) , expected_version , lease , ctx).await , &key)
+        }
+
+        LockCommand::KvDelete { key, expected_version, .. } => {
+            kv_response(manager.kv_delete(&key , expected_version , ctx).await , &key)
+        }
+
+        LockCommand::BarrierCreate { name, kind, count, .. } => {
+            barrier_response(manager.barrier_create(&name , kind , count , ctx).await , &name)
+        }
+
+        LockCommand::BarrierJoin { name, client_id, ttl_seconds, .. } => {
+            barrier_response(manager.barrier_join(&name , &ClientId(client_id) , Duration::from_secs(ttl_seconds) , ctx).await , &name)
+        }
+
+        LockCommand::BarrierArrive { name, client_id, .. } => {
+            barrier_response(manager.barrier_arrive(&name , &ClientId(client_id) , ctx).await , &name)
+        }
+
+        LockCommand::BarrierDelete { name, .. } => match manager.barrier_delete(&name , ctx).await {
+            BarrierResult::Updated { remaining } => CommandResponse::BarrierDeleted { remaining },
+            result => barrier_response(result , &name)
+        },
     }
 }
-    fn tick(&mut self){
+    pub fn tick(&mut self){
         self.raft.tick();
     }
 
     
-    pub async fn propose(&mut self , command : LockCommand ) -> Result<u64 , String> {
-        let data = serde_json::to_vec(&command)
+    pub async fn propose(&mut self , command : LockCommand ) -> Result<() , String> {
+        let log_entry = LogEntry { command, proposer: self.id, issued_at: self.clock.now(), lease_seed: self.rng.r#gen() };
+        let data = serde_json::to_vec(&log_entry)
             .map_err(|e| format!("Serialization Error {}" , e))?;
 
-        self.raft.propose(vec![] , data).unwrap();
-
-        Ok(0)
+        self.raft.propose(vec![] , data)
+            .map_err(|e| format!("Proposal Error {}" , e))
     }
     
 }
+
+fn kv_response(result : KvResult , key : &str) -> CommandResponse {
+    match result {
+        KvResult::Written { version } => CommandResponse::KvWritten { version },
+        KvResult::Deleted { version } => CommandResponse::KvDeleted { version },
+        KvResult::NotFound => CommandResponse::Error {
+            error_type: "NotFound".to_string(),
+            message: format!("No key '{}'" , key),
+        },
+        KvResult::VersionMismatch { current } => CommandResponse::Error {
+            error_type: VERSION_MISMATCH.to_string(),
+            message: format!("'{}' is at version {}" , key , current),
+        },
+        KvResult::NotHolder => CommandResponse::Error {
+            error_type: "NotHolder".to_string(),
+            message: "Not the lock holder".to_string(),
+        },
+        KvResult::Expired => CommandResponse::Error {
+            error_type: "Expired".to_string(),
+            message: "Lock already expired".to_string(),
+        },
+    }
+}
+
+fn barrier_response(result : BarrierResult , name : &str) -> CommandResponse {
+    match result {
+        BarrierResult::Updated { remaining } => CommandResponse::BarrierUpdated { remaining },
+        BarrierResult::NotFound => CommandResponse::Error {
+            error_type: "NotFound".to_string(),
+            message: format!("No barrier '{}'" , name),
+        },
+        BarrierResult::Conflict => CommandResponse::Error {
+            error_type: "Conflict".to_string(),
+            message: format!("'{}' already exists with another kind or count" , name),
+        },
+        BarrierResult::Full => CommandResponse::Error {
+            error_type: "Full".to_string(),
+            message: format!("Every remaining arrival at '{}' is taken" , name),
+        },
+    }
+}
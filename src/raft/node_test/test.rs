@@ -0,0 +1,581 @@
+
+
+#[cfg(test)]
+mod tests{
+    use std::{sync::{Arc, Mutex}, time::Duration};
+    use async_trait::async_trait;
+    use chrono::{DateTime, Utc};
+    use tokio::sync::mpsc;
+    use crate::{auth::acl::{AclRule, AclTable, Permission, Principal, hash_token}, config::server_config::RaftConfig, lock::{backend::LockBackend, barrier::{Barrier, BarrierKind, BarrierResult}, history::HistoryEvent, kv::{KvEntry, KvLease, KvResult}, manager::InMemoryLockManager, metadata::Metadata, quota::LockQuotas, stats::LockStats, types::{AcquireResult, ApplyContext, ClientId, LeaseId, LockHolder, LockId, LockState, ReleaseResult, RenewResult, TransferResult}}, raft::{node::RaftNode, raft_client::RaftClient, raft_commands::{AdminResponse, CommandResponse, IdempotencyKey}, storage::DistlockStorage, transport::LocalTransport}};
+
+    /// Backend that notes every write it is given, with the index of the
+    /// entry, and keeps its state in an `InMemoryLockManager`. Writes must
+    /// come in log order.
+    #[derive(Default)]
+    struct RecordingBackend{
+        inner : InMemoryLockManager,
+        writes : Arc<Mutex<Vec<(u64 , String)>>>
+    }
+
+    impl RecordingBackend{
+        fn write(&self , ctx : &ApplyContext , write : String) {
+            let mut writes = self.writes.lock().unwrap();
+            assert!(writes.last().is_none_or(|(index , _)| *index <= ctx.index) , "{} applied out of log order" , write);
+            writes.push((ctx.index , write));
+        }
+    }
+
+    #[async_trait]
+    impl LockBackend for RecordingBackend{
+        async fn acquire(&self , lock_id : &LockId , client_id : &ClientId , ttl : Duration , metadata : Metadata , ctx : &ApplyContext) -> AcquireResult {
+            self.write(ctx , format!("acquire {} {}" , lock_id.0 , client_id.0));
+            LockBackend::acquire(&self.inner , lock_id , client_id , ttl , metadata , ctx).await
+        }
+
+        async fn release(&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId , ctx : &ApplyContext) -> ReleaseResult {
+            self.write(ctx , format!("release {} {}" , lock_id.0 , client_id.0));
+            LockBackend::release(&self.inner , lock_id , client_id , lease_id , ctx).await
+        }
+
+        async fn renew(&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId , ttl : Duration , metadata : Option<Metadata> , ctx : &ApplyContext) -> RenewResult {
+            self.write(ctx , format!("renew {} {}" , lock_id.0 , client_id.0));
+            LockBackend::renew(&self.inner , lock_id , client_id , lease_id , ttl , metadata , ctx).await
+        }
+
+        async fn transfer(&self , lock_id : &LockId , client_id : &ClientId , lease_id : &LeaseId , target : &ClientId , ttl : Duration , ctx : &ApplyContext) -> TransferResult {
+            self.write(ctx , format!("transfer {} {} {}" , lock_id.0 , client_id.0 , target.0));
+            LockBackend::transfer(&self.inner , lock_id , client_id , lease_id , target , ttl , ctx).await
+        }
+
+        async fn force_release(&self , lock_id : &LockId , lease_id : Option<&LeaseId> , actor : &str , ctx : &ApplyContext) -> Option<(ClientId , Option<ClientId>)> {
+            self.write(ctx , format!("force_release {} {}" , lock_id.0 , actor));
+            LockBackend::force_release(&self.inner , lock_id , lease_id , actor , ctx).await
+        }
+
+        async fn evict_waiter(&self , lock_id : &LockId , client_id : &ClientId , actor : &str , ctx : &ApplyContext) -> usize {
+            self.write(ctx , format!("evict_waiter {} {} {}" , lock_id.0 , client_id.0 , actor));
+            LockBackend::evict_waiter(&self.inner , lock_id , client_id , actor , ctx).await
+        }
+
+        async fn revoke_client(&self , client_id : &ClientId , actor : &str , ctx : &ApplyContext) -> (usize , usize) {
+            self.write(ctx , format!("revoke_client {} {}" , client_id.0 , actor));
+            LockBackend::revoke_client(&self.inner , client_id , actor , ctx).await
+        }
+
+        async fn restore(&self , locks : Vec<(LockId , LockState)> , history : Vec<HistoryEvent> , kv : Vec<KvEntry> , barriers : Vec<(String , Barrier)> , ctx : &ApplyContext) {
+            self.write(ctx , "restore".to_string());
+            LockBackend::restore(&self.inner , locks , history , kv , barriers , ctx).await
+        }
+
+        async fn set_quotas(&self , quotas : LockQuotas) {
+            LockBackend::set_quotas(&self.inner , quotas).await
+        }
+
+        async fn kv_get(&self , key : &str , ctx : &ApplyContext) -> Option<KvEntry> {
+            LockBackend::kv_get(&self.inner , key , ctx).await
+        }
+
+        async fn kv_put(&self , key : &str , value : String , client_id : &ClientId , expected_version : Option<u64> , lease : Option<KvLease> , ctx : &ApplyContext) -> KvResult {
+            self.write(ctx , format!("kv_put {}" , key));
+            LockBackend::kv_put(&self.inner , key , value , client_id , expected_version , lease , ctx).await
+        }
+
+        async fn kv_delete(&self , key : &str , expected_version : Option<u64> , ctx : &ApplyContext) -> KvResult {
+            self.write(ctx , format!("kv_delete {}" , key));
+            LockBackend::kv_delete(&self.inner , key , expected_version , ctx).await
+        }
+
+        async fn barrier_create(&self , name : &str , kind : BarrierKind , count : u32 , ctx : &ApplyContext) -> BarrierResult {
+            self.write(ctx , format!("barrier_create {}" , name));
+            LockBackend::barrier_create(&self.inner , name , kind , count , ctx).await
+        }
+
+        async fn barrier_join(&self , name : &str , client_id : &ClientId , ttl : Duration , ctx : &ApplyContext) -> BarrierResult {
+            self.write(ctx , format!("barrier_join {} {}" , name , client_id.0));
+            LockBackend::barrier_join(&self.inner , name , client_id , ttl , ctx).await
+        }
+
+        async fn barrier_arrive(&self , name : &str , client_id : &ClientId , ctx : &ApplyContext) -> BarrierResult {
+            self.write(ctx , format!("barrier_arrive {} {}" , name , client_id.0));
+            LockBackend::barrier_arrive(&self.inner , name , client_id , ctx).await
+        }
+
+        async fn barrier_delete(&self , name : &str , ctx : &ApplyContext) -> BarrierResult {
+            self.write(ctx , format!("barrier_delete {}" , name));
+            LockBackend::barrier_delete(&self.inner , name , ctx).await
+        }
+
+        async fn status(&self , lock_id : &LockId) -> Option<LockState> {
+            LockBackend::status(&self.inner , lock_id).await
+        }
+
+        async fn list(&self) -> Vec<(LockId , LockState)> {
+            LockBackend::list(&self.inner).await
+        }
+
+        async fn list_page(&self , prefix : &str , after : Option<&str> , limit : usize) -> Vec<(LockId , LockState)> {
+            LockBackend::list_page(&self.inner , prefix , after , limit).await
+        }
+
+        async fn history(&self , lock_id : Option<&LockId> , since : Option<DateTime<Utc>> , until : Option<DateTime<Utc>> , limit : usize) -> Vec<HistoryEvent> {
+            LockBackend::history(&self.inner , lock_id , since , until , limit).await
+        }
+
+        async fn history_events(&self) -> Vec<HistoryEvent> {
+            LockBackend::history_events(&self.inner).await
+        }
+
+        async fn stats(&self , lock_id : &LockId) -> Option<LockStats> {
+            LockBackend::stats(&self.inner , lock_id).await
+        }
+
+        async fn most_contended(&self) -> Vec<(LockId , LockStats)> {
+            LockBackend::most_contended(&self.inner).await
+        }
+
+        async fn kv_entries(&self , now : DateTime<Utc>) -> Vec<KvEntry> {
+            LockBackend::kv_entries(&self.inner , now).await
+        }
+
+        async fn barrier(&self , name : &str) -> Option<Barrier> {
+            LockBackend::barrier(&self.inner , name).await
+        }
+
+        async fn barriers(&self) -> Vec<(String , Barrier)> {
+            LockBackend::barriers(&self.inner).await
+        }
+
+        async fn quotas(&self) -> LockQuotas {
+            LockBackend::quotas(&self.inner).await
+        }
+
+        async fn record_gauges(&self , now : DateTime<Utc>) {
+            LockBackend::record_gauges(&self.inner , now).await
+        }
+    }
+
+    fn start_cluster(ids : &[u64]) -> Vec<(u64 , RaftClient)> {
+        let transport = Arc::new(LocalTransport::new());
+        let mut clients = Vec::new();
+        for id in ids {
+            let (command_tx , command_rx) = mpsc::channel(100);
+            let (admin_tx , admin_rx) = mpsc::channel(10);
+            let node = RaftNode::new(*id, ids.to_vec(), command_rx, admin_rx).with_transport(transport.clone());
+            transport.register(*id, node.message_sender());
+            tokio::spawn(node.run());
+            clients.push((*id , RaftClient::new(command_tx, admin_tx)));
+        }
+        clients
+    }
+
+    async fn find_leader(clients : &[(u64 , RaftClient)]) -> u64 {
+        for _ in 0..100 {
+            for (id , client) in clients {
+                let result = client.propose_acquire("probe".to_string(), format!("client_{}" , id), 30, None).await.unwrap();
+                if !matches!(result , CommandResponse::Error { .. }) {
+                    return *id
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("No leader elected")
+    }
+
+    #[tokio::test]
+
+    async fn test_transfer_leader_to_named_peer(){
+        let clients = start_cluster(&[1 , 2 , 3]);
+        let leader = find_leader(&clients).await;
+        let target = clients.iter().map(|(id , _)| *id).find(|id| *id != leader).unwrap();
+        let leader_client = &clients.iter().find(|(id , _)| *id == leader).unwrap().1;
+
+        let result = leader_client.transfer_leader(Some(target), 5_000).await.unwrap();
+        assert!(matches!(result , AdminResponse::LeaderTransferred { to , .. } if to == target));
+
+        let result = leader_client.propose_acquire("lock".to_string(), "client_1".to_string(), 30, None).await.unwrap();
+        assert!(matches!(result , CommandResponse::Error { ref error_type , .. } if error_type == "NotLeader"));
+    }
+
+    #[tokio::test]
+
+    async fn test_read_index_is_served_only_by_the_leader(){
+        let clients = start_cluster(&[1 , 2 , 3]);
+        let leader = find_leader(&clients).await;
+        let (_ , leader_client) = clients.iter().find(|(id , _)| *id == leader).unwrap();
+        let (_ , follower_client) = clients.iter().find(|(id , _)| *id != leader).unwrap();
+
+        let result = leader_client.read_index().await.unwrap();
+        assert!(matches!(result , AdminResponse::ReadIndex { index } if index > 0));
+
+        let result = follower_client.read_index().await.unwrap();
+        assert!(matches!(result , AdminResponse::Error { ref error_type , .. } if error_type == "NotLeader"));
+    }
+
+    #[tokio::test]
+
+    async fn test_draining_node_rejects_writes(){
+        let clients = start_cluster(&[1]);
+        let leader = find_leader(&clients).await;
+        let client = &clients.iter().find(|(id , _)| *id == leader).unwrap().1;
+
+        let result = client.set_draining(true).await.unwrap();
+        assert!(matches!(result , AdminResponse::DrainUpdated { draining : true , .. }));
+
+        let result = client.propose_acquire("lock".to_string(), "client_1".to_string(), 30, None).await.unwrap();
+        assert!(matches!(result , CommandResponse::Error { ref error_type , .. } if error_type == "Draining"));
+
+        let result = client.transfer_leader(None, 1_000).await.unwrap();
+        assert!(matches!(result , AdminResponse::Error { ref error_type , .. } if error_type == "NoTransferee"));
+    }
+
+    #[tokio::test]
+
+    async fn test_retried_acquire_is_applied_once(){
+        let clients = start_cluster(&[1]);
+        let leader = find_leader(&clients).await;
+        let client = &clients.iter().find(|(id , _)| *id == leader).unwrap().1;
+
+        let first = client.propose_acquire("lock".to_string(), "client_1".to_string(), 30, Some(7)).await.unwrap();
+        let retry = client.propose_acquire("lock".to_string(), "client_1".to_string(), 30, Some(7)).await.unwrap();
+
+        let (CommandResponse::AcquireGranted { lease_id : first_lease , .. } , CommandResponse::AcquireGranted { lease_id : retry_lease , .. }) = (first , retry) else {
+            panic!("Expected both attempts to be granted")
+        };
+        assert_eq!(first_lease , retry_lease);
+
+        let next = client.propose_acquire("lock".to_string(), "client_1".to_string(), 30, Some(8)).await.unwrap();
+        assert!(matches!(next , CommandResponse::AcquireQueued { position : 0 , .. }));
+    }
+
+    #[tokio::test]
+
+    async fn test_removed_voter_leaves_membership(){
+        let clients = start_cluster(&[1 , 2 , 3]);
+        let leader = find_leader(&clients).await;
+        let removed = clients.iter().map(|(id , _)| *id).find(|id| *id != leader).unwrap();
+        let leader_client = &clients.iter().find(|(id , _)| *id == leader).unwrap().1;
+
+        let result = leader_client.remove_node(removed).await.unwrap();
+        assert!(matches!(result , AdminResponse::MembershipChanged { ref voters } if voters.len() == 2 && !voters.contains(&removed)));
+
+        let result = leader_client.members().await.unwrap();
+        assert!(matches!(result , AdminResponse::Members { leader_id , ref voters , .. } if leader_id == leader && !voters.contains(&removed)));
+
+        let follower = &clients.iter().find(|(id , _)| *id != leader && *id != removed).unwrap().1;
+        let result = follower.remove_node(leader).await.unwrap();
+        assert!(matches!(result , AdminResponse::Error { ref error_type , .. } if error_type == "NotLeader"));
+    }
+
+    #[tokio::test]
+
+    async fn test_restore_replaces_lock_table(){
+        let clients = start_cluster(&[1]);
+        let leader = find_leader(&clients).await;
+        let client = &clients.iter().find(|(id , _)| *id == leader).unwrap().1;
+
+        let now = Utc::now();
+        let holder = LockHolder { client_id: ClientId("client_2".to_string()), lease_id: LeaseId("lease_2".to_string()), acquired_at: now, expires_at: now + chrono::Duration::seconds(60), renewal_count: 0, fencing_token: 0, metadata: Default::default() };
+        let locks = vec![(LockId("restored".to_string()) , LockState { holder: Some(holder), wait_queue: Vec::new(), created_at: now })];
+
+        let result = client.propose_restore(locks , Vec::new() , Vec::new() , Vec::new()).await.unwrap();
+        assert!(matches!(result , CommandResponse::Restored { locks : 1 }));
+
+        let result = client.propose_acquire("restored".to_string(), "client_1".to_string(), 30, None).await.unwrap();
+        assert!(matches!(result , CommandResponse::AcquireQueued { position : 0 , .. }));
+
+        let result = client.propose_acquire("probe".to_string(), "client_1".to_string(), 30, None).await.unwrap();
+        assert!(matches!(result , CommandResponse::AcquireGranted { .. }));
+    }
+
+    #[tokio::test]
+
+    async fn test_restarted_node_replays_the_log_from_its_data_dir(){
+        let dir = std::env::temp_dir().join(format!("distlock-node-test-{}" , uuid::Uuid::new_v4()));
+        let start = |dir : &std::path::Path| {
+            let (command_tx , command_rx) = mpsc::channel(100);
+            let (admin_tx , admin_rx) = mpsc::channel(10);
+            let storage = DistlockStorage::open(dir , vec![1]).unwrap();
+            let node = RaftNode::with_storage(1 , &RaftConfig::default() , storage , InMemoryLockManager::new() , command_rx , admin_rx);
+            let state_machine = node.state_machine();
+            (tokio::spawn(node.run()) , RaftClient::new(command_tx , admin_tx) , state_machine)
+        };
+
+        let (task , client , _) = start(&dir);
+        let clients = vec![(1 , client)];
+        find_leader(&clients).await;
+        let result = clients[0].1.propose_acquire("kept".to_string(), "client_1".to_string(), 60, None).await.unwrap();
+        let CommandResponse::AcquireGranted { lease_id , .. } = result else { panic!("Expected granted") };
+        task.abort();
+        let _ = task.await;
+
+        let (_task , client , state_machine) = start(&dir);
+        find_leader(&[(1 , client)]).await;
+        let holder = state_machine.status(&LockId("kept".to_string())).await.and_then(|lock| lock.holder).unwrap();
+        assert_eq!(holder.lease_id.0 , lease_id);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+
+    async fn test_try_acquire_does_not_queue(){
+        let clients = start_cluster(&[1]);
+        let leader = find_leader(&clients).await;
+        let client = &clients.iter().find(|(id , _)| *id == leader).unwrap().1;
+
+        let result = client.propose_try_acquire("lock".to_string(), "client_1".to_string(), 30).await.unwrap();
+        assert!(matches!(result , CommandResponse::AcquireGranted { .. }));
+
+        let result = client.propose_try_acquire("lock".to_string(), "client_2".to_string(), 30).await.unwrap();
+        assert!(matches!(result , CommandResponse::Error { ref error_type , .. } if error_type == "LockHeld"));
+
+        let result = client.propose_acquire("lock".to_string(), "client_3".to_string(), 30, None).await.unwrap();
+        assert!(matches!(result , CommandResponse::AcquireQueued { position : 0 , .. }));
+    }
+
+    #[tokio::test]
+
+    async fn test_acl_is_replicated_through_the_log(){
+        let (command_tx , command_rx) = mpsc::channel(100);
+        let (admin_tx , admin_rx) = mpsc::channel(10);
+        let node = RaftNode::new(1, vec![1], command_rx, admin_rx);
+        let acl = node.acl();
+        tokio::spawn(node.run());
+        let clients = vec![(1 , RaftClient::new(command_tx, admin_tx))];
+        find_leader(&clients).await;
+        let client = &clients[0].1;
+
+        let table = AclTable {
+            principals: vec![Principal { name: "billing".to_string(), token_sha256: hash_token("secret") }],
+            rules: vec![AclRule { principal: "billing".to_string(), prefix: "billing/".to_string(), permissions: vec![Permission::Acquire] }]
+        };
+        let result = client.propose_acl(table.clone()).await.unwrap();
+        assert!(matches!(result , CommandResponse::AclUpdated { principals : 1 , rules : 1 }));
+        assert_eq!(*acl.read().await , table);
+
+        let mut invalid = table.clone();
+        invalid.rules[0].principal = "ops".to_string();
+        let result = client.propose_acl(invalid).await.unwrap();
+        assert!(matches!(result , CommandResponse::Error { ref error_type , .. } if error_type == "InvalidAcl"));
+        assert_eq!(*acl.read().await , table);
+    }
+
+    #[tokio::test]
+
+    async fn test_quotas_are_replicated_through_the_log(){
+        let ids = [1 , 2 , 3];
+        let transport = Arc::new(LocalTransport::new());
+        let mut clients = Vec::new();
+        let mut backends = Vec::new();
+        for id in ids {
+            let (command_tx , command_rx) = mpsc::channel(100);
+            let (admin_tx , admin_rx) = mpsc::channel(10);
+            let node = RaftNode::new(id, ids.to_vec(), command_rx, admin_rx).with_transport(transport.clone());
+            transport.register(id, node.message_sender());
+            backends.push(node.state_machine());
+            tokio::spawn(node.run());
+            clients.push((id , RaftClient::new(command_tx, admin_tx)));
+        }
+        let leader = find_leader(&clients).await;
+        let client = &clients.iter().find(|(id , _)| *id == leader).unwrap().1;
+
+        let quotas = LockQuotas { max_locks_per_client: 1, ..Default::default() };
+        let result = client.propose_quotas(quotas).await.unwrap();
+        assert_eq!(result , CommandResponse::QuotasUpdated { quotas });
+
+        let result = client.propose_acquire("other".to_string(), format!("client_{}" , leader), 30, None).await.unwrap();
+        assert!(matches!(result , CommandResponse::Error { ref error_type , .. } if error_type == "QuotaExceeded"));
+
+        for _ in 0..50 {
+            let mut replicated = true;
+            for backend in &backends {
+                replicated &= backend.quotas().await == quotas;
+            }
+            if replicated {
+                return
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("The quotas did not reach every replica")
+    }
+
+    #[tokio::test]
+
+    async fn test_force_release_promotes_the_next_waiter(){
+        let clients = start_cluster(&[1]);
+        let leader = find_leader(&clients).await;
+        let client = &clients.iter().find(|(id , _)| *id == leader).unwrap().1;
+
+        client.propose_acquire("jobs".to_string(), "dead-host".to_string(), 3600, None).await.unwrap();
+        client.propose_acquire("jobs".to_string(), "standby".to_string(), 30, None).await.unwrap();
+
+        let result = client.propose_force_release("jobs".to_string(), None, "oncall".to_string(), None).await.unwrap();
+        assert_eq!(result , CommandResponse::ForceReleased { previous_holder: "dead-host".to_string(), promoted: Some("standby".to_string()) });
+        let result = client.propose_force_release("free".to_string(), None, "oncall".to_string(), None).await.unwrap();
+        assert!(matches!(result , CommandResponse::Error { ref error_type , .. } if error_type == "NotHeld"));
+
+        client.propose_acquire("mail".to_string(), "standby".to_string(), 30, None).await.unwrap();
+        client.propose_acquire("mail".to_string(), "dead-host".to_string(), 30, None).await.unwrap();
+        let result = client.propose_revoke_client("standby".to_string(), "oncall".to_string(), None).await.unwrap();
+        assert_eq!(result , CommandResponse::ClientRevoked { released: 2, evicted: 0 });
+        let result = client.propose_evict_waiter("mail".to_string(), "dead-host".to_string(), "oncall".to_string(), None).await.unwrap();
+        assert!(matches!(result , CommandResponse::Error { ref error_type , .. } if error_type == "NotFound"));
+    }
+
+    #[tokio::test]
+
+    async fn test_retried_force_release_leaves_the_promoted_waiter(){
+        let clients = start_cluster(&[1]);
+        let leader = find_leader(&clients).await;
+        let client = &clients.iter().find(|(id , _)| *id == leader).unwrap().1;
+
+        let CommandResponse::AcquireGranted { lease_id , .. } = client.propose_acquire("jobs".to_string(), "dead-host".to_string(), 3600, None).await.unwrap() else {
+            panic!("Expected granted")
+        };
+        client.propose_acquire("jobs".to_string(), "standby".to_string(), 30, None).await.unwrap();
+        client.propose_acquire("jobs".to_string(), "spare".to_string(), 30, None).await.unwrap();
+
+        // A retry after a timeout reuses the key and gets the first answer.
+        let key = Some(IdempotencyKey { client_id: "ctl".to_string(), seq: 1 });
+        let expected = CommandResponse::ForceReleased { previous_holder: "dead-host".to_string(), promoted: Some("standby".to_string()) };
+        for _ in 0..2 {
+            let result = client.propose_force_release("jobs".to_string(), None, "oncall".to_string(), key.clone()).await.unwrap();
+            assert_eq!(result , expected);
+        }
+
+        // The lease of the old holder no longer matches the lock.
+        let result = client.propose_force_release("jobs".to_string(), Some(lease_id), "oncall".to_string(), None).await.unwrap();
+        assert!(matches!(result , CommandResponse::Error { ref error_type , .. } if error_type == "NotHolder"));
+        let result = client.propose_force_release("jobs".to_string(), None, "oncall".to_string(), None).await.unwrap();
+        assert_eq!(result , CommandResponse::ForceReleased { previous_holder: "standby".to_string(), promoted: Some("spare".to_string()) });
+    }
+
+    #[tokio::test]
+
+    async fn test_transfer_bypasses_the_wait_queue(){
+        let clients = start_cluster(&[1]);
+        let leader = find_leader(&clients).await;
+        let client = &clients.iter().find(|(id , _)| *id == leader).unwrap().1;
+
+        let CommandResponse::AcquireGranted { lease_id , .. } = client.propose_acquire("job".to_string(), "coordinator".to_string(), 30, None).await.unwrap() else {
+            panic!("Expected granted")
+        };
+        client.propose_acquire("job".to_string(), "bystander".to_string(), 30, None).await.unwrap();
+
+        let result = client.propose_transfer("wrong".to_string(), "job".to_string(), "coordinator".to_string(), "worker".to_string(), 30, None).await.unwrap();
+        assert!(matches!(result , CommandResponse::Error { ref error_type , .. } if error_type == "NotHolder"));
+
+        let CommandResponse::Transferred { lease_id : worker_lease , fencing_token , .. } = client.propose_transfer(lease_id.clone(), "job".to_string(), "coordinator".to_string(), "worker".to_string(), 30, None).await.unwrap() else {
+            panic!("Expected transferred")
+        };
+        assert!(fencing_token > 0);
+
+        let result = client.propose_release(lease_id, "job".to_string(), "coordinator".to_string(), None).await.unwrap();
+        assert!(matches!(result , CommandResponse::Error { ref error_type , .. } if error_type == "NotHolder"));
+        let result = client.propose_release(worker_lease, "job".to_string(), "worker".to_string(), None).await.unwrap();
+        assert_eq!(result , CommandResponse::ReleaseSuccess);
+        let result = client.propose_force_release("job".to_string(), None, "oncall".to_string(), None).await.unwrap();
+        assert_eq!(result , CommandResponse::ForceReleased { previous_holder: "bystander".to_string(), promoted: None });
+    }
+
+    #[tokio::test]
+
+    async fn test_fencing_tokens_increase_across_transfer_and_reacquire(){
+        let clients = start_cluster(&[1]);
+        let leader = find_leader(&clients).await;
+        let client = &clients.iter().find(|(id , _)| *id == leader).unwrap().1;
+
+        let CommandResponse::AcquireGranted { lease_id , fencing_token : first , .. } = client.propose_acquire("job".to_string(), "a".to_string(), 30, None).await.unwrap() else {
+            panic!("Expected granted")
+        };
+        let CommandResponse::Transferred { lease_id , fencing_token : second , .. } = client.propose_transfer(lease_id, "job".to_string(), "a".to_string(), "b".to_string(), 30, None).await.unwrap() else {
+            panic!("Expected transferred")
+        };
+        let result = client.propose_release(lease_id, "job".to_string(), "b".to_string(), None).await.unwrap();
+        assert_eq!(result , CommandResponse::ReleaseSuccess);
+        let CommandResponse::AcquireGranted { fencing_token : third , .. } = client.propose_acquire("job".to_string(), "a".to_string(), 30, None).await.unwrap() else {
+            panic!("Expected granted")
+        };
+
+        assert!(first > 0);
+        assert!(first < second && second < third , "{} {} {}" , first , second , third);
+    }
+
+    #[tokio::test]
+
+    async fn test_kv_compare_and_swap_and_leased_keys(){
+        let clients = start_cluster(&[1]);
+        let leader = find_leader(&clients).await;
+        let client = &clients.iter().find(|(id , _)| *id == leader).unwrap().1;
+
+        let CommandResponse::KvWritten { version } = client.propose_kv_put("config".to_string(), "v1".to_string(), "a".to_string(), Some(0), None, None).await.unwrap() else {
+            panic!("Expected written")
+        };
+        let result = client.propose_kv_put("config".to_string(), "v2".to_string(), "b".to_string(), Some(0), None, None).await.unwrap();
+        assert!(matches!(result , CommandResponse::Error { ref error_type , .. } if error_type == "VersionMismatch"));
+        let result = client.propose_kv_put("config".to_string(), "v2".to_string(), "b".to_string(), Some(version), None, None).await.unwrap();
+        assert!(matches!(result , CommandResponse::KvWritten { version : next } if next > version));
+        let CommandResponse::KvValue { entry: Some(entry) } = client.propose_kv_get("config".to_string()).await.unwrap() else {
+            panic!("Expected a value")
+        };
+        assert_eq!(entry.value , "v2");
+
+        let CommandResponse::AcquireGranted { lease_id , .. } = client.propose_acquire("leader".to_string(), "a".to_string(), 30, None).await.unwrap() else {
+            panic!("Expected granted")
+        };
+        let lease = KvLease { lock_id: LockId("leader".to_string()), lease_id: LeaseId(lease_id.clone()) };
+        let result = client.propose_kv_put("leader/endpoint".to_string(), "10.0.0.1:80".to_string(), "b".to_string(), None, Some(lease.clone()), None).await.unwrap();
+        assert!(matches!(result , CommandResponse::Error { ref error_type , .. } if error_type == "NotHolder"));
+        let result = client.propose_kv_put("leader/endpoint".to_string(), "10.0.0.1:80".to_string(), "a".to_string(), None, Some(lease), None).await.unwrap();
+        assert!(matches!(result , CommandResponse::KvWritten { .. }));
+
+        client.propose_release(lease_id, "leader".to_string(), "a".to_string(), None).await.unwrap();
+        assert_eq!(client.propose_kv_get("leader/endpoint".to_string()).await.unwrap() , CommandResponse::KvValue { entry: None });
+        let result = client.propose_kv_delete("config".to_string(), "a".to_string(), None, None).await.unwrap();
+        assert!(matches!(result , CommandResponse::KvDeleted { .. }));
+    }
+
+    #[tokio::test]
+
+    async fn test_every_replica_applies_the_same_writes_to_its_backend(){
+        let ids = [1 , 2 , 3];
+        let transport = Arc::new(LocalTransport::new());
+        let (mut clients , mut replicas) = (Vec::new() , Vec::new());
+        for id in ids {
+            let (command_tx , command_rx) = mpsc::channel(100);
+            let (admin_tx , admin_rx) = mpsc::channel(10);
+            let backend = RecordingBackend::default();
+            let writes = backend.writes.clone();
+            let node = RaftNode::with_config(id, ids.to_vec(), &RaftConfig::default(), backend, command_rx, admin_rx).with_transport(transport.clone());
+            transport.register(id, node.message_sender());
+            replicas.push((node.state_machine() , writes));
+            tokio::spawn(node.run());
+            clients.push((id , RaftClient::new(command_tx, admin_tx)));
+        }
+        let leader = find_leader(&clients).await;
+        let client = &clients.iter().find(|(id , _)| *id == leader).unwrap().1;
+
+        let CommandResponse::AcquireGranted { lease_id , .. } = client.propose_acquire("job".to_string(), "a".to_string(), 30, None).await.unwrap() else {
+            panic!("Expected granted")
+        };
+        client.propose_acquire("job".to_string(), "b".to_string(), 30, None).await.unwrap();
+        client.propose_release(lease_id, "job".to_string(), "a".to_string(), None).await.unwrap();
+        let result = client.propose_force_release("job".to_string(), None, "oncall".to_string(), None).await.unwrap();
+        // The answer is the backend's.
+        assert_eq!(result , CommandResponse::ForceReleased { previous_holder: "b".to_string(), promoted: None });
+
+        let expected = vec!["acquire job a" , "acquire job b" , "release job a" , "force_release job oncall"];
+        let of_job = |writes : &Mutex<Vec<(u64 , String)>>| writes.lock().unwrap().iter().filter(|(_ , write)| write.contains(" job ")).cloned().collect::<Vec<_>>();
+        for _ in 0..50 {
+            if replicas.iter().all(|(_ , writes)| of_job(writes).len() == expected.len()) {
+                break
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let leader_writes = of_job(&replicas[leader as usize - 1].1);
+        assert_eq!(leader_writes.iter().map(|(_ , write)| write.as_str()).collect::<Vec<_>>() , expected);
+        for (state_machine , writes) in &replicas {
+            assert_eq!(of_job(writes) , leader_writes);
+            assert!(state_machine.status(&LockId("job".to_string())).await.unwrap().holder.is_none());
+        }
+    }
+}
@@ -2,20 +2,38 @@ use std::sync::atomic::AtomicU64;
 
 use tokio::sync::{mpsc::{self, Sender}, oneshot};
 
-use crate::raft::raft_commands::{CommandResponse, LockCommand};
+use crate::{auth::acl::AclTable, config::server_config::PeerConfig, lock::{barrier::{self, Barrier, BarrierKind, INVALID_BARRIER}, history::HistoryEvent, kv::{self, INVALID_KV, KvEntry, KvLease}, metadata::{self, INVALID_METADATA, Metadata}, quota::{LockQuotas, QUOTA_EXCEEDED, RateLimiter}, types::{LockId, LockState}}, raft::raft_commands::{AdminCommand, AdminResponse, CommandResponse, IdempotencyKey, LockCommand}};
 
 
 // #[derive(Clone)]
 pub struct RaftClient{
     pub command_tx : Sender<(LockCommand , oneshot::Sender<CommandResponse>)>,
-    next_request_id : AtomicU64
+    pub admin_tx : Sender<(AdminCommand , oneshot::Sender<AdminResponse>)>,
+    next_request_id : AtomicU64,
+    // Request rate per client id, checked before proposing.
+    rate_limiter : Option<RateLimiter>
 }
 
 impl RaftClient{
-   pub fn new(command_tx : mpsc::Sender<(LockCommand , oneshot::Sender<CommandResponse>)>) -> Self{
-    Self { command_tx  , next_request_id : AtomicU64::new(1)}
+   pub fn new(command_tx : mpsc::Sender<(LockCommand , oneshot::Sender<CommandResponse>)> , admin_tx : mpsc::Sender<(AdminCommand , oneshot::Sender<AdminResponse>)>) -> Self{
+    Self { command_tx , admin_tx , next_request_id : AtomicU64::new(1) , rate_limiter : None}
    }
 
+    /// Refuses acquires and renewals of clients over the limiter's rate with
+    /// a `QuotaExceeded` error.
+    pub fn with_rate_limiter(mut self , rate_limiter : RateLimiter) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    fn admit(&self , client_id : &str) -> Result<() , CommandResponse> {
+        let Some(rate_limiter) = &self.rate_limiter else { return Ok(()) };
+        rate_limiter.check(client_id).map_err(|wait| CommandResponse::Error {
+            error_type: QUOTA_EXCEEDED.to_string(),
+            message: format!("'{}' exceeded its request rate, retry in {} ms" , client_id , wait.as_millis().max(1))
+        })
+    }
+
    pub fn generate_new_index(&self) -> u64 {
     self.next_request_id.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
 
@@ -34,30 +52,210 @@ impl RaftClient{
     
     }
 
-    pub async fn propose_acquire(&self , lock_id : String , client_id : String , ttl_seconds: u64 , ) -> Result<CommandResponse , String>{
+    /// `seq` is the caller's sequence number for this request. Passing the
+    /// same `seq` again for the same client returns the original response.
+    pub async fn propose_acquire(&self , lock_id : String , client_id : String , ttl_seconds: u64 , seq : Option<u64>) -> Result<CommandResponse , String>{
+        self.propose_acquire_with_metadata(lock_id , client_id , ttl_seconds , Metadata::new() , seq).await
+    }
+
+    /// Like `propose_acquire`, attaching `metadata` to the lease.
+    pub async fn propose_acquire_with_metadata(&self , lock_id : String , client_id : String , ttl_seconds: u64 , metadata : Metadata , seq : Option<u64>) -> Result<CommandResponse , String>{
+        if let Err(message) = metadata::validate(&metadata) {
+            return Ok(CommandResponse::Error { error_type: INVALID_METADATA.to_string(), message })
+        }
+        if let Err(refused) = self.admit(&client_id) {
+            return Ok(refused)
+        }
         let request_id = self.generate_new_index();
+        let idempotency_key = seq.map(|seq| IdempotencyKey { client_id: client_id.clone(), seq });
 
         let command = LockCommand::Acquire { lock_id
-            , client_id, ttl_seconds, request_id };
+            , client_id, ttl_seconds, request_id , idempotency_key , queue : true , metadata };
 
         self.propose(command).await
 
     }
-    pub async fn propose_renew(&self ,lease_id : String ,  lock_id : String , client_id : String , ttl_seconds: u64 , ) -> Result<CommandResponse , String>{
+
+    /// Acquires only if the lock is free; a held lock is answered with a
+    /// `LockHeld` error instead of a place in the queue.
+    pub async fn propose_try_acquire(&self , lock_id : String , client_id : String , ttl_seconds: u64) -> Result<CommandResponse , String>{
+        if let Err(refused) = self.admit(&client_id) {
+            return Ok(refused)
+        }
         let request_id = self.generate_new_index();
+        self.propose(LockCommand::Acquire { lock_id, client_id, ttl_seconds, request_id, idempotency_key: None, queue: false, metadata: Metadata::new() }).await
+    }
+    pub async fn propose_renew(&self ,lease_id : String ,  lock_id : String , client_id : String , ttl_seconds: u64 , seq : Option<u64>) -> Result<CommandResponse , String>{
+        self.propose_renew_with_metadata(lease_id , lock_id , client_id , ttl_seconds , None , seq).await
+    }
 
-        let command = LockCommand::Renew { request_id, lock_id, client_id, ttl_seconds, lease_id };
+    /// Like `propose_renew`, replacing the lease's metadata when `metadata`
+    /// is set.
+    pub async fn propose_renew_with_metadata(&self ,lease_id : String ,  lock_id : String , client_id : String , ttl_seconds: u64 , metadata : Option<Metadata> , seq : Option<u64>) -> Result<CommandResponse , String>{
+        if let Some(Err(message)) = metadata.as_ref().map(metadata::validate) {
+            return Ok(CommandResponse::Error { error_type: INVALID_METADATA.to_string(), message })
+        }
+        if let Err(refused) = self.admit(&client_id) {
+            return Ok(refused)
+        }
+        let request_id = self.generate_new_index();
+        let idempotency_key = seq.map(|seq| IdempotencyKey { client_id: client_id.clone(), seq });
+
+        let command = LockCommand::Renew { request_id, lock_id, client_id, ttl_seconds, lease_id , idempotency_key , metadata };
 
         self.propose(command).await
 
     }
-    pub async fn propose_release(&self ,lease_id : String ,  lock_id : String , client_id : String  ) -> Result<CommandResponse , String>{
+    /// Hands `lock_id` from `client_id`, holding `lease_id`, to `target`.
+    pub async fn propose_transfer(&self , lease_id : String , lock_id : String , client_id : String , target : String , ttl_seconds : u64 , seq : Option<u64>) -> Result<CommandResponse , String>{
+        if let Err(refused) = self.admit(&client_id) {
+            return Ok(refused)
+        }
         let request_id = self.generate_new_index();
+        let idempotency_key = seq.map(|seq| IdempotencyKey { client_id: client_id.clone(), seq });
+        self.propose(LockCommand::Transfer { request_id, lock_id, client_id, lease_id, target, ttl_seconds, idempotency_key }).await
+    }
 
-        let command = LockCommand::Release { request_id, lock_id, client_id, lease_id };
+    pub async fn propose_release(&self ,lease_id : String ,  lock_id : String , client_id : String , seq : Option<u64>) -> Result<CommandResponse , String>{
+        let request_id = self.generate_new_index();
+        let idempotency_key = seq.map(|seq| IdempotencyKey { client_id: client_id.clone(), seq });
+
+        let command = LockCommand::Release { request_id, lock_id, client_id, lease_id , idempotency_key };
 
         self.propose(command).await
 
     }
 
-}
\ No newline at end of file
+    /// Replaces the lock table and ownership history on every replica.
+    pub async fn propose_restore(&self , locks : Vec<(LockId , LockState)> , history : Vec<HistoryEvent> , kv : Vec<KvEntry> , barriers : Vec<(String , Barrier)>) -> Result<CommandResponse , String>{
+        let request_id = self.generate_new_index();
+        self.propose(LockCommand::Restore { request_id, locks, history, kv, barriers, idempotency_key: None }).await
+    }
+
+    pub async fn propose_kv_get(&self , key : String) -> Result<CommandResponse , String>{
+        let request_id = self.generate_new_index();
+        self.propose(LockCommand::KvGet { request_id, key }).await
+    }
+
+    /// Writes `key`, if it is at `expected_version` when one is given.
+    pub async fn propose_kv_put(&self , key : String , value : String , client_id : String , expected_version : Option<u64> , lease : Option<KvLease> , seq : Option<u64>) -> Result<CommandResponse , String>{
+        if let Err(message) = kv::validate(&key , &value) {
+            return Ok(CommandResponse::Error { error_type: INVALID_KV.to_string(), message })
+        }
+        if let Err(refused) = self.admit(&client_id) {
+            return Ok(refused)
+        }
+        let request_id = self.generate_new_index();
+        let idempotency_key = seq.map(|seq| IdempotencyKey { client_id: client_id.clone(), seq });
+        self.propose(LockCommand::KvPut { request_id, key, value, client_id, expected_version, lease, idempotency_key }).await
+    }
+
+    pub async fn propose_kv_delete(&self , key : String , client_id : String , expected_version : Option<u64> , seq : Option<u64>) -> Result<CommandResponse , String>{
+        if let Err(refused) = self.admit(&client_id) {
+            return Ok(refused)
+        }
+        let request_id = self.generate_new_index();
+        let idempotency_key = seq.map(|seq| IdempotencyKey { client_id: client_id.clone(), seq });
+        self.propose(LockCommand::KvDelete { request_id, key, client_id, expected_version, idempotency_key }).await
+    }
+
+    pub async fn propose_barrier_create(&self , name : String , kind : BarrierKind , count : u32) -> Result<CommandResponse , String>{
+        if let Err(message) = barrier::validate(&name , count) {
+            return Ok(CommandResponse::Error { error_type: INVALID_BARRIER.to_string(), message })
+        }
+        let request_id = self.generate_new_index();
+        self.propose(LockCommand::BarrierCreate { request_id, name, kind, count, idempotency_key: None }).await
+    }
+
+    pub async fn propose_barrier_join(&self , name : String , client_id : String , ttl_seconds : u64 , seq : Option<u64>) -> Result<CommandResponse , String>{
+        if let Err(refused) = self.admit(&client_id) {
+            return Ok(refused)
+        }
+        let request_id = self.generate_new_index();
+        let idempotency_key = seq.map(|seq| IdempotencyKey { client_id: client_id.clone(), seq });
+        self.propose(LockCommand::BarrierJoin { request_id, name, client_id, ttl_seconds, idempotency_key }).await
+    }
+
+    /// Counts one arrival. Latches count every one, so retries should carry
+    /// a `seq`.
+    pub async fn propose_barrier_arrive(&self , name : String , client_id : String , seq : Option<u64>) -> Result<CommandResponse , String>{
+        if let Err(refused) = self.admit(&client_id) {
+            return Ok(refused)
+        }
+        let request_id = self.generate_new_index();
+        let idempotency_key = seq.map(|seq| IdempotencyKey { client_id: client_id.clone(), seq });
+        self.propose(LockCommand::BarrierArrive { request_id, name, client_id, idempotency_key }).await
+    }
+
+    pub async fn propose_barrier_delete(&self , name : String) -> Result<CommandResponse , String>{
+        let request_id = self.generate_new_index();
+        self.propose(LockCommand::BarrierDelete { request_id, name, idempotency_key: None }).await
+    }
+
+    /// Replaces the ACL table on every replica.
+    pub async fn propose_acl(&self , acl : AclTable) -> Result<CommandResponse , String>{
+        let request_id = self.generate_new_index();
+        self.propose(LockCommand::SetAcl { request_id, acl, idempotency_key: None }).await
+    }
+
+    /// Replaces the limits on the lock table on every replica.
+    pub async fn propose_quotas(&self , quotas : LockQuotas) -> Result<CommandResponse , String>{
+        let request_id = self.generate_new_index();
+        self.propose(LockCommand::SetQuotas { request_id, quotas, idempotency_key: None }).await
+    }
+
+    /// Takes `lock_id` from its holder on behalf of `actor`; with
+    /// `lease_id`, only from a holder of that lease. The admin writes are
+    /// keyed by the client asking for them, so a retry is applied once.
+    pub async fn propose_force_release(&self , lock_id : String , lease_id : Option<String> , actor : String , idempotency_key : Option<IdempotencyKey>) -> Result<CommandResponse , String>{
+        let request_id = self.generate_new_index();
+        self.propose(LockCommand::ForceRelease { request_id, lock_id, lease_id, actor, idempotency_key }).await
+    }
+
+    pub async fn propose_evict_waiter(&self , lock_id : String , client_id : String , actor : String , idempotency_key : Option<IdempotencyKey>) -> Result<CommandResponse , String>{
+        let request_id = self.generate_new_index();
+        self.propose(LockCommand::EvictWaiter { request_id, lock_id, client_id, actor, idempotency_key }).await
+    }
+
+    pub async fn propose_revoke_client(&self , client_id : String , actor : String , idempotency_key : Option<IdempotencyKey>) -> Result<CommandResponse , String>{
+        let request_id = self.generate_new_index();
+        self.propose(LockCommand::RevokeClient { request_id, client_id, actor, idempotency_key }).await
+    }
+
+    pub async fn admin(&self , command : AdminCommand) -> Result<AdminResponse , String>{
+        let (response_tx , response_rx)= oneshot::channel();
+
+        self.admin_tx.send((command , response_tx)).await
+            .map_err(|e| format!("Failed to send admin command : {}" , e))?;
+
+        response_rx.await
+            .map_err(|e|format!("Failed to receive response : {}" , e))
+    }
+
+    pub async fn transfer_leader(&self , target : Option<u64> , timeout_ms : u64) -> Result<AdminResponse , String>{
+        self.admin(AdminCommand::TransferLeader { target, timeout_ms }).await
+    }
+
+    pub async fn set_draining(&self , draining : bool) -> Result<AdminResponse , String>{
+        self.admin(AdminCommand::SetDraining(draining)).await
+    }
+
+    pub async fn members(&self) -> Result<AdminResponse , String>{
+        self.admin(AdminCommand::Members).await
+    }
+
+    pub async fn add_node(&self , peer : PeerConfig) -> Result<AdminResponse , String>{
+        self.admin(AdminCommand::AddNode(peer)).await
+    }
+
+    pub async fn remove_node(&self , id : u64) -> Result<AdminResponse , String>{
+        self.admin(AdminCommand::RemoveNode(id)).await
+    }
+
+    /// Resolves once reads of the local state machine see every write
+    /// committed before the call.
+    pub async fn read_index(&self) -> Result<AdminResponse , String>{
+        self.admin(AdminCommand::ReadIndex).await
+    }
+
+}
@@ -1,38 +1,290 @@
 use async_raft::AppData;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::{auth::acl::AclTable, config::server_config::PeerConfig, lock::{barrier::{Barrier, BarrierKind}, history::HistoryEvent, kv::{KvEntry, KvLease}, metadata::Metadata, quota::LockQuotas, types::{LockId, LockState}}};
 
-#[derive(Deserialize, Serialize , Clone , Debug)]
+
+/// Client supplied key identifying one logical request. Retries of the same
+/// request reuse the key and get the response of the first application.
+#[derive(Deserialize, Serialize , Clone , Debug , PartialEq , Eq , Hash)]
+pub struct IdempotencyKey{
+    pub client_id : String,
+    pub seq : u64
+}
+
+#[derive(Deserialize, Serialize , Clone , Debug , PartialEq)]
 pub enum LockCommand{
     Acquire{
         lock_id : String, 
         client_id : String , 
         ttl_seconds : u64 , 
-        request_id : u64
+        request_id : u64,
+        #[serde(default)]
+        idempotency_key : Option<IdempotencyKey>,
+        /// Join the wait queue when the lock is held. Otherwise a held lock
+        /// is answered with a `LockHeld` error and nothing changes.
+        #[serde(default = "queue_by_default")]
+        queue : bool,
+        /// Given to the lease, now or when the client leaves the queue.
+        #[serde(default)]
+        metadata : Metadata
     },
     Release{
         request_id : u64,
         lock_id : String, 
         client_id : String , 
-        lease_id : String
+        lease_id : String,
+        #[serde(default)]
+        idempotency_key : Option<IdempotencyKey>
     },
     Renew{
         request_id : u64,
         lock_id : String, 
         client_id : String , 
         ttl_seconds : u64 , 
-        lease_id : String
+        lease_id : String,
+        #[serde(default)]
+        idempotency_key : Option<IdempotencyKey>,
+        /// Replaces the lease's metadata; `None` keeps it.
+        #[serde(default)]
+        metadata : Option<Metadata>
+    },
+    /// Hands a held lock to `target` with a new lease, bypassing the wait
+    /// queue. The holder proves ownership with its lease.
+    Transfer{
+        request_id : u64,
+        lock_id : String,
+        client_id : String,
+        lease_id : String,
+        target : String,
+        ttl_seconds : u64,
+        #[serde(default)]
+        idempotency_key : Option<IdempotencyKey>
+    },
+    /// Replaces the whole lock table with a snapshot taken by an operator.
+    Restore{
+        request_id : u64,
+        locks : Vec<(LockId , LockState)>,
+        /// Ownership history of the snapshot. Older snapshots have none.
+        #[serde(default)]
+        history : Vec<HistoryEvent>,
+        /// Key-value entries of the snapshot. Older snapshots have none.
+        #[serde(default)]
+        kv : Vec<KvEntry>,
+        /// Barriers and latches of the snapshot. Older snapshots have none.
+        #[serde(default)]
+        barriers : Vec<(String , Barrier)>,
+        #[serde(default)]
+        idempotency_key : Option<IdempotencyKey>
+    },
+    /// Replaces the table of principals and ACL rules.
+    SetAcl{
+        request_id : u64,
+        acl : AclTable,
+        #[serde(default)]
+        idempotency_key : Option<IdempotencyKey>
+    },
+    /// Replaces the limits on the lock table.
+    SetQuotas{
+        request_id : u64,
+        quotas : LockQuotas,
+        #[serde(default)]
+        idempotency_key : Option<IdempotencyKey>
+    },
+    /// Takes a lock from its holder and grants it to the next waiter.
+    /// `actor` is whoever asked for it, kept for the record. With
+    /// `lease_id`, only a holder of that lease loses the lock.
+    ForceRelease{
+        request_id : u64,
+        lock_id : String,
+        #[serde(default)]
+        lease_id : Option<String>,
+        actor : String,
+        #[serde(default)]
+        idempotency_key : Option<IdempotencyKey>
+    },
+    /// Removes a client from the wait queue of a lock.
+    EvictWaiter{
+        request_id : u64,
+        lock_id : String,
+        client_id : String,
+        actor : String,
+        #[serde(default)]
+        idempotency_key : Option<IdempotencyKey>
+    },
+    /// Releases every lock of a client and removes it from every queue.
+    RevokeClient{
+        request_id : u64,
+        client_id : String,
+        actor : String,
+        #[serde(default)]
+        idempotency_key : Option<IdempotencyKey>
+    },
+    /// Reads a key. It goes through the log so the value is never stale.
+    KvGet{
+        request_id : u64,
+        key : String
+    },
+    /// Writes a key, only if it is at `expected_version` when one is given,
+    /// 0 meaning absent. With `lease`, the key is deleted when the lease
+    /// ends.
+    KvPut{
+        request_id : u64,
+        key : String,
+        value : String,
+        client_id : String,
+        #[serde(default)]
+        expected_version : Option<u64>,
+        #[serde(default)]
+        lease : Option<KvLease>,
+        #[serde(default)]
+        idempotency_key : Option<IdempotencyKey>
+    },
+    KvDelete{
+        request_id : u64,
+        key : String,
+        client_id : String,
+        #[serde(default)]
+        expected_version : Option<u64>,
+        #[serde(default)]
+        idempotency_key : Option<IdempotencyKey>
+    },
+    /// Creates a barrier or latch, or does nothing if it already exists
+    /// with the same kind and count.
+    BarrierCreate{
+        request_id : u64,
+        name : String,
+        kind : BarrierKind,
+        count : u32,
+        #[serde(default)]
+        idempotency_key : Option<IdempotencyKey>
+    },
+    /// Reserves an arrival for a client for `ttl_seconds`.
+    BarrierJoin{
+        request_id : u64,
+        name : String,
+        client_id : String,
+        ttl_seconds : u64,
+        #[serde(default)]
+        idempotency_key : Option<IdempotencyKey>
+    },
+    BarrierArrive{
+        request_id : u64,
+        name : String,
+        client_id : String,
+        #[serde(default)]
+        idempotency_key : Option<IdempotencyKey>
+    },
+    BarrierDelete{
+        request_id : u64,
+        name : String,
+        #[serde(default)]
+        idempotency_key : Option<IdempotencyKey>
     },
 }
 
-#[derive(Debug, Clone , Serialize , Deserialize)]
+fn queue_by_default() -> bool {
+    true
+}
+
+impl LockCommand{
+    pub fn request_id(&self) -> u64 {
+        match self{
+            LockCommand::Acquire { request_id,.. } => *request_id,
+            LockCommand::Release { request_id, .. } => *request_id,
+            LockCommand::Renew { request_id,.. } => *request_id,
+            LockCommand::Transfer { request_id, .. } => *request_id,
+            LockCommand::Restore { request_id, .. } => *request_id,
+            LockCommand::SetAcl { request_id, .. } => *request_id,
+            LockCommand::SetQuotas { request_id, .. } => *request_id,
+            LockCommand::ForceRelease { request_id, .. } => *request_id,
+            LockCommand::EvictWaiter { request_id, .. } => *request_id,
+            LockCommand::RevokeClient { request_id, .. } => *request_id,
+            LockCommand::KvGet { request_id, .. } => *request_id,
+            LockCommand::KvPut { request_id, .. } => *request_id,
+            LockCommand::KvDelete { request_id, .. } => *request_id,
+            LockCommand::BarrierCreate { request_id, .. } => *request_id,
+            LockCommand::BarrierJoin { request_id, .. } => *request_id,
+            LockCommand::BarrierArrive { request_id, .. } => *request_id,
+            LockCommand::BarrierDelete { request_id, .. } => *request_id,
+        }
+    }
+
+    /// The lock the command touches, `None` when it touches all of them or
+    /// none.
+    pub fn lock_id(&self) -> Option<&str> {
+        match self{
+            LockCommand::Acquire { lock_id,.. } => Some(lock_id),
+            LockCommand::Release { lock_id, .. } => Some(lock_id),
+            LockCommand::Renew { lock_id,.. } => Some(lock_id),
+            LockCommand::Transfer { lock_id, .. } => Some(lock_id),
+            LockCommand::ForceRelease { lock_id, .. } => Some(lock_id),
+            LockCommand::EvictWaiter { lock_id, .. } => Some(lock_id),
+            LockCommand::Restore { .. } | LockCommand::SetAcl { .. } | LockCommand::SetQuotas { .. } | LockCommand::RevokeClient { .. } => None,
+            LockCommand::KvGet { .. } | LockCommand::KvPut { .. } | LockCommand::KvDelete { .. } => None,
+            LockCommand::BarrierCreate { .. } | LockCommand::BarrierJoin { .. } | LockCommand::BarrierArrive { .. } | LockCommand::BarrierDelete { .. } => None,
+        }
+    }
+
+    pub fn idempotency_key(&self) -> Option<&IdempotencyKey> {
+        match self{
+            LockCommand::Acquire { idempotency_key,.. } => idempotency_key.as_ref(),
+            LockCommand::Release { idempotency_key, .. } => idempotency_key.as_ref(),
+            LockCommand::Renew { idempotency_key,.. } => idempotency_key.as_ref(),
+            LockCommand::Transfer { idempotency_key, .. } => idempotency_key.as_ref(),
+            LockCommand::Restore { idempotency_key, .. } => idempotency_key.as_ref(),
+            LockCommand::SetAcl { idempotency_key, .. } => idempotency_key.as_ref(),
+            LockCommand::SetQuotas { idempotency_key, .. } => idempotency_key.as_ref(),
+            LockCommand::ForceRelease { idempotency_key, .. } => idempotency_key.as_ref(),
+            LockCommand::EvictWaiter { idempotency_key, .. } => idempotency_key.as_ref(),
+            LockCommand::RevokeClient { idempotency_key, .. } => idempotency_key.as_ref(),
+            LockCommand::KvGet { .. } => None,
+            LockCommand::KvPut { idempotency_key, .. } => idempotency_key.as_ref(),
+            LockCommand::KvDelete { idempotency_key, .. } => idempotency_key.as_ref(),
+            LockCommand::BarrierCreate { idempotency_key, .. } => idempotency_key.as_ref(),
+            LockCommand::BarrierJoin { idempotency_key, .. } => idempotency_key.as_ref(),
+            LockCommand::BarrierArrive { idempotency_key, .. } => idempotency_key.as_ref(),
+            LockCommand::BarrierDelete { idempotency_key, .. } => idempotency_key.as_ref(),
+        }
+    }
+}
+
+/// Data of a raft log entry: the client command plus the time and lease
+/// seed chosen by the leader, so that every replica applies it identically.
+#[derive(Deserialize, Serialize , Clone , Debug)]
+pub struct LogEntry{
+    pub command : LockCommand,
+    /// Node that proposed the entry. Only it holds a pending response.
+    pub proposer : u64,
+    pub issued_at : DateTime<Utc>,
+    pub lease_seed : u64
+}
+
+/// Published by a node after it applied a command to its state machine.
+#[derive(Clone , Debug)]
+pub struct AppliedEntry{
+    pub index : u64,
+    pub term : u64,
+    pub applied_at : DateTime<Utc>,
+    pub command : LockCommand,
+    pub response : CommandResponse
+}
+
+#[derive(Debug, Clone , PartialEq , Serialize , Deserialize)]
 pub enum CommandResponse {
     AcquireGranted {
         lease_id : String , 
-        expires_at : String 
+        expires_at : String ,
+        /// Raft index of the entry that granted the lease.
+        #[serde(default)]
+        fencing_token : u64
     },
     AcquireQueued{
-        position :usize
+        position :usize ,
+        /// Seconds until the current lease expires, at the time of queueing.
+        #[serde(default)]
+        estimated_wait : u64
     }, 
     // Error(String),
     Error{
@@ -40,6 +292,79 @@ pub enum CommandResponse {
         message : String
     },
     ReleaseSuccess, 
-    RenewSuccess { new_expiry : String}
+    RenewSuccess { new_expiry : String},
+    Transferred { lease_id : String , expires_at : String , fencing_token : u64 },
+    Restored { locks : usize },
+    AclUpdated { principals : usize , rules : usize },
+    QuotasUpdated { quotas : LockQuotas },
+    ForceReleased { previous_holder : String , promoted : Option<String> },
+    WaiterEvicted { removed : usize },
+    ClientRevoked { released : usize , evicted : usize },
+    KvValue { entry : Option<KvEntry> },
+    KvWritten { version : u64 },
+    KvDeleted { version : u64 },
+    BarrierUpdated { remaining : u32 },
+    BarrierDeleted { remaining : u32 }
+}
+impl AppData for LockCommand{}
+
+#[derive(Debug , Clone)]
+pub enum ReadResponse{
+    Status(Option<LockState>),
+    Error{
+        error_type : String , 
+        message : String
+    }
+}
+
+/// Operator commands handled by the local node only. They are never
+/// written to the raft log.
+#[derive(Debug , Clone)]
+pub enum AdminCommand{
+    /// Hands leadership to `target`, or to the most caught-up peer when
+    /// `target` is `None`. The node drains before transferring.
+    TransferLeader{
+        target : Option<u64>,
+        timeout_ms : u64
+    },
+    SetDraining(bool),
+    Members,
+    /// Adds a voter. Its addresses travel with the membership change so
+    /// every node learns how to reach it.
+    AddNode(PeerConfig),
+    RemoveNode(u64),
+    /// Confirms this node still leads and waits until its state machine
+    /// applied everything committed before, so local reads are current.
+    ReadIndex,
+}
+
+#[derive(Debug, Clone , Serialize , Deserialize)]
+pub enum AdminResponse{
+    LeaderTransferred{
+        from : u64,
+        to : u64,
+        term : u64
+    },
+    DrainUpdated{
+        draining : bool,
+        in_flight : usize
+    },
+    Members{
+        leader_id : u64,
+        voters : Vec<u64>,
+        learners : Vec<u64>,
+        /// Addresses of the nodes added at runtime.
+        added : Vec<PeerConfig>
+    },
+    MembershipChanged{
+        voters : Vec<u64>
+    },
+    /// The state machine applied at least up to `index`.
+    ReadIndex{
+        index : u64
+    },
+    Error{
+        error_type : String , 
+        message : String
+    },
 }
-impl AppData for LockCommand{}
\ No newline at end of file
@@ -1,24 +1,203 @@
-use std::{sync::{Arc , RwLock}, time::Duration};
+use std::{fs::{File, OpenOptions}, io::{ErrorKind, Read, Write}, path::{Path, PathBuf}, sync::{Arc , Mutex , RwLock}};
 
-use raft::{storage::{MemStorage, Storage}};
-use crate::lock::types::{LockId , ClientId , LeaseId};
-use crate::{lock::{manager::InMemoryLockManager, types::LockManager}, raft::raft_commands::LockCommand};
+use protobuf::Message as _;
+use raft::{prelude::{ConfState, Entry, HardState, Snapshot}, storage::{MemStorage, Storage}, Error as RaftError, StorageError};
 
+const LOG_FILE : &str = "raft.log";
+const HARD_STATE_FILE : &str = "hard_state";
+const CONF_STATE_FILE : &str = "conf_state";
+const SNAPSHOT_FILE : &str = "snapshot";
 
+/// Raft state kept under a node's data dir. Entries are appended to
+/// `raft.log` as frames, a big-endian u32 length followed by the protobuf
+/// encoded entry. A frame overwrites the earlier ones from its index on,
+/// as `MemStorage::append` does, so the file is replayed in order.
+struct Disk{
+    dir : PathBuf,
+    log : Mutex<File>
+}
 
 #[derive(Clone)]
 pub struct DistlockStorage{
     inner : Arc<RwLock<MemStorage>>,
+    /// `None` keeps everything in memory, as in tests and the simulation.
+    disk : Option<Arc<Disk>>
+}
+
+impl Default for DistlockStorage{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn store_error(e : impl std::error::Error + Send + Sync + 'static) -> RaftError {
+    RaftError::Store(StorageError::Other(Box::new(e)))
+}
+
+impl Disk{
+    fn append(&self , entries : &[Entry]) -> raft::Result<()> {
+        let mut frames = Vec::new();
+        for entry in entries {
+            let data = entry.write_to_bytes().map_err(store_error)?;
+            frames.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            frames.extend_from_slice(&data);
+        }
+        let mut log = self.log.lock().unwrap();
+        log.write_all(&frames).map_err(store_error)?;
+        log.sync_data().map_err(store_error)
+    }
+
+    /// Replaces a small file in one step, so a crash leaves the old or the
+    /// new version.
+    fn write(&self , name : &str , message : &impl protobuf::Message) -> raft::Result<()> {
+        let data = message.write_to_bytes().map_err(store_error)?;
+        let path = self.dir.join(name);
+        let staged = self.dir.join(format!("{}.tmp" , name));
+        let mut file = File::create(&staged).map_err(store_error)?;
+        file.write_all(&data).map_err(store_error)?;
+        file.sync_all().map_err(store_error)?;
+        std::fs::rename(&staged , &path).map_err(store_error)
+    }
+
+    /// Entries before a snapshot are never read again.
+    fn truncate_log(&self) -> raft::Result<()> {
+        let log = self.log.lock().unwrap();
+        log.set_len(0).map_err(store_error)?;
+        log.sync_all().map_err(store_error)
+    }
+}
+
+fn read_message<M : protobuf::Message>(path : &Path) -> Result<Option<M> , String> {
+    match std::fs::read(path) {
+        Ok(data) => M::parse_from_bytes(&data).map(Some).map_err(|e| format!("Failed to parse {}: {}" , path.display() , e)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Failed to read {}: {}" , path.display() , e))
+    }
+}
+
+/// Reads every complete frame of the log. A frame cut short by a crash is
+/// cut off the file, so the next append starts on a frame boundary.
+fn read_log(path : &Path) -> Result<Vec<Entry> , String> {
+    let mut data = Vec::new();
+    match File::open(path) {
+        Ok(mut file) => { file.read_to_end(&mut data).map_err(|e| format!("Failed to read {}: {}" , path.display() , e))?; }
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to read {}: {}" , path.display() , e))
+    }
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while let Some(header) = data.get(offset..offset + 4) {
+        let length = u32::from_be_bytes(header.try_into().unwrap()) as usize;
+        let Some(frame) = data.get(offset + 4..offset + 4 + length) else { break };
+        entries.push(Entry::parse_from_bytes(frame).map_err(|e| format!("Failed to parse {} at byte {}: {}" , path.display() , offset , e))?);
+        offset += 4 + length;
+    }
+    if offset < data.len() {
+        tracing::warn!("Dropping {} bytes of a partly written entry from {}" , data.len() - offset , path.display());
+        OpenOptions::new().write(true).open(path).and_then(|file| file.set_len(offset as u64))
+            .map_err(|e| format!("Failed to truncate {}: {}" , path.display() , e))?;
+    }
+    Ok(entries)
 }
 
 impl DistlockStorage{
     pub fn new() -> Self {
         Self { inner
             : Arc::new(RwLock::new(MemStorage::new())) ,
+            disk : None
         }
     }
 
+    /// Bootstraps the storage with the initial set of voters. Every node of a
+    /// fresh cluster must be started with the same list.
+    pub fn new_with_voters(voters : Vec<u64>) -> Self {
+        Self { inner : Arc::new(RwLock::new(MemStorage::new_with_conf_state((voters , vec![])))) , disk : None }
+    }
 
+    /// Opens the raft state persisted under `dir`, creating it on first
+    /// start. `voters` only bootstraps a directory that holds no membership
+    /// yet; after that the recorded one wins.
+    pub fn open(dir : &Path , voters : Vec<u64>) -> Result<Self , String> {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create data dir {}: {}" , dir.display() , e))?;
+        let storage = MemStorage::new();
+        {
+            let mut core = storage.wl();
+            if let Some(snapshot) = read_message::<Snapshot>(&dir.join(SNAPSHOT_FILE))? {
+                core.apply_snapshot(snapshot).map_err(|e| e.to_string())?;
+            }
+            for entry in read_log(&dir.join(LOG_FILE))? {
+                core.append(&[entry]).map_err(|e| e.to_string())?;
+            }
+            if let Some(hard_state) = read_message::<HardState>(&dir.join(HARD_STATE_FILE))? {
+                core.set_hardstate(hard_state);
+            }
+            if let Some(conf_state) = read_message::<ConfState>(&dir.join(CONF_STATE_FILE))? {
+                core.set_conf_state(conf_state);
+            }
+        }
+        let log = OpenOptions::new().create(true).append(true).open(dir.join(LOG_FILE))
+            .map_err(|e| format!("Failed to open {}: {}" , dir.join(LOG_FILE).display() , e))?;
+        let disk = Disk { dir: dir.to_path_buf(), log: Mutex::new(log) };
+        if !storage.initial_state().unwrap().initialized() {
+            let conf_state = ConfState::from((voters , vec![]));
+            disk.write(CONF_STATE_FILE , &conf_state).map_err(|e| e.to_string())?;
+            storage.wl().set_conf_state(conf_state);
+        }
+        Ok(Self { inner: Arc::new(RwLock::new(storage)), disk: Some(Arc::new(disk)) })
+    }
+
+    pub fn append(&self , entries : &[Entry]) -> raft::Result<()> {
+        if let Some(disk) = &self.disk {
+            disk.append(entries)?;
+        }
+        let storage = self.inner.read().unwrap();
+        storage.wl().append(entries)
+    }
+
+    pub fn set_hardstate(&self , hard_state : HardState) -> raft::Result<()> {
+        if let Some(disk) = &self.disk {
+            disk.write(HARD_STATE_FILE , &hard_state)?;
+        }
+        let storage = self.inner.read().unwrap();
+        storage.wl().set_hardstate(hard_state);
+        Ok(())
+    }
+
+    pub fn set_commit(&self , commit : u64) -> raft::Result<()> {
+        let storage = self.inner.read().unwrap();
+        let mut core = storage.wl();
+        core.mut_hard_state().set_commit(commit);
+        match &self.disk {
+            Some(disk) => disk.write(HARD_STATE_FILE , core.hard_state()),
+            None => Ok(())
+        }
+    }
+
+    /// Records the membership after a conf change is applied, so a restart
+    /// comes back with the same voters.
+    pub fn set_conf_state(&self , conf_state : ConfState) -> raft::Result<()> {
+        if let Some(disk) = &self.disk {
+            disk.write(CONF_STATE_FILE , &conf_state)?;
+        }
+        let storage = self.inner.read().unwrap();
+        storage.wl().set_conf_state(conf_state);
+        Ok(())
+    }
+
+    pub fn apply_snapshot(&self , snapshot : Snapshot) -> raft::Result<()> {
+        let storage = self.inner.read().unwrap();
+        storage.wl().apply_snapshot(snapshot.clone())?;
+        match &self.disk {
+            Some(disk) => {
+                let state = storage.initial_state()?;
+                disk.write(SNAPSHOT_FILE , &snapshot)?;
+                disk.write(HARD_STATE_FILE , &state.hard_state)?;
+                disk.write(CONF_STATE_FILE , &state.conf_state)?;
+                disk.truncate_log()
+            }
+            None => Ok(())
+        }
+    }
 
 }
 
@@ -55,4 +234,4 @@ impl Storage for DistlockStorage{
         let storage = self.inner.read().unwrap();
         storage.snapshot(request_index , to)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,216 @@
+use std::{collections::HashMap, sync::{Arc, RwLock}};
+
+use metrics::counter;
+use protobuf::Message as _;
+use raft::prelude::Message;
+use rustls::pki_types::ServerName;
+use tokio::{io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt}, net::{TcpListener, TcpStream}, sync::mpsc};
+
+use crate::{telemetry::prometheus::PEER_MESSAGE_ERRORS, tls::material::TlsMaterial};
+
+// Snapshots are the largest messages; anything above this is corrupt input.
+const MAX_FRAME_SIZE : usize = 64 * 1024 * 1024;
+
+
+/// Delivers outgoing raft messages to the peer named in `message.to`.
+/// `send` is called from the node loop, so it must never block.
+pub trait Transport : Send + Sync {
+    fn send(&self , message : Message);
+
+    /// Called when a membership change adds `id`. Transports that route by
+    /// id alone can ignore it.
+    fn add_peer(&self , _id : u64 , _addr : &str) {}
+
+    fn remove_peer(&self , _id : u64) {}
+}
+
+/// Transport for a node without peers. Every message is dropped.
+pub struct NoopTransport;
+
+impl Transport for NoopTransport{
+    fn send(&self , _message : Message) {}
+}
+
+/// Routes messages between nodes living in the same process, using the
+/// mailboxes returned by `RaftNode::message_sender`.
+#[derive(Default)]
+pub struct LocalTransport{
+    mailboxes : RwLock<HashMap<u64 , mpsc::UnboundedSender<Message>>>
+}
+
+impl LocalTransport{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&self , id : u64 , mailbox : mpsc::UnboundedSender<Message>) {
+        self.mailboxes.write().unwrap().insert(id , mailbox);
+    }
+}
+
+impl Transport for LocalTransport{
+    fn send(&self , message : Message) {
+        let mailboxes = self.mailboxes.read().unwrap();
+        if let Some(mailbox) = mailboxes.get(&message.to){
+            let _ = mailbox.send(message);
+        }
+    }
+}
+
+/// Sends raft messages to peers over TCP, one connection per peer. Each
+/// frame is a big-endian u32 length followed by the protobuf encoded message.
+pub struct TcpTransport{
+    outboxes : RwLock<HashMap<u64 , mpsc::UnboundedSender<Message>>>,
+    tls : Option<Arc<TlsMaterial>>
+}
+
+impl TcpTransport{
+    /// Spawns one writer task per peer, so it must be called from within a
+    /// tokio runtime.
+    pub fn new(peers : &HashMap<u64 , String>) -> Self {
+        Self::build(peers , None)
+    }
+
+    /// Connects to peers with mutual TLS when `tls` has a peer CA.
+    pub fn with_tls(peers : &HashMap<u64 , String> , tls : Arc<TlsMaterial>) -> Self {
+        Self::build(peers , Some(tls))
+    }
+
+    fn build(peers : &HashMap<u64 , String> , tls : Option<Arc<TlsMaterial>>) -> Self {
+        let transport = Self { outboxes: RwLock::new(HashMap::new()), tls };
+        for (id , addr) in peers {
+            transport.add_peer(*id , addr);
+        }
+        transport
+    }
+}
+
+impl Transport for TcpTransport{
+    fn send(&self , message : Message) {
+        if let Some(outbox) = self.outboxes.read().unwrap().get(&message.to){
+            let _ = outbox.send(message);
+        }
+    }
+
+    /// Replaces the writer of a peer that is already known. Dropping the old
+    /// outbox stops its writer task.
+    fn add_peer(&self , id : u64 , addr : &str) {
+        let (outbox_tx , outbox_rx) = mpsc::unbounded_channel();
+        tokio::spawn(peer_writer(id , addr.to_string() , outbox_rx , self.tls.clone()));
+        self.outboxes.write().unwrap().insert(id , outbox_tx);
+    }
+
+    fn remove_peer(&self , id : u64) {
+        self.outboxes.write().unwrap().remove(&id);
+    }
+}
+
+type PeerStream = Box<dyn AsyncWrite + Unpin + Send>;
+
+/// The TLS server name is the host part of the peer address, so peer
+/// certificates must name it.
+async fn connect(addr : &str , tls : Option<&TlsMaterial>) -> Result<PeerStream , String> {
+    let stream = TcpStream::connect(addr).await.map_err(|e| e.to_string())?;
+    let _ = stream.set_nodelay(true);
+    let Some(connector) = tls.and_then(TlsMaterial::peer_connector) else { return Ok(Box::new(stream)) };
+
+    let host = addr.rsplit_once(':').map_or(addr , |(host , _)| host).trim_start_matches('[').trim_end_matches(']');
+    let server_name = ServerName::try_from(host.to_string()).map_err(|e| format!("Invalid server name '{}': {}" , host , e))?;
+    let stream = connector.connect(server_name , stream).await.map_err(|e| format!("TLS handshake failed: {}" , e))?;
+    Ok(Box::new(stream))
+}
+
+async fn peer_writer(id : u64 , addr : String , mut outbox : mpsc::UnboundedReceiver<Message> , tls : Option<Arc<TlsMaterial>>) {
+    let mut stream : Option<PeerStream> = None;
+
+    while let Some(message) = outbox.recv().await {
+        if stream.is_none() {
+            match connect(&addr , tls.as_deref()).await {
+                Ok(connected) => {
+                    stream = Some(connected);
+                }
+                Err(e) => {
+                    // Raft retransmits, so dropping the message is fine.
+                    peer_message_error(id , "connect");
+                    tracing::debug!("Failed to connect to peer {} at {}: {}" , id , addr , e);
+                    continue
+                }
+            }
+        }
+        let data = match message.write_to_bytes() {
+            Ok(data) => data,
+            Err(e) => {
+                peer_message_error(id , "encode");
+                tracing::error!("Failed to encode message for peer {}: {}" , id , e);
+                continue
+            }
+        };
+        let connection = stream.as_mut().unwrap();
+        let written = async {
+            connection.write_u32(data.len() as u32).await?;
+            connection.write_all(&data).await?;
+            // TLS buffers records until flushed.
+            connection.flush().await
+        }.await;
+        if let Err(e) = written {
+            peer_message_error(id , "write");
+            tracing::debug!("Lost connection to peer {} at {}: {}" , id , addr , e);
+            stream = None;
+        }
+    }
+}
+
+fn peer_message_error(peer : u64 , reason : &'static str) {
+    counter!(PEER_MESSAGE_ERRORS , "peer" => peer.to_string() , "reason" => reason).increment(1);
+}
+
+/// Accepts peer connections and forwards every decoded message to `mailbox`.
+pub async fn serve_peers(listener : TcpListener , mailbox : mpsc::UnboundedSender<Message>) {
+    accept_peers(listener , mailbox , None).await
+}
+
+/// Like `serve_peers`, requiring mutual TLS when `tls` has a peer CA.
+pub async fn serve_peers_tls(listener : TcpListener , mailbox : mpsc::UnboundedSender<Message> , tls : Arc<TlsMaterial>) {
+    accept_peers(listener , mailbox , Some(tls)).await
+}
+
+async fn accept_peers(listener : TcpListener , mailbox : mpsc::UnboundedSender<Message> , tls : Option<Arc<TlsMaterial>>) {
+    loop {
+        let (connection , remote) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                tracing::warn!("Failed to accept peer connection: {}" , e);
+                continue
+            }
+        };
+        let mailbox = mailbox.clone();
+        let acceptor = tls.as_deref().and_then(TlsMaterial::peer_acceptor);
+        tokio::spawn(async move {
+            let read = match acceptor {
+                Some(acceptor) => match acceptor.accept(connection).await {
+                    Ok(connection) => read_frames(connection , mailbox).await,
+                    Err(e) => Err(format!("TLS handshake failed: {}" , e))
+                },
+                None => read_frames(connection , mailbox).await
+            };
+            if let Err(e) = read {
+                tracing::debug!("Peer connection from {} closed: {}" , remote , e);
+            }
+        });
+    }
+}
+
+async fn read_frames<S : AsyncRead + Unpin>(mut connection : S , mailbox : mpsc::UnboundedSender<Message>) -> Result<() , String> {
+    loop {
+        let len = connection.read_u32().await.map_err(|e| e.to_string())? as usize;
+        if len > MAX_FRAME_SIZE {
+            return Err(format!("Frame of {} bytes exceeds the limit" , len))
+        }
+        let mut data = vec![0u8 ; len];
+        connection.read_exact(&mut data).await.map_err(|e| e.to_string())?;
+        let message = Message::parse_from_bytes(&data).map_err(|e| e.to_string())?;
+        if mailbox.send(message).is_err() {
+            return Err("Node stopped".to_string())
+        }
+    }
+}
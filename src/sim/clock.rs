@@ -0,0 +1,39 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use chrono::{DateTime, Duration as ChronoDuration, TimeZone, Utc};
+
+use crate::lock::clock::Clock;
+
+
+/// Virtual clock shared by every node of a simulation. Time only moves when
+/// the simulation advances it.
+pub struct SimClock{
+    start : DateTime<Utc>,
+    elapsed_ms : AtomicI64
+}
+
+impl SimClock{
+    pub fn new() -> Self {
+        Self { start: Utc.with_ymd_and_hms(2024 , 1 , 1 , 0 , 0 , 0).unwrap(), elapsed_ms: AtomicI64::new(0) }
+    }
+
+    pub fn advance(&self , ms : u64) {
+        self.elapsed_ms.fetch_add(ms as i64 , Ordering::SeqCst);
+    }
+
+    pub fn elapsed_ms(&self) -> u64 {
+        self.elapsed_ms.load(Ordering::SeqCst) as u64
+    }
+}
+
+impl Default for SimClock{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SimClock{
+    fn now(&self) -> DateTime<Utc> {
+        self.start + ChronoDuration::milliseconds(self.elapsed_ms.load(Ordering::SeqCst))
+    }
+}
@@ -0,0 +1,369 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use rand::{Rng, SeedableRng, rngs::StdRng, seq::SliceRandom};
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+use crate::{config::server_config::RaftConfig, lock::{clock::Clock, manager::InMemoryLockManager, types::LockId}, raft::{node::RaftNode, raft_commands::{AppliedEntry, CommandResponse, LockCommand, ReadResponse}, storage::DistlockStorage}, sim::{clock::SimClock, history::{History, OpInput, OpOutput}, invariants::SafetyChecker, network::{SimNetwork, SimTransport}}};
+
+#[derive(Debug , Clone)]
+pub struct SimConfig{
+    pub nodes : u64,
+    pub seed : u64,
+    pub steps : u64,
+    /// Virtual time that passes per step.
+    pub step_ms : u64,
+    pub tick_ms : u64,
+    pub drop_rate : f64,
+    pub max_delay_ms : u64,
+    /// Per step probability of splitting the cluster, or of healing it.
+    pub partition_rate : f64,
+    /// Per step probability of crashing a node, or of restarting one.
+    pub crash_rate : f64,
+    /// Per step probability of a client issuing a request.
+    pub request_rate : f64,
+    /// Share of requests that are linearizable status reads.
+    pub read_rate : f64,
+    pub locks : usize,
+    pub clients : usize,
+    pub ttl_seconds : u64,
+    /// Requests without a response after this long are abandoned.
+    pub request_timeout_ms : u64
+}
+
+impl Default for SimConfig{
+    fn default() -> Self {
+        Self {
+            nodes: 3,
+            seed: 0,
+            steps: 2_000,
+            step_ms: 10,
+            tick_ms: 100,
+            drop_rate: 0.05,
+            max_delay_ms: 50,
+            partition_rate: 0.002,
+            crash_rate: 0.001,
+            request_rate: 0.3,
+            read_rate: 0.2,
+            locks: 3,
+            clients: 5,
+            ttl_seconds: 2,
+            request_timeout_ms: 10_000
+        }
+    }
+}
+
+#[derive(Debug , Default)]
+pub struct SimReport{
+    pub applied : usize,
+    pub grants : usize,
+    pub responses : usize,
+    pub crashes : usize,
+    pub partitions : usize,
+    /// Every client call with the response it observed, if any.
+    pub history : History
+}
+
+struct SimNode{
+    node : RaftNode,
+    applied : broadcast::Receiver<AppliedEntry>,
+    next_tick_ms : u64
+}
+
+enum PendingResponse{
+    Command(LockCommand , oneshot::Receiver<CommandResponse>),
+    Read(oneshot::Receiver<ReadResponse>)
+}
+
+struct PendingRequest{
+    client : usize,
+    operation : usize,
+    deadline_ms : u64,
+    response : PendingResponse
+}
+
+#[derive(Default)]
+struct SimClient{
+    // lock id -> lease id
+    held : BTreeMap<String , String>
+}
+
+pub struct Simulation{
+    config : SimConfig,
+    rng : StdRng,
+    clock : Arc<SimClock>,
+    network : SimNetwork,
+    transport : SimTransport,
+    raft_configs : BTreeMap<u64 , RaftConfig>,
+    nodes : BTreeMap<u64 , SimNode>,
+    crashed : BTreeMap<u64 , DistlockStorage>,
+    clients : Vec<SimClient>,
+    pending : Vec<PendingRequest>,
+    checker : SafetyChecker,
+    history : History,
+    next_request_id : u64,
+    report : SimReport
+}
+
+impl Simulation{
+    pub fn new(config : SimConfig) -> Self {
+        let rng = StdRng::seed_from_u64(config.seed);
+        let mut simulation = Self {
+            network: SimNetwork::new(config.drop_rate , config.max_delay_ms),
+            clients: (0..config.clients).map(|_| SimClient::default()).collect(),
+            checker: SafetyChecker::new(),
+            clock: Arc::new(SimClock::new()),
+            transport: SimTransport::default(),
+            raft_configs: BTreeMap::new(),
+            nodes: BTreeMap::new(),
+            crashed: BTreeMap::new(),
+            pending: Vec::new(),
+            history: History::new(),
+            next_request_id: 1,
+            report: SimReport::default(),
+            config, rng
+        };
+
+        let ids : Vec<u64> = (1..=simulation.config.nodes).collect();
+        for id in &ids {
+            // raft-rs draws election timeouts from an unseeded RNG, so each
+            // node gets a fixed timeout drawn from the simulation seed instead.
+            let election_tick = simulation.rng.gen_range(10..20);
+            simulation.raft_configs.insert(*id , RaftConfig {
+                tick_interval_ms: simulation.config.tick_ms,
+                election_tick,
+                max_election_tick: election_tick + 1,
+                ..RaftConfig::default()
+            });
+        }
+        for id in &ids {
+            let seed = simulation.rng.r#gen();
+            let first_tick = simulation.rng.gen_range(0..simulation.config.tick_ms);
+            let sim_node = simulation.start_node(*id , DistlockStorage::new_with_voters(ids.clone()) , seed , first_tick);
+            simulation.nodes.insert(*id , sim_node);
+        }
+        simulation
+    }
+
+    fn start_node(&self , id : u64 , storage : DistlockStorage , seed : u64 , next_tick_ms : u64) -> SimNode {
+        // The simulation drives nodes directly, the channels stay unused.
+        let (_ , command_rx) = mpsc::channel(1);
+        let (_ , admin_rx) = mpsc::channel(1);
+        let state_machine = InMemoryLockManager::new();
+        let node = RaftNode::with_storage(id , &self.raft_configs[&id] , storage , state_machine , command_rx , admin_rx)
+            .with_transport(Arc::new(self.transport.clone()))
+            .with_clock(self.clock.clone())
+            .with_seed(seed);
+        let applied = node.subscribe_applied();
+        SimNode { node, applied, next_tick_ms }
+    }
+
+    /// Runs every step and checks the safety invariants after each one.
+    pub async fn run(mut self) -> Result<SimReport , String> {
+        for _ in 0..self.config.steps {
+            self.step(true).await
+                .map_err(|e| format!("seed {} at {}ms: {}" , self.config.seed , self.clock.elapsed_ms() , e))?;
+        }
+
+        // Heal everything so that requests still in flight learn their
+        // outcome, which keeps the recorded history precise.
+        self.network.heal();
+        while let Some(&id) = self.crashed.keys().next() {
+            self.restart(id);
+        }
+        let settle_until = self.clock.elapsed_ms() + self.config.request_timeout_ms;
+        while !self.pending.is_empty() && self.clock.elapsed_ms() < settle_until {
+            self.step(false).await
+                .map_err(|e| format!("seed {} at {}ms: {}" , self.config.seed , self.clock.elapsed_ms() , e))?;
+        }
+        self.report.applied = self.checker.applied_count();
+        self.report.grants = self.checker.grants();
+        self.report.history = self.history;
+        Ok(self.report)
+    }
+
+    /// One step of virtual time. Faults and new requests are only
+    /// injected while `active`.
+    async fn step(&mut self , active : bool) -> Result<() , String> {
+        self.clock.advance(self.config.step_ms);
+        let now = self.clock.elapsed_ms();
+
+        if active {
+            self.inject_faults();
+        }
+
+        for message in self.network.deliver(now , &mut self.rng) {
+            if let Some(sim_node) = self.nodes.get_mut(&message.to) {
+                sim_node.node.step(message);
+            }
+        }
+        for sim_node in self.nodes.values_mut() {
+            while sim_node.next_tick_ms <= now {
+                sim_node.node.tick();
+                sim_node.next_tick_ms += self.config.tick_ms;
+            }
+        }
+
+        if active && self.rng.gen_bool(self.config.request_rate) {
+            self.issue_request().await;
+        }
+
+        for (id , sim_node) in self.nodes.iter_mut() {
+            sim_node.node.process_raft_ready().await;
+            loop {
+                match sim_node.applied.try_recv() {
+                    Ok(entry) => self.checker.observe(*id , entry)?,
+                    Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+                    Err(_) => break
+                }
+            }
+        }
+        for message in self.transport.take() {
+            self.network.submit(now , message , &mut self.rng);
+        }
+
+        self.collect_responses(now);
+        Ok(())
+    }
+
+    fn inject_faults(&mut self) {
+        let ids : Vec<u64> = (1..=self.config.nodes).collect();
+
+        if self.rng.gen_bool(self.config.partition_rate) {
+            if self.network.is_partitioned() {
+                self.network.heal();
+            } else {
+                let mut shuffled = ids.clone();
+                shuffled.shuffle(&mut self.rng);
+                let split = self.rng.gen_range(1..shuffled.len().max(2)).min(shuffled.len());
+                let (left , right) = shuffled.split_at(split);
+                self.network.partition(&[left.to_vec() , right.to_vec()]);
+                self.report.partitions += 1;
+            }
+        }
+
+        if self.rng.gen_bool(self.config.crash_rate) {
+            // Keep a majority alive so the cluster can still make progress.
+            let max_crashed = (self.config.nodes as usize - 1) / 2;
+            let restart = !self.crashed.is_empty() && (self.crashed.len() >= max_crashed || self.rng.gen_bool(0.5));
+            if !restart && self.crashed.len() < max_crashed {
+                let live : Vec<u64> = self.nodes.keys().copied().collect();
+                let victim = live[self.rng.gen_range(0..live.len())];
+                let sim_node = self.nodes.remove(&victim).unwrap();
+                self.crashed.insert(victim , sim_node.node.storage());
+                self.network.isolate_in_flight(victim);
+                self.report.crashes += 1;
+            } else if let Some(&id) = self.crashed.keys().next() {
+                self.restart(id);
+            }
+        }
+    }
+
+    fn restart(&mut self , id : u64) {
+        let storage = self.crashed.remove(&id).unwrap();
+        let seed = self.rng.r#gen();
+        let next_tick = self.clock.elapsed_ms() + self.rng.gen_range(0..self.config.tick_ms);
+        let sim_node = self.start_node(id , storage , seed , next_tick);
+        self.nodes.insert(id , sim_node);
+    }
+
+    async fn issue_request(&mut self) {
+        let client = self.rng.gen_range(0..self.clients.len());
+        let client_id = format!("client_{}" , client);
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+
+        let held : Vec<(String , String)> = self.clients[client].held.iter().map(|(lock , lease)| (lock.clone() , lease.clone())).collect();
+        let command = if self.rng.gen_bool(self.config.read_rate) {
+            None
+        } else if !held.is_empty() && self.rng.gen_bool(0.6) {
+            let (lock_id , lease_id) = held[self.rng.gen_range(0..held.len())].clone();
+            if self.rng.gen_bool(0.5) {
+                Some(LockCommand::Renew { request_id, lock_id, client_id, ttl_seconds: self.config.ttl_seconds, lease_id, idempotency_key: None, metadata: None })
+            } else {
+                Some(LockCommand::Release { request_id, lock_id, client_id, lease_id, idempotency_key: None })
+            }
+        } else {
+            let lock_id = format!("lock_{}" , self.rng.gen_range(0..self.config.locks));
+            Some(LockCommand::Acquire { lock_id, client_id, ttl_seconds: self.config.ttl_seconds, request_id, idempotency_key: None, queue: true, metadata: Default::default() })
+        };
+        let (lock_id , input) = match &command {
+            Some(command) => OpInput::from_command(command),
+            None => (format!("lock_{}" , self.rng.gen_range(0..self.config.locks)) , OpInput::Status)
+        };
+
+        let live : Vec<u64> = self.nodes.keys().copied().collect();
+        if live.is_empty() {
+            return
+        }
+        // Clients mostly find the leader, but sometimes hit a stale node.
+        let leader = self.nodes.values().find(|sim_node| sim_node.node.is_leader()).map(|sim_node| sim_node.node.id());
+        let target = match leader {
+            Some(leader) if self.rng.gen_bool(0.8) => leader,
+            _ => live[self.rng.gen_range(0..live.len())]
+        };
+
+        let operation = self.history.invoke(client , lock_id.clone() , input , self.clock.now());
+        let node = &mut self.nodes.get_mut(&target).unwrap().node;
+        let response = match command {
+            Some(command) => {
+                let (response_tx , response_rx) = oneshot::channel();
+                node.handle_command(command.clone() , response_tx).await;
+                PendingResponse::Command(command , response_rx)
+            }
+            None => {
+                let (response_tx , response_rx) = oneshot::channel();
+                node.read_status(LockId(lock_id) , response_tx);
+                PendingResponse::Read(response_rx)
+            }
+        };
+        self.pending.push(PendingRequest {
+            client,
+            operation,
+            deadline_ms: self.clock.elapsed_ms() + self.config.request_timeout_ms,
+            response
+        });
+    }
+
+    fn collect_responses(&mut self , now : u64) {
+        let mut still_pending = Vec::new();
+        for mut request in std::mem::take(&mut self.pending) {
+            let received = match &mut request.response {
+                PendingResponse::Command(command , response) => response.try_recv().map(|response| {
+                    self.clients[request.client].on_response(command , &response);
+                    OpOutput::from_response(&response)
+                }),
+                PendingResponse::Read(response) => response.try_recv().map(|response| OpOutput::from_read(&response))
+            };
+            match received {
+                Ok(output) => {
+                    self.report.responses += 1;
+                    match output {
+                        Some(output) => self.history.complete(request.operation , output , self.clock.now()),
+                        None => self.history.discard(request.operation)
+                    }
+                }
+                Err(oneshot::error::TryRecvError::Empty) if request.deadline_ms > now => still_pending.push(request),
+                // Timed out or the node crashed: the outcome is unknown.
+                Err(_) => {}
+            }
+        }
+        self.pending = still_pending;
+    }
+}
+
+impl SimClient{
+    fn on_response(&mut self , command : &LockCommand , response : &CommandResponse) {
+        match (command , response) {
+            (LockCommand::Acquire { lock_id , .. } , CommandResponse::AcquireGranted { lease_id , .. }) => {
+                self.held.insert(lock_id.clone() , lease_id.clone());
+            }
+            (LockCommand::Release { lock_id , .. } , _) => {
+                self.held.remove(lock_id);
+            }
+            (LockCommand::Renew { lock_id , .. } , CommandResponse::Error { error_type , .. }) if matches!(error_type.as_str() , "NotHolder" | "NotFound" | "Expired") => {
+                self.held.remove(lock_id);
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use chrono::{DateTime, Utc};
+
+use crate::{lock::types::LockState, raft::raft_commands::{CommandResponse, LockCommand, ReadResponse}};
+
+
+#[derive(Debug , Clone , PartialEq)]
+pub enum OpInput{
+    Acquire { client_id : String , ttl_seconds : u64 },
+    Release { client_id : String , lease_id : String },
+    Renew { client_id : String , lease_id : String , ttl_seconds : u64 },
+    Status
+}
+
+#[derive(Debug , Clone , PartialEq)]
+pub struct HolderView{
+    pub client_id : String,
+    pub lease_id : String,
+    pub expires_at : DateTime<Utc>
+}
+
+#[derive(Debug , Clone , PartialEq)]
+pub struct StatusView{
+    pub holder : Option<HolderView>,
+    pub queue : Vec<String>
+}
+
+#[derive(Debug , Clone , PartialEq)]
+pub enum OpOutput{
+    Granted { lease_id : String , expires_at : DateTime<Utc> },
+    Queued { position : usize },
+    Released,
+    Renewed { new_expiry : DateTime<Utc> },
+    /// `None` when the lock does not exist.
+    Status(Option<StatusView>),
+    Failed { error_type : String }
+}
+
+/// One client call. `output` and `returned_at` stay `None` when the client
+/// never learned the outcome, e.g. because of a timeout or a crash.
+#[derive(Debug , Clone)]
+pub struct Operation{
+    pub id : usize,
+    pub client : usize,
+    pub lock_id : String,
+    pub input : OpInput,
+    pub output : Option<OpOutput>,
+    pub invoked_at : DateTime<Utc>,
+    pub returned_at : Option<DateTime<Utc>>
+}
+
+/// Concurrent history of client operations, recorded with the time each
+/// call was made and the time its response arrived.
+#[derive(Debug , Clone , Default)]
+pub struct History{
+    operations : Vec<Operation>,
+    discarded : Vec<bool>
+}
+
+impl History{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn invoke(&mut self , client : usize , lock_id : String , input : OpInput , at : DateTime<Utc>) -> usize {
+        let id = self.operations.len();
+        self.operations.push(Operation { id, client, lock_id, input, output: None, invoked_at: at, returned_at: None });
+        self.discarded.push(false);
+        id
+    }
+
+    pub fn complete(&mut self , id : usize , output : OpOutput , at : DateTime<Utc>) {
+        let operation = &mut self.operations[id];
+        operation.output = Some(output);
+        operation.returned_at = Some(at);
+    }
+
+    /// Forgets an operation that provably had no effect, such as a request
+    /// rejected by a follower.
+    pub fn discard(&mut self , id : usize) {
+        self.discarded[id] = true;
+    }
+
+    pub fn operations(&self) -> impl Iterator<Item = &Operation> {
+        self.operations.iter().filter(|operation| !self.discarded[operation.id])
+    }
+
+    pub fn len(&self) -> usize {
+        self.operations().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl OpInput{
+    /// Splits a command into the lock it targets and the recorded input.
+    pub fn from_command(command : &LockCommand) -> (String , OpInput) {
+        match command {
+            LockCommand::Acquire { lock_id , client_id , ttl_seconds , .. } =>
+                (lock_id.clone() , OpInput::Acquire { client_id: client_id.clone(), ttl_seconds: *ttl_seconds }),
+            LockCommand::Release { lock_id , client_id , lease_id , .. } =>
+                (lock_id.clone() , OpInput::Release { client_id: client_id.clone(), lease_id: lease_id.clone() }),
+            LockCommand::Renew { lock_id , client_id , lease_id , ttl_seconds , .. } =>
+                (lock_id.clone() , OpInput::Renew { client_id: client_id.clone(), lease_id: lease_id.clone(), ttl_seconds: *ttl_seconds }),
+            LockCommand::Restore { .. } => unreachable!("simulated clients never restore snapshots"),
+            LockCommand::Transfer { .. } => unreachable!("simulated clients never transfer locks"),
+            LockCommand::SetAcl { .. } => unreachable!("simulated clients never change the ACL"),
+            LockCommand::SetQuotas { .. } => unreachable!("simulated clients never change the quotas"),
+            LockCommand::ForceRelease { .. } | LockCommand::EvictWaiter { .. } | LockCommand::RevokeClient { .. } =>
+                unreachable!("simulated clients never use admin commands"),
+            LockCommand::KvGet { .. } | LockCommand::KvPut { .. } | LockCommand::KvDelete { .. } =>
+                unreachable!("simulated clients never use the key-value store"),
+            LockCommand::BarrierCreate { .. } | LockCommand::BarrierJoin { .. } | LockCommand::BarrierArrive { .. } | LockCommand::BarrierDelete { .. } =>
+                unreachable!("simulated clients never use barriers"),
+        }
+    }
+}
+
+/// Rejections that guarantee the request never reached the log.
+fn is_rejection(error_type : &str) -> bool {
+    matches!(error_type , "NotLeader" | "Draining" | "ProposalFailed" | "ProposalDropped")
+}
+
+impl OpOutput{
+    /// `None` means the command was rejected before being proposed.
+    pub fn from_response(response : &CommandResponse) -> Option<OpOutput> {
+        let output = match response {
+            CommandResponse::AcquireGranted { lease_id , expires_at , .. } =>
+                OpOutput::Granted { lease_id: lease_id.clone(), expires_at: parse_time(expires_at) },
+            CommandResponse::AcquireQueued { position , .. } => OpOutput::Queued { position: *position },
+            CommandResponse::ReleaseSuccess => OpOutput::Released,
+            CommandResponse::RenewSuccess { new_expiry } => OpOutput::Renewed { new_expiry: parse_time(new_expiry) },
+            CommandResponse::Error { error_type , .. } if is_rejection(error_type) => return None,
+            CommandResponse::Error { error_type , .. } => OpOutput::Failed { error_type: error_type.clone() },
+            CommandResponse::Restored { .. } => unreachable!("simulated clients never restore snapshots"),
+            CommandResponse::Transferred { .. } => unreachable!("simulated clients never transfer locks"),
+            CommandResponse::AclUpdated { .. } => unreachable!("simulated clients never change the ACL"),
+            CommandResponse::QuotasUpdated { .. } => unreachable!("simulated clients never change the quotas"),
+            CommandResponse::ForceReleased { .. } | CommandResponse::WaiterEvicted { .. } | CommandResponse::ClientRevoked { .. } =>
+                unreachable!("simulated clients never use admin commands"),
+            CommandResponse::KvValue { .. } | CommandResponse::KvWritten { .. } | CommandResponse::KvDeleted { .. } =>
+                unreachable!("simulated clients never use the key-value store"),
+            CommandResponse::BarrierUpdated { .. } | CommandResponse::BarrierDeleted { .. } =>
+                unreachable!("simulated clients never use barriers"),
+        };
+        Some(output)
+    }
+
+    pub fn from_read(response : &ReadResponse) -> Option<OpOutput> {
+        match response {
+            ReadResponse::Status(state) => Some(OpOutput::Status(state.as_ref().map(StatusView::from_state))),
+            ReadResponse::Error { .. } => None
+        }
+    }
+}
+
+impl StatusView{
+    pub fn from_state(state : &LockState) -> Self {
+        Self {
+            holder: state.holder.as_ref().map(|holder| HolderView {
+                client_id: holder.client_id.0.clone(),
+                lease_id: holder.lease_id.0.clone(),
+                expires_at: holder.expires_at
+            }),
+            queue: state.wait_queue.iter().map(|waiter| waiter.client_id.0.clone()).collect()
+        }
+    }
+}
+
+fn parse_time(value : &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .expect("state machine produced an invalid timestamp")
+}
@@ -0,0 +1,136 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+
+use crate::raft::raft_commands::{AppliedEntry, CommandResponse, IdempotencyKey, LockCommand};
+
+
+struct ModelHolder{
+    lease_id : Option<String>,
+    expires_at : DateTime<Utc>
+}
+
+#[derive(Default)]
+struct ModelLock{
+    holder : Option<ModelHolder>,
+    /// TTL of each queued waiter, in promotion order.
+    queued : VecDeque<ChronoDuration>
+}
+
+/// Checks the entries applied by every node of a cluster.
+///
+/// * Agreement: nodes that apply the same log index apply the same command,
+///   at the same time, with the same response.
+/// * Exclusivity: replaying the log against a model of the lock table, a
+///   lock is never granted while another lease on it is unexpired.
+pub struct SafetyChecker{
+    applied : BTreeMap<u64 , (u64 , AppliedEntry)>,
+    last_modeled : u64,
+    locks : HashMap<String , ModelLock>,
+    seen_keys : HashSet<IdempotencyKey>,
+    grants : usize
+}
+
+impl Default for SafetyChecker{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SafetyChecker{
+    pub fn new() -> Self {
+        Self { applied: BTreeMap::new(), last_modeled: 0, locks: HashMap::new(), seen_keys: HashSet::new(), grants: 0 }
+    }
+
+    /// Number of grants and promotions seen so far.
+    pub fn grants(&self) -> usize {
+        self.grants
+    }
+
+    pub fn applied_count(&self) -> usize {
+        self.applied.len()
+    }
+
+    pub fn observe(&mut self , node : u64 , entry : AppliedEntry) -> Result<() , String> {
+        if let Some((first_node , first)) = self.applied.get(&entry.index) {
+            if first.command != entry.command || first.response != entry.response || first.applied_at != entry.applied_at {
+                return Err(format!(
+                    "Nodes {} and {} applied index {} differently: {:?} -> {:?} vs {:?} -> {:?}",
+                    first_node , node , entry.index , first.command , first.response , entry.command , entry.response
+                ))
+            }
+            return Ok(())
+        }
+
+        // Every node applies the log in order, so the first report of a new
+        // index always extends the modeled prefix.
+        if entry.index <= self.last_modeled {
+            return Err(format!("Node {} applied index {} out of order, index {} was applied before it" , node , entry.index , self.last_modeled))
+        }
+        self.last_modeled = entry.index;
+        self.model(&entry)?;
+        self.applied.insert(entry.index , (node , entry));
+        Ok(())
+    }
+
+    fn model(&mut self , entry : &AppliedEntry) -> Result<() , String> {
+        if let Some(key) = entry.command.idempotency_key()
+            && !self.seen_keys.insert(key.clone()) {
+            // Replays of a deduplicated command do not touch the lock table.
+            return Ok(())
+        }
+        let now = entry.applied_at;
+
+        match (&entry.command , &entry.response) {
+            (LockCommand::Acquire { lock_id , .. } , CommandResponse::AcquireGranted { lease_id , expires_at , .. }) => {
+                let lock = self.locks.entry(lock_id.clone()).or_default();
+                if let Some(holder) = &lock.holder
+                    && holder.expires_at >= now {
+                    return Err(format!(
+                        "Lock {} granted to lease {} at index {} while lease {:?} is held until {}",
+                        lock_id , lease_id , entry.index , holder.lease_id , holder.expires_at
+                    ))
+                }
+                lock.holder = Some(ModelHolder { lease_id: Some(lease_id.clone()), expires_at: parse_time(expires_at)? });
+                self.grants += 1;
+            }
+            (LockCommand::Acquire { lock_id , ttl_seconds , .. } , CommandResponse::AcquireQueued { .. }) => {
+                self.locks.entry(lock_id.clone()).or_default().queued.push_back(ChronoDuration::seconds(*ttl_seconds as i64));
+            }
+            (LockCommand::Release { lock_id , lease_id , .. } , CommandResponse::ReleaseSuccess) => {
+                let lock = self.locks.entry(lock_id.clone()).or_default();
+                match &lock.holder {
+                    Some(holder) if holder.lease_id.as_ref().is_none_or(|held| held == lease_id) => {}
+                    other => return Err(format!(
+                        "Lease {} released lock {} at index {} but the holder is {:?}",
+                        lease_id , lock_id , entry.index , other.as_ref().map(|holder| &holder.lease_id)
+                    ))
+                }
+                lock.holder = None;
+                if let Some(ttl) = lock.queued.pop_front() {
+                    // The promoted waiter's lease id is not part of any response.
+                    lock.holder = Some(ModelHolder { lease_id: None, expires_at: now + ttl });
+                    self.grants += 1;
+                }
+            }
+            (LockCommand::Renew { lock_id , lease_id , .. } , CommandResponse::RenewSuccess { new_expiry }) => {
+                let lock = self.locks.entry(lock_id.clone()).or_default();
+                match lock.holder.as_mut() {
+                    Some(holder) if holder.expires_at >= now => {
+                        holder.lease_id.get_or_insert_with(|| lease_id.clone());
+                        holder.expires_at = parse_time(new_expiry)?;
+                    }
+                    _ => return Err(format!("Lease {} renewed lock {} at index {} without holding it" , lease_id , lock_id , entry.index))
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn parse_time(value : &str) -> Result<DateTime<Utc> , String> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|e| format!("Invalid timestamp {} : {}" , value , e))
+}
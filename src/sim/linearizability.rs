@@ -0,0 +1,434 @@
+use std::{collections::{BTreeMap, HashSet}, fmt};
+
+use chrono::{DateTime, Utc};
+
+use crate::sim::history::{History, OpInput, OpOutput, Operation};
+
+
+/// Times are compared in microseconds since the epoch.
+type Micros = i64;
+
+const UNBOUNDED : Micros = Micros::MAX;
+
+fn micros(time : DateTime<Utc>) -> Micros {
+    time.timestamp_micros()
+}
+
+#[derive(Debug , Clone , PartialEq , Eq , Hash)]
+struct ModelHolder{
+    client_id : String,
+    /// `None` when the lease was handed out by an operation whose response
+    /// was never observed.
+    lease_id : Option<String>,
+    /// Bounds on the expiry time, which is only known exactly once a
+    /// response reveals it.
+    expires_lo : Micros,
+    expires_hi : Micros
+}
+
+/// Sequential model of a single lock, as `InMemoryLockManager` sees it.
+#[derive(Debug , Clone , PartialEq , Eq , Hash)]
+struct ModelState{
+    exists : bool,
+    holder : Option<ModelHolder>,
+    /// Waiters with the TTL their lease gets on promotion.
+    queue : Vec<(String , Micros)>,
+    /// Lower bound on the apply time of the next command. Apply times never
+    /// go backwards along the log.
+    now : Micros
+}
+
+struct CheckOp<'a>{
+    input : &'a OpInput,
+    /// `None` when the response is unknown or ignored.
+    output : Option<&'a OpOutput>,
+    call : Micros,
+    ret : Micros,
+    /// Operations that returned took effect. The others may never have.
+    returned : bool
+}
+
+impl ModelHolder{
+    fn new(client_id : &str , lease_id : Option<&String> , expires_lo : Micros , expires_hi : Micros) -> Self {
+        Self { client_id: client_id.to_string(), lease_id: lease_id.cloned(), expires_lo, expires_hi }
+    }
+
+    fn exact(client_id : &str , lease_id : &str , expires_at : Micros) -> Self {
+        Self::new(client_id , Some(&lease_id.to_string()) , expires_at , expires_at)
+    }
+
+    fn owned_by(&self , client_id : &str , lease_id : &str) -> bool {
+        self.client_id == client_id && self.lease_id.as_ref().is_none_or(|lease| lease == lease_id)
+    }
+
+    fn maybe_not_owned_by(&self , client_id : &str , lease_id : &str) -> bool {
+        self.client_id != client_id || self.lease_id.as_ref().is_none_or(|lease| lease != lease_id)
+    }
+}
+
+impl ModelState{
+    fn initial() -> Self {
+        Self { exists: false, holder: None, queue: Vec::new(), now: Micros::MIN }
+    }
+
+    /// Every state the lock can be in after `op` takes effect, given the
+    /// response it returned. An empty result means the response is
+    /// impossible from this state.
+    fn step(&self , op : &CheckOp) -> Vec<ModelState> {
+        let lo = self.now.max(op.call);
+        if lo > op.ret {
+            return Vec::new()
+        }
+        match op.input {
+            OpInput::Acquire { client_id , ttl_seconds } => self.acquire(op , lo , client_id , *ttl_seconds as Micros * 1_000_000),
+            OpInput::Release { client_id , lease_id } => self.release(op , lo , client_id , lease_id),
+            OpInput::Renew { client_id , lease_id , ttl_seconds } => self.renew(op , lo , client_id , lease_id , *ttl_seconds as Micros * 1_000_000),
+            OpInput::Status => self.status(op.output)
+        }
+    }
+
+    fn with(&self , now : Micros , change : impl FnOnce(&mut ModelState)) -> ModelState {
+        let mut next = self.clone();
+        next.exists = true;
+        next.now = now;
+        change(&mut next);
+        next
+    }
+
+    /// Pins the apply time from an expiry that was computed as `t + ttl`.
+    fn pinned(lo : Micros , ret : Micros , expires_at : Micros , ttl : Micros) -> Option<Micros> {
+        let at = expires_at - ttl;
+        (lo <= at && at <= ret).then_some(at)
+    }
+
+    fn acquire(&self , op : &CheckOp , lo : Micros , client_id : &str , ttl : Micros) -> Vec<ModelState> {
+        let mut next = Vec::new();
+        // Lower bound on the apply time if the current holder had expired.
+        let free_from = match &self.holder {
+            None => Some(lo),
+            Some(holder) => (holder.expires_lo < op.ret).then(|| lo.max(holder.expires_lo + 1))
+        };
+        let can_queue = self.holder.as_ref().is_some_and(|holder| holder.expires_hi >= lo);
+
+        match op.output {
+            Some(OpOutput::Granted { lease_id , expires_at }) => {
+                let expires_at = micros(*expires_at);
+                if let (Some(free_from) , Some(at)) = (free_from , Self::pinned(lo , op.ret , expires_at , ttl))
+                    && free_from <= at {
+                    next.push(self.with(at , |state| state.holder = Some(ModelHolder::exact(client_id , lease_id , expires_at))));
+                }
+            }
+            Some(OpOutput::Queued { position }) => {
+                if can_queue && *position == self.queue.len() {
+                    next.push(self.with(lo , |state| state.queue.push((client_id.to_string() , ttl))));
+                }
+            }
+            Some(_) => {}
+            None => {
+                if let Some(free_from) = free_from {
+                    let expires_hi = if op.ret == UNBOUNDED { UNBOUNDED } else { op.ret + ttl };
+                    next.push(self.with(free_from , |state| state.holder = Some(ModelHolder::new(client_id , None , free_from + ttl , expires_hi))));
+                }
+                if can_queue {
+                    next.push(self.with(lo , |state| state.queue.push((client_id.to_string() , ttl))));
+                }
+            }
+        }
+        next
+    }
+
+    fn released(&self , lo : Micros , ret : Micros) -> ModelState {
+        self.with(lo , |state| {
+            state.holder = None;
+            if !state.queue.is_empty() {
+                let (waiter , ttl) = state.queue.remove(0);
+                let expires_hi = if ret == UNBOUNDED { UNBOUNDED } else { ret + ttl };
+                state.holder = Some(ModelHolder::new(&waiter , None , lo + ttl , expires_hi));
+            }
+        })
+    }
+
+    fn release(&self , op : &CheckOp , lo : Micros , client_id : &str , lease_id : &str) -> Vec<ModelState> {
+        let can_release = self.holder.as_ref().is_some_and(|holder| holder.owned_by(client_id , lease_id));
+        match op.output {
+            Some(OpOutput::Released) => if can_release { vec![self.released(lo , op.ret)] } else { Vec::new() },
+            // Any response: released, or one of the failures that leave the
+            // lock alone.
+            None => {
+                let mut next = Vec::new();
+                if can_release {
+                    next.push(self.released(lo , op.ret));
+                }
+                if op.returned {
+                    next.push(ModelState { now: lo, ..self.clone() });
+                }
+                next
+            }
+            Some(OpOutput::Failed { error_type }) => {
+                let possible = match error_type.as_str() {
+                    "NotFound" => !self.exists,
+                    "NotHolder" => self.exists && self.holder.as_ref().is_none_or(|holder| holder.maybe_not_owned_by(client_id , lease_id)),
+                    _ => false
+                };
+                if possible { vec![ModelState { now: lo, ..self.clone() }] } else { Vec::new() }
+            }
+            _ => Vec::new()
+        }
+    }
+
+    fn renew(&self , op : &CheckOp , lo : Micros , client_id : &str , lease_id : &str , ttl : Micros) -> Vec<ModelState> {
+        let owned = self.holder.as_ref().filter(|holder| holder.owned_by(client_id , lease_id));
+        let renewed = |at : Micros , expires_lo : Micros , expires_hi : Micros| self.with(at , |state| {
+            state.holder = Some(ModelHolder::new(client_id , Some(&lease_id.to_string()) , expires_lo , expires_hi));
+        });
+        match op.output {
+            Some(OpOutput::Renewed { new_expiry }) => {
+                let new_expiry = micros(*new_expiry);
+                match (owned , Self::pinned(lo , op.ret , new_expiry , ttl)) {
+                    (Some(holder) , Some(at)) if holder.expires_hi >= at => vec![renewed(at , new_expiry , new_expiry)],
+                    _ => Vec::new()
+                }
+            }
+            Some(OpOutput::Failed { error_type }) => match error_type.as_str() {
+                "NotFound" if !self.exists => vec![ModelState { now: lo, ..self.clone() }],
+                "NotHolder" if self.exists && self.holder.as_ref().is_none_or(|holder| holder.maybe_not_owned_by(client_id , lease_id)) =>
+                    vec![ModelState { now: lo, ..self.clone() }],
+                "Expired" => match owned {
+                    Some(holder) if holder.expires_lo < op.ret => vec![ModelState { now: lo.max(holder.expires_lo + 1), ..self.clone() }],
+                    _ => Vec::new()
+                },
+                _ => Vec::new()
+            },
+            Some(_) => Vec::new(),
+            None => {
+                let mut next = Vec::new();
+                if let Some(holder) = owned
+                    && holder.expires_hi >= lo {
+                    let expires_hi = if op.ret == UNBOUNDED { UNBOUNDED } else { op.ret + ttl };
+                    next.push(renewed(lo , lo + ttl , expires_hi));
+                }
+                if op.returned {
+                    next.push(ModelState { now: lo, ..self.clone() });
+                }
+                next
+            }
+        }
+    }
+
+    /// Reads carry no timestamp of their own, so they leave `now` alone.
+    fn status(&self , output : Option<&OpOutput>) -> Vec<ModelState> {
+        let view = match output {
+            Some(OpOutput::Status(view)) => view,
+            _ => return Vec::new()
+        };
+        let Some(view) = view else {
+            return if self.exists { Vec::new() } else { vec![self.clone()] }
+        };
+        if !self.exists || !view.queue.iter().eq(self.queue.iter().map(|(client_id , _)| client_id)) {
+            return Vec::new()
+        }
+        let holder = match (&self.holder , &view.holder) {
+            (None , None) => None,
+            (Some(holder) , Some(seen)) => {
+                let expires_at = micros(seen.expires_at);
+                if !holder.owned_by(&seen.client_id , &seen.lease_id) || expires_at < holder.expires_lo || expires_at > holder.expires_hi {
+                    return Vec::new()
+                }
+                Some(ModelHolder::exact(&seen.client_id , &seen.lease_id , expires_at))
+            }
+            _ => return Vec::new()
+        };
+        vec![ModelState { holder, ..self.clone() }]
+    }
+}
+
+/// Wing & Gong style search, with the state cache used by Porcupine.
+///
+/// Operations whose response was never observed may take effect at any
+/// point after they were invoked, or not at all.
+struct Search<'a>{
+    ops : &'a [CheckOp<'a>],
+    cache : HashSet<(Vec<u64> , ModelState)>,
+    /// Number of states left to explore, unlimited when `None`.
+    budget : Option<usize>
+}
+
+impl Search<'_>{
+    /// `None` when the budget ran out before an answer was found.
+    fn run(mut self) -> Option<bool> {
+        let mut done = vec![0u64 ; self.ops.len().div_ceil(64)];
+        self.search(&ModelState::initial() , &mut done)
+    }
+
+    fn search(&mut self , state : &ModelState , done : &mut Vec<u64>) -> Option<bool> {
+        let ops = self.ops;
+        let is_done = |done : &[u64] , index : usize| done[index / 64] & (1 << (index % 64)) != 0;
+
+        // The next operation to take effect must have been invoked before
+        // any outstanding operation returned.
+        let deadline = (0..ops.len())
+            .filter(|index| !is_done(done , *index) && ops[*index].returned)
+            .map(|index| ops[index].ret)
+            .min();
+        let Some(deadline) = deadline else {
+            return Some(true)
+        };
+        if !self.cache.insert((done.clone() , state.clone())) {
+            return Some(false)
+        }
+        if let Some(budget) = &mut self.budget {
+            *budget = budget.checked_sub(1)?;
+        }
+
+        // Operations that returned go first, so that a linearization
+        // leaving out the others is found without backtracking.
+        let candidates = (0..ops.len()).filter(|index| ops[*index].returned)
+            .chain((0..ops.len()).filter(|index| !ops[*index].returned));
+        for index in candidates {
+            if is_done(done , index) || ops[index].call > deadline {
+                continue
+            }
+            for next in state.step(&ops[index]) {
+                done[index / 64] |= 1 << (index % 64);
+                let found = self.search(&next , done);
+                done[index / 64] &= !(1 << (index % 64));
+                if found != Some(false) {
+                    return found
+                }
+            }
+        }
+        Some(false)
+    }
+}
+
+/// How an operation takes part in a check.
+#[derive(Debug , Clone , Copy , PartialEq)]
+enum Role{
+    Observed,
+    /// Its response is ignored. It still took effect if it returned.
+    Ignored,
+    Dropped
+}
+
+fn check_ops(operations : &[&Operation] , roles : &[Role] , budget : Option<usize>) -> Option<bool> {
+    let ops : Vec<CheckOp> = operations.iter().zip(roles)
+        .filter(|(operation , role)| match role {
+            Role::Dropped => false,
+            // A read that never returned tells us nothing.
+            Role::Ignored => operation.input != OpInput::Status,
+            Role::Observed => operation.input != OpInput::Status || operation.output.is_some()
+        })
+        .map(|(operation , role)| {
+            let output = if *role == Role::Observed { operation.output.as_ref() } else { None };
+            CheckOp {
+                input: &operation.input,
+                output,
+                call: micros(operation.invoked_at),
+                ret: operation.returned_at.map_or(UNBOUNDED , micros),
+                returned: operation.returned_at.is_some()
+            }
+        })
+        .collect();
+    Search { ops: &ops, cache: HashSet::new(), budget }.run()
+}
+
+/// A history that no sequential execution of the lock model can explain.
+#[derive(Debug , Clone)]
+pub struct Counterexample{
+    pub lock_id : String,
+    /// Operations whose observed responses are enough to make the history
+    /// non-linearizable.
+    pub operations : Vec<Operation>,
+    /// Operations on the same lock that still take part in the failure,
+    /// with their responses ignored.
+    pub ignored : Vec<Operation>,
+    /// Invocation of the first operation on the lock, times are printed
+    /// relative to it.
+    pub since : DateTime<Utc>
+}
+
+impl fmt::Display for Counterexample{
+    fn fmt(&self , f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f , "history of {} is not linearizable:" , self.lock_id)?;
+        let origin = self.since;
+        let mut operations : Vec<(&Operation , bool)> = self.operations.iter().map(|operation| (operation , true))
+            .chain(self.ignored.iter().map(|operation| (operation , false)))
+            .collect();
+        operations.sort_by_key(|(operation , _)| (operation.invoked_at , operation.id));
+        for (operation , observed) in operations {
+            let returned = operation.returned_at.map(|at| format!("{}" , (at - origin).num_milliseconds())).unwrap_or("?".to_string());
+            let output = if observed { format!("{:?}" , operation.output) } else { "(any result)".to_string() };
+            writeln!(f , "  #{} client {} [{}ms, {}ms] {:?} -> {}" ,
+                operation.id , operation.client , (operation.invoked_at - origin).num_milliseconds() , returned , operation.input , output)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks every lock's history against the sequential lock model.
+///
+/// Locks are independent, so each one is checked on its own. On failure
+/// the history of the first failing lock is shrunk to a minimal
+/// counterexample.
+pub fn check(history : &History) -> Result<() , Counterexample> {
+    let mut by_lock : BTreeMap<&str , Vec<&Operation>> = BTreeMap::new();
+    for operation in history.operations() {
+        by_lock.entry(&operation.lock_id).or_default().push(operation);
+    }
+
+    for (lock_id , mut operations) in by_lock {
+        operations.sort_by_key(|operation| (operation.invoked_at , operation.id));
+        if check_ops(&operations , &vec![Role::Observed ; operations.len()] , None) == Some(false) {
+            return Err(shrink(lock_id , &operations))
+        }
+    }
+    Ok(())
+}
+
+/// States a single check may explore while shrinking. Checks that run out
+/// count as passing, which only makes the counterexample larger.
+const SHRINK_BUDGET : usize = 20_000;
+
+fn shrink(lock_id : &str , operations : &[&Operation]) -> Counterexample {
+    let fails = |roles : &[Role]| check_ops(operations , roles , Some(SHRINK_BUDGET)) == Some(false);
+
+    // Shortest failing prefix by invocation order. Later operations that
+    // overlap the prefix may still have taken effect within it, so they are
+    // kept with their responses ignored. The ones invoked after every
+    // prefix operation returned can only come last and are dropped.
+    let prefix_roles = |len : usize| -> Vec<Role> {
+        let last_return = operations[..len].iter().filter_map(|operation| operation.returned_at).max();
+        operations.iter().enumerate().map(|(index , operation)| {
+            if index < len {
+                Role::Observed
+            } else if last_return.is_some_and(|at| operation.invoked_at > at) {
+                Role::Dropped
+            } else {
+                Role::Ignored
+            }
+        }).collect()
+    };
+    let (mut lo , mut hi) = (1 , operations.len());
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if fails(&prefix_roles(mid)) { hi = mid } else { lo = mid + 1 }
+    }
+
+    // Then ignore every response that is not needed for the failure.
+    let mut roles = prefix_roles(lo);
+    for index in 0..lo {
+        roles[index] = Role::Ignored;
+        if !fails(&roles) {
+            roles[index] = Role::Observed;
+        }
+    }
+
+    let with_role = |wanted : Role| -> Vec<Operation> {
+        operations.iter().zip(&roles)
+            .filter(|(operation , role)| **role == wanted && (wanted == Role::Observed || operation.input != OpInput::Status))
+            .map(|(operation , _)| (*operation).clone())
+            .collect()
+    };
+    let (essential , ignored) = (with_role(Role::Observed) , with_role(Role::Ignored));
+    Counterexample { lock_id: lock_id.to_string(), ignored, operations: essential, since: operations[0].invoked_at }
+}
@@ -0,0 +1,17 @@
+//! Deterministic in-process simulation of a distlock cluster.
+//!
+//! A `Simulation` runs several `RaftNode`s on a virtual clock and a
+//! simulated network. Message loss, delays, reordering, partitions and
+//! crashes are all drawn from a single seed, so a failing schedule can be
+//! replayed by running the same seed again.
+//!
+//! Each run also records the history of client calls, which
+//! `linearizability::check` compares against a sequential lock model.
+
+pub mod clock;
+pub mod network;
+pub mod invariants;
+pub mod cluster;
+pub mod history;
+pub mod linearizability;
+pub mod sim_test;
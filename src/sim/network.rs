@@ -0,0 +1,102 @@
+use std::{collections::BTreeSet, sync::{Arc, Mutex}};
+
+use rand::{Rng, rngs::StdRng, seq::SliceRandom};
+use raft::prelude::Message;
+
+use crate::raft::transport::Transport;
+
+
+/// Collects the messages a node sends so the simulation can route them.
+#[derive(Clone , Default)]
+pub struct SimTransport{
+    outbox : Arc<Mutex<Vec<Message>>>
+}
+
+impl SimTransport{
+    pub fn take(&self) -> Vec<Message> {
+        std::mem::take(&mut *self.outbox.lock().unwrap())
+    }
+}
+
+impl Transport for SimTransport{
+    fn send(&self , message : Message) {
+        self.outbox.lock().unwrap().push(message);
+    }
+}
+
+struct InFlight{
+    deliver_at_ms : u64,
+    message : Message
+}
+
+/// Lossy network with random per-message delays. Messages that become due
+/// in the same step are delivered in random order.
+pub struct SimNetwork{
+    in_flight : Vec<InFlight>,
+    // Directed (from, to) pairs that cannot talk to each other.
+    blocked : BTreeSet<(u64 , u64)>,
+    drop_rate : f64,
+    max_delay_ms : u64
+}
+
+impl SimNetwork{
+    pub fn new(drop_rate : f64 , max_delay_ms : u64) -> Self {
+        Self { in_flight: Vec::new(), blocked: BTreeSet::new(), drop_rate, max_delay_ms }
+    }
+
+    pub fn submit(&mut self , now_ms : u64 , message : Message , rng : &mut StdRng) {
+        if self.is_blocked(message.from , message.to) || rng.gen_bool(self.drop_rate) {
+            return
+        }
+        let delay = rng.gen_range(1..=self.max_delay_ms.max(1));
+        self.in_flight.push(InFlight { deliver_at_ms: now_ms + delay, message });
+    }
+
+    /// Removes and returns the messages due at `now_ms`. Messages sent across
+    /// a partition that formed while they were in flight are dropped.
+    pub fn deliver(&mut self , now_ms : u64 , rng : &mut StdRng) -> Vec<Message> {
+        let (due , pending) : (Vec<InFlight> , Vec<InFlight>) = std::mem::take(&mut self.in_flight)
+            .into_iter()
+            .partition(|in_flight| in_flight.deliver_at_ms <= now_ms);
+        self.in_flight = pending;
+
+        let mut due : Vec<Message> = due.into_iter()
+            .map(|in_flight| in_flight.message)
+            .filter(|message| !self.is_blocked(message.from , message.to))
+            .collect();
+        due.shuffle(rng);
+        due
+    }
+
+    /// Splits the cluster: nodes in different groups cannot reach each other.
+    pub fn partition(&mut self , groups : &[Vec<u64>]) {
+        self.blocked.clear();
+        for (i , group) in groups.iter().enumerate() {
+            for other in groups.iter().skip(i + 1) {
+                for a in group {
+                    for b in other {
+                        self.blocked.insert((*a , *b));
+                        self.blocked.insert((*b , *a));
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn heal(&mut self) {
+        self.blocked.clear();
+    }
+
+    pub fn is_partitioned(&self) -> bool {
+        !self.blocked.is_empty()
+    }
+
+    /// Drops everything addressed to or sent by `node`, e.g. when it crashes.
+    pub fn isolate_in_flight(&mut self , node : u64) {
+        self.in_flight.retain(|in_flight| in_flight.message.to != node && in_flight.message.from != node);
+    }
+
+    fn is_blocked(&self , from : u64 , to : u64) -> bool {
+        self.blocked.contains(&(from , to))
+    }
+}
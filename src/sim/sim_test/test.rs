@@ -0,0 +1,92 @@
+
+
+#[cfg(test)]
+mod tests{
+    use chrono::{TimeZone, Utc};
+
+    use crate::sim::{cluster::{SimConfig, Simulation}, history::{History, OpInput, OpOutput}, linearizability};
+
+    fn schedules() -> u64 {
+        std::env::var("DISTLOCK_SIM_SCHEDULES").ok().and_then(|value| value.parse().ok()).unwrap_or(1_000)
+    }
+
+    #[tokio::test]
+
+    async fn test_lock_safety_across_random_schedules(){
+        let mut grants = 0;
+        for seed in 0..schedules() {
+            let config = SimConfig { seed , steps : 600 , ..Default::default() };
+            let report = Simulation::new(config).run().await.unwrap_or_else(|e| panic!("{}" , e));
+            grants += report.grants;
+        }
+        assert!(grants > 0 , "No lock was ever granted");
+    }
+
+    #[tokio::test]
+
+    async fn test_lock_safety_under_heavy_faults(){
+        for seed in 0..20 {
+            let config = SimConfig {
+                seed,
+                nodes : 5,
+                steps : 3_000,
+                drop_rate : 0.2,
+                max_delay_ms : 300,
+                partition_rate : 0.01,
+                crash_rate : 0.01,
+                ..Default::default()
+            };
+            Simulation::new(config).run().await.unwrap_or_else(|e| panic!("{}" , e));
+        }
+    }
+
+    #[tokio::test]
+
+    async fn test_same_seed_replays_same_schedule(){
+        let config = SimConfig { seed : 42 , steps : 1_000 , ..Default::default() };
+        let first = Simulation::new(config.clone()).run().await.unwrap();
+        let second = Simulation::new(config).run().await.unwrap();
+        assert_eq!(format!("{:?}" , first) , format!("{:?}" , second));
+        assert!(first.applied > 0);
+    }
+
+    #[tokio::test]
+
+    async fn test_histories_are_linearizable(){
+        for seed in 0..100 {
+            let config = SimConfig { seed , steps : 1_000 , ..Default::default() };
+            let report = Simulation::new(config).run().await.unwrap_or_else(|e| panic!("{}" , e));
+            assert!(!report.history.is_empty());
+            if let Err(counterexample) = linearizability::check(&report.history) {
+                panic!("seed {}: {}" , seed , counterexample);
+            }
+        }
+    }
+
+    #[test]
+
+    fn test_checker_reports_minimal_counterexample(){
+        let at = |ms : i64| Utc.timestamp_millis_opt(1_700_000_000_000 + ms).unwrap();
+        let acquire = |client : &str| OpInput::Acquire { client_id : client.to_string() , ttl_seconds : 10 };
+        let mut history = History::new();
+
+        let first = history.invoke(0 , "lock".to_string() , acquire("a") , at(0));
+        history.complete(first , OpOutput::Granted { lease_id : "l1".to_string() , expires_at : at(10_000) } , at(5));
+        let other = history.invoke(2 , "other".to_string() , acquire("c") , at(20));
+        history.complete(other , OpOutput::Granted { lease_id : "l3".to_string() , expires_at : at(10_020) } , at(25));
+        let queued = history.invoke(2 , "lock".to_string() , acquire("c") , at(30));
+        history.complete(queued , OpOutput::Queued { position : 0 } , at(40));
+        // Granted while the first lease is still live.
+        let second = history.invoke(1 , "lock".to_string() , acquire("b") , at(100));
+        history.complete(second , OpOutput::Granted { lease_id : "l2".to_string() , expires_at : at(10_100) } , at(105));
+
+        let counterexample = linearizability::check(&history).unwrap_err();
+        assert_eq!(counterexample.lock_id , "lock");
+        // The first acquire returned, so it holds the lock whatever it
+        // answered: only the second grant's response is needed.
+        assert_eq!(counterexample.operations.len() , 1);
+        assert_eq!(counterexample.operations[0].id , second);
+        assert!(counterexample.ignored.iter().any(|operation| operation.id == first));
+        assert!(counterexample.ignored.iter().chain(&counterexample.operations).all(|operation| operation.id != other));
+    }
+}
@@ -0,0 +1,74 @@
+//! Metric names and the Prometheus recorder behind `/metrics`.
+//!
+//! Lock operations are counted by the state machine of every replica, so
+//! they describe what a node applied, not what clients asked of it. Sum
+//! them over the leader only, or compare nodes to spot a lagging one.
+
+use metrics::{Unit, describe_counter, describe_gauge, describe_histogram};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle, PrometheusRecorder};
+
+/// Labels `operation` (acquire, release, renew) and `result`.
+pub const LOCK_OPERATIONS : &str = "distlock_lock_operations_total";
+/// Leases that expired and were taken over by another client.
+pub const LOCK_EXPIRATIONS : &str = "distlock_lock_expirations_total";
+pub const QUEUE_WAIT : &str = "distlock_queue_wait_seconds";
+pub const LOCKS : &str = "distlock_locks";
+pub const LOCKS_HELD : &str = "distlock_locks_held";
+pub const WAITERS : &str = "distlock_waiters";
+pub const MAX_QUEUE_LENGTH : &str = "distlock_max_queue_length";
+
+pub const GRANT_LATENCY : &str = "distlock_grant_latency_seconds";
+/// Labels `endpoint` (the route) and `status`.
+pub const HTTP_REQUESTS : &str = "distlock_http_requests_total";
+pub const HTTP_REQUEST_DURATION : &str = "distlock_http_request_duration_seconds";
+
+pub const RAFT_TERM : &str = "distlock_raft_term";
+pub const RAFT_COMMIT_INDEX : &str = "distlock_raft_commit_index";
+pub const RAFT_APPLIED_INDEX : &str = "distlock_raft_applied_index";
+pub const RAFT_LEADER_CHANGES : &str = "distlock_raft_leader_changes_total";
+pub const PROPOSAL_LATENCY : &str = "distlock_raft_proposal_latency_seconds";
+pub const PENDING_PROPOSALS : &str = "distlock_raft_pending_proposals";
+/// Labels `peer` and `reason` (connect, encode, write).
+pub const PEER_MESSAGE_ERRORS : &str = "distlock_peer_message_errors_total";
+
+// Queue waits run into minutes, proposals into milliseconds.
+const BUCKETS : &[f64] = &[0.001 , 0.0025 , 0.005 , 0.01 , 0.025 , 0.05 , 0.1 , 0.25 , 0.5 , 1.0 , 2.5 , 5.0 , 10.0 , 30.0 , 60.0 , 300.0];
+
+/// Recorder rendering histograms with `BUCKETS`. Not installed; tests use
+/// it as a local recorder.
+pub fn recorder() -> PrometheusRecorder {
+    PrometheusBuilder::new()
+        .set_buckets(BUCKETS)
+        .expect("buckets are not empty")
+        .build_recorder()
+}
+
+/// Installs the recorder for the whole process. Fails when one is already
+/// installed.
+pub fn install() -> Result<PrometheusHandle , String> {
+    let recorder = recorder();
+    let handle = recorder.handle();
+    metrics::set_global_recorder(recorder).map_err(|e| format!("Failed to install the metrics recorder : {}" , e))?;
+    describe();
+    Ok(handle)
+}
+
+fn describe() {
+    describe_counter!(LOCK_OPERATIONS , "Lock operations applied to the state machine, by operation and result");
+    describe_counter!(LOCK_EXPIRATIONS , "Leases that expired and were taken over");
+    describe_histogram!(QUEUE_WAIT , Unit::Seconds , "Time waiters spent queued before being granted the lock");
+    describe_gauge!(LOCKS , "Lock entries in the state machine");
+    describe_gauge!(LOCKS_HELD , "Locks with an unexpired holder");
+    describe_gauge!(WAITERS , "Clients queued across all locks");
+    describe_gauge!(MAX_QUEUE_LENGTH , "Length of the longest wait queue");
+    describe_histogram!(GRANT_LATENCY , Unit::Seconds , "Time from receiving an acquire to answering it with a grant");
+    describe_counter!(HTTP_REQUESTS , "HTTP requests by endpoint and status");
+    describe_histogram!(HTTP_REQUEST_DURATION , Unit::Seconds , "HTTP request duration by endpoint");
+    describe_gauge!(RAFT_TERM , "Current raft term");
+    describe_gauge!(RAFT_COMMIT_INDEX , "Highest log index known to be committed");
+    describe_gauge!(RAFT_APPLIED_INDEX , "Highest log index applied to the state machine");
+    describe_counter!(RAFT_LEADER_CHANGES , "Times this node saw a new leader");
+    describe_histogram!(PROPOSAL_LATENCY , Unit::Seconds , "Time from proposing a command to applying it, on the proposer");
+    describe_gauge!(PENDING_PROPOSALS , "Proposals waiting to be applied");
+    describe_counter!(PEER_MESSAGE_ERRORS , "Raft messages that could not be sent to a peer");
+}
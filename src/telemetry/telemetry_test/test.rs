@@ -0,0 +1,38 @@
+#[cfg(test)]
+mod tests{
+    use std::time::Duration;
+
+    use chrono::Utc;
+
+    use crate::{lock::{manager::InMemoryLockManager, types::{AcquireResult, ApplyContext, ClientId, LockId, LockManager}}, telemetry::prometheus::recorder};
+
+    #[test]
+
+    fn test_lock_operations_are_recorded(){
+        let recorder = recorder();
+        let handle = recorder.handle();
+        metrics::with_local_recorder(&recorder , || {
+            let manager = InMemoryLockManager::new();
+            let lock_id = LockId("jobs".to_string());
+            let lease_id = match manager.try_acquire(&lock_id , &ClientId("a".to_string()) , Duration::from_secs(30)) {
+                AcquireResult::Granted { lease_id , .. } => lease_id,
+                other => panic!("Expected granted, got {:?}" , other)
+            };
+            manager.try_acquire(&lock_id , &ClientId("b".to_string()) , Duration::from_secs(30));
+            manager.record_gauges(Utc::now());
+            manager.release_at(&lock_id , &ClientId("a".to_string()) , &lease_id , &ApplyContext::local());
+        });
+
+        let rendered = handle.render();
+        for line in [
+            r#"distlock_lock_operations_total{operation="acquire",result="granted"} 1"#,
+            r#"distlock_lock_operations_total{operation="acquire",result="queued"} 1"#,
+            r#"distlock_lock_operations_total{operation="release",result="success"} 1"#,
+            "distlock_locks_held 1",
+            "distlock_waiters 1",
+            "distlock_queue_wait_seconds_count 1"
+        ] {
+            assert!(rendered.lines().any(|rendered| rendered == line) , "missing '{}' in\n{}" , line , rendered);
+        }
+    }
+}
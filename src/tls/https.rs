@@ -0,0 +1,74 @@
+//! Serving client protocols over TLS. `axum::serve` only speaks plain
+//! TCP, so connections are accepted here and handed to hyper after the
+//! handshake.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{Extension, Router};
+use hyper_util::{rt::{TokioExecutor, TokioIo}, server::conn::auto, service::TowerToHyperService};
+use tokio::{net::{TcpListener, TcpStream}, sync::mpsc};
+use tokio_rustls::server::TlsStream;
+
+use crate::tls::material::{TlsMaterial, certificate_subject};
+
+const HANDSHAKE_QUEUE : usize = 64;
+
+/// Subject of the certificate the client presented, attached to every
+/// request of the connection when client ids are bound to certificates.
+/// `None` when the certificate has no usable subject.
+#[derive(Clone , Debug)]
+pub struct ClientCertificate(pub Option<String>);
+
+/// Accepts connections until the listener fails and yields them once their
+/// handshake completes. Handshakes run concurrently, so a slow client does
+/// not hold up the others, and each uses the material current at the time.
+pub fn accept_tls(listener : TcpListener , tls : Arc<TlsMaterial>) -> mpsc::Receiver<(TlsStream<TcpStream> , SocketAddr)> {
+    let (tx , rx) = mpsc::channel(HANDSHAKE_QUEUE);
+    tokio::spawn(async move {
+        loop {
+            let (stream , remote) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    tracing::warn!("Failed to accept client connection: {}" , e);
+                    continue
+                }
+            };
+            if tx.is_closed() {
+                return
+            }
+            let acceptor = tls.api_acceptor();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                match acceptor.accept(stream).await {
+                    Ok(stream) => {
+                        let _ = tx.send((stream , remote)).await;
+                    }
+                    Err(e) => tracing::debug!("TLS handshake with {} failed: {}" , remote , e)
+                }
+            });
+        }
+    });
+    rx
+}
+
+/// Serves `app` over TLS until the listener fails.
+pub async fn serve_https(listener : TcpListener , app : Router , tls : Arc<TlsMaterial>) {
+    let bind_client_id = tls.client_id_from_cert();
+    let mut accepted = accept_tls(listener , tls);
+    while let Some((stream , remote)) = accepted.recv().await {
+        let app = if bind_client_id {
+            let subject = stream.get_ref().1.peer_certificates().and_then(|certs| certs.first()).and_then(certificate_subject);
+            app.clone().layer(Extension(ClientCertificate(subject)))
+        } else {
+            app.clone()
+        };
+        tokio::spawn(async move {
+            let served = auto::Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream) , TowerToHyperService::new(app))
+                .await;
+            if let Err(e) = served {
+                tracing::debug!("Client connection from {} closed: {}" , remote , e);
+            }
+        });
+    }
+}
@@ -0,0 +1,142 @@
+//! Certificates and keys of a node, loaded from the PEM files named in the
+//! config. `reload` swaps them in place; connections already established
+//! keep the material they were set up with.
+
+use std::{fs::File, io::BufReader, path::Path, sync::{Arc, RwLock}};
+
+use rustls::{ClientConfig, RootCertStore, ServerConfig, crypto::{CryptoProvider, ring}, pki_types::{CertificateDer, PrivateKeyDer}, server::WebPkiClientVerifier};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::config::server_config::TlsConfig;
+
+pub struct TlsMaterial{
+    config : TlsConfig,
+    loaded : RwLock<Loaded>
+}
+
+struct Loaded{
+    api : Arc<ServerConfig>,
+    peer_server : Option<Arc<ServerConfig>>,
+    peer_client : Option<Arc<ClientConfig>>
+}
+
+impl TlsMaterial{
+    /// Fails unless `config` names a certificate and a key.
+    pub fn load(config : &TlsConfig) -> Result<Self , String> {
+        let loaded = Loaded::from_config(config)?;
+        Ok(Self { config: config.clone(), loaded: RwLock::new(loaded) })
+    }
+
+    /// Reads the files again. On error the current material stays in use.
+    pub fn reload(&self) -> Result<() , String> {
+        let loaded = Loaded::from_config(&self.config)?;
+        *self.loaded.write().unwrap() = loaded;
+        Ok(())
+    }
+
+    pub fn api_acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.loaded.read().unwrap().api.clone())
+    }
+
+    /// `None` when raft traffic is not encrypted.
+    pub fn peer_acceptor(&self) -> Option<TlsAcceptor> {
+        self.loaded.read().unwrap().peer_server.clone().map(TlsAcceptor::from)
+    }
+
+    pub fn peer_connector(&self) -> Option<TlsConnector> {
+        self.loaded.read().unwrap().peer_client.clone().map(TlsConnector::from)
+    }
+
+    pub fn client_id_from_cert(&self) -> bool {
+        self.config.client_id_from_cert
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+impl Loaded{
+    fn from_config(config : &TlsConfig) -> Result<Self , String> {
+        let (Some(cert_file) , Some(key_file)) = (&config.cert_file , &config.key_file) else {
+            return Err("TLS needs a certificate and a key".to_string())
+        };
+        let chain = load_certs(cert_file)?;
+        let key = load_key(key_file)?;
+
+        let api = server_config(&chain , &key , config.client_ca_file.as_deref())?;
+        let (peer_server , peer_client) = match &config.peer_ca_file {
+            Some(peer_ca_file) => {
+                let server = server_config(&chain , &key , Some(peer_ca_file))?;
+                let client = ClientConfig::builder_with_provider(provider())
+                    .with_safe_default_protocol_versions()
+                    .map_err(|e| e.to_string())?
+                    .with_root_certificates(load_roots(peer_ca_file)?)
+                    .with_client_auth_cert(chain.clone() , key.clone_key())
+                    .map_err(|e| format!("Invalid certificate or key : {}" , e))?;
+                (Some(server) , Some(Arc::new(client)))
+            }
+            None => (None , None)
+        };
+        Ok(Self { api, peer_server, peer_client })
+    }
+}
+
+/// Requires client certificates signed by `client_ca` when it is given.
+fn server_config(chain : &[CertificateDer<'static>] , key : &PrivateKeyDer<'static> , client_ca : Option<&Path>) -> Result<Arc<ServerConfig> , String> {
+    let builder = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?;
+    let builder = match client_ca {
+        Some(client_ca) => {
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(load_roots(client_ca)?) , provider())
+                .build()
+                .map_err(|e| format!("Invalid CA {} : {}" , client_ca.display() , e))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth()
+    };
+    let mut config = builder.with_single_cert(chain.to_vec() , key.clone_key())
+        .map_err(|e| format!("Invalid certificate or key : {}" , e))?;
+    config.alpn_protocols = vec![b"h2".to_vec() , b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
+fn open(path : &Path) -> Result<BufReader<File> , String> {
+    File::open(path).map(BufReader::new).map_err(|e| format!("Failed to open {} : {}" , path.display() , e))
+}
+
+pub fn load_certs(path : &Path) -> Result<Vec<CertificateDer<'static>> , String> {
+    let certs = rustls_pemfile::certs(&mut open(path)?).collect::<Result<Vec<_> , _>>()
+        .map_err(|e| format!("Invalid PEM in {} : {}" , path.display() , e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificate in {}" , path.display()))
+    }
+    Ok(certs)
+}
+
+pub fn load_key(path : &Path) -> Result<PrivateKeyDer<'static> , String> {
+    rustls_pemfile::private_key(&mut open(path)?)
+        .map_err(|e| format!("Invalid PEM in {} : {}" , path.display() , e))?
+        .ok_or_else(|| format!("No private key in {}" , path.display()))
+}
+
+fn load_roots(path : &Path) -> Result<RootCertStore , String> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert).map_err(|e| format!("Invalid CA certificate in {} : {}" , path.display() , e))?;
+    }
+    Ok(roots)
+}
+
+/// Common name of the certificate's subject, or the whole subject when it
+/// has none.
+pub fn certificate_subject(cert : &CertificateDer<'_>) -> Option<String> {
+    let (_ , cert) = X509Certificate::from_der(cert.as_ref()).ok()?;
+    let subject = cert.subject();
+    match subject.iter_common_name().next().and_then(|name| name.as_str().ok()) {
+        Some(name) => Some(name.to_string()),
+        None => Some(subject.to_string()).filter(|subject| !subject.is_empty())
+    }
+}
@@ -0,0 +1,3 @@
+pub mod https;
+pub mod material;
+pub mod tls_test;
@@ -0,0 +1,162 @@
+#[cfg(test)]
+mod tests{
+    use std::{collections::HashMap, path::{Path, PathBuf}, sync::Arc, time::Duration};
+
+    use axum::{Extension, Router, routing::get};
+    use raft::prelude::Message;
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa, KeyPair};
+    use tokio::{net::TcpListener, sync::mpsc};
+
+    use crate::{config::server_config::TlsConfig, raft::transport::{TcpTransport, Transport, serve_peers_tls}, tls::{https::{ClientCertificate, serve_https}, material::{TlsMaterial, certificate_subject, load_certs}}};
+
+    struct TestCa{
+        cert : Certificate,
+        key : KeyPair
+    }
+
+    impl TestCa{
+        fn new(name : &str) -> Self {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            params.distinguished_name.push(DnType::CommonName , name);
+            Self { cert: params.self_signed(&key).unwrap(), key }
+        }
+
+        /// Certificate and key PEM for `name`, valid for localhost.
+        fn issue(&self , name : &str) -> (String , String) {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(vec!["localhost".to_string() , "127.0.0.1".to_string()]).unwrap();
+            params.distinguished_name.push(DnType::CommonName , name);
+            let cert = params.signed_by(&key , &self.cert , &self.key).unwrap();
+            (cert.pem() , key.serialize_pem())
+        }
+    }
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("distlock-tls-{}" , uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes a node certificate issued by `ca` and returns a config
+    /// trusting `ca` for peers and clients.
+    fn node_config(dir : &Path , ca : &TestCa , name : &str) -> TlsConfig {
+        let (cert , key) = ca.issue(name);
+        let write = |file : &str , contents : &str| {
+            let path = dir.join(file);
+            std::fs::write(&path , contents).unwrap();
+            path
+        };
+        TlsConfig {
+            cert_file: Some(write(&format!("{}.pem" , name) , &cert)),
+            key_file: Some(write(&format!("{}-key.pem" , name) , &key)),
+            peer_ca_file: Some(write("ca.pem" , &ca.cert.pem())),
+            client_ca_file: Some(dir.join("ca.pem")),
+            client_id_from_cert: true
+        }
+    }
+
+    fn message(to : u64) -> Message {
+        let mut message = Message::default();
+        message.set_to(to);
+        message.set_from(1);
+        message.set_term(7);
+        message
+    }
+
+    /// Starts a TLS peer listener and sends it one message from a node with
+    /// `sender` material. Returns what arrived.
+    async fn exchange(receiver : TlsConfig , sender : TlsConfig) -> Option<Message> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (mailbox , mut received) = mpsc::unbounded_channel();
+        tokio::spawn(serve_peers_tls(listener , mailbox , Arc::new(TlsMaterial::load(&receiver).unwrap())));
+
+        let transport = TcpTransport::with_tls(&HashMap::from([(2 , addr)]) , Arc::new(TlsMaterial::load(&sender).unwrap()));
+        transport.send(message(2));
+        tokio::time::timeout(Duration::from_secs(2) , received.recv()).await.ok().flatten()
+    }
+
+    #[tokio::test]
+
+    async fn test_peers_exchange_messages_over_mutual_tls(){
+        let dir = temp_dir();
+        let ca = TestCa::new("cluster ca");
+        let received = exchange(node_config(&dir , &ca , "node-2") , node_config(&dir , &ca , "node-1")).await.unwrap();
+        assert_eq!((received.get_to() , received.get_term()) , (2 , 7));
+    }
+
+    #[tokio::test]
+
+    async fn test_peer_from_another_ca_is_rejected(){
+        let (dir , other_dir) = (temp_dir() , temp_dir());
+        let ca = TestCa::new("cluster ca");
+        let intruder = node_config(&other_dir , &TestCa::new("other ca") , "intruder");
+        // The intruder trusts the cluster CA, so only the server side can
+        // refuse it.
+        let intruder = TlsConfig { peer_ca_file: Some(dir.join("ca.pem")), ..intruder };
+        let receiver = node_config(&dir , &ca , "node-2");
+        assert!(exchange(receiver , intruder).await.is_none());
+    }
+
+    async fn serve_subject(config : &TlsConfig) -> (String , Arc<TlsMaterial>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("https://localhost:{}/" , listener.local_addr().unwrap().port());
+        let tls = Arc::new(TlsMaterial::load(config).unwrap());
+        let app = Router::new().route("/" , get(|Extension(ClientCertificate(subject)) : Extension<ClientCertificate>| async move { subject.unwrap_or_default() }));
+        tokio::spawn(serve_https(listener , app , tls.clone()));
+        (url , tls)
+    }
+
+    fn https_client(ca_pem : &str , identity : Option<(String , String)>) -> reqwest::Client {
+        let mut builder = reqwest::Client::builder().use_rustls_tls().add_root_certificate(reqwest::Certificate::from_pem(ca_pem.as_bytes()).unwrap());
+        if let Some((cert , key)) = identity {
+            builder = builder.identity(reqwest::Identity::from_pem(format!("{}{}" , cert , key).as_bytes()).unwrap());
+        }
+        builder.build().unwrap()
+    }
+
+    #[tokio::test]
+
+    async fn test_https_requires_and_reports_client_certificate(){
+        let dir = temp_dir();
+        let ca = TestCa::new("cluster ca");
+        let (url , _) = serve_subject(&node_config(&dir , &ca , "node-1")).await;
+
+        let client = https_client(&ca.cert.pem() , Some(ca.issue("billing")));
+        assert_eq!(client.get(&url).send().await.unwrap().text().await.unwrap() , "billing");
+
+        assert!(https_client(&ca.cert.pem() , None).get(&url).send().await.is_err());
+        assert!(https_client(&ca.cert.pem() , Some(TestCa::new("other ca").issue("billing"))).get(&url).send().await.is_err());
+    }
+
+    #[tokio::test]
+
+    async fn test_reload_swaps_certificates(){
+        let dir = temp_dir();
+        let ca = TestCa::new("cluster ca");
+        let config = node_config(&dir , &ca , "node-1");
+        let (url , tls) = serve_subject(&config).await;
+        let identity = ca.issue("billing");
+        let (cert_file , key_file) = (config.cert_file.clone().unwrap() , config.key_file.clone().unwrap());
+
+        // A broken file is refused and the node keeps serving.
+        let current = std::fs::read_to_string(&cert_file).unwrap();
+        std::fs::write(&cert_file , "not a certificate").unwrap();
+        assert!(tls.reload().is_err());
+        assert!(https_client(&ca.cert.pem() , Some(identity.clone())).get(&url).send().await.is_ok());
+        std::fs::write(&cert_file , current).unwrap();
+
+        // A certificate from a new CA is served once reloaded.
+        let new_ca = TestCa::new("new cluster ca");
+        let (cert , key) = new_ca.issue("node-1");
+        std::fs::write(&cert_file , cert).unwrap();
+        std::fs::write(&key_file , key).unwrap();
+        tls.reload().unwrap();
+
+        assert_eq!(certificate_subject(&load_certs(&cert_file).unwrap()[0]).as_deref() , Some("node-1"));
+        assert!(https_client(&new_ca.cert.pem() , Some(identity.clone())).get(&url).send().await.is_ok());
+        assert!(https_client(&ca.cert.pem() , Some(identity)).get(&url).send().await.is_err());
+    }
+}